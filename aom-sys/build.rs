@@ -1,35 +1,788 @@
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
+#[cfg(feature = "build_sources")]
+use std::io::Read;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "build_sources")]
+use std::process::Command;
+
+#[cfg(feature = "build_sources")]
+use sha2::{Digest, Sha256};
+
+// Bindings shipped for docs.rs and the offline builds, generated with the
+// default features from the libaom version stored alongside
+const PREGENERATED: &str = "data/pregenerated/aom.rs";
+const PREGENERATED_VERSION: &str = "data/pregenerated/version";
+
+// Turn the doxygen comments of the headers into rustdoc markdown
+//
+// The indented lines would become code blocks, compiled as doctests, the
+// brackets intra-doc links and the angle brackets html tags, the doxygen
+// commands are rewritten or dropped.
+fn doxygen_to_markdown(comment: &str) -> String {
+    let mut out = String::new();
+
+    for line in comment.lines() {
+        let mut line = line.trim();
+
+        for brief in ["\\brief", "@brief"] {
+            if let Some(rest) = line.strip_prefix(brief) {
+                line = rest.trim_start();
+            }
+        }
+
+        let line = line
+            .replace("\\param[in,out] ", "* ")
+            .replace("\\param[in] ", "* ")
+            .replace("\\param[out] ", "* ")
+            .replace("\\param ", "* ")
+            .replace("\\retval ", "* ")
+            .replace("\\return ", "Returns ")
+            .replace("\\note ", "Note: ")
+            .replace("\\deprecated ", "Deprecated: ")
+            .replace("\\ref ", "")
+            .replace('[', "\\[")
+            .replace(']', "\\]")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+
+        out.push_str(&line);
+        out.push('\n');
+    }
+
+    out.trim_end().to_owned()
+}
+
+#[derive(Debug)]
+struct Doxygen;
+
+impl bindgen::callbacks::ParseCallbacks for Doxygen {
+    fn process_comment(&self, comment: &str) -> Option<String> {
+        Some(doxygen_to_markdown(comment))
+    }
+}
 
 fn format_write(builder: bindgen::Builder) -> String {
     builder
+        .parse_callbacks(Box::new(Doxygen))
         .generate()
         .unwrap()
         .to_string()
-        .replace("/**", "/*")
-        .replace("/*!", "/*")
+}
+
+fn use_pregenerated(out_path: &Path, docs_rs: bool) {
+    println!("cargo:rerun-if-changed={}", PREGENERATED);
+    println!("cargo:rerun-if-changed={}", PREGENERATED_VERSION);
+
+    if env::var_os("CARGO_FEATURE_ACCOUNTING").is_some() {
+        panic!("the accounting feature needs bindings generated from the libaom sources");
+    }
+
+    let missing = |path| {
+        format!(
+            "{} is missing, regenerate it from the libaom {} sources: \
+             AOM_SOURCE_DIR=<sources> AOM_SYS_UPDATE_BINDINGS=1 \
+             cargo build -p aom-sys --features build_sources",
+            path, AOM_VERSION
+        )
+    };
+    let bindings =
+        fs::read_to_string(PREGENERATED).unwrap_or_else(|_| panic!("{}", missing(PREGENERATED)));
+    let version = fs::read_to_string(PREGENERATED_VERSION)
+        .unwrap_or_else(|_| panic!("{}", missing(PREGENERATED_VERSION)));
+
+    // docs.rs has no libaom, the documentation does not link anyway
+    if docs_rs {
+        export(version.trim(), false);
+    } else {
+        probe_system();
+        export(version.trim(), system_static_link());
+    }
+
+    fs::write(out_path.join("aom.rs"), bindings).unwrap();
+}
+
+// Make the bound version and the linkage available to the crate, as
+// AOM_BOUND_VERSION and AOM_STATIC_LINK, and to the build scripts of the
+// dependents, as DEP_AOM_VERSION and DEP_AOM_STATIC
+fn export(version: &str, static_link: bool) {
+    println!("cargo:rustc-env=AOM_BOUND_VERSION={}", version);
+    println!("cargo:rustc-env=AOM_STATIC_LINK={}", static_link as u8);
+    println!("cargo:version={}", version);
+    println!("cargo:static={}", static_link as u8);
+}
+
+// Whether system-deps was asked to link the system libaom statically
+fn system_static_link() -> bool {
+    ["SYSTEM_DEPS_AOM_LINK", "SYSTEM_DEPS_LINK"]
+        .iter()
+        .find_map(|var| env::var(var).ok())
+        .is_some_and(|link| link == "static")
+}
+
+// Lowest libaom supported, as in the system-deps metadata of Cargo.toml
+const MIN_VERSION: &str = "3.0.0";
+
+fn parse_version(version: &str) -> Vec<u32> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split(['.', '-'])
+        .map_while(|n| n.parse().ok())
+        .collect()
+}
+
+// Probe the system libaom, failing with directions instead of the raw
+// system-deps error
+//
+// AOM_MIN_VERSION raises the version required, e.g. to the one the
+// controls used by the application appeared in.
+fn probe_system() -> system_deps::Dependencies {
+    println!("cargo:rerun-if-env-changed=AOM_MIN_VERSION");
+
+    // system-deps takes the linkage from the environment only, the
+    // variable set explicitly wins
+    if env::var_os("CARGO_FEATURE_STATIC").is_some()
+        && env::var_os("SYSTEM_DEPS_AOM_LINK").is_none()
+    {
+        env::set_var("SYSTEM_DEPS_AOM_LINK", "static");
+    }
+
+    let fail = |reason: String| -> ! {
+        panic!(
+            "\n\n{}\n\n\
+             aom-sys needs libaom {} or newer, either:\n\
+             - install the libaom development package, and set PKG_CONFIG_PATH \
+             to the directory holding aom.pc if it is not found\n\
+             - enable the `build` feature of libaom, `build_sources` of aom-sys, \
+             to build it from source\n\
+             - enable `use-pregenerated-bindings` to build the bindings without \
+             the headers, the library is still needed to link\n",
+            reason,
+            env::var("AOM_MIN_VERSION").unwrap_or_else(|_| MIN_VERSION.to_owned())
+        )
+    };
+
+    let libs = system_deps::Config::new()
+        .probe()
+        .unwrap_or_else(|err| fail(format!("libaom was not found: {}", err)));
+
+    let version = libs
+        .get_by_name("aom")
+        .map_or(String::new(), |lib| lib.version.clone());
+
+    if let Ok(min) = env::var("AOM_MIN_VERSION") {
+        if parse_version(&version) < parse_version(&min) {
+            fail(format!(
+                "libaom {} found, AOM_MIN_VERSION requires {}",
+                version, min
+            ));
+        }
+    }
+
+    libs
+}
+
+// Use the system libaom, return its include directories and version
+#[cfg(not(feature = "build_sources"))]
+fn probe() -> (Vec<PathBuf>, String) {
+    let libs = probe_system();
+    let version = libs
+        .get_by_name("aom")
+        .map_or(String::new(), |lib| lib.version.clone());
+
+    (
+        libs.all_include_paths().into_iter().cloned().collect(),
+        version,
+    )
+}
+
+// libaom release built by the build_sources feature, and the one of the
+// pregenerated bindings
+const AOM_VERSION: &str = "3.8.0";
+// SHA-256 of the release tarball, as published alongside it in the
+// libaom-<version>.tar.gz.sha256 file
+#[cfg(feature = "build_sources")]
+const AOM_SHA256: Option<&str> = None;
+
+// Locate the libaom sources to build
+//
+// In order of preference: the AOM_SOURCE_DIR tree, a git checkout of the
+// AOM_SOURCE_GIT revision for the unreleased versions, or the AOM_VERSION
+// release tarball, verified against its SHA-256. The fetched trees are kept
+// in OUT_DIR, the following builds reuse them.
+//
+// The version is returned only when known beforehand, for the release.
+#[cfg(feature = "build_sources")]
+fn fetch() -> (PathBuf, Option<&'static str>) {
+    println!("cargo:rerun-if-env-changed=AOM_SOURCE_DIR");
+    println!("cargo:rerun-if-env-changed=AOM_SOURCE_GIT");
+    println!("cargo:rerun-if-env-changed=AOM_SOURCE_SHA256");
+
+    if let Some(dir) = env::var_os("AOM_SOURCE_DIR") {
+        return (PathBuf::from(dir), None);
+    }
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    if let Ok(rev) = env::var("AOM_SOURCE_GIT") {
+        return (fetch_git(&out, &rev), None);
+    }
+
+    let dir = out.join(format!("libaom-{}", AOM_VERSION));
+    let stamp = dir.join(".aom-sys-verified");
+    if stamp.exists() {
+        return (dir, Some(AOM_VERSION));
+    }
+
+    let expected = env::var("AOM_SOURCE_SHA256")
+        .ok()
+        .or(AOM_SHA256.map(str::to_owned))
+        .unwrap_or_else(|| {
+            panic!(
+                "no SHA-256 pinned for libaom {}, set AOM_SOURCE_SHA256 to the \
+                 checksum published with the release or AOM_SOURCE_DIR to a \
+                 source tree",
+                AOM_VERSION
+            )
+        });
+
+    let url = format!(
+        "https://storage.googleapis.com/aom-releases/libaom-{}.tar.gz",
+        AOM_VERSION
+    );
+    let mut tarball = Vec::new();
+    ureq::get(&url)
+        .call()
+        .map_err(|err| err.to_string())
+        .and_then(|res| {
+            res.into_reader()
+                .read_to_end(&mut tarball)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            panic!(
+                "cannot download {}: {}, set AOM_SOURCE_DIR to build offline",
+                url, err
+            )
+        });
+
+    let digest: String = Sha256::digest(&tarball)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if !digest.eq_ignore_ascii_case(expected.trim()) {
+        panic!(
+            "checksum mismatch for {}: expected {}, got {}",
+            url,
+            expected.trim(),
+            digest
+        );
+    }
+
+    // Extracted aside, the tree is only moved in place once complete
+    let tmp = out.join("libaom-extract");
+    let _ = fs::remove_dir_all(&tmp);
+    let _ = fs::remove_dir_all(&dir);
+    tar::Archive::new(flate2::read::GzDecoder::new(&tarball[..]))
+        .unpack(&tmp)
+        .unwrap_or_else(|err| panic!("cannot extract {}: {}", url, err));
+
+    // The sources may sit in a top-level directory
+    let root = match fs::read_dir(&tmp).unwrap().collect::<Vec<_>>().as_slice() {
+        [Ok(entry)] if entry.path().join("CMakeLists.txt").exists() => entry.path(),
+        _ => tmp.clone(),
+    };
+    fs::rename(&root, &dir).unwrap();
+    let _ = fs::remove_dir_all(&tmp);
+
+    fs::write(&stamp, digest).unwrap();
+
+    (dir, Some(AOM_VERSION))
+}
+
+// Clone a libaom revision, for the versions without a release tarball
+#[cfg(feature = "build_sources")]
+fn fetch_git(out: &Path, rev: &str) -> PathBuf {
+    let dir = out.join(format!("aom-git-{}", rev.replace(['/', '\\'], "_")));
+    if dir.join("CMakeLists.txt").exists() {
+        return dir;
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+    let status = Command::new("git")
+        .args(["clone", "--depth", "1", "--branch", rev])
+        .arg("https://aomedia.googlesource.com/aom")
+        .arg(&dir)
+        .status()
+        .unwrap_or_else(|err| panic!("cannot run git to fetch libaom {}: {}", rev, err));
+
+    if !status.success() {
+        panic!("git clone of libaom {} failed with {}", rev, status);
+    }
+
+    dir
+}
+
+// The cmake build type of libaom
+//
+// Even a debug Rust build links an optimized libaom, an unoptimized one is
+// too slow to be usable and with MSVC would not link against the release C
+// runtime Rust uses. The debug-libaom feature asks for a Debug build,
+// AOM_BUILD_TYPE for any one.
+#[cfg(feature = "build_sources")]
+fn build_type() -> String {
+    if let Ok(build_type) = env::var("AOM_BUILD_TYPE") {
+        return build_type;
+    }
+
+    if env::var_os("CARGO_FEATURE_DEBUG_LIBAOM").is_some() {
+        return "Debug".to_owned();
+    }
+
+    match env::var("OPT_LEVEL").as_deref() {
+        Ok("s") | Ok("z") => "MinSizeRel",
+        _ => "Release",
+    }
+    .to_owned()
+}
+
+// The extra cmake options of AOM_CMAKE_FLAGS
+//
+// They are separated by semicolons, as cmake lists are, and given as
+// KEY=VALUE with or without the leading -D, e.g.
+// AOM_CMAKE_FLAGS="-DCONFIG_REALTIME_ONLY=1;ENABLE_NASM=1"
+#[cfg(feature = "build_sources")]
+fn cmake_flags() -> Vec<(String, String)> {
+    let flags = env::var("AOM_CMAKE_FLAGS").unwrap_or_default();
+
+    flags
+        .split(';')
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+        .map(|flag| {
+            let flag = flag.strip_prefix("-D").unwrap_or(flag);
+            let (key, value) = flag
+                .split_once('=')
+                .unwrap_or_else(|| panic!("AOM_CMAKE_FLAGS: {} is not KEY=VALUE", flag));
+            // -DKEY:TYPE=VALUE
+            let key = key.split(':').next().unwrap();
+
+            println!("aom-sys: cmake option {}={}", key, value);
+
+            (key.to_owned(), value.to_owned())
+        })
+        .collect()
+}
+
+// Describe the target system to cmake when cross compiling without a
+// toolchain file
+#[cfg(feature = "build_sources")]
+fn configure_cross(defines: &mut Vec<(String, String)>, target: &str, os: &str, arch: &str) {
+    let system = match os {
+        "linux" => "Linux",
+        "android" => "Android",
+        "windows" => "Windows",
+        "macos" => "Darwin",
+        "ios" => "iOS",
+        other => panic!(
+            "no cmake system known for {}, set AOM_CMAKE_TOOLCHAIN_FILE",
+            other
+        ),
+    };
+    define(defines, "CMAKE_SYSTEM_NAME", system);
+    define(defines, "CMAKE_SYSTEM_PROCESSOR", arch);
+
+    // The cc crate conventions, CC_<target> first
+    let target_env = |var: &str| {
+        [
+            format!("{}_{}", var, target),
+            format!("{}_{}", var, target.replace('-', "_")),
+            format!("TARGET_{}", var),
+        ]
+        .iter()
+        .find_map(|name| {
+            println!("cargo:rerun-if-env-changed={}", name);
+            env::var(name).ok()
+        })
+    };
+    if let Some(cc) = target_env("CC") {
+        define(defines, "CMAKE_C_COMPILER", &cc);
+    }
+    if let Some(cxx) = target_env("CXX") {
+        define(defines, "CMAKE_CXX_COMPILER", &cxx);
+    }
+
+    // The libaom cpu names, its assembly is x86 and arm only
+    let cpu = match arch {
+        "x86_64" => "x86_64",
+        "x86" => "x86",
+        "aarch64" => "arm64",
+        "arm" => "armv7",
+        _ => "generic",
+    };
+    define(defines, "AOM_TARGET_CPU", cpu);
+
+    // The runtime detection reads /proc/cpuinfo or needs cpufeatures on
+    // Android, the features of the target are assumed instead
+    if os == "android" {
+        define(defines, "CONFIG_RUNTIME_CPU_DETECT", "0");
+    }
+}
+
+#[cfg(feature = "build_sources")]
+fn define(defines: &mut Vec<(String, String)>, key: &str, value: &str) {
+    defines.push((key.to_owned(), value.to_owned()));
+}
+
+// A field of the pkg-config file installed by a previous build
+#[cfg(feature = "build_sources")]
+fn installed_pc_field(prefix: &Path, field: &str) -> Option<String> {
+    // GNUInstallDirs picks lib64 on some distributions
+    ["lib", "lib64"]
+        .iter()
+        .filter_map(|lib| fs::read_to_string(prefix.join(lib).join("pkgconfig/aom.pc")).ok())
+        .find_map(|pc| {
+            pc.lines()
+                .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+                .map(|v| v.trim().to_owned())
+        })
+}
+
+#[cfg(feature = "build_sources")]
+fn installed_version(prefix: &Path) -> Option<String> {
+    installed_pc_field(prefix, "Version")
+}
+
+// Link the system libraries a static libaom depends on
+//
+// They are listed in Libs.private, e.g. -lm and -lpthread, and the C++
+// runtime when an optional C++ component is enabled.
+#[cfg(feature = "build_sources")]
+fn link_private(prefix: &Path, target_os: &str) {
+    let libs = match installed_pc_field(prefix, "Libs.private") {
+        Some(libs) => libs,
+        // MSVC needs nothing more than its C runtime
+        None => return,
+    };
+
+    for lib in libs.split_whitespace().filter_map(|l| l.strip_prefix("-l")) {
+        match (lib, target_os) {
+            // Bionic provides the threads in libc
+            ("pthread", "android") => {}
+            ("stdc++", "macos" | "ios") => println!("cargo:rustc-link-lib=c++"),
+            _ => println!("cargo:rustc-link-lib={}", lib),
+        }
+    }
+}
+
+// The install prefix of the libaom build, cmake builds in its build
+// subdirectory
+//
+// It depends on OUT_DIR alone, never on the working directory cargo runs
+// the build script from. Host and target builds, e.g. of a build
+// dependency, do not share a build tree.
+#[cfg(feature = "build_sources")]
+fn install_dir() -> PathBuf {
+    PathBuf::from(env::var("OUT_DIR").unwrap())
+        .join("aom-build")
+        .join(env::var("TARGET").unwrap())
+}
+
+// Build and link the libaom sources, statically with the static feature,
+// return the installed include directory and the version
+//
+// The cmake crate picks the generator, so it works with MSBuild on MSVC
+// as well as with make or Ninja.
+#[cfg(feature = "build_sources")]
+fn build_sources() -> (Vec<PathBuf>, String) {
+    println!("cargo:rerun-if-env-changed=AOM_TARGET_CPU");
+    println!("cargo:rerun-if-env-changed=AOM_CMAKE_TOOLCHAIN_FILE");
+    println!("cargo:rerun-if-env-changed=AOM_CMAKE_FLAGS");
+    println!("cargo:rerun-if-env-changed=AOM_BUILD_TYPE");
+    println!("cargo:rerun-if-env-changed=AOM_SANITIZER");
+
+    // Not cargo directives, kept in the build script output for -vv
+    println!("aom-sys: libaom build options, set in the environment:");
+    println!("aom-sys:   AOM_SOURCE_DIR, AOM_SOURCE_GIT, AOM_SOURCE_SHA256: the sources");
+    println!("aom-sys:   AOM_TARGET_CPU: the cpu to optimize for, generic without nasm");
+    println!("aom-sys:   AOM_CMAKE_TOOLCHAIN_FILE: the cmake toolchain to cross compile");
+    println!("aom-sys:   AOM_BUILD_TYPE: the cmake build type, Release by default");
+    println!("aom-sys:   AOM_SANITIZER: the sanitizer to instrument libaom with, e.g. address");
+    println!("aom-sys:   AOM_CMAKE_FLAGS: ;-separated -DKEY=VALUE cmake options");
+    println!("aom-sys: and the realtime-only, static and debug-libaom cargo features");
+
+    // Serialize the builds sharing OUT_DIR, the fetch included, the lock is
+    // released when dropped
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::create_dir_all(&out).unwrap();
+    let lock = File::create(out.join(".aom-sys-lock")).unwrap();
+    lock.lock().unwrap();
+
+    let (src, src_version) = fetch();
+    let enabled = |feature| match env::var_os(feature) {
+        Some(_) => "1",
+        None => "0",
+    };
+
+    let mut defines = Vec::new();
+    define(
+        &mut defines,
+        "CONFIG_AV1_ENCODER",
+        enabled("CARGO_FEATURE_ENCODER"),
+    );
+    define(
+        &mut defines,
+        "CONFIG_AV1_DECODER",
+        enabled("CARGO_FEATURE_DECODER"),
+    );
+    if env::var_os("CARGO_FEATURE_STATIC").is_some() {
+        define(&mut defines, "BUILD_SHARED_LIBS", "0");
+        // The static library may end up in a cdylib
+        define(&mut defines, "CMAKE_POSITION_INDEPENDENT_CODE", "ON");
+    } else {
+        define(&mut defines, "BUILD_SHARED_LIBS", "1");
+    }
+    define(&mut defines, "ENABLE_DOCS", "0");
+    define(&mut defines, "ENABLE_EXAMPLES", "0");
+    define(&mut defines, "ENABLE_TESTS", "0");
+    define(&mut defines, "ENABLE_TOOLS", "0");
+
+    if env::var_os("CARGO_FEATURE_REALTIME_ONLY").is_some() {
+        define(&mut defines, "CONFIG_REALTIME_ONLY", "1");
+    }
+
+    // The inspection shim reads the decoder state the accounting fills
+    if env::var_os("CARGO_FEATURE_ACCOUNTING").is_some() {
+        define(&mut defines, "CONFIG_ACCOUNTING", "1");
+        define(&mut defines, "CONFIG_INSPECTION", "1");
+    }
+
+    let target = env::var("TARGET").unwrap();
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+
+    if let Ok(toolchain) = env::var("AOM_CMAKE_TOOLCHAIN_FILE") {
+        define(&mut defines, "CMAKE_TOOLCHAIN_FILE", &toolchain);
+    } else if target != env::var("HOST").unwrap() {
+        configure_cross(&mut defines, &target, &target_os, &target_arch);
+    }
+
+    // Without nasm, e.g. on a bare MSVC setup, AOM_TARGET_CPU=generic
+    // disables the assembly
+    if let Ok(cpu) = env::var("AOM_TARGET_CPU") {
+        define(&mut defines, "AOM_TARGET_CPU", &cpu);
+    }
+
+    // libaom adds -fsanitize=<sanitizer> to its compile and link flags
+    let sanitizer = env::var("AOM_SANITIZER").ok();
+    if let Some(sanitizer) = &sanitizer {
+        define(&mut defines, "SANITIZE", sanitizer);
+    }
+
+    // Applied last, so they override the options set above
+    defines.extend(cmake_flags());
+
+    let profile = build_type();
+
+    let dst = install_dir();
+    fs::create_dir_all(&dst).unwrap();
+
+    // What the installed library was built from, a release is not rebuilt
+    // unless the options change
+    let fingerprint = format!("{}\n{}\n{:?}\n", src.display(), profile, defines);
+    let stamp = dst.join(".aom-sys-build");
+    let up_to_date = src_version.is_some()
+        && installed_version(&dst).as_deref() == src_version
+        && fs::read_to_string(&stamp).is_ok_and(|s| s == fingerprint);
+
+    if !up_to_date {
+        let _ = fs::remove_file(&stamp);
+
+        let mut config = cmake::Config::new(&src);
+        config.out_dir(&dst).profile(&profile);
+        for (key, value) in &defines {
+            config.define(key, value);
+        }
+        config.build();
+
+        fs::write(&stamp, fingerprint).unwrap();
+    }
+
+    // GNUInstallDirs picks lib64 on some distributions
+    for lib in ["lib", "lib64"] {
+        println!("cargo:rustc-link-search=native={}", dst.join(lib).display());
+    }
+    if env::var_os("CARGO_FEATURE_STATIC").is_some() {
+        // aom.lib with MSVC, libaom.a elsewhere
+        println!("cargo:rustc-link-lib=static=aom");
+        link_private(&dst, &target_os);
+
+        // An instrumented Rust build links the sanitizer runtime already,
+        // otherwise the one of the C compiler is needed
+        let rust_sanitizers = env::var("CARGO_CFG_SANITIZE").unwrap_or_default();
+        if let Some(sanitizer) = sanitizer {
+            if !rust_sanitizers.split(',').any(|s| s == sanitizer) {
+                match sanitizer.as_str() {
+                    "address" => println!("cargo:rustc-link-lib=asan"),
+                    "undefined" => println!("cargo:rustc-link-lib=ubsan"),
+                    "thread" => println!("cargo:rustc-link-lib=tsan"),
+                    other => println!(
+                        "cargo:warning=link the runtime of the {} sanitizer, \
+                         e.g. building with -Zsanitizer={}",
+                        other, other
+                    ),
+                }
+            }
+        }
+    } else {
+        // The DLL lands in bin, cargo puts the search paths in the loader
+        // path of cargo run and cargo test
+        println!(
+            "cargo:rustc-link-search=native={}",
+            dst.join("bin").display()
+        );
+        println!("cargo:rustc-link-lib=dylib=aom");
+    }
+
+    let version = installed_version(&dst).unwrap_or_default();
+
+    (vec![dst.join("include")], version)
+}
+
+// The libaom source tree and the build tree holding the generated
+// config/aom_config.h, the accounting structures are only declared in the
+// internal headers
+//
+// The libaom built from the sources is configured for the accounting, a
+// system one must have been built with -DCONFIG_ACCOUNTING=1
+// -DCONFIG_INSPECTION=1 from the AOM_SOURCE_DIR and AOM_BUILD_DIR trees.
+#[cfg(feature = "accounting")]
+fn internal_trees() -> (PathBuf, PathBuf) {
+    #[cfg(feature = "build_sources")]
+    {
+        let (src, _) = fetch();
+
+        (src, install_dir().join("build"))
+    }
+
+    #[cfg(not(feature = "build_sources"))]
+    {
+        println!("cargo:rerun-if-env-changed=AOM_SOURCE_DIR");
+        println!("cargo:rerun-if-env-changed=AOM_BUILD_DIR");
+
+        match (env::var_os("AOM_SOURCE_DIR"), env::var_os("AOM_BUILD_DIR")) {
+            (Some(src), Some(build)) => (PathBuf::from(src), PathBuf::from(build)),
+            _ => panic!(
+                "\n\nthe accounting feature needs the internal libaom headers, either:\n\
+                 - enable the `build` feature of libaom, `build_sources` of aom-sys, \
+                 to build libaom with the accounting\n\
+                 - set AOM_SOURCE_DIR and AOM_BUILD_DIR to the source and build \
+                 trees of a libaom configured with -DCONFIG_ACCOUNTING=1 \
+                 -DCONFIG_INSPECTION=1\n"
+            ),
+        }
+    }
+}
+
+// Bind the accounting structures and build the inspection shim
+#[cfg(feature = "accounting")]
+fn accounting(builder: bindgen::Builder, headers: &[PathBuf]) -> bindgen::Builder {
+    println!("cargo:rerun-if-changed=data/inspection.c");
+
+    let (src, build) = internal_trees();
+
+    // The decoder state is opaque, a small shim reads it in C using the
+    // same headers libaom was built with.
+    cc::Build::new()
+        .file("data/inspection.c")
+        .include(&src)
+        .include(&build)
+        .includes(headers)
+        .compile("aom_sys_inspection");
+
+    builder
+        .allowlist_type("Accounting")
+        .header("data/accounting.h")
+        .header("data/inspection.h")
+        .clang_arg("-I")
+        .clang_arg(src.to_str().unwrap())
+        .clang_arg("-I")
+        .clang_arg(build.to_str().unwrap())
 }
 
 fn main() {
-    let libs = system_deps::Config::new().probe().unwrap();
-    let headers = libs.all_include_paths();
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    println!("cargo:rerun-if-env-changed=DOCS_RS");
+    println!("cargo:rerun-if-env-changed=AOM_SYS_UPDATE_BINDINGS");
+
+    let docs_rs = env::var_os("DOCS_RS").is_some();
+    if docs_rs || env::var_os("CARGO_FEATURE_USE_PREGENERATED_BINDINGS").is_some() {
+        use_pregenerated(&out_path, docs_rs);
+        return;
+    }
+
+    #[cfg(feature = "build_sources")]
+    let (headers, version) = build_sources();
+    #[cfg(not(feature = "build_sources"))]
+    let (headers, version) = probe();
 
+    // Exported so the users can compare it with the library loaded at runtime
+    let static_link = if cfg!(feature = "build_sources") {
+        cfg!(feature = "static")
+    } else {
+        system_static_link()
+    };
+    export(&version, static_link);
+
+    // Only the libaom API is bound, not the libc items the headers pull in
     let mut builder = bindgen::builder()
         .header("data/aom.h")
+        .allowlist_function("aom_.*")
+        .allowlist_type("(aom|AOM|av1|OBU).*")
+        .allowlist_var("(AOM|AV1).*")
         .blocklist_type("max_align_t")
         .size_t_is_usize(true)
         .default_enum_style(bindgen::EnumVariation::ModuleConsts);
 
-    for header in headers {
+    for header in &headers {
         builder = builder.clang_arg("-I").clang_arg(header.to_str().unwrap());
     }
 
-    // Manually fix the comment so rustdoc won't try to pick them
+    // Only the interfaces enabled are bound, so a libaom built without the
+    // encoder or the decoder links
+    if env::var_os("CARGO_FEATURE_ENCODER").is_some() {
+        builder = builder.clang_arg("-DAOM_SYS_ENCODER");
+    }
+    if env::var_os("CARGO_FEATURE_DECODER").is_some() {
+        builder = builder.clang_arg("-DAOM_SYS_DECODER");
+    }
+
+    #[cfg(feature = "accounting")]
+    {
+        builder = accounting(builder, &headers);
+    }
+
+    // The header comments are kept as documentation, e.g. the value type of
+    // every control id
     let s = format_write(builder);
 
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    // Refresh the shipped bindings, CI checks they match the generated ones
+    if env::var_os("AOM_SYS_UPDATE_BINDINGS").is_some() {
+        let default_features = env::var_os("CARGO_FEATURE_ENCODER").is_some()
+            && env::var_os("CARGO_FEATURE_DECODER").is_some()
+            && env::var_os("CARGO_FEATURE_ACCOUNTING").is_none();
+        assert!(
+            default_features,
+            "the shipped bindings are generated with the default features"
+        );
+
+        fs::create_dir_all(Path::new(PREGENERATED).parent().unwrap()).unwrap();
+        fs::write(PREGENERATED, &s).unwrap();
+        fs::write(PREGENERATED_VERSION, format!("{}\n", version)).unwrap();
+    }
 
     let mut file = File::create(out_path.join("aom.rs")).unwrap();
 
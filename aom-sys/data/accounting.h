@@ -0,0 +1 @@
+#include <av1/decoder/accounting.h>
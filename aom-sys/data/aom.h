@@ -1,4 +1,16 @@
+/* Included explicitly, the headers including them vary across versions */
+#include <aom/aom_integer.h>
+#include <aom/aom_frame_buffer.h>
+
+#include <aom/aom_codec.h>
+#include <aom/aom_image.h>
+
+#ifdef AOM_SYS_DECODER
 #include <aom/aom_decoder.h>
+#include <aom/aomdx.h>
+#endif
+
+#ifdef AOM_SYS_ENCODER
 #include <aom/aom_encoder.h>
 #include <aom/aomcx.h>
-#include <aom/aomdx.h>
+#endif
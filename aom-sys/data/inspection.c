@@ -0,0 +1,17 @@
+#include "av1/decoder/decoder.h"
+
+#include "inspection.h"
+
+int aom_sys_inspect_film_grain(void *decoder, aom_film_grain_t *grain) {
+  const AV1Decoder *pbi = (const AV1Decoder *)decoder;
+  const RefCntBuffer *buf;
+
+  if (!pbi || !grain) return -1;
+
+  buf = pbi->common.cur_frame;
+  if (!buf) return -1;
+
+  *grain = buf->film_grain_params;
+
+  return 0;
+}
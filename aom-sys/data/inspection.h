@@ -0,0 +1,5 @@
+#include <aom_dsp/grain_params.h>
+
+/* Copy the film grain parameters of the frame being inspected, the decoder
+ * is the one passed to the inspection callback. Return 0 on success. */
+int aom_sys_inspect_film_grain(void *decoder, aom_film_grain_t *grain);
@@ -9,20 +9,162 @@ pub mod aom {
 
 pub use aom::*;
 
+/// Version of the libaom the bindings were generated from, as reported by
+/// pkg-config or the built sources
+///
+/// The build scripts of the dependents read it as `DEP_AOM_VERSION`.
+pub const AOM_BOUND_VERSION: &str = env!("AOM_BOUND_VERSION");
+
+/// Whether libaom is linked statically, as asked with the `static` feature
+/// or to system-deps
+///
+/// The build scripts of the dependents read it as `DEP_AOM_STATIC`, `1` or
+/// `0`.
+pub const AOM_STATIC_LINK: bool = matches!(env!("AOM_STATIC_LINK").as_bytes(), b"1");
+
+fn static_str(s: *const std::os::raw::c_char) -> &'static str {
+    if s.is_null() {
+        return "";
+    }
+
+    // The strings are static and ASCII
+    unsafe { std::ffi::CStr::from_ptr(s) }
+        .to_str()
+        .unwrap_or("")
+}
+
+/// Version of the libaom loaded at runtime, e.g. `v3.8.1`
+///
+/// It calls `aom_codec_version_str`.
+pub fn version_string() -> &'static str {
+    static_str(unsafe { aom_codec_version_str() })
+}
+
+/// Configuration libaom was built with, e.g. its cmake options
+///
+/// It calls `aom_codec_build_config`.
+pub fn build_config_string() -> &'static str {
+    static_str(unsafe { aom_codec_build_config() })
+}
+
 #[cfg(test)]
 mod tests {
     use super::aom::*;
-    use std::ffi::CStr;
+    use super::{build_config_string, version_string};
+    #[cfg(feature = "encoder")]
     use std::mem;
     #[test]
     fn version() {
-        println!("{}", unsafe {
-            CStr::from_ptr(aom_codec_version_str()).to_string_lossy()
-        });
-        println!("{}", unsafe {
-            CStr::from_ptr(aom_codec_build_config()).to_string_lossy()
-        });
+        println!("{}", version_string());
+        println!("{}", build_config_string());
+        assert!(!version_string().is_empty());
     }
+    // The items the safe wrappers use, so a header layout missing one
+    // fails here rather than in libaom
+    #[test]
+    fn bound_items() {
+        let _: aom_codec_err_t::Type = aom_codec_err_t::AOM_CODEC_INCAPABLE;
+        let _ = [
+            aom_img_fmt::AOM_IMG_FMT_I420,
+            aom_img_fmt::AOM_IMG_FMT_I422,
+            aom_img_fmt::AOM_IMG_FMT_I444,
+            aom_img_fmt::AOM_IMG_FMT_NV12,
+            aom_img_fmt::AOM_IMG_FMT_I42016,
+            aom_img_fmt::AOM_IMG_FMT_I42216,
+            aom_img_fmt::AOM_IMG_FMT_I44416,
+        ];
+        let _ = [aom_bit_depth::AOM_BITS_8, aom_bit_depth::AOM_BITS_10];
+        let _ = [
+            aom_superblock_size::AOM_SUPERBLOCK_SIZE_64X64,
+            aom_superblock_size::AOM_SUPERBLOCK_SIZE_128X128,
+            aom_superblock_size::AOM_SUPERBLOCK_SIZE_DYNAMIC,
+        ];
+        let _ = (AOM_IMG_FMT_HIGHBITDEPTH, AOM_CODEC_USE_PSNR);
+        let _ = [
+            AOM_FRAME_IS_KEY,
+            AOM_FRAME_IS_DROPPABLE,
+            AOM_FRAME_IS_INTRAONLY,
+            AOM_FRAME_IS_SWITCH,
+            AOM_FRAME_IS_ERROR_RESILIENT,
+            AOM_FRAME_IS_DELAYED_RANDOM_ACCESS_POINT,
+        ];
+        let _: Option<aom_get_frame_buffer_cb_fn_t> = None;
+        let _: Option<aom_codec_frame_buffer_t> = None;
+        let _: (u8, i16, u32, i64) = (0 as uint8_t, 0 as int16_t, 0 as uint32_t, 0 as int64_t);
+    }
+
+    #[cfg(feature = "encoder")]
+    #[test]
+    fn bound_encoder_items() {
+        let _ = [
+            AOM_USAGE_GOOD_QUALITY,
+            AOM_USAGE_REALTIME,
+            AOM_USAGE_ALL_INTRA,
+        ];
+        let _ = [AOM_ENCODER_ABI_VERSION, AOM_CODEC_USE_HIGHBITDEPTH];
+        let _ = [
+            AOM_EFLAG_FORCE_KF,
+            AOM_EFLAG_NO_REF_LAST,
+            AOM_EFLAG_NO_REF_LAST2,
+            AOM_EFLAG_NO_REF_LAST3,
+            AOM_EFLAG_NO_REF_GF,
+            AOM_EFLAG_NO_REF_ARF,
+            AOM_EFLAG_NO_REF_BWD,
+            AOM_EFLAG_NO_REF_ARF2,
+            AOM_EFLAG_NO_UPD_LAST,
+            AOM_EFLAG_NO_UPD_GF,
+            AOM_EFLAG_NO_UPD_ARF,
+            AOM_EFLAG_NO_UPD_ENTROPY,
+            AOM_EFLAG_NO_REF_FRAME_MVS,
+            AOM_EFLAG_ERROR_RESILIENT,
+            AOM_EFLAG_SET_S_FRAME,
+            AOM_EFLAG_SET_PRIMARY_REF_NONE,
+        ]
+        .map(|flag| flag as aom_enc_frame_flags_t);
+        let _ = [
+            aom_timing_info_type::AOM_TIMING_UNSPECIFIED,
+            aom_timing_info_type::AOM_TIMING_EQUAL,
+            aom_timing_info_type::AOM_TIMING_DEC_MODEL,
+        ];
+        let _ = [
+            aom_superres_mode::AOM_SUPERRES_NONE,
+            aom_superres_mode::AOM_SUPERRES_FIXED,
+            aom_superres_mode::AOM_SUPERRES_QTHRESH,
+        ];
+        let _ = [
+            aom_rc_mode::AOM_VBR,
+            aom_rc_mode::AOM_CBR,
+            aom_rc_mode::AOM_CQ,
+            aom_rc_mode::AOM_Q,
+        ];
+        let _ = [
+            aom_enc_pass::AOM_RC_ONE_PASS,
+            aom_enc_pass::AOM_RC_FIRST_PASS,
+            aom_enc_pass::AOM_RC_LAST_PASS,
+        ];
+        let _ = [aom_kf_mode::AOM_KF_AUTO, aom_kf_mode::AOM_KF_DISABLED];
+        let _ = [
+            aome_enc_control_id::AV1E_SET_TARGET_SEQ_LEVEL_IDX,
+            aome_enc_control_id::AV1E_SET_ROW_MT,
+            aome_enc_control_id::AV1E_SET_TUNE_CONTENT,
+            aome_enc_control_id::AV1E_SET_DENOISE_NOISE_LEVEL,
+        ];
+        let _: Option<aom_fixed_buf_t> = None;
+    }
+
+    #[cfg(feature = "decoder")]
+    #[test]
+    fn bound_decoder_items() {
+        let _ = AOM_DECODER_ABI_VERSION;
+        let _ = [
+            aom_dec_control_id::AV1D_SET_OUTPUT_ALL_LAYERS,
+            aom_dec_control_id::AV1D_SET_SKIP_FILM_GRAIN,
+            aom_dec_control_id::AOMD_GET_SB_SIZE,
+        ];
+        let _: Option<aom_codec_dec_cfg_t> = None;
+    }
+
+    #[cfg(feature = "encoder")]
     #[test]
     fn encode() {
         let w = 360;
@@ -37,9 +179,8 @@ mod tests {
         if ret.is_null() {
             panic!("Image allocation failed");
         }
-        #[allow(clippy::forget_copy)]
-        mem::forget(ret); // raw and ret are the same (ret does not implement Drop trait)
-        let raw = unsafe { raw.assume_init() };
+        // aom_img_alloc initialized raw, ret points to it
+        let mut raw = unsafe { raw.assume_init() };
         print!("{:#?}", raw);
 
         let mut cfg = mem::MaybeUninit::uninit();
@@ -100,6 +241,11 @@ mod tests {
             }
         }
 
+        unsafe {
+            aom_codec_destroy(&mut ctx);
+            aom_img_free(&mut raw);
+        }
+
         if out != 1 {
             panic!("No packet produced");
         }
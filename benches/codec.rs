@@ -0,0 +1,119 @@
+//! Measure the encode and decode hot paths
+//!
+//!     cargo bench --bench codec --features bench
+//!
+//! Every measure is repeated and the median is reported, see the README of
+//! this directory to compare two revisions.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use aom_sys::aom_img_fmt;
+use av_data::frame::Frame;
+use av_data::pixel::formats::YUV420;
+use av_data::rational::Rational64;
+use av_data::timeinfo::TimeInfo;
+
+use libaom::bench::{frame_from_img, img_from_frame};
+use libaom::common::AomImage;
+use libaom::decoder::AV1Decoder;
+use libaom::encoder::{AOMPacket, AV1EncoderConfig};
+use libaom::testing::MovingBox;
+
+const WIDTH: usize = 640;
+const HEIGHT: usize = 360;
+const FRAMES: usize = 30;
+const ROUNDS: usize = 5;
+
+// Median time of a round, `round` runs `count` operations
+fn measure(name: &str, count: usize, mut round: impl FnMut()) {
+    round();
+
+    let mut times: Vec<Duration> = (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            round();
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+
+    let median = times[ROUNDS / 2] / count as u32;
+
+    println!(
+        "{:<16} {:>12.2?}/op {:>10.1} op/s",
+        name,
+        median,
+        1.0 / median.as_secs_f64()
+    );
+}
+
+fn config() -> AV1EncoderConfig {
+    AV1EncoderConfig::low_latency()
+        .unwrap()
+        .width(WIDTH as u32)
+        .height(HEIGHT as u32)
+        .timebase(Rational64::new(1, 30))
+        .cpu_used(8)
+}
+
+fn main() {
+    let t = TimeInfo {
+        timebase: Some(Rational64::new(1, 30)),
+        ..Default::default()
+    };
+    let format = Arc::new(*YUV420);
+    let mut frames: Vec<Frame> = MovingBox::new(WIDTH, HEIGHT, &format, &t)
+        .take(FRAMES)
+        .collect();
+
+    // Realtime encode, the timestamps keep increasing across the rounds
+    let mut e = config().get_encoder().unwrap();
+    let mut pts = 0;
+    measure("encode", FRAMES, || {
+        for f in &mut frames {
+            f.t.pts = Some(pts);
+            pts += 1;
+
+            e.encode(f).unwrap();
+            while let Some(p) = e.get_packet() {
+                black_box(p);
+            }
+        }
+    });
+
+    let mut e = config().get_encoder().unwrap();
+    let mut packets = Vec::new();
+    for f in &frames {
+        e.encode(f).unwrap();
+        while let Some(p) = e.get_packet() {
+            if let AOMPacket::Packet(p) = p {
+                packets.push(p.data);
+            }
+        }
+    }
+
+    measure("decode", FRAMES, || {
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        for p in &packets {
+            d.decode(p, None).unwrap();
+            while let Some(f) = d.get_frame() {
+                black_box(f);
+            }
+        }
+    });
+
+    measure("img_from_frame", 10_000, || {
+        for _ in 0..10_000 {
+            black_box(img_from_frame(black_box(&frames[0])).unwrap());
+        }
+    });
+
+    let img = AomImage::new(aom_img_fmt::AOM_IMG_FMT_I420, WIDTH as u32, HEIGHT as u32).unwrap();
+    measure("frame_from_img", 100, || {
+        for _ in 0..100 {
+            black_box(frame_from_img(black_box(&img)).unwrap());
+        }
+    });
+}
@@ -0,0 +1,57 @@
+//! Compare the packet copy strategies
+//!
+//!     cargo bench --bench packet_copy
+//!
+//! `AOMPacket` used to copy the libaom buffer with `copy_nonoverlapping` and
+//! `set_len`, it now copies a checked slice with `extend_from_slice`.
+
+use std::hint::black_box;
+use std::ptr;
+use std::slice;
+use std::time::{Duration, Instant};
+
+fn raw_copy(buf: *const u8, sz: usize) -> Vec<u8> {
+    let mut v = Vec::with_capacity(sz);
+    unsafe {
+        ptr::copy_nonoverlapping(buf, v.as_mut_ptr(), sz);
+        v.set_len(sz);
+    }
+    v
+}
+
+fn slice_copy(buf: *const u8, sz: usize) -> Vec<u8> {
+    let data = if buf.is_null() || sz == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(buf, sz) }
+    };
+
+    let mut v = Vec::with_capacity(data.len());
+    v.extend_from_slice(data);
+    v
+}
+
+fn measure(f: fn(*const u8, usize) -> Vec<u8>, src: &[u8]) -> Duration {
+    let iterations = (64 << 20) / src.len();
+    let start = Instant::now();
+
+    for _ in 0..iterations {
+        black_box(f(black_box(src.as_ptr()), src.len()));
+    }
+
+    start.elapsed() / iterations as u32
+}
+
+fn main() {
+    for size in [64, 1 << 10, 16 << 10, 256 << 10, 1 << 20] {
+        let src: Vec<u8> = (0..size).map(|i| i as u8).collect();
+
+        let raw = measure(raw_copy, &src);
+        let checked = measure(slice_copy, &src);
+
+        println!(
+            "{:>8} bytes: copy_nonoverlapping {:>10.2?} extend_from_slice {:>10.2?}",
+            size, raw, checked
+        );
+    }
+}
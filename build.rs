@@ -0,0 +1,39 @@
+use std::env;
+
+// libaom releases adding controls or structures the wrappers may depend on,
+// each one gets an aom_ge_<major>_<minor> cfg when the bound version is at
+// least as recent
+const RELEASES: &[(u32, u32)] = &[
+    (3, 1),
+    (3, 2),
+    (3, 3),
+    (3, 4),
+    (3, 5),
+    (3, 6),
+    (3, 7),
+    (3, 8),
+    (3, 9),
+    (3, 10),
+    (3, 11),
+    (3, 12),
+];
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=DEP_AOM_VERSION");
+
+    // Set by aom-sys, empty if the version is unknown
+    let version = env::var("DEP_AOM_VERSION").unwrap_or_default();
+    let mut parts = version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let bound = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+
+    for &(major, minor) in RELEASES {
+        println!("cargo:rustc-check-cfg=cfg(aom_ge_{}_{})", major, minor);
+
+        if bound >= (major, minor) {
+            println!("cargo:rustc-cfg=aom_ge_{}_{}", major, minor);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Encode a y4m clip into an IVF file
+//!
+//!     cargo run --example encode_y4m --features ivf,y4m -- [input.y4m] [output.ivf]
+//!
+//! It defaults to the bundled `examples/tiny.y4m` clip, the output can be
+//! checked with `aomdec`.
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+use libaom::ivf::{IvfWriter, AV1_FOURCC};
+use libaom::prelude::*;
+use libaom::y4m::Y4mReader;
+
+fn write_packets(
+    enc: &mut AV1Encoder,
+    ivf: &mut IvfWriter<BufWriter<File>>,
+) -> Result<usize, Box<dyn Error>> {
+    let mut count = 0;
+
+    while let Some(pkt) = enc.get_packet() {
+        if let AOMPacket::Packet(pkt) = pkt {
+            ivf.write_packet(&pkt)?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args_os().skip(1);
+    let input = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/examples/tiny.y4m").into());
+    let output = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join("tiny.ivf"));
+
+    let y4m = Y4mReader::new(BufReader::new(File::open(&input)?))?;
+
+    let mut cfg = AV1EncoderConfig::new()?
+        .width(y4m.width() as u32)
+        .height(y4m.height() as u32)
+        .timebase(y4m.timebase())
+        .rc_target_bitrate(200)
+        .kf_max_dist(60)
+        .cpu_used(8);
+    let mut enc = cfg.get_encoder()?;
+
+    let mut ivf = IvfWriter::new(
+        BufWriter::new(File::create(&output)?),
+        AV1_FOURCC,
+        y4m.width() as u16,
+        y4m.height() as u16,
+        y4m.timebase(),
+    )?;
+
+    let mut frames = 0;
+    let mut packets = 0;
+
+    for frame in y4m {
+        enc.encode(&frame?)?;
+        frames += 1;
+        packets += write_packets(&mut enc, &mut ivf)?;
+    }
+
+    // Every flush call outputs at most one frame
+    loop {
+        enc.flush()?;
+        match write_packets(&mut enc, &mut ivf)? {
+            0 => break,
+            n => packets += n,
+        }
+    }
+
+    println!(
+        "{} frames encoded in {} packets to {}",
+        frames,
+        packets,
+        output.display()
+    );
+
+    Ok(())
+}
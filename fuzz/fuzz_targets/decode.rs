@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Once as a whole and once split in temporal units
+    libaom_fuzz::decode([data]);
+    libaom_fuzz::decode(libaom::obu::split_temporal_units(data));
+});
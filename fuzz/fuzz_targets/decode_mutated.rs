@@ -0,0 +1,87 @@
+#![no_main]
+
+use std::sync::OnceLock;
+
+use libfuzzer_sys::arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Mutation {
+    Flip { offset: u32, bit: u8 },
+    Set { offset: u32, val: u8 },
+    Truncate { packet: u8, len: u16 },
+    Remove { packet: u8 },
+    Duplicate { packet: u8 },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    stream: u8,
+    mutations: Vec<Mutation>,
+}
+
+fn seeds() -> &'static [Vec<Vec<u8>>] {
+    static SEEDS: OnceLock<Vec<Vec<Vec<u8>>>> = OnceLock::new();
+
+    SEEDS.get_or_init(libaom_fuzz::seed_streams)
+}
+
+fn byte(stream: &mut [Vec<u8>], offset: u32) -> Option<&mut u8> {
+    let total: usize = stream.iter().map(|p| p.len()).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut offset = offset as usize % total;
+    for p in stream.iter_mut() {
+        if offset < p.len() {
+            return p.get_mut(offset);
+        }
+        offset -= p.len();
+    }
+
+    None
+}
+
+fuzz_target!(|input: Input| {
+    let seeds = seeds();
+    if seeds.is_empty() {
+        return;
+    }
+
+    let mut stream = seeds[input.stream as usize % seeds.len()].clone();
+
+    for m in input.mutations {
+        let packets = stream.len().max(1);
+
+        match m {
+            Mutation::Flip { offset, bit } => {
+                if let Some(b) = byte(&mut stream, offset) {
+                    *b ^= 1 << (bit % 8);
+                }
+            }
+            Mutation::Set { offset, val } => {
+                if let Some(b) = byte(&mut stream, offset) {
+                    *b = val;
+                }
+            }
+            Mutation::Truncate { packet, len } => {
+                if let Some(p) = stream.get_mut(packet as usize % packets) {
+                    p.truncate(len as usize);
+                }
+            }
+            Mutation::Remove { packet } => {
+                if !stream.is_empty() {
+                    stream.remove(packet as usize % packets);
+                }
+            }
+            Mutation::Duplicate { packet } => {
+                if let Some(p) = stream.get(packet as usize % packets).cloned() {
+                    stream.insert(packet as usize % packets, p);
+                }
+            }
+        }
+    }
+
+    libaom_fuzz::decode(stream.iter().map(|p| p.as_slice()));
+});
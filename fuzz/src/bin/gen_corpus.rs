@@ -0,0 +1,36 @@
+//! Write the seed corpora of the fuzz targets
+//!
+//!     cargo run --bin gen_corpus [corpus directory]
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+fn main() -> io::Result<()> {
+    let root = env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/corpus").into());
+
+    let decode = root.join("decode");
+    let mutated = root.join("decode_mutated");
+    fs::create_dir_all(&decode)?;
+    fs::create_dir_all(&mutated)?;
+
+    let seeds = libaom_fuzz::seed_streams();
+
+    for (i, stream) in seeds.iter().enumerate() {
+        fs::write(decode.join(format!("stream-{}", i)), stream.concat())?;
+        for (j, packet) in stream.iter().enumerate() {
+            fs::write(decode.join(format!("stream-{}-packet-{}", i, j)), packet)?;
+        }
+
+        // The stream index alone, without mutations
+        fs::write(mutated.join(format!("stream-{}", i)), [i as u8])?;
+    }
+
+    println!("{} seed streams written to {}", seeds.len(), root.display());
+
+    Ok(())
+}
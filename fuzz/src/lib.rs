@@ -0,0 +1,81 @@
+//! Helpers shared by the fuzz targets
+
+use av_data::frame::{Frame, FrameBufferConv, FrameType, VideoInfo};
+use av_data::pixel::formats::YUV420;
+use av_data::rational::Rational64;
+use av_data::timeinfo::TimeInfo;
+use libaom::decoder::AV1Decoder;
+use libaom::encoder::{AOMPacket, AV1EncoderConfig};
+use std::sync::Arc;
+
+/// Feed every chunk to a fresh decoder and retrieve the frames
+pub fn decode<'a, I: IntoIterator<Item = &'a [u8]>>(chunks: I) {
+    let mut d = match AV1Decoder::<()>::new() {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+
+    for chunk in chunks {
+        let _ = d.decode(chunk, None);
+        while d.get_frame().is_some() {}
+    }
+
+    if d.flush().is_ok() {
+        while d.get_frame().is_some() {}
+    }
+}
+
+fn frame(w: usize, h: usize, pts: i64) -> Frame {
+    let t = TimeInfo {
+        pts: Some(pts),
+        timebase: Some(Rational64::new(1, 30)),
+        ..Default::default()
+    };
+    let info = VideoInfo::new(w, h, false, FrameType::OTHER, Arc::new(*YUV420));
+    let mut f = Frame::new_default_frame(info, Some(t));
+
+    for p in 0..3 {
+        let stride = f.buf.linesize(p).unwrap();
+        let plane: &mut [u8] = f.buf.as_mut_slice(p).unwrap();
+        for (y, row) in plane.chunks_mut(stride).enumerate() {
+            for (x, v) in row.iter_mut().enumerate() {
+                *v = (x * 3 + y * 5 + pts as usize * 7 + p * 40) as u8;
+            }
+        }
+    }
+
+    f
+}
+
+/// Valid streams to mutate, as lists of temporal units
+///
+/// They mirror the round-trip tests of the crate: a few frames encoded with
+/// the default, the low latency and the error resilient configurations.
+pub fn seed_streams() -> Vec<Vec<Vec<u8>>> {
+    let configs = [
+        AV1EncoderConfig::new().map(|c| c.width(64).height(64)),
+        AV1EncoderConfig::low_latency().map(|c| c.width(96).height(48)),
+        AV1EncoderConfig::new().map(|c| c.width(33).height(17).error_resilient(true)),
+    ];
+
+    configs
+        .into_iter()
+        .filter_map(|cfg| {
+            let mut cfg = cfg.ok()?.timebase(Rational64::new(1, 30)).lag_in_frames(0);
+            let (w, h) = (cfg.cfg.g_w as usize, cfg.cfg.g_h as usize);
+            let mut e = cfg.get_encoder().ok()?;
+            let mut stream = Vec::new();
+
+            for pts in 0..8 {
+                e.encode(&frame(w, h, pts)).ok()?;
+                while let Some(p) = e.get_packet() {
+                    if let AOMPacket::Packet(pkt) = p {
+                        stream.push(pkt.data);
+                    }
+                }
+            }
+
+            Some(stream)
+        })
+        .collect()
+}
@@ -0,0 +1,21 @@
+//! Internal conversions exposed to the benchmarks
+//!
+//! Not part of the stable API, enabled by the `bench` feature.
+
+#![deny(missing_docs)]
+
+use av_data::frame::{Frame, FrameType};
+
+use crate::common::{AomError, AomImage};
+use crate::ffi::*;
+use crate::image::{img_to_frame, new_frame};
+
+/// Wrap the planes of `frame` in an `aom_image`, as the encoder does
+pub fn img_from_frame(frame: &Frame) -> Result<aom_image, AomError> {
+    crate::encoder::img_from_frame(frame)
+}
+
+/// Copy `img` into a new frame, as the decoder does
+pub fn frame_from_img(img: &AomImage) -> Option<Frame> {
+    img_to_frame(*img.raw(), FrameType::OTHER, |v| new_frame(v, None))
+}
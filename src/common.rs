@@ -1,5 +1,12 @@
 use crate::ffi::aom::*;
+#[cfg(test)]
+use std::cell::Cell;
 use std::ffi::CStr;
+use std::fmt;
+
+pub use crate::image::{new_frame, with_depth, AomImage, AomImageView};
+
+pub use crate::ffi::{AOM_BOUND_VERSION, AOM_STATIC_LINK};
 
 pub trait AOMCodec {
     fn get_context(&mut self) -> &mut aom_codec_ctx;
@@ -13,3 +20,581 @@ pub trait AOMCodec {
         }
     }
 }
+
+/// Kind of failure reported by libaom
+///
+/// It mirrors `aom_codec_err_t`.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AomErrorKind {
+    /// Unspecified error, `AOM_CODEC_ERROR`
+    Error,
+    /// Memory operation failed, `AOM_CODEC_MEM_ERROR`
+    MemError,
+    /// ABI version mismatch, `AOM_CODEC_ABI_MISMATCH`
+    AbiMismatch,
+    /// The algorithm does not have the required capability,
+    /// `AOM_CODEC_INCAPABLE`
+    Incapable,
+    /// The bitstream uses an unsupported feature,
+    /// `AOM_CODEC_UNSUP_BITSTREAM`
+    UnsupBitstream,
+    /// The encoded data uses an unsupported feature,
+    /// `AOM_CODEC_UNSUP_FEATURE`
+    UnsupFeature,
+    /// The coded data is corrupt or incomplete, `AOM_CODEC_CORRUPT_FRAME`
+    CorruptFrame,
+    /// An application-supplied parameter is not valid,
+    /// `AOM_CODEC_INVALID_PARAM`
+    InvalidParam,
+    /// An iterator reached the end of the list, `AOM_CODEC_LIST_END`
+    ListEnd,
+}
+
+/// # Handling
+///
+/// The codec is left usable after an [`InvalidParam`], [`Incapable`],
+/// [`UnsupFeature`] or [`CorruptFrame`] error: the failed call had no
+/// effect, or only dropped the faulty data. The other errors leave it in an
+/// unknown state and it should be recreated.
+///
+/// [`InvalidParam`]: AomErrorKind::InvalidParam
+/// [`Incapable`]: AomErrorKind::Incapable
+/// [`UnsupFeature`]: AomErrorKind::UnsupFeature
+/// [`CorruptFrame`]: AomErrorKind::CorruptFrame
+impl AomErrorKind {
+    /// The requested feature is not available
+    ///
+    /// Retrying the same call is pointless. With [`Incapable`] and
+    /// [`UnsupFeature`] the codec lacks the capability, other settings may
+    /// work; with [`UnsupBitstream`] the decoder cannot handle the stream
+    /// at all.
+    ///
+    /// [`Incapable`]: AomErrorKind::Incapable
+    /// [`UnsupFeature`]: AomErrorKind::UnsupFeature
+    /// [`UnsupBitstream`]: AomErrorKind::UnsupBitstream
+    pub fn is_unsupported(self) -> bool {
+        matches!(
+            self,
+            AomErrorKind::Incapable | AomErrorKind::UnsupFeature | AomErrorKind::UnsupBitstream
+        )
+    }
+
+    /// The call was rejected, it can be retried with other parameters
+    pub fn is_invalid_param(self) -> bool {
+        self == AomErrorKind::InvalidParam
+    }
+
+    /// The compressed data could not be decoded
+    ///
+    /// Skip it and keep decoding, the decoder recovers at the next
+    /// keyframe at the latest.
+    pub fn is_corrupt_frame(self) -> bool {
+        self == AomErrorKind::CorruptFrame
+    }
+
+    /// An iterator is exhausted, it is not a failure
+    pub fn is_list_end(self) -> bool {
+        self == AomErrorKind::ListEnd
+    }
+
+    /// The codec can still be used after the error
+    pub fn is_recoverable(self) -> bool {
+        matches!(
+            self,
+            AomErrorKind::InvalidParam
+                | AomErrorKind::Incapable
+                | AomErrorKind::UnsupFeature
+                | AomErrorKind::CorruptFrame
+                | AomErrorKind::ListEnd
+        )
+    }
+}
+
+impl AomErrorKind {
+    /// Map a libaom error code, `None` for `AOM_CODEC_OK` or unknown codes
+    pub fn from_code(code: aom_codec_err_t::Type) -> Option<AomErrorKind> {
+        use self::aom_codec_err_t::*;
+
+        match code {
+            AOM_CODEC_ERROR => Some(AomErrorKind::Error),
+            AOM_CODEC_MEM_ERROR => Some(AomErrorKind::MemError),
+            AOM_CODEC_ABI_MISMATCH => Some(AomErrorKind::AbiMismatch),
+            AOM_CODEC_INCAPABLE => Some(AomErrorKind::Incapable),
+            AOM_CODEC_UNSUP_BITSTREAM => Some(AomErrorKind::UnsupBitstream),
+            AOM_CODEC_UNSUP_FEATURE => Some(AomErrorKind::UnsupFeature),
+            AOM_CODEC_CORRUPT_FRAME => Some(AomErrorKind::CorruptFrame),
+            AOM_CODEC_INVALID_PARAM => Some(AomErrorKind::InvalidParam),
+            AOM_CODEC_LIST_END => Some(AomErrorKind::ListEnd),
+            _ => None,
+        }
+    }
+
+    /// The matching libaom error code
+    pub fn code(self) -> aom_codec_err_t::Type {
+        use self::aom_codec_err_t::*;
+
+        match self {
+            AomErrorKind::Error => AOM_CODEC_ERROR,
+            AomErrorKind::MemError => AOM_CODEC_MEM_ERROR,
+            AomErrorKind::AbiMismatch => AOM_CODEC_ABI_MISMATCH,
+            AomErrorKind::Incapable => AOM_CODEC_INCAPABLE,
+            AomErrorKind::UnsupBitstream => AOM_CODEC_UNSUP_BITSTREAM,
+            AomErrorKind::UnsupFeature => AOM_CODEC_UNSUP_FEATURE,
+            AomErrorKind::CorruptFrame => AOM_CODEC_CORRUPT_FRAME,
+            AomErrorKind::InvalidParam => AOM_CODEC_INVALID_PARAM,
+            AomErrorKind::ListEnd => AOM_CODEC_LIST_END,
+        }
+    }
+}
+
+/// Error returned by the encoder and the decoder
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AomError {
+    /// A libaom call failed
+    Codec {
+        /// What went wrong
+        kind: AomErrorKind,
+        /// Additional information provided by libaom or by this crate
+        detail: Option<String>,
+    },
+    /// The data sent to the decoder is corrupt, `AOM_CODEC_CORRUPT_FRAME`
+    ///
+    /// Skip the packet and keep the decoder, see
+    /// [`AomErrorKind::is_corrupt_frame`].
+    CorruptFrame {
+        /// Additional information provided by libaom
+        detail: Option<String>,
+    },
+    /// The libaom loaded at runtime is too old for the call
+    ///
+    /// It is reported before calling libaom, see [`runtime_version`].
+    UnsupportedByLibaom {
+        /// The first libaom release supporting the call
+        needs: &'static str,
+    },
+    /// libaom has no interface for the codec, or the one given does not
+    /// implement it
+    ///
+    /// Some distributions build libaom without the encoder, see
+    /// [`encoder_available`] and [`decoder_available`].
+    InterfaceUnavailable {
+        /// `"encoder"` or `"decoder"`
+        codec: &'static str,
+    },
+}
+
+impl AomError {
+    /// Create an error with a detail message
+    pub(crate) fn new(kind: AomErrorKind, detail: &str) -> AomError {
+        AomError::Codec {
+            kind,
+            detail: Some(detail.to_owned()),
+        }
+    }
+
+    /// Collect the error detail libaom stored in `ctx` for the failed `code`
+    pub(crate) fn from_ctx(ctx: &aom_codec_ctx, code: aom_codec_err_t::Type) -> AomError {
+        let detail = unsafe {
+            let c_str = aom_codec_error_detail(ctx);
+
+            if c_str.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(c_str).to_string_lossy().into_owned())
+            }
+        };
+
+        AomError::Codec {
+            kind: AomErrorKind::from_code(code).unwrap_or(AomErrorKind::Error),
+            detail,
+        }
+    }
+
+    // The decoding failures of a single packet
+    pub(crate) fn into_corrupt_frame(self) -> AomError {
+        match self {
+            AomError::Codec {
+                kind: AomErrorKind::CorruptFrame,
+                detail,
+            } => AomError::CorruptFrame { detail },
+            err => err,
+        }
+    }
+
+    /// The kind of libaom failure
+    pub fn kind(&self) -> AomErrorKind {
+        match self {
+            AomError::Codec { kind, .. } => *kind,
+            AomError::CorruptFrame { .. } => AomErrorKind::CorruptFrame,
+            AomError::UnsupportedByLibaom { .. } => AomErrorKind::UnsupFeature,
+            AomError::InterfaceUnavailable { .. } => AomErrorKind::Incapable,
+        }
+    }
+
+    /// The detail message, if any
+    pub fn detail(&self) -> Option<&str> {
+        match self {
+            AomError::Codec { detail, .. } | AomError::CorruptFrame { detail } => detail.as_deref(),
+            AomError::UnsupportedByLibaom { .. } | AomError::InterfaceUnavailable { .. } => None,
+        }
+    }
+
+    /// The codec can still be used, see [`AomErrorKind::is_recoverable`]
+    pub fn is_recoverable(&self) -> bool {
+        self.kind().is_recoverable()
+    }
+}
+
+impl From<aom_codec_err_t::Type> for AomError {
+    fn from(code: aom_codec_err_t::Type) -> AomError {
+        AomError::Codec {
+            kind: AomErrorKind::from_code(code).unwrap_or(AomErrorKind::Error),
+            detail: None,
+        }
+    }
+}
+
+impl fmt::Display for AomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = unsafe { CStr::from_ptr(aom_codec_err_to_string(self.kind().code())) };
+
+        write!(f, "{}", desc.to_string_lossy())?;
+
+        if let Some(detail) = self.detail() {
+            write!(f, ": {}", detail)?;
+        }
+
+        if let AomError::UnsupportedByLibaom { needs } = self {
+            write!(
+                f,
+                ": needs libaom {}, running {}",
+                needs,
+                Version(runtime_version())
+            )?;
+        }
+
+        if let AomError::InterfaceUnavailable { codec } = self {
+            write!(
+                f,
+                ": no AV1 {} interface, libaom may be built without it",
+                codec
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for AomError {}
+
+// Version set by the tests in place of the library one
+#[cfg(test)]
+thread_local! {
+    pub(crate) static MOCK_RUNTIME_VERSION: Cell<Option<(u16, u16, u16)>> = const { Cell::new(None) };
+    // Capabilities the interfaces lose
+    pub(crate) static MOCK_MISSING_CAPS: Cell<aom_codec_caps_t> = const { Cell::new(0) };
+}
+
+struct Version((u16, u16, u16));
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (major, minor, patch) = self.0;
+
+        write!(f, "{}.{}.{}", major, minor, patch)
+    }
+}
+
+/// Parse a `major.minor.patch` version, the missing components are 0
+fn parse_version(version: &str) -> (u16, u16, u16) {
+    let mut parts = version.split('.').map(|part| {
+        part.chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>()
+            .parse()
+            .unwrap_or(0)
+    });
+
+    (
+        parts.next().unwrap_or(0),
+        parts.next().unwrap_or(0),
+        parts.next().unwrap_or(0),
+    )
+}
+
+/// Version of the libaom loaded at runtime, as `(major, minor, patch)`
+///
+/// It calls `aom_codec_version`.
+pub fn runtime_version() -> (u16, u16, u16) {
+    #[cfg(test)]
+    if let Some(version) = MOCK_RUNTIME_VERSION.with(Cell::get) {
+        return version;
+    }
+
+    let v = unsafe { aom_codec_version() } as u32;
+
+    (
+        ((v >> 16) & 0xff) as u16,
+        ((v >> 8) & 0xff) as u16,
+        (v & 0xff) as u16,
+    )
+}
+
+/// Version of the libaom headers the bindings were generated from
+pub fn headers_version() -> (u16, u16, u16) {
+    parse_version(AOM_BOUND_VERSION)
+}
+
+/// Check that the libaom loaded at runtime matches the bindings
+///
+/// The runtime library must have the major version of the headers and be
+/// at least as recent, the structures and controls bound could be missing
+/// otherwise. Call it once at startup, the codecs do not.
+pub fn check_runtime_version() -> Result<(), AomError> {
+    let (headers, runtime) = (headers_version(), runtime_version());
+
+    if runtime.0 != headers.0 || runtime < headers {
+        return Err(AomError::Codec {
+            kind: AomErrorKind::AbiMismatch,
+            detail: Some(format!(
+                "bindings generated for libaom {}, running {}",
+                Version(headers),
+                Version(runtime)
+            )),
+        });
+    }
+
+    Ok(())
+}
+
+/// A libaom codec interface
+///
+/// The codecs default to the AV1 interfaces, builds of libaom may expose
+/// others, e.g. experimental ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Iface(*mut aom_codec_iface_t);
+
+// The interfaces are immutable statics.
+unsafe impl Send for Iface {}
+unsafe impl Sync for Iface {}
+
+impl Iface {
+    /// The AV1 encoder, `aom_codec_av1_cx`
+    ///
+    /// `None` if libaom is built without it.
+    #[cfg(feature = "encoder")]
+    pub fn av1_encoder() -> Option<Iface> {
+        let iface = unsafe { Iface::from_raw(crate::ffi::aom_codec_av1_cx()) }?;
+
+        iface.is_encoder().then_some(iface)
+    }
+
+    /// The AV1 decoder, `aom_codec_av1_dx`
+    ///
+    /// `None` if libaom is built without it.
+    #[cfg(feature = "decoder")]
+    pub fn av1_decoder() -> Option<Iface> {
+        let iface = unsafe { Iface::from_raw(crate::ffi::aom_codec_av1_dx()) }?;
+
+        iface.is_decoder().then_some(iface)
+    }
+
+    /// Wrap a raw interface, `None` if it is null
+    ///
+    /// # Safety
+    ///
+    /// `iface` must be a libaom codec interface, valid for the lifetime of
+    /// the program.
+    pub unsafe fn from_raw(iface: *const aom_codec_iface_t) -> Option<Iface> {
+        (!iface.is_null()).then_some(Iface(iface as *mut _))
+    }
+
+    /// The raw interface
+    pub fn as_raw(self) -> *mut aom_codec_iface_t {
+        self.0
+    }
+
+    /// Name and version of the interface
+    ///
+    /// It calls `aom_codec_iface_name`.
+    pub fn name(self) -> String {
+        unsafe { CStr::from_ptr(aom_codec_iface_name(self.0)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Capabilities, the `AOM_CODEC_CAP_*` bits
+    ///
+    /// It calls `aom_codec_get_caps`.
+    pub fn caps(self) -> aom_codec_caps_t {
+        let caps = unsafe { aom_codec_get_caps(self.0) };
+
+        #[cfg(test)]
+        let caps = caps & !MOCK_MISSING_CAPS.with(Cell::get);
+
+        caps
+    }
+
+    /// The interface is an encoder
+    pub fn is_encoder(self) -> bool {
+        self.caps() & AOM_CODEC_CAP_ENCODER as aom_codec_caps_t != 0
+    }
+
+    /// The interface is a decoder
+    pub fn is_decoder(self) -> bool {
+        self.caps() & AOM_CODEC_CAP_DECODER as aom_codec_caps_t != 0
+    }
+}
+
+/// Whether libaom is built with the AV1 encoder
+#[cfg(feature = "encoder")]
+pub fn encoder_available() -> bool {
+    Iface::av1_encoder().is_some()
+}
+
+/// Whether libaom is built with the AV1 decoder
+#[cfg(feature = "decoder")]
+pub fn decoder_available() -> bool {
+    Iface::av1_decoder().is_some()
+}
+
+/// Fail with [`AomError::UnsupportedByLibaom`] if the runtime libaom is older
+/// than `needs`
+pub(crate) fn require_version(needs: &'static str) -> Result<(), AomError> {
+    if runtime_version() < parse_version(needs) {
+        return Err(AomError::UnsupportedByLibaom { needs });
+    }
+
+    Ok(())
+}
+
+/// Turn the return value of a libaom call on `ctx` into a `Result`
+pub(crate) fn check(ctx: &aom_codec_ctx, ret: aom_codec_err_t::Type) -> Result<(), AomError> {
+    match ret {
+        aom_codec_err_t::AOM_CODEC_OK => Ok(()),
+        _ => Err(AomError::from_ctx(ctx, ret)),
+    }
+}
+
+/// Destroy a context, once
+///
+/// The contexts libaom failed to initialize or already destroyed have no
+/// interface and are skipped.
+pub(crate) fn destroy(ctx: &mut aom_codec_ctx) -> Result<(), AomError> {
+    if ctx.iface.is_null() {
+        return Ok(());
+    }
+
+    let ret = unsafe { aom_codec_destroy(ctx) };
+
+    // libaom clears the interface on success only
+    ctx.iface = std::ptr::null_mut();
+
+    match ret {
+        aom_codec_err_t::AOM_CODEC_OK => Ok(()),
+        _ => Err(ret.into()),
+    }
+}
+
+/// Destroy a context from `Drop`, the failures are reported in debug builds
+pub(crate) fn destroy_on_drop(ctx: &mut aom_codec_ctx) {
+    if let Err(err) = destroy(ctx) {
+        debug_assert!(
+            std::thread::panicking(),
+            "aom_codec_destroy failed: {}",
+            err
+        );
+    }
+}
+
+#[cfg(feature = "codec-trait")]
+impl From<AomError> for av_codec::error::Error {
+    fn from(err: AomError) -> Self {
+        use av_codec::error::Error;
+
+        match err.kind() {
+            AomErrorKind::InvalidParam => Error::ConfigurationInvalid,
+            AomErrorKind::CorruptFrame | AomErrorKind::UnsupBitstream => Error::InvalidData,
+            _ => Error::Unsupported(err.to_string()),
+        }
+    }
+}
+
+/// Properties of a coded frame
+///
+/// They match the `AOM_FRAME_*` constants, as reported in the encoder
+/// packets and by the `AOMD_GET_FRAME_FLAGS` decoder control.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FrameFlags(aom_codec_frame_flags_t);
+
+impl FrameFlags {
+    /// The frame is a keyframe
+    pub const KEY: FrameFlags = FrameFlags(AOM_FRAME_IS_KEY as aom_codec_frame_flags_t);
+    /// The frame can be dropped without affecting the stream
+    pub const DROPPABLE: FrameFlags = FrameFlags(AOM_FRAME_IS_DROPPABLE as aom_codec_frame_flags_t);
+    /// The frame is an intra-only frame
+    pub const INTRA_ONLY: FrameFlags =
+        FrameFlags(AOM_FRAME_IS_INTRAONLY as aom_codec_frame_flags_t);
+    /// The frame is a switch frame
+    pub const SWITCH: FrameFlags = FrameFlags(AOM_FRAME_IS_SWITCH as aom_codec_frame_flags_t);
+    /// The frame is coded in error resilient mode
+    pub const ERROR_RESILIENT: FrameFlags =
+        FrameFlags(AOM_FRAME_IS_ERROR_RESILIENT as aom_codec_frame_flags_t);
+    /// The packet carries a hidden keyframe, shown later on
+    pub const DELAYED_RANDOM_ACCESS_POINT: FrameFlags =
+        FrameFlags(AOM_FRAME_IS_DELAYED_RANDOM_ACCESS_POINT as aom_codec_frame_flags_t);
+
+    /// No flag set
+    pub const fn empty() -> FrameFlags {
+        FrameFlags(0)
+    }
+
+    /// Raw `aom_codec_frame_flags_t` value
+    pub const fn bits(self) -> aom_codec_frame_flags_t {
+        self.0
+    }
+
+    /// Wrap a raw `aom_codec_frame_flags_t` value
+    pub const fn from_bits(bits: aom_codec_frame_flags_t) -> FrameFlags {
+        FrameFlags(bits)
+    }
+
+    /// All the flags of `other` are set
+    pub const fn contains(self, other: FrameFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// How the frame shown by a temporal unit is coded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FrameKind {
+    /// Keyframe, decoding can start from it
+    Key,
+    /// Intra-only frame, it does not reset the references
+    IntraOnly,
+    /// Switch frame
+    Switch,
+    /// Inter frame
+    Inter,
+    /// Frame coded earlier without being shown, e.g. an altref or a forward
+    /// keyframe, and output through `show_existing_frame`
+    Hidden,
+}
+
+impl FrameKind {
+    pub(crate) fn classify(flags: FrameFlags, show_existing_frame: bool) -> FrameKind {
+        if show_existing_frame {
+            FrameKind::Hidden
+        } else if flags.contains(FrameFlags::KEY) {
+            FrameKind::Key
+        } else if flags.contains(FrameFlags::SWITCH) {
+            FrameKind::Switch
+        } else if flags.contains(FrameFlags::INTRA_ONLY) {
+            FrameKind::IntraOnly
+        } else {
+            FrameKind::Inter
+        }
+    }
+}
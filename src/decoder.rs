@@ -3,46 +3,216 @@
 //!
 
 use crate::ffi::*;
+use std::collections::VecDeque;
 use std::marker::PhantomData;
 use std::mem::{zeroed, MaybeUninit};
 use std::os::raw;
 use std::ptr;
-use std::sync::Arc;
-
-use crate::common::AOMCodec;
-use av_data::frame::{Frame, FrameBufferCopy, FrameType, VideoInfo};
-use av_data::pixel::formats::YUV420;
-
-fn frame_from_img(img: aom_image_t) -> Frame {
-    let f = match img.fmt {
-        aom_img_fmt::AOM_IMG_FMT_I42016 => YUV420,
-        ob_fmt => panic!("Received unknown format: {}", ob_fmt),
-    };
-
-    f.set_primaries_from_u32(img.cp as u32);
-    f.set_xfer_from_u32(img.tc as u32);
-    f.set_matrix_from_u32(img.mc as u32);
-
-    let v = VideoInfo::new(
-        img.d_w as usize,
-        img.d_h as usize,
-        false,
-        FrameType::OTHER,
-        Arc::new(*f),
-    );
-
-    let mut f = Frame::new_default_frame(v, None);
-
-    let src = img
-        .planes
-        .iter()
-        .zip(img.stride.iter())
-        .map(|(v, l)| unsafe { std::slice::from_raw_parts(*v as *const u8, *l as usize) });
-
-    let linesize = img.stride.iter().map(|l| *l as usize);
-
-    f.copy_from_slice(src, linesize);
-    f
+use std::sync::{Arc, Mutex};
+
+use crate::common::{check, destroy_on_drop, AOMCodec, AomError, AomErrorKind, AomImage, Iface};
+use crate::frame_buffer::{
+    get_frame_buffer, release_frame_buffer, PooledFrameBuffer, SharedAllocator,
+};
+use crate::image::{img_to_frame, is_high_bitdepth, new_frame, video_info_from_img};
+#[cfg(feature = "accounting")]
+use crate::inspect::Inspection;
+use av_data::frame::{Frame, FrameType};
+use av_data::timeinfo::TimeInfo;
+
+pub use crate::decoder_info::{
+    DecodedFrameInfo, SFrameInfo, ScreenContentTools, StillPicture, StreamProperties,
+    SuperblockSize,
+};
+pub use crate::frame_buffer::{FrameBufferAllocator, FramePool, FramePoolStats};
+#[cfg(feature = "accounting")]
+pub use crate::inspect::{Accounting, AccountingSymbol, FilmGrainParams};
+
+fn frame_from_img(
+    img: aom_image_t,
+    frame_type: FrameType,
+    pool: Option<&FramePool>,
+) -> Option<Frame> {
+    img_to_frame(img, frame_type, |v| match pool {
+        Some(pool) => pool.get(v),
+        None => new_frame(v, None),
+    })
+}
+
+// Only the keyframes are I frames, the intra-only frames cannot start the
+// decoding
+fn frame_type_from_flags(flags: Option<raw::c_int>) -> FrameType {
+    match flags {
+        Some(f) if f as u32 & AOM_FRAME_IS_KEY != 0 => FrameType::I,
+        Some(f) if f as u32 & AOM_FRAME_IS_INTRAONLY != 0 => FrameType::OTHER,
+        Some(_) => FrameType::P,
+        None => FrameType::OTHER,
+    }
+}
+
+/// Large-scale tile decoding options
+///
+/// Streams produced with [`TileCodingMode::LargeScale`] (light-field style
+/// content) can only be consumed with the tile mode enabled, and are usually
+/// decoded one tile at a time.
+///
+/// The anchor frames are decoded as usual. Every camera frame carries its own
+/// frame header in front of the tile data: libaom parses it again on each
+/// [`decode`] call, so to extract several tiles from the same camera frame the
+/// whole temporal unit, header included, must be sent again after selecting
+/// the next tile with [`set_decode_tile`].
+///
+/// [`TileCodingMode::LargeScale`]: crate::encoder::TileCodingMode::LargeScale
+/// [`decode`]: AV1Decoder::decode
+/// [`set_decode_tile`]: AV1Decoder::set_decode_tile
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TileDecoding {
+    /// Decode the stream in large-scale tile mode
+    ///
+    /// It matches `AV1_SET_TILE_MODE`.
+    pub large_scale: bool,
+    /// Output only the selected tile instead of the whole frame
+    ///
+    /// It matches `AV1D_EXT_TILE_DEBUG`.
+    pub ext_tile_debug: bool,
+    /// Tile row to decode, `None` decodes every row
+    ///
+    /// It matches `AV1_SET_DECODE_TILE_ROW`.
+    pub row: Option<u32>,
+    /// Tile column to decode, `None` decodes every column
+    ///
+    /// It matches `AV1_SET_DECODE_TILE_COL`.
+    pub col: Option<u32>,
+    /// Decode the tiles in the inverse order, the output is the same
+    ///
+    /// Meant to check that the tiles of a stream encoded with
+    /// [`single_tile_decoding`] do not depend on each other. The
+    /// multi-threaded tile decoding ignores it.
+    ///
+    /// It matches `AV1_INVERT_TILE_DECODE_ORDER`.
+    ///
+    /// [`single_tile_decoding`]: crate::encoder::AV1EncoderConfig::single_tile_decoding
+    pub invert_order: bool,
+}
+
+/// Builder for [`AV1Decoder`]
+///
+/// Created by [`AV1Decoder::builder`], it holds the decoder configuration
+/// passed to `aom_codec_dec_init_ver`.
+pub struct AV1DecoderBuilder<T> {
+    cfg: aom_codec_dec_cfg_t,
+    frame_pool: Option<FramePool>,
+    tolerant: bool,
+    // None if libaom is built without the decoder
+    iface: Option<Iface>,
+    private_data: PhantomData<T>,
+}
+
+impl<T> AV1DecoderBuilder<T> {
+    fn new() -> Self {
+        AV1DecoderBuilder {
+            cfg: unsafe { zeroed() },
+            frame_pool: None,
+            tolerant: false,
+            iface: Iface::av1_decoder(),
+            private_data: PhantomData,
+        }
+    }
+
+    /// Maximum number of threads to use, 0 is equivalent to 1
+    pub fn threads(mut self, val: u32) -> Self {
+        self.cfg.threads = val;
+        self
+    }
+
+    /// Copy the frames into buffers recycled by `pool`
+    ///
+    /// See [`AV1Decoder::with_frame_pool`].
+    pub fn frame_pool(mut self, pool: FramePool) -> Self {
+        self.frame_pool = Some(pool);
+        self
+    }
+
+    /// Skip the corrupt data instead of failing
+    ///
+    /// The [`decode`] calls failing with [`AomError::CorruptFrame`] succeed
+    /// instead, they are counted by [`corruption_count`].
+    ///
+    /// [`decode`]: AV1Decoder::decode
+    /// [`corruption_count`]: AV1Decoder::corruption_count
+    pub fn tolerant(mut self, val: bool) -> Self {
+        self.tolerant = val;
+        self
+    }
+
+    /// Create the decoder with `iface` instead of the AV1 one
+    ///
+    /// # Safety
+    ///
+    /// `iface` must be a libaom decoder interface, valid for the lifetime of
+    /// the program.
+    ///
+    /// # Panics
+    ///
+    /// If `iface` is null.
+    pub unsafe fn with_iface(mut self, iface: *const aom_codec_iface_t) -> Self {
+        self.iface = Some(Iface::from_raw(iface).expect("null codec interface"));
+        self
+    }
+
+    /// Create the decoder
+    pub fn build(self) -> Result<AV1Decoder<T>, AomError> {
+        let mut dec = self.build_deferred();
+
+        match dec.init_error.take() {
+            Some(err) => Err(err),
+            None => Ok(dec),
+        }
+    }
+
+    // The initialization error, if any, is reported by the decode calls
+    fn build_deferred(self) -> AV1Decoder<T> {
+        self.init(AOM_DECODER_ABI_VERSION as i32)
+    }
+
+    fn init(self, abi_version: i32) -> AV1Decoder<T> {
+        // Zeroed so the error detail can be read if the initialization fails
+        let mut ctx = MaybeUninit::zeroed();
+
+        let iface = self.iface.filter(|iface| iface.is_decoder());
+        let ret = match iface {
+            Some(iface) => unsafe {
+                aom_codec_dec_init_ver(ctx.as_mut_ptr(), iface.as_raw(), &self.cfg, 0, abi_version)
+            },
+            None => aom_codec_err_t::AOM_CODEC_INCAPABLE,
+        };
+
+        // On failure libaom leaves a context every call rejects, as the
+        // zeroed one is
+        let ctx = unsafe { ctx.assume_init() };
+        let init_error = match (iface, ret) {
+            (None, _) => Some(AomError::InterfaceUnavailable { codec: "decoder" }),
+            (_, aom_codec_err_t::AOM_CODEC_OK) => None,
+            _ => Some(AomError::from_ctx(&ctx, ret)),
+        };
+
+        AV1Decoder {
+            ctx,
+            iter: ptr::null(),
+            private_data: PhantomData,
+            allocator: None,
+            frame_pool: self.frame_pool,
+            pending: VecDeque::new(),
+            private: None,
+            started: false,
+            last_size: [None; 4],
+            tolerant: self.tolerant,
+            corruption_count: 0,
+            init_error,
+            #[cfg(feature = "accounting")]
+            inspection: None,
+        }
+    }
 }
 
 /// AV1 Decoder
@@ -50,6 +220,21 @@ pub struct AV1Decoder<T> {
     pub(crate) ctx: aom_codec_ctx,
     pub(crate) iter: aom_codec_iter_t,
     private_data: PhantomData<T>,
+    // Boxed so libaom can keep a pointer to it
+    allocator: Option<Box<SharedAllocator>>,
+    frame_pool: Option<FramePool>,
+    pending: VecDeque<(Frame, DecodedFrameInfo, Option<Box<T>>)>,
+    // The private data of the last decode call, libaom only keeps a pointer
+    private: Option<Box<T>>,
+    // libaom sets up the decoding state on the first decode call
+    pub(crate) started: bool,
+    // Size of the last frame of each spatial layer
+    last_size: [Option<(u32, u32)>; 4],
+    tolerant: bool,
+    corruption_count: u64,
+    init_error: Option<AomError>,
+    #[cfg(feature = "accounting")]
+    pub(crate) inspection: Option<Box<Inspection>>,
 }
 
 unsafe impl<T: Send> Send for AV1Decoder<T> {} // TODO: Make sure it cannot be abused
@@ -57,30 +242,55 @@ unsafe impl<T: Sync> Sync for AV1Decoder<T> {} // TODO: Make sure it cannot be a
 
 impl<T> AV1Decoder<T> {
     /// Create a new decoder
-    pub fn new() -> Result<AV1Decoder<T>, aom_codec_err_t::Type> {
-        let mut ctx = MaybeUninit::uninit();
-        let cfg = unsafe { zeroed() };
+    pub fn new() -> Result<AV1Decoder<T>, AomError> {
+        Self::builder().build()
+    }
+
+    /// Configure a new decoder
+    pub fn builder() -> AV1DecoderBuilder<T> {
+        AV1DecoderBuilder::new()
+    }
+
+    /// Create a new decoder storing its frames in buffers provided by `alloc`
+    ///
+    /// The frames returned by [`get_frame`] reference those buffers instead
+    /// of copying the decoded image, see [`FrameBufferAllocator`] for the
+    /// details. The 8-bit images libaom stores in 16-bit samples are still
+    /// copied, to be converted to 8-bit frames.
+    ///
+    /// It matches a call to `aom_codec_set_frame_buffer_functions`.
+    ///
+    /// [`get_frame`]: #method.get_frame
+    pub fn with_allocator<A>(alloc: A) -> Result<AV1Decoder<T>, AomError>
+    where
+        A: FrameBufferAllocator + 'static,
+    {
+        let mut dec = Self::new()?;
+        let alloc: Box<SharedAllocator> = Box::new(Arc::new(Mutex::new(alloc)));
 
         let ret = unsafe {
-            aom_codec_dec_init_ver(
-                ctx.as_mut_ptr(),
-                aom_codec_av1_dx(),
-                &cfg as *const aom_codec_dec_cfg_t,
-                0,
-                AOM_DECODER_ABI_VERSION as i32,
+            aom_codec_set_frame_buffer_functions(
+                &mut dec.ctx,
+                Some(get_frame_buffer),
+                Some(release_frame_buffer),
+                &*alloc as *const SharedAllocator as *mut raw::c_void,
             )
         };
-        match ret {
-            aom_codec_err_t::AOM_CODEC_OK => {
-                let ctx = unsafe { ctx.assume_init() };
-                Ok(AV1Decoder {
-                    ctx,
-                    iter: ptr::null(),
-                    private_data: PhantomData,
-                })
-            }
-            _ => Err(ret),
-        }
+
+        check(&dec.ctx, ret)?;
+
+        dec.allocator = Some(alloc);
+
+        Ok(dec)
+    }
+
+    /// Create a new decoder copying its frames into buffers recycled by `pool`
+    ///
+    /// Keep a clone of the pool around to inspect its [`stats`].
+    ///
+    /// [`stats`]: FramePool::stats
+    pub fn with_frame_pool(pool: FramePool) -> Result<AV1Decoder<T>, AomError> {
+        Self::builder().frame_pool(pool).build()
     }
 
     /// Feed some compressed data to the encoder
@@ -89,38 +299,158 @@ impl<T> AV1Decoder<T> {
     /// `private` struct.
     ///
     /// The [`get_frame`] method must be called to retrieve the decompressed
-    /// frame, do not call this method again before calling [`get_frame`].
+    /// frame. The frames not retrieved before calling this method again are
+    /// kept and returned first.
+    ///
+    /// Corrupt data is reported as [`AomError::CorruptFrame`], the packet is
+    /// skipped and the decoder can be fed the next ones.
     ///
     /// It matches a call to `aom_codec_decode`.
     ///
     /// [`get_frame`]: #method.get_frame
-    pub fn decode<O>(&mut self, data: &[u8], private: O) -> Result<(), aom_codec_err_t::Type>
+    pub fn decode<O>(&mut self, data: &[u8], private: O) -> Result<(), AomError>
     where
         O: Into<Option<T>>,
     {
-        let priv_data = private
-            .into()
-            .map(|v| Box::into_raw(Box::new(v)))
-            .unwrap_or(ptr::null_mut());
+        if let Some(ref err) = self.init_error {
+            return Err(err.clone());
+        }
+
+        self.stash_frames();
+
+        // Every image carrying the previous private data has been stashed,
+        // libaom has no use for it anymore.
+        self.private = private.into().map(Box::new);
+
+        let priv_data = self
+            .private
+            .as_deref_mut()
+            .map_or(ptr::null_mut(), |p| p as *mut T);
+
+        #[cfg(feature = "accounting")]
+        if let Some(insp) = self.inspection.as_deref_mut() {
+            insp.ctx = &mut self.ctx;
+        }
         let ret = unsafe {
             aom_codec_decode(
                 &mut self.ctx,
                 data.as_ptr(),
                 data.len(),
-                priv_data as *mut raw::c_void, // mem::transmute(priv_data)
+                priv_data as *mut raw::c_void,
             )
         };
 
         // Safety measure to not call get_frame on an invalid iterator
         self.iter = ptr::null();
 
-        match ret {
-            aom_codec_err_t::AOM_CODEC_OK => Ok(()),
-            _ => {
-                let _ = unsafe { Box::from_raw(priv_data) };
-                Err(ret)
+        self.started |= ret == aom_codec_err_t::AOM_CODEC_OK;
+
+        // The private data is kept even on error, frames already output by
+        // this call may still carry it.
+        match check(&self.ctx, ret).map_err(AomError::into_corrupt_frame) {
+            Err(AomError::CorruptFrame { .. }) if self.tolerant => {
+                self.corruption_count += 1;
+                Ok(())
             }
+            ret => ret,
+        }
+    }
+
+    /// Number of corrupt [`decode`] calls skipped in tolerant mode
+    ///
+    /// See [`AV1DecoderBuilder::tolerant`].
+    ///
+    /// [`decode`]: #method.decode
+    pub fn corruption_count(&self) -> u64 {
+        self.corruption_count
+    }
+
+    /// Update the decoder parameters after-creation
+    ///
+    /// It calls `aom_codec_control_`
+    pub fn control_raw(&mut self, id: aom_dec_control_id::Type, val: i32) -> Result<(), AomError> {
+        let ret = unsafe { aom_codec_control(&mut self.ctx, id as i32, val) };
+
+        check(&self.ctx, ret)
+    }
+
+    /// Update the decoder parameters after-creation
+    #[deprecated(since = "0.4.0", note = "use control_raw")]
+    pub fn control(&mut self, id: aom_dec_control_id::Type, val: i32) -> Result<(), AomError> {
+        self.control_raw(id, val)
+    }
+
+    /// Configure the large-scale tile decoding
+    ///
+    /// Must be called before sending the first frame of a stream encoded
+    /// with large-scale tiles.
+    pub fn set_tile_decoding(&mut self, tiles: &TileDecoding) -> Result<(), AomError> {
+        self.control_raw(
+            aom_dec_control_id::AV1_SET_TILE_MODE,
+            tiles.large_scale as i32,
+        )?;
+        self.control_raw(
+            aom_dec_control_id::AV1D_EXT_TILE_DEBUG,
+            tiles.ext_tile_debug as i32,
+        )?;
+        self.control_raw(
+            aom_dec_control_id::AV1_SET_DECODE_TILE_ROW,
+            tiles.row.map_or(-1, |r| r as i32),
+        )?;
+        self.control_raw(
+            aom_dec_control_id::AV1_SET_DECODE_TILE_COL,
+            tiles.col.map_or(-1, |c| c as i32),
+        )?;
+        self.control_raw(
+            aom_dec_control_id::AV1_INVERT_TILE_DECODE_ORDER,
+            tiles.invert_order as i32,
+        )
+    }
+
+    /// Select the single tile to decode from the next frames
+    ///
+    /// See [`TileDecoding`] for the camera frame header handling.
+    pub fn set_decode_tile(&mut self, row: u32, col: u32) -> Result<(), AomError> {
+        self.control_raw(aom_dec_control_id::AV1_SET_DECODE_TILE_ROW, row as i32)?;
+        self.control_raw(aom_dec_control_id::AV1_SET_DECODE_TILE_COL, col as i32)
+    }
+
+    /// Output the frames without applying the film grain
+    ///
+    /// It matches `AV1D_SET_SKIP_FILM_GRAIN`.
+    pub fn set_skip_film_grain(&mut self, skip: bool) -> Result<(), AomError> {
+        self.control_raw(aom_dec_control_id::AV1D_SET_SKIP_FILM_GRAIN, skip as i32)
+    }
+
+    /// Align the start of the planes libaom decodes into to `align` bytes
+    ///
+    /// `align` is a power of two from 32 to 1024, or 0 for the legacy
+    /// alignment, the default, which leaves the planes unaligned. The
+    /// strides stay multiples of 32 whatever the alignment. It applies to
+    /// the buffers of a [`FrameBufferAllocator`] as well, the space it
+    /// takes is part of the size requested.
+    ///
+    /// It must be called before the first [`decode`] call.
+    ///
+    /// It matches `AV1_SET_BYTE_ALIGNMENT`.
+    ///
+    /// [`decode`]: AV1Decoder::decode
+    pub fn set_byte_alignment(&mut self, align: u32) -> Result<(), AomError> {
+        if self.started {
+            return Err(AomError::new(
+                AomErrorKind::Error,
+                "the byte alignment must be set before decoding",
+            ));
+        }
+
+        if align != 0 && !(align.is_power_of_two() && (32..=1024).contains(&align)) {
+            return Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "byte alignment other than 0 or a power of two from 32 to 1024",
+            ));
         }
+
+        self.control_raw(aom_dec_control_id::AV1_SET_BYTE_ALIGNMENT, align as i32)
     }
 
     /// Notify the decoder to return any pending frame
@@ -131,15 +461,15 @@ impl<T> AV1Decoder<T> {
     /// It matches a call to `aom_codec_decode` with NULL arguments.
     ///
     /// [`get_frame`]: #method.get_frame
-    pub fn flush(&mut self) -> Result<(), aom_codec_err_t::Type> {
+    pub fn flush(&mut self) -> Result<(), AomError> {
+        self.stash_frames();
+        self.private = None;
+
         let ret = unsafe { aom_codec_decode(&mut self.ctx, ptr::null(), 0, ptr::null_mut()) };
 
         self.iter = ptr::null();
 
-        match ret {
-            aom_codec_err_t::AOM_CODEC_OK => Ok(()),
-            _ => Err(ret),
-        }
+        check(&self.ctx, ret)
     }
 
     /// Retrieve decoded frames
@@ -148,27 +478,319 @@ impl<T> AV1Decoder<T> {
     ///
     /// It matches a call to `aom_codec_get_frame`.
     pub fn get_frame(&mut self) -> Option<(Frame, Option<Box<T>>)> {
-        let img = unsafe { aom_codec_get_frame(&mut self.ctx, &mut self.iter) };
+        self.get_frame_with_info().map(|(f, _, p)| (f, p))
+    }
+
+    /// Retrieve decoded frames along with their [`DecodedFrameInfo`]
+    ///
+    /// It behaves like [`get_frame`].
+    ///
+    /// [`get_frame`]: #method.get_frame
+    pub fn get_frame_with_info(&mut self) -> Option<(Frame, DecodedFrameInfo, Option<Box<T>>)> {
+        self.pending.pop_front().or_else(|| self.next_frame())
+    }
+
+    /// Iterate over the decoded frames
+    ///
+    /// It calls [`get_frame`] until it returns `None`.
+    ///
+    /// [`get_frame`]: #method.get_frame
+    pub fn frames(&mut self) -> Frames<'_, T> {
+        Frames { dec: self }
+    }
+
+    /// Flush the decoder and iterate over the remaining frames
+    ///
+    /// It is equivalent to [`flush`] followed by [`frames`].
+    ///
+    /// [`flush`]: #method.flush
+    /// [`frames`]: #method.frames
+    pub fn drain(&mut self) -> Result<Frames<'_, T>, AomError> {
+        self.flush()?;
+
+        Ok(self.frames())
+    }
+
+    // The flags describe the last decoded frame, they are still valid for
+    // every image returned by the same decode call.
+    fn frame_type(&mut self) -> FrameType {
+        let flags = unsafe { self.query::<raw::c_int>(aom_dec_control_id::AOMD_GET_FRAME_FLAGS) };
+
+        frame_type_from_flags(flags)
+    }
+
+    /// Call a control writing its result through a pointer
+    ///
+    /// # Safety
+    ///
+    /// `V` must be the type the control `id` expects a pointer to.
+    pub(crate) unsafe fn query<V>(&mut self, id: aom_dec_control_id::Type) -> Option<V> {
+        let mut val = MaybeUninit::<V>::zeroed();
+
+        self.control_ptr(id, val.as_mut_ptr())
+            .ok()
+            .map(|_| val.assume_init())
+    }
+
+    /// Call a control taking a pointer argument
+    ///
+    /// # Safety
+    ///
+    /// `V` must be the type the control `id` expects a pointer to.
+    pub(crate) unsafe fn control_ptr<V>(
+        &mut self,
+        id: aom_dec_control_id::Type,
+        val: *mut V,
+    ) -> Result<(), AomError> {
+        let ret = aom_codec_control(&mut self.ctx, id as i32, val);
+
+        check(&self.ctx, ret)
+    }
 
-        if img.is_null() {
-            None
+    // The frame controls dereference the decoding state unchecked
+    fn check_started(&self) -> Result<(), AomError> {
+        if self.started {
+            Ok(())
         } else {
+            Err(AomError::new(AomErrorKind::Error, "nothing decoded yet"))
+        }
+    }
+
+    fn reference_raw(&mut self, idx: u32) -> Result<av1_ref_frame_t, AomError> {
+        self.check_started()?;
+
+        let mut r: av1_ref_frame_t = unsafe { zeroed() };
+        r.idx = idx as i32;
+
+        unsafe { self.control_ptr(aom_com_control_id::AV1_GET_REFERENCE, &mut r)? };
+
+        Ok(r)
+    }
+
+    /// Copy the reference frame `idx`
+    ///
+    /// It matches `AV1_GET_REFERENCE`.
+    pub fn get_reference(&mut self, idx: u32) -> Result<AomImage, AomError> {
+        let r = self.reference_raw(idx)?;
+
+        unsafe { AomImage::copy_from_raw(&r.img) }
+            .ok_or_else(|| AomError::new(AomErrorKind::MemError, "cannot allocate the image"))
+    }
+
+    /// Overwrite the reference frame `idx` with `img`
+    ///
+    /// The image must have the same format and dimensions as the reference.
+    ///
+    /// It matches `AV1_SET_REFERENCE`.
+    pub fn set_reference(&mut self, idx: u32, img: &AomImage) -> Result<(), AomError> {
+        let mut r = self.reference_raw(idx)?;
+
+        if !img.same_geometry(&r.img) {
+            return Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "the image does not match the reference",
+            ));
+        }
+
+        r.use_external_ref = 0;
+        r.img = *img.raw();
+
+        unsafe { self.control_ptr(aom_com_control_id::AV1_SET_REFERENCE, &mut r) }
+    }
+
+    /// Copy the reference frame `idx` into `img`
+    ///
+    /// The image must have the same format and dimensions as the reference.
+    ///
+    /// It matches `AV1_COPY_REFERENCE`.
+    pub fn copy_reference(&mut self, idx: u32, img: &mut AomImage) -> Result<(), AomError> {
+        let mut r = self.reference_raw(idx)?;
+
+        if !img.same_geometry(&r.img) {
+            return Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "the image does not match the reference",
+            ));
+        }
+
+        r.img = *img.raw();
+
+        unsafe { self.control_ptr(aom_com_control_id::AV1_COPY_REFERENCE, &mut r) }
+    }
+
+    /// Copy the most recently decoded frame
+    ///
+    /// Unlike [`get_frame`] it returns the last frame decoded, shown or not.
+    ///
+    /// It matches `AV1_GET_NEW_FRAME_IMAGE`.
+    ///
+    /// [`get_frame`]: #method.get_frame
+    pub fn new_frame_image(&mut self) -> Result<AomImage, AomError> {
+        self.check_started()?;
+
+        let mut img: aom_image_t = unsafe { zeroed() };
+        unsafe { self.control_ptr(aom_com_control_id::AV1_GET_NEW_FRAME_IMAGE, &mut img)? };
+
+        unsafe { AomImage::copy_from_raw(&img) }
+            .ok_or_else(|| AomError::new(AomErrorKind::MemError, "cannot allocate the image"))
+    }
+
+    /// Copy the most recently decoded frame into `img`
+    ///
+    /// The image must have the same format and dimensions as the frame, and
+    /// the same border: libaom decodes with a 64 pixels border, see
+    /// [`AomImage::new_with_border`].
+    ///
+    /// It matches `AV1_COPY_NEW_FRAME_IMAGE`.
+    pub fn copy_new_frame_image(&mut self, img: &mut AomImage) -> Result<(), AomError> {
+        self.check_started()?;
+
+        let mut new: aom_image_t = unsafe { zeroed() };
+        unsafe { self.control_ptr(aom_com_control_id::AV1_GET_NEW_FRAME_IMAGE, &mut new)? };
+
+        if !img.same_geometry(&new) {
+            return Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "the image does not match the frame",
+            ));
+        }
+
+        let mut dst = *img.raw();
+
+        unsafe { self.control_ptr(aom_com_control_id::AV1_COPY_NEW_FRAME_IMAGE, &mut dst) }
+    }
+
+    // libaom releases the frames of the previous call on the next
+    // aom_codec_decode, keep them around.
+    fn stash_frames(&mut self) {
+        while let Some(f) = self.next_frame() {
+            self.pending.push_back(f);
+        }
+    }
+
+    // Compare with the previous frame of the layer, the pool drops the free
+    // buffers of the previous size
+    fn size_changed(&mut self, img: &aom_image_t, layer: u32) -> bool {
+        let size = (img.d_w, img.d_h);
+
+        match self.last_size[layer.min(3) as usize].replace(size) {
+            Some((w, h)) if (w, h) != size => {
+                if let Some(ref pool) = self.frame_pool {
+                    pool.evict(w as usize, h as usize);
+                }
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // The images that cannot be represented as a Frame, in a format libaom
+    // does not decode to, are skipped
+    fn next_frame(&mut self) -> Option<(Frame, DecodedFrameInfo, Option<Box<T>>)> {
+        loop {
+            let img = unsafe { aom_codec_get_frame(&mut self.ctx, &mut self.iter) };
+
+            if img.is_null() {
+                return None;
+            }
+
             let im = unsafe { *img };
-            let priv_data = if im.user_priv.is_null() {
-                None
+            let frame_type = self.frame_type();
+            let mut info = self.frame_info(&im);
+            // The 16-bit buffers of 8-bit content are converted to 8-bit
+            // frames
+            let narrow = is_high_bitdepth(&im) && im.bit_depth == 8;
+            let pooled = if self.allocator.is_some() && !narrow {
+                unsafe { PooledFrameBuffer::from_img(&im) }
             } else {
-                let p: *mut T = im.user_priv as *mut T;
-                Some(unsafe { Box::from_raw(p) })
+                None
             };
-            let frame = frame_from_img(im);
-            Some((frame, priv_data))
+            let frame = match pooled {
+                Some(buf) => video_info_from_img(&im, frame_type).map(|v| Frame {
+                    kind: v.into(),
+                    buf: Box::new(buf),
+                    t: Default::default(),
+                }),
+                None => frame_from_img(im, frame_type, self.frame_pool.as_ref()),
+            };
+
+            if let Some(frame) = frame {
+                info.resolution_changed = self.size_changed(&im, info.spatial_id);
+
+                // Only the first image carrying the private data gets it back
+                let priv_data = match self.private {
+                    Some(ref p) if ptr::eq(&**p, im.user_priv as *const T) => self.private.take(),
+                    _ => None,
+                };
+
+                return Some((frame, info, priv_data));
+            }
         }
     }
 }
 
+/// Timestamp passthrough
+///
+/// The [`TimeInfo`] sent with the compressed data is set on the frames
+/// decoded from it.
+impl AV1Decoder<TimeInfo> {
+    /// Feed some compressed data alongside its timestamps
+    ///
+    /// It is a shorthand for [`decode`], the frames must be retrieved with
+    /// [`get_timed_frame`].
+    ///
+    /// [`decode`]: #method.decode
+    /// [`get_timed_frame`]: #method.get_timed_frame
+    pub fn decode_with_time(&mut self, data: &[u8], t: TimeInfo) -> Result<(), AomError> {
+        self.decode(data, t)
+    }
+
+    /// Retrieve decoded frames with their timestamps stored in `Frame::t`
+    ///
+    /// It behaves like [`get_frame`].
+    ///
+    /// [`get_frame`]: #method.get_frame
+    pub fn get_timed_frame(&mut self) -> Option<Frame> {
+        self.get_frame().map(|(mut f, t)| {
+            f.t = t.map(|b| *b).unwrap_or_default();
+            f
+        })
+    }
+}
+
+/// Iterator over the decoded frames
+///
+/// Created by [`AV1Decoder::frames`] and [`AV1Decoder::drain`].
+pub struct Frames<'a, T> {
+    dec: &'a mut AV1Decoder<T>,
+}
+
+impl<T> Iterator for Frames<'_, T> {
+    type Item = (Frame, Option<Box<T>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.dec.get_frame()
+    }
+}
+
+/// The decoder is created with the default configuration
+///
+/// If libaom fails to create it, the error is returned by every [`decode`]
+/// call.
+///
+/// [`decode`]: AV1Decoder::decode
+impl<T> Default for AV1Decoder<T> {
+    fn default() -> Self {
+        AV1DecoderBuilder::new().build_deferred()
+    }
+}
+
+// The private data still referenced by libaom is dropped with the other
+// fields, after the context.
 impl<T> Drop for AV1Decoder<T> {
     fn drop(&mut self) {
-        unsafe { aom_codec_destroy(&mut self.ctx) };
+        destroy_on_drop(&mut self.ctx);
     }
 }
 
@@ -185,18 +807,64 @@ mod decoder_trait {
     use av_codec::error::*;
     use av_data::frame::ArcFrame;
     use av_data::packet::Packet;
-    use av_data::timeinfo::TimeInfo;
     use std::sync::Arc;
 
-    pub struct Des {
+    /// Private data carried by the codec-trait decoder from the packets to
+    /// the frames decoded from them
+    pub trait FramePrivate: Send + Sync + Sized {
+        /// Extract the data from the packet sent to the decoder
+        fn from_packet(pkt: &Packet) -> Self;
+
+        /// Attach the data to the decoded frame
+        fn apply(self, frame: &mut Frame);
+    }
+
+    /// The timestamps of the packet are set on the frame
+    impl FramePrivate for TimeInfo {
+        fn from_packet(pkt: &Packet) -> Self {
+            pkt.t.clone()
+        }
+
+        fn apply(self, frame: &mut Frame) {
+            frame.t = self;
+        }
+    }
+
+    /// Descriptor of the AV1 decoder carrying `T` along the frames
+    pub struct AV1Descriptor<T = TimeInfo> {
         descr: Descr,
+        private: PhantomData<fn() -> T>,
+    }
+
+    /// Descriptor of the decoder carrying the packet timestamps
+    pub type TimeInfoDescriptor = AV1Descriptor<TimeInfo>;
+
+    impl<T> AV1Descriptor<T> {
+        /// Describe the libaom decoder
+        pub const fn new() -> Self {
+            AV1Descriptor {
+                descr: Descr {
+                    codec: "av1",
+                    name: "aom",
+                    desc: "libaom AV1 decoder",
+                    mime: "video/AV1",
+                },
+                private: PhantomData,
+            }
+        }
+    }
+
+    impl<T> Default for AV1Descriptor<T> {
+        fn default() -> Self {
+            Self::new()
+        }
     }
 
-    impl Descriptor for Des {
-        type OutputDecoder = AV1Decoder<TimeInfo>;
+    impl<T: FramePrivate> Descriptor for AV1Descriptor<T> {
+        type OutputDecoder = AV1Decoder<T>;
 
         fn create(&self) -> Self::OutputDecoder {
-            AV1Decoder::new().unwrap()
+            AV1Decoder::default()
         }
 
         fn describe(&self) -> &Descr {
@@ -204,25 +872,26 @@ mod decoder_trait {
         }
     }
 
-    impl Decoder for AV1Decoder<TimeInfo> {
+    impl<T: FramePrivate> Decoder for AV1Decoder<T> {
         fn set_extradata(&mut self, _extra: &[u8]) {
             // No-op
         }
         fn send_packet(&mut self, pkt: &Packet) -> Result<()> {
-            self.decode(&pkt.data, pkt.t.clone())
-                .map_err(|err| Error::Unsupported(format!("{}", err)))
+            self.decode(&pkt.data, T::from_packet(pkt))
+                .map_err(Error::from)
         }
         fn receive_frame(&mut self) -> Result<ArcFrame> {
             self.get_frame()
-                .map(|(mut f, t)| {
-                    f.t = t.map(|b| *b).unwrap();
+                .map(|(mut f, private)| {
+                    if let Some(private) = private {
+                        private.apply(&mut f);
+                    }
                     Arc::new(f)
                 })
                 .ok_or(Error::MoreDataNeeded)
         }
         fn flush(&mut self) -> Result<()> {
-            self.flush()
-                .map_err(|err| Error::Unsupported(format!("{}", err)))
+            self.flush().map_err(Error::from)
         }
         fn configure(&mut self) -> Result<()> {
             Ok(())
@@ -232,20 +901,15 @@ mod decoder_trait {
     /// AV1 Decoder
     ///
     /// To be used with [av-codec](https://docs.rs/av-codec) `Context`.
-    pub const AV1_DESCR: &Des = &Des {
-        descr: Descr {
-            codec: "av1",
-            name: "aom",
-            desc: "libaom AV1 decoder",
-            mime: "video/AV1",
-        },
-    };
+    ///
+    /// Use an [`AV1Descriptor`] to carry other data than the timestamps.
+    pub const AV1_DESCR: &TimeInfoDescriptor = &AV1Descriptor::new();
 }
 
 #[cfg(feature = "codec-trait")]
-pub use self::decoder_trait::AV1_DESCR;
+pub use self::decoder_trait::{AV1Descriptor, FramePrivate, TimeInfoDescriptor, AV1_DESCR};
 
-#[cfg(test)]
+#[cfg(all(test, feature = "encoder"))]
 mod tests {
     use super::*;
     #[test]
@@ -257,10 +921,14 @@ mod tests {
 
     use super::super::encoder::tests as enc;
     use super::super::encoder::AOMPacket;
+    use av_data::frame::FrameBufferConv;
     use av_data::rational::*;
     use av_data::timeinfo::TimeInfo;
     #[test]
     fn decode() {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats;
+
         let w = 200;
         let h = 200;
 
@@ -273,36 +941,1498 @@ mod tests {
         };
 
         let mut e = enc::setup(w, h, &t);
-        let mut f = enc::setup_frame(w, h, &t);
+        let frames = MovingBox::new(w as usize, h as usize, &Arc::new(*formats::YUV420), &t);
 
         let mut d = AV1Decoder::<()>::new().unwrap();
         let mut out = 0;
 
-        for i in 0..100 {
-            e.encode(&f).unwrap();
-            f.t.pts = Some(i);
+        // Luma at 10,10, in the box of the first frame, and at 150,10
+        let mut check = |f: &Frame| {
+            let stride = f.buf.linesize(0).unwrap();
+            let y: &[u8] = f.buf.as_slice(0).unwrap();
+            let near = |v: u8, expected: i32| (v as i32 - expected).abs() <= 8;
 
-            // println!("{:#?}", f);
-            loop {
-                let p = e.get_packet();
+            if out == 0 {
+                assert!(near(y[10 * stride + 10], 81));
+                assert!(near(y[10 * stride + 150], 150 * 255 / 199));
+            }
+            out += 1;
+        };
 
-                match p {
-                    Some(AOMPacket::Packet(ref pkt)) => {
-                        d.decode(&pkt.data, None).unwrap();
+        for f in frames.take(100) {
+            e.encode(&f).unwrap();
 
-                        // No multiframe expected.
-                        if let Some(f) = d.get_frame() {
-                            out = 1;
-                            println!("{:#?}", f);
-                        }
-                    }
-                    _ => break,
+            while let Some(AOMPacket::Packet(ref pkt)) = e.get_packet() {
+                d.decode(&pkt.data, None).unwrap();
+
+                while let Some((f, _)) = d.get_frame() {
+                    check(&f);
                 }
             }
         }
 
-        if out != 1 {
-            panic!("No frame decoded");
+        for p in e.close().unwrap() {
+            if let AOMPacket::Packet(pkt) = p {
+                d.decode(&pkt.data, None).unwrap();
+                while let Some((f, _)) = d.get_frame() {
+                    check(&f);
+                }
+            }
         }
+
+        assert_eq!(out, 100);
+    }
+
+    #[test]
+    fn decode_large_scale_tile() {
+        use crate::encoder::{AV1EncoderConfig, TileCodingMode};
+        use crate::ffi::aome_enc_control_id::*;
+
+        let w = 256;
+        let h = 256;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(w)
+            .height(h)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0)
+            .large_scale_tile(TileCodingMode::LargeScale)
+            .single_tile_decoding(true);
+
+        let mut e = cfg.get_encoder().unwrap();
+
+        // 2x2 tiles, each one decodable on its own
+        e.control_raw(AV1E_SET_TILE_COLUMNS, 1).unwrap();
+        e.control_raw(AV1E_SET_TILE_ROWS, 1).unwrap();
+        e.control_raw(AV1E_ENABLE_EXT_TILE_DEBUG, 1).unwrap();
+        e.control_raw(AOME_SET_ENABLEAUTOALTREF, 0).unwrap();
+
+        let mut f = enc::setup_frame(w, h, &t);
+        let mut packets = Vec::new();
+
+        for i in 0..5 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+
+            while let Some(AOMPacket::Packet(pkt)) = e.get_packet() {
+                packets.push(pkt.data);
+            }
+        }
+
+        let decode = |tiles: &TileDecoding| {
+            let mut d = AV1Decoder::<()>::new().unwrap();
+            d.set_tile_decoding(tiles).unwrap();
+
+            let mut frames = Vec::new();
+            for p in &packets {
+                d.decode(p, None).unwrap();
+                while let Some((f, _)) = d.get_frame() {
+                    frames.push(f);
+                }
+            }
+
+            frames
+        };
+
+        let tile = TileDecoding {
+            large_scale: true,
+            ext_tile_debug: true,
+            row: Some(1),
+            col: Some(1),
+            ..Default::default()
+        };
+
+        let frames = decode(&tile);
+        if frames.is_empty() {
+            panic!("No tile decoded");
+        }
+
+        for f in &frames {
+            let v = f.kind.get_video_info().unwrap();
+            assert_eq!((v.width, v.height), (w as usize / 2, h as usize / 2));
+        }
+
+        // The whole frames, the tiles do not depend on each other
+        let hashes =
+            |tiles: &TileDecoding| decode(tiles).iter().map(frame_hash).collect::<Vec<_>>();
+        let all = TileDecoding {
+            row: None,
+            col: None,
+            ..tile
+        };
+        let inverted = TileDecoding {
+            invert_order: true,
+            ..all
+        };
+
+        let expected = hashes(&all);
+        assert!(!expected.is_empty());
+        assert_eq!(hashes(&inverted), expected);
+    }
+
+    /// Flush the encoder lookahead through the decoder, return the frames decoded
+    fn drain<T>(e: &mut crate::encoder::AV1Encoder, d: &mut AV1Decoder<T>) -> usize {
+        let mut out = 0;
+
+        loop {
+            e.flush().unwrap();
+
+            let mut drained = true;
+            while let Some(AOMPacket::Packet(ref pkt)) = e.get_packet() {
+                drained = false;
+                d.decode(&pkt.data, None).unwrap();
+                while d.get_frame().is_some() {
+                    out += 1;
+                }
+            }
+
+            if drained {
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn decode_drain() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let w = 64;
+        let h = 64;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(w)
+            .height(h)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(10);
+
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = enc::setup_frame(w, h, &t);
+        let mut d = AV1Decoder::<i64>::new().unwrap();
+
+        let mut packets = Vec::new();
+
+        for i in 0..20 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                packets.push(p);
+            }
+        }
+
+        // The lookahead holds back the last frames
+        assert!(packets.len() < 20);
+
+        loop {
+            e.flush().unwrap();
+            let before = packets.len();
+            while let Some(p) = e.get_packet() {
+                packets.push(p);
+            }
+            if packets.len() == before {
+                break;
+            }
+        }
+
+        // Never retrieve the frames before the end
+        for (i, p) in packets.iter().enumerate() {
+            if let AOMPacket::Packet(ref pkt) = p {
+                d.decode(&pkt.data, i as i64).unwrap();
+            }
+        }
+
+        let frames: Vec<_> = d.drain().unwrap().collect();
+
+        assert_eq!(frames.len(), 20);
+        assert!(d.frames().next().is_none());
+    }
+
+    #[test]
+    fn private_data_is_reclaimed() {
+        let w = 64;
+        let h = 64;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let tracker = Arc::new(());
+
+        let mut e = enc::setup(w, h, &t);
+        let mut f = enc::setup_frame(w, h, &t);
+
+        let mut packets = Vec::new();
+        for i in 0..40 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            while let Some(AOMPacket::Packet(p)) = e.get_packet() {
+                packets.push(p);
+            }
+        }
+        assert!(!packets.is_empty());
+
+        // Frames never retrieved
+        let mut d = AV1Decoder::new().unwrap();
+        for p in &packets {
+            d.decode(&p.data, tracker.clone()).unwrap();
+        }
+        drop(d);
+        assert_eq!(Arc::strong_count(&tracker), 1);
+
+        // Decoding errors
+        let mut d = AV1Decoder::new().unwrap();
+        assert!(d.decode(&[0xff; 16], tracker.clone()).is_err());
+        assert!(d.decode(&[0xff; 16], tracker.clone()).is_err());
+        d.flush().unwrap();
+        assert_eq!(Arc::strong_count(&tracker), 1);
+        drop(d);
+
+        // Retrieved frames give the data back
+        let mut d = AV1Decoder::new().unwrap();
+        let mut privs = Vec::new();
+        for p in &packets {
+            d.decode(&p.data, tracker.clone()).unwrap();
+            privs.extend(d.frames().filter_map(|(_, p)| p));
+        }
+        assert_eq!(Arc::strong_count(&tracker), 1 + privs.len());
+        drop(d);
+        drop(privs);
+        assert_eq!(Arc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    fn decode_with_time() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .lag_in_frames(10);
+
+        let mut d = AV1Decoder::<TimeInfo>::new().unwrap();
+        let mut pts = Vec::new();
+
+        for (i, p) in encode_all(&mut cfg, 20).iter().enumerate() {
+            let t = TimeInfo {
+                pts: Some(i as i64),
+                ..Default::default()
+            };
+            d.decode_with_time(p, t).unwrap();
+            while let Some(f) = d.get_timed_frame() {
+                pts.push(f.t.pts.unwrap());
+            }
+        }
+
+        d.flush().unwrap();
+        while let Some(f) = d.get_timed_frame() {
+            pts.push(f.t.pts.unwrap());
+        }
+
+        assert_eq!(pts, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn decode_frame_info() {
+        use crate::encoder::AV1EncoderConfig;
+        use crate::ffi::aom_dec_control_id::*;
+        use crate::ffi::aome_enc_control_id::*;
+
+        let w = 64;
+        let h = 64;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(w)
+            .height(h)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0);
+
+        let mut e = cfg.get_encoder().unwrap();
+        e.control_raw(AOME_SET_NUMBER_SPATIAL_LAYERS, 2).unwrap();
+
+        let mut f = enc::setup_frame(w, h, &t);
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        d.control_raw(AV1D_SET_OUTPUT_ALL_LAYERS, 1).unwrap();
+
+        let mut layers = Vec::new();
+
+        for i in 0..3 {
+            f.t.pts = Some(i);
+            for layer in 0..2 {
+                e.control_raw(AOME_SET_SPATIAL_LAYER_ID, layer).unwrap();
+                e.encode(&f).unwrap();
+                while let Some(p) = e.get_packet() {
+                    if let AOMPacket::Packet(ref pkt) = p {
+                        d.decode(&pkt.data, None).unwrap();
+                    }
+                }
+                while let Some((_, info, _)) = d.get_frame_with_info() {
+                    assert!(!info.corrupted);
+                    assert!(!info.show_existing_frame);
+                    layers.push(info.spatial_id);
+                }
+            }
+        }
+
+        assert_eq!(layers, [0, 1, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn decode_frame_type() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let w = 64;
+        let h = 64;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(w)
+            .height(h)
+            .timebase(t.timebase.unwrap())
+            .kf_min_dist(10)
+            .kf_max_dist(10);
+
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = enc::setup_frame(w, h, &t);
+        let mut d = AV1Decoder::<()>::new().unwrap();
+
+        let mut types = Vec::new();
+
+        for i in 0..30 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(ref pkt) = p {
+                    d.decode(&pkt.data, None).unwrap();
+                }
+            }
+            types.extend(
+                d.frames()
+                    .map(|(f, _)| f.kind.get_video_info().unwrap().frame_type),
+            );
+        }
+
+        loop {
+            e.flush().unwrap();
+            let mut drained = true;
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(ref pkt) = p {
+                    drained = false;
+                    d.decode(&pkt.data, None).unwrap();
+                }
+            }
+            types.extend(
+                d.frames()
+                    .map(|(f, _)| f.kind.get_video_info().unwrap().frame_type),
+            );
+            if drained {
+                break;
+            }
+        }
+
+        assert_eq!(types.len(), 30);
+
+        let keys: Vec<_> = types
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| **t == FrameType::I)
+            .map(|(i, _)| i)
+            .collect();
+
+        assert_eq!(keys, [0, 10, 20]);
+    }
+
+    #[test]
+    fn frame_type_flags() {
+        let flags = |f: u32| Some(f as raw::c_int);
+
+        assert_eq!(frame_type_from_flags(flags(AOM_FRAME_IS_KEY)), FrameType::I);
+        assert_eq!(
+            frame_type_from_flags(flags(AOM_FRAME_IS_KEY | AOM_FRAME_IS_DROPPABLE)),
+            FrameType::I
+        );
+        assert_eq!(
+            frame_type_from_flags(flags(AOM_FRAME_IS_INTRAONLY)),
+            FrameType::OTHER
+        );
+        assert_eq!(frame_type_from_flags(flags(0)), FrameType::P);
+        assert_eq!(frame_type_from_flags(None), FrameType::OTHER);
+    }
+
+    /// Encode `frames` frames of moving content, return the packets
+    fn encode_all(cfg: &mut crate::encoder::AV1EncoderConfig, frames: i64) -> Vec<Vec<u8>> {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats;
+
+        let (w, h) = (cfg.g_w, cfg.g_h);
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let mut e = cfg.get_encoder().unwrap();
+        let format = Arc::new(*formats::YUV420);
+        let mut packets = Vec::new();
+
+        // Moving content, so the encoder has something to predict
+        for f in MovingBox::new(w as usize, h as usize, &format, &t).take(frames as usize) {
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    packets.push(pkt.data);
+                }
+            }
+        }
+
+        loop {
+            e.flush().unwrap();
+            let before = packets.len();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    packets.push(pkt.data);
+                }
+            }
+            if packets.len() == before {
+                return packets;
+            }
+        }
+    }
+
+    #[test]
+    fn stream_properties() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        assert_eq!(d.stream_properties().still_picture, None);
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .limit(1);
+
+        for p in encode_all(&mut cfg, 1) {
+            d.decode(&p, None).unwrap();
+        }
+
+        let props = d.stream_properties();
+        assert_eq!(
+            props.still_picture,
+            Some(StillPicture {
+                is_still_picture: true,
+                is_reduced_still_picture_hdr: true,
+            })
+        );
+        assert!(props.superblock_size.is_some());
+        assert_eq!(props.show_existing_frame, Some(false));
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .lag_in_frames(35)
+            .kf_max_dist(30)
+            .fwd_kf_enabled(true);
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut altref = false;
+        let mut show_existing = false;
+        for p in encode_all(&mut cfg, 40) {
+            d.decode(&p, None).unwrap();
+            let props = d.stream_properties();
+            assert!(!props.still_picture.unwrap().is_still_picture);
+            // One-pass encoding does not place forward keyframes
+            assert!(props.fwd_kf_present.is_some());
+            altref |= props.altref_present.unwrap();
+            show_existing |= props.show_existing_frame.unwrap();
+        }
+
+        assert!(altref);
+        assert!(show_existing);
+    }
+
+    #[test]
+    fn frame_kinds() {
+        use crate::common::{FrameFlags, FrameKind};
+        use crate::encoder::AV1EncoderConfig;
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(35)
+            .kf_max_dist(30)
+            .fwd_kf_enabled(true);
+
+        let mut e = cfg.get_encoder().unwrap();
+        let format = Arc::new(*formats::YUV420);
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut encoded = Vec::new();
+        let mut decoded = Vec::new();
+
+        let mut drain = |e: &mut crate::encoder::AV1Encoder, d: &mut AV1Decoder<()>| {
+            let before = encoded.len();
+            while let Some((p, info)) = e.get_packet_with_info() {
+                if let AOMPacket::Packet(ref pkt) = p {
+                    let info = info.unwrap();
+                    assert_eq!(pkt.is_key, info.flags.contains(FrameFlags::KEY));
+                    encoded.push(info.kind());
+                    d.decode(&pkt.data, None).unwrap();
+                }
+                while let Some((_, info, _)) = d.get_frame_with_info() {
+                    decoded.push(info.kind());
+                }
+            }
+            encoded.len() > before
+        };
+
+        // Moving content, so the encoder uses altrefs
+        for f in MovingBox::new(64, 64, &format, &t).take(40) {
+            e.encode(&f).unwrap();
+            drain(&mut e, &mut d);
+        }
+
+        e.flush().unwrap();
+        while drain(&mut e, &mut d) {
+            e.flush().unwrap();
+        }
+
+        assert_eq!(encoded.len(), 40);
+        assert_eq!(encoded[0], FrameKind::Key);
+        assert!(encoded.contains(&FrameKind::Hidden));
+        assert_eq!(decoded, encoded);
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn decode_codec_trait_show_existing() {
+        use super::AV1_DESCR;
+        use crate::encoder::AV1EncoderConfig;
+        use av_codec::common::CodecList;
+        use av_codec::decoder::*;
+        use av_codec::error::*;
+        use av_data::packet::Packet;
+
+        let decoders = Codecs::from_list(&[AV1_DESCR]);
+        let mut ctx = Context::by_name(&decoders, "av1").unwrap();
+
+        // Altrefs are shown again through show_existing_frame
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .lag_in_frames(35);
+
+        let mut out = 0;
+
+        for (i, data) in encode_all(&mut cfg, 40).into_iter().enumerate() {
+            let mut pkt = Packet::new();
+            pkt.data = data;
+            pkt.t.pts = Some(i as i64);
+
+            ctx.send_packet(&pkt).unwrap();
+
+            loop {
+                match ctx.receive_frame() {
+                    Ok(_) => out += 1,
+                    Err(Error::MoreDataNeeded) => break,
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+        }
+
+        ctx.flush().unwrap();
+
+        loop {
+            match ctx.receive_frame() {
+                Ok(_) => out += 1,
+                Err(Error::MoreDataNeeded) => break,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+
+        assert_eq!(out, 40);
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn decode_codec_trait_private() {
+        use super::{AV1Descriptor, FramePrivate};
+        use crate::encoder::AV1EncoderConfig;
+        use av_codec::common::CodecList;
+        use av_codec::decoder::*;
+        use av_data::packet::Packet;
+        use std::sync::Arc;
+
+        struct Rotation {
+            angle: u32,
+        }
+
+        impl FramePrivate for Rotation {
+            fn from_packet(pkt: &Packet) -> Self {
+                let angle = pkt
+                    .t
+                    .user_private
+                    .as_ref()
+                    .and_then(|p| p.downcast_ref::<u32>())
+                    .copied()
+                    .unwrap_or_default();
+
+                Rotation { angle }
+            }
+
+            fn apply(self, frame: &mut Frame) {
+                frame.t.user_private = Some(Arc::new(self.angle));
+            }
+        }
+
+        const DESCR: &AV1Descriptor<Rotation> = &AV1Descriptor::new();
+
+        let decoders = Codecs::from_list(&[DESCR]);
+        let mut ctx = Context::by_name(&decoders, "av1").unwrap();
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .lag_in_frames(0);
+
+        let mut angles = Vec::new();
+
+        for (i, data) in encode_all(&mut cfg, 8).into_iter().enumerate() {
+            let mut pkt = Packet::new();
+            pkt.data = data;
+            pkt.t.user_private = Some(Arc::new(i as u32 * 90 % 360));
+
+            ctx.send_packet(&pkt).unwrap();
+
+            while let Ok(f) = ctx.receive_frame() {
+                let angle = f.t.user_private.as_ref().unwrap();
+                angles.push(*angle.downcast_ref::<u32>().unwrap());
+            }
+        }
+
+        assert_eq!(angles, vec![0, 90, 180, 270, 0, 90, 180, 270]);
+    }
+
+    #[test]
+    fn decode_builder() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .lag_in_frames(0);
+
+        let mut d = AV1Decoder::<()>::builder().threads(4).build().unwrap();
+        let mut out = 0;
+
+        for data in encode_all(&mut cfg, 5) {
+            d.decode(&data, None).unwrap();
+            out += d.frames().count();
+        }
+
+        assert_eq!(out, 5);
+    }
+
+    #[test]
+    fn decode_deferred_init_error() {
+        // An ABI mismatch is the only reliable way to make libaom fail
+        let mut d = AV1DecoderBuilder::<()>::new().init(-1);
+
+        let err = d.decode(&[0x12, 0x00], None).unwrap_err();
+        assert_eq!(err.kind(), AomErrorKind::AbiMismatch);
+        assert!(d.get_frame().is_none());
+
+        assert!(AV1Decoder::<()>::default().init_error.is_none());
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn decode_codec_trait_init_error() {
+        use av_codec::decoder::Decoder;
+        use av_codec::error::Error;
+        use av_data::packet::Packet;
+
+        let mut d = AV1DecoderBuilder::<TimeInfo>::new().init(-1);
+        let mut pkt = Packet::new();
+        pkt.data = vec![0x12, 0x00];
+
+        assert!(matches!(d.send_packet(&pkt), Err(Error::Unsupported(_))));
+    }
+
+    #[cfg(feature = "accounting")]
+    #[test]
+    fn decode_accounting() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .limit(1);
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        d.enable_inspection().unwrap();
+
+        for p in encode_all(&mut cfg, 1) {
+            d.decode(&p, None).unwrap();
+        }
+
+        let acct = d.take_accounting();
+        assert_eq!(acct.len(), 1);
+        assert!(acct[0].total_bits() > 0.0);
+        assert!(acct[0].symbols.iter().all(|s| acct[0].name(s).is_some()));
+        assert!(d.take_accounting().is_empty());
+    }
+
+    #[cfg(feature = "accounting")]
+    #[test]
+    fn decode_film_grain_params() {
+        use crate::encoder::AV1EncoderConfig;
+        use crate::ffi::aome_enc_control_id::*;
+        use crate::testing::luma_noise;
+        use av_data::pixel::formats;
+
+        let (w, h) = (64, 64);
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(w)
+            .height(h)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0);
+
+        let mut e = cfg.get_encoder().unwrap();
+        e.control_raw(AV1E_SET_DENOISE_NOISE_LEVEL, 50).unwrap();
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        d.enable_inspection().unwrap();
+        d.set_skip_film_grain(true).unwrap();
+
+        let format = Arc::new(*formats::YUV420);
+        let mut grain = None;
+
+        for i in 0..5 {
+            // Noisy content, so the encoder has some grain to model
+            let mut f = luma_noise(w as usize, h as usize, &format, i as u64, 16);
+            f.t = TimeInfo {
+                pts: Some(i),
+                ..t.clone()
+            };
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(ref pkt) = p {
+                    d.decode(&pkt.data, None).unwrap();
+                    grain = grain.or_else(|| d.film_grain_params());
+                }
+            }
+        }
+
+        let grain = grain.expect("No film grain parameters");
+        assert!(!grain.scaling_points_y.is_empty());
+        assert_eq!(
+            grain.ar_coeffs_y.len(),
+            2 * grain.ar_coeff_lag as usize * (grain.ar_coeff_lag as usize + 1)
+        );
+    }
+
+    #[test]
+    fn reference_round_trip() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000));
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        assert!(d.get_reference(0).is_err());
+
+        for p in encode_all(&mut cfg, 10) {
+            d.decode(&p, None).unwrap();
+        }
+
+        let rows = |img: &AomImage, i| -> Vec<Vec<u8>> {
+            img.plane(i)
+                .chunks(img.stride(i))
+                .map(|r| r[..img.plane_row_bytes(i)].to_vec())
+                .collect()
+        };
+
+        let mut r = d.get_reference(0).unwrap();
+        assert_eq!((r.width(), r.height()), (64, 64));
+
+        for v in r.plane_mut(0).iter_mut() {
+            *v = 0x40;
+        }
+
+        d.set_reference(1, &r).unwrap();
+
+        let back = d.get_reference(1).unwrap();
+        assert_eq!(rows(&back, 0), rows(&r, 0));
+        assert_eq!(rows(&back, 1), rows(&r, 1));
+
+        let mut copy = AomImage::new(r.format(), 64, 64).unwrap();
+        d.copy_reference(1, &mut copy).unwrap();
+        assert_eq!(rows(&copy, 0), rows(&r, 0));
+
+        let small = AomImage::new(r.format(), 16, 16).unwrap();
+        assert_eq!(
+            d.set_reference(1, &small).map_err(|e| e.kind()),
+            Err(AomErrorKind::InvalidParam)
+        );
+
+        let new = d.new_frame_image().unwrap();
+        assert_eq!((new.width(), new.height()), (64, 64));
+
+        let mut copy = AomImage::new_with_border(new.format(), 64, 64, 64).unwrap();
+        d.copy_new_frame_image(&mut copy).unwrap();
+        assert_eq!(rows(&copy, 0), rows(&new, 0));
+    }
+
+    #[test]
+    fn decode_with_frame_pool() {
+        let w = 64;
+        let h = 64;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let pool = FramePool::new();
+
+        let mut e = enc::setup(w, h, &t);
+        let mut f = enc::setup_frame(w, h, &t);
+        let mut d = AV1Decoder::<()>::with_frame_pool(pool.clone()).unwrap();
+
+        let mut out = 0;
+
+        for i in 0..20 {
+            e.encode(&f).unwrap();
+            f.t.pts = Some(i);
+
+            while let Some(AOMPacket::Packet(ref pkt)) = e.get_packet() {
+                d.decode(&pkt.data, None).unwrap();
+
+                while let Some((f, _)) = d.get_frame() {
+                    let v = f.kind.get_video_info().unwrap();
+                    assert_eq!((v.width, v.height), (w as usize, h as usize));
+                    out += 1;
+                }
+            }
+        }
+
+        out += drain(&mut e, &mut d);
+
+        assert_eq!(out, 20);
+
+        let stats = pool.stats();
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, out - 1);
+    }
+
+    #[test]
+    fn frame_pool_capacity() {
+        use av_data::frame::{FrameType, VideoInfo};
+        use av_data::pixel::formats::YUV420;
+
+        let pool = FramePool::with_capacity(2);
+        let info = VideoInfo::new(64, 64, false, FrameType::OTHER, Arc::new(*YUV420));
+
+        let frames: Vec<_> = (0..4).map(|_| pool.get(info.clone())).collect();
+        assert_eq!(pool.stats().misses, 4);
+        drop(frames);
+
+        // The other buffers are freed
+        assert_eq!(pool.free_count(), 2);
+        let _f = pool.get(info);
+        assert_eq!(pool.stats().hits, 1);
+    }
+
+    #[test]
+    fn decode_resolution_change() {
+        use crate::encoder::AV1EncoderConfig;
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats::YUV420;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let format = Arc::new(*YUV420);
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(128)
+            .height(96)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0)
+            .cpu_used(6);
+        let mut e = cfg.get_encoder().unwrap();
+
+        // The second segment is half the size, libaom refuses to grow past
+        // the initial one
+        let mut packets = Vec::new();
+        for (segment, (w, h)) in [(128, 96), (64, 48)].into_iter().enumerate() {
+            if segment > 0 {
+                cfg = cfg.width(w).height(h);
+                e.set_config(&cfg).unwrap();
+            }
+
+            for (i, mut f) in MovingBox::new(w as usize, h as usize, &format, &t)
+                .take(10)
+                .enumerate()
+            {
+                f.t.pts = Some((segment * 10 + i) as i64);
+                e.encode(&f).unwrap();
+                while let Some(p) = e.get_packet() {
+                    if let AOMPacket::Packet(pkt) = p {
+                        packets.push(pkt.data);
+                    }
+                }
+            }
+        }
+
+        let pool = FramePool::new();
+        let mut d = AV1Decoder::<()>::with_frame_pool(pool.clone()).unwrap();
+        let mut frames = Vec::new();
+
+        for p in &packets {
+            d.decode(p, None).unwrap();
+            while let Some((f, info, _)) = d.get_frame_with_info() {
+                let v = f.kind.get_video_info().unwrap();
+                frames.push(((v.width, v.height), info.resolution_changed));
+            }
+        }
+
+        let mut expected = vec![((128, 96), false); 10];
+        expected.extend(vec![((64, 48), false); 10]);
+        expected[10].1 = true;
+        assert_eq!(frames, expected);
+
+        // One allocation per size, the 128x96 buffer is dropped
+        assert_eq!(pool.stats().misses, 2);
+        assert_eq!(pool.free_count(), 1);
+    }
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Pool {
+        free: Vec<Box<[u8]>>,
+        allocated: Arc<AtomicUsize>,
+        in_use: Arc<AtomicUsize>,
+    }
+
+    impl FrameBufferAllocator for Pool {
+        fn get(&mut self, min_size: usize) -> Option<Box<[u8]>> {
+            self.in_use.fetch_add(1, Ordering::SeqCst);
+            match self.free.iter().position(|b| b.len() >= min_size) {
+                Some(i) => Some(self.free.swap_remove(i)),
+                None => {
+                    self.allocated.fetch_add(1, Ordering::SeqCst);
+                    Some(vec![0; min_size].into_boxed_slice())
+                }
+            }
+        }
+
+        fn release(&mut self, buf: Box<[u8]>) {
+            self.in_use.fetch_sub(1, Ordering::SeqCst);
+            self.free.push(buf);
+        }
+    }
+
+    #[test]
+    fn decode_with_allocator() {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats;
+
+        let w = 64;
+        let h = 64;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let pool = Pool::default();
+        let allocated = pool.allocated.clone();
+        let in_use = pool.in_use.clone();
+
+        let mut e = enc::setup(w, h, &t);
+        let frames = MovingBox::new(w as usize, h as usize, &Arc::new(*formats::YUV420), &t);
+        let mut d = AV1Decoder::<()>::with_allocator(pool).unwrap();
+        let mut reference = AV1Decoder::<()>::new().unwrap();
+
+        let mut out = 0;
+
+        for f in frames.take(100) {
+            e.encode(&f).unwrap();
+
+            while let Some(AOMPacket::Packet(ref pkt)) = e.get_packet() {
+                d.decode(&pkt.data, None).unwrap();
+                reference.decode(&pkt.data, None).unwrap();
+
+                while let Some((f, _)) = d.get_frame() {
+                    let (r, _) = reference.get_frame().unwrap();
+                    let v = f.kind.get_video_info().unwrap();
+
+                    // The strides may differ, every visible sample must not
+                    for (i, c) in v.format.iter().flatten().enumerate() {
+                        let (cw, ch) = (c.get_width(v.width), c.get_height(v.height));
+                        let rows = |f: &Frame| -> Vec<Vec<u8>> {
+                            let stride = f.buf.linesize(i).unwrap();
+                            let data: &[u8] = f.buf.as_slice(i).unwrap();
+                            data.chunks(stride)
+                                .take(ch)
+                                .map(|row| row[..cw].to_vec())
+                                .collect()
+                        };
+                        assert_eq!(rows(&f), rows(&r), "plane {}", i);
+                    }
+                    out += 1;
+                }
+            }
+        }
+
+        out += drain(&mut e, &mut d);
+
+        assert_eq!(out, 100);
+        // libaom keeps at most its references and work buffers around
+        assert!(allocated.load(Ordering::SeqCst) <= 16);
+
+        drop(d);
+        assert_eq!(in_use.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn decode_byte_alignment() {
+        let t = TimeInfo {
+            pts: Some(0),
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        let mut e = enc::setup(66, 50, &t);
+        e.encode(&enc::setup_frame(66, 50, &t)).unwrap();
+        let packets = e.close().unwrap();
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        for align in [1, 16, 48, 2048] {
+            let err = d.set_byte_alignment(align).unwrap_err();
+            assert!(err.kind().is_invalid_param());
+        }
+        d.set_byte_alignment(256).unwrap();
+
+        for p in &packets {
+            if let AOMPacket::Packet(pkt) = p {
+                d.decode(&pkt.data, None).unwrap();
+            }
+        }
+
+        // The images of libaom, get_frame returns copies
+        let img = unsafe { aom_codec_get_frame(&mut d.ctx, &mut d.iter) };
+        assert!(!img.is_null());
+        let img = unsafe { &*img };
+        for plane in &img.planes {
+            assert_eq!(*plane as usize % 256, 0);
+        }
+
+        assert!(d.set_byte_alignment(0).is_err());
+    }
+
+    #[test]
+    fn frame_from_invalid_img() {
+        use av_data::frame::MediaKind;
+
+        let mut data: Vec<u8> = (0..64 * 64 * 2).map(|i| i as u8).collect();
+        let mut img: aom_image_t = unsafe { zeroed() };
+
+        img.fmt = aom_img_fmt::AOM_IMG_FMT_I42016;
+        img.d_w = 64;
+        img.d_h = 64;
+        img.x_chroma_shift = 1;
+        img.y_chroma_shift = 1;
+        img.planes = [data.as_mut_ptr(); 3];
+        img.bit_depth = 8;
+        img.stride = [128, 64, 64];
+        assert!(frame_from_img(img, FrameType::I, None).is_some());
+
+        let mut unknown = img;
+        unknown.fmt = aom_img_fmt::AOM_IMG_FMT_NV12;
+        assert!(frame_from_img(unknown, FrameType::I, None).is_none());
+
+        let mut negative = img;
+        negative.stride[0] = -128;
+        assert!(frame_from_img(negative, FrameType::I, None).is_none());
+
+        let mut short = img;
+        short.stride[1] = 32;
+        assert!(frame_from_img(short, FrameType::I, None).is_none());
+
+        let mut null = img;
+        null.planes[2] = ptr::null_mut();
+        assert!(frame_from_img(null, FrameType::I, None).is_none());
+
+        let mut odd_depth = img;
+        odd_depth.bit_depth = 9;
+        assert!(frame_from_img(odd_depth, FrameType::I, None).is_none());
+
+        // The 10-bit samples are kept in 16 bits
+        let mut deep = img;
+        deep.bit_depth = 10;
+        let f = frame_from_img(deep, FrameType::I, None).unwrap();
+        let v = match f.kind {
+            MediaKind::Video(ref v) => v,
+            _ => unreachable!(),
+        };
+        assert_eq!(v.format.get_chromaton(0).unwrap().get_depth(), 10);
+        assert_eq!(v.format.get_chromaton(2).unwrap().get_depth(), 10);
+        let luma: &[u8] = f.buf.as_slice(0).unwrap();
+        assert_eq!(luma[..128], data[..128]);
+    }
+
+    // Decode a lossless frame and compare the display rectangle of every plane
+    fn lossless_roundtrip(w: u32, h: u32) {
+        use crate::encoder::AV1EncoderConfig;
+        use crate::testing::moving_box;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(w)
+            .height(h)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0);
+        let mut e = cfg.get_encoder().unwrap();
+        e.control_raw(aome_enc_control_id::AV1E_SET_LOSSLESS, 1)
+            .unwrap();
+
+        let mut f = moving_box(w as usize, h as usize, &Arc::new(*formats::YUV420), 0);
+        f.t = t;
+
+        e.encode(&f).unwrap();
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        while let Some(p) = e.get_packet() {
+            if let AOMPacket::Packet(pkt) = p {
+                d.decode(&pkt.data, None).unwrap();
+            }
+        }
+
+        let (out, _) = d.get_frame().unwrap();
+        match out.kind {
+            av_data::frame::MediaKind::Video(ref v) => {
+                assert_eq!((v.width, v.height), (w as usize, h as usize));
+            }
+            _ => panic!("Not a video frame"),
+        }
+
+        let (cw, ch) = ((w as usize).div_ceil(2), (h as usize).div_ceil(2));
+        for (p, (pw, ph)) in [(w as usize, h as usize), (cw, ch), (cw, ch)]
+            .into_iter()
+            .enumerate()
+        {
+            let src: &[u8] = f.buf.as_slice(p).unwrap();
+            let src_stride = f.buf.linesize(p).unwrap();
+            let dst: &[u8] = out.buf.as_slice(p).unwrap();
+            let dst_stride = out.buf.linesize(p).unwrap();
+
+            for y in 0..ph {
+                assert_eq!(
+                    src[y * src_stride..y * src_stride + pw],
+                    dst[y * dst_stride..y * dst_stride + pw],
+                    "plane {} row {}",
+                    p,
+                    y
+                );
+                // Nothing past the display width
+                assert!(dst[y * dst_stride + pw..(y + 1) * dst_stride]
+                    .iter()
+                    .all(|&v| v == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn decode_lossless_planes() {
+        lossless_roundtrip(64, 48);
+    }
+
+    #[test]
+    fn decode_cropped() {
+        lossless_roundtrip(354, 288);
+        lossless_roundtrip(355, 289);
+    }
+
+    #[test]
+    fn decode_without_get_frame() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0);
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = enc::setup_frame(64, 64, &t);
+        let mut d = AV1Decoder::<i64>::new().unwrap();
+
+        for i in 0..5 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    d.decode(&pkt.data, i).unwrap();
+                }
+            }
+        }
+
+        let private: Vec<_> = d.frames().map(|(_, p)| *p.unwrap()).collect();
+        assert_eq!(private, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn decode_garbage() {
+        use crate::testing::random_bytes;
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut bytes = random_bytes(7);
+
+        for len in [1, 2, 16, 300, 4096] {
+            let data: Vec<u8> = bytes.by_ref().take(len).collect();
+
+            let _ = d.decode(&data, None);
+            while d.get_frame().is_some() {}
+        }
+
+        let _ = d.flush();
+        while d.get_frame().is_some() {}
+    }
+
+    #[test]
+    fn decode_after_corrupt_frame() {
+        use crate::common::AomError;
+        use crate::encoder::AV1EncoderConfig;
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .lag_in_frames(0)
+            .kf_min_dist(5)
+            .kf_max_dist(5);
+        let packets = encode_all(&mut cfg, 10);
+        assert_eq!(packets.len(), 10);
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        d.decode(&packets[0], None).unwrap();
+        assert!(d.get_frame().is_some());
+
+        // The frame OBU claims more data than the packet holds
+        let garbage = &packets[1][..packets[1].len() / 2];
+        let err = d.decode(garbage, None).err().unwrap();
+        assert!(matches!(err, AomError::CorruptFrame { .. }));
+        assert!(err.kind().is_corrupt_frame());
+        assert!(err.is_recoverable());
+        while d.get_frame().is_some() {}
+
+        let mut frames = 0;
+        for p in &packets[1..] {
+            d.decode(p, None).unwrap();
+            frames += d.frames().count();
+        }
+
+        // At the latest from the next keyframe on
+        assert!(frames >= 5);
+    }
+
+    #[test]
+    fn decode_tolerant() {
+        use crate::encoder::AV1EncoderConfig;
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .lag_in_frames(0);
+        let packets = encode_all(&mut cfg, 10);
+
+        let mut d = AV1Decoder::<()>::builder().tolerant(true).build().unwrap();
+        let mut frames = 0;
+        for p in &packets {
+            d.decode(p, None).unwrap();
+            frames += d.frames().count();
+            d.decode(&p[..p.len() / 2], None).unwrap();
+            frames += d.frames().count();
+        }
+
+        assert_eq!(frames, 10);
+        assert_eq!(d.corruption_count(), 10);
+
+        // Not tolerant by default
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        d.decode(&packets[0], None).unwrap();
+        assert!(d.decode(&packets[1][..packets[1].len() / 2], None).is_err());
+        assert_eq!(d.corruption_count(), 0);
+    }
+
+    // FNV-1a of the displayed samples of every plane, the 16-bit ones in
+    // little endian
+    fn frame_hash(f: &Frame) -> u64 {
+        let v = f.kind.get_video_info().unwrap();
+        let deep = v.format.get_chromaton(0).unwrap().get_depth() > 8;
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let mut update = |b: u8| hash = (hash ^ b as u64).wrapping_mul(0x100_0000_01b3);
+
+        for (i, c) in v.format.iter().flatten().enumerate() {
+            let stride = f.buf.linesize(i).unwrap();
+            let data: &[u8] = f.buf.as_slice(i).unwrap();
+            let (w, h) = (c.get_width(v.width), c.get_height(v.height));
+
+            for row in data.chunks(stride).take(h) {
+                if deep {
+                    for s in row[..w * 2].chunks_exact(2) {
+                        u16::from_ne_bytes([s[0], s[1]])
+                            .to_le_bytes()
+                            .into_iter()
+                            .for_each(&mut update);
+                    }
+                } else {
+                    row[..w].iter().copied().for_each(&mut update);
+                }
+            }
+        }
+
+        hash
+    }
+
+    /// Decode the same frames coded as Annex B temporal units
+    #[test]
+    fn decode_annexb() {
+        use crate::encoder::AV1EncoderConfig;
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats::YUV420;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0)
+            .cpu_used(8);
+
+        let decode = |annexb: bool| {
+            let mut e = cfg.clone().save_as_annexb(annexb).get_encoder().unwrap();
+            let mut d = AV1Decoder::<()>::new().unwrap();
+            d.control_raw(aom_dec_control_id::AV1D_SET_IS_ANNEXB, annexb as i32)
+                .unwrap();
+
+            for f in MovingBox::new(64, 64, &Arc::new(*YUV420), &t).take(3) {
+                e.encode(&f).unwrap();
+            }
+
+            let mut hashes = Vec::new();
+            for p in e.close().unwrap() {
+                if let AOMPacket::Packet(pkt) = p {
+                    d.decode(&pkt.data, None).unwrap();
+                    hashes.extend(d.frames().map(|(f, _)| frame_hash(&f)));
+                }
+            }
+
+            hashes
+        };
+
+        let hashes = decode(true);
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes, decode(false));
     }
 }
@@ -0,0 +1,183 @@
+#![deny(missing_docs)]
+
+use crate::common::{FrameFlags, FrameKind};
+use crate::decoder::AV1Decoder;
+use crate::ffi::*;
+
+/// Still picture signaling from the sequence header
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StillPicture {
+    /// The stream contains a single picture
+    pub is_still_picture: bool,
+    /// The sequence header uses the reduced still picture syntax
+    pub is_reduced_still_picture_hdr: bool,
+}
+
+/// Switch frame information of the last decoded frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SFrameInfo {
+    /// The frame is an S-Frame
+    pub is_s_frame: bool,
+    /// The S-Frame is coded at an altref position
+    pub is_s_frame_at_altref: bool,
+}
+
+/// Screen content tools enabled for the last decoded frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScreenContentTools {
+    /// Screen content tools are allowed
+    pub allow_screen_content_tools: bool,
+    /// Intra block copy is allowed
+    pub allow_intrabc: bool,
+    /// Motion vectors are forced to integer precision
+    pub force_integer_mv: bool,
+}
+
+/// Superblock size
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuperblockSize {
+    /// 64x64 superblocks
+    Size64x64,
+    /// 128x128 superblocks
+    Size128x128,
+    /// Size selected per frame, only meaningful on the encoder side
+    Dynamic,
+}
+
+impl SuperblockSize {
+    fn from_aom(val: aom_superblock_size_t) -> Option<SuperblockSize> {
+        use self::aom_superblock_size::*;
+
+        match val {
+            AOM_SUPERBLOCK_SIZE_64X64 => Some(SuperblockSize::Size64x64),
+            AOM_SUPERBLOCK_SIZE_128X128 => Some(SuperblockSize::Size128x128),
+            AOM_SUPERBLOCK_SIZE_DYNAMIC => Some(SuperblockSize::Dynamic),
+            _ => None,
+        }
+    }
+}
+
+/// Per-frame information reported alongside a decoded frame
+///
+/// Returned by [`get_frame_with_info`].
+///
+/// [`get_frame_with_info`]: AV1Decoder::get_frame_with_info
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodedFrameInfo {
+    /// Spatial layer of the frame, from its OBU extension header
+    pub spatial_id: u32,
+    /// Temporal layer of the frame, from its OBU extension header
+    pub temporal_id: u32,
+    /// The frame was output through `show_existing_frame`
+    ///
+    /// It matches `AOMD_GET_SHOW_EXISTING_FRAME_FLAG`.
+    pub show_existing_frame: bool,
+    /// The decoder found the frame corrupted
+    ///
+    /// It matches `AOMD_GET_FRAME_CORRUPTED`.
+    pub corrupted: bool,
+    /// It matches `AOMD_GET_FRAME_FLAGS`
+    pub flags: FrameFlags,
+    /// The frame size differs from the previous frame of the same spatial
+    /// layer
+    ///
+    /// The size can change at keyframes, or on any frame with resize and
+    /// superres. It is not set on the first frame.
+    pub resolution_changed: bool,
+}
+
+impl DecodedFrameInfo {
+    /// How the frame is coded, see [`FrameKind`]
+    pub fn kind(&self) -> FrameKind {
+        FrameKind::classify(self.flags, self.show_existing_frame)
+    }
+}
+
+/// Bitstream properties reported by the decoder
+///
+/// Most of them describe the last temporal unit sent to [`decode`], each
+/// field is `None` if libaom could not report it (e.g. nothing has been
+/// decoded yet).
+///
+/// [`decode`]: AV1Decoder::decode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamProperties {
+    /// It matches `AOMD_GET_STILL_PICTURE`
+    pub still_picture: Option<StillPicture>,
+    /// A forward keyframe is present, it matches `AOMD_GET_FWD_KF_PRESENT`
+    pub fwd_kf_present: Option<bool>,
+    /// An altref frame is present, it matches `AOMD_GET_ALTREF_PRESENT`
+    pub altref_present: Option<bool>,
+    /// It matches `AOMD_GET_SB_SIZE`
+    pub superblock_size: Option<SuperblockSize>,
+    /// The last frame was shown through `show_existing_frame`
+    ///
+    /// It matches `AOMD_GET_SHOW_EXISTING_FRAME_FLAG`.
+    pub show_existing_frame: Option<bool>,
+    /// It matches `AOMD_GET_S_FRAME_INFO`
+    pub s_frame: Option<SFrameInfo>,
+    /// It matches `AOMD_GET_SCREEN_CONTENT_TOOLS_INFO`
+    pub screen_content_tools: Option<ScreenContentTools>,
+}
+
+impl<T> AV1Decoder<T> {
+    /// Query the bitstream properties of the stream being decoded
+    pub fn stream_properties(&mut self) -> StreamProperties {
+        use self::aom_dec_control_id::*;
+
+        unsafe {
+            StreamProperties {
+                still_picture: self
+                    .query::<aom_still_picture_info>(AOMD_GET_STILL_PICTURE)
+                    .map(|v| StillPicture {
+                        is_still_picture: v.is_still_picture != 0,
+                        is_reduced_still_picture_hdr: v.is_reduced_still_picture_hdr != 0,
+                    }),
+                fwd_kf_present: self.query::<i32>(AOMD_GET_FWD_KF_PRESENT).map(|v| v != 0),
+                altref_present: self.query::<i32>(AOMD_GET_ALTREF_PRESENT).map(|v| v != 0),
+                superblock_size: self
+                    .query::<aom_superblock_size_t>(AOMD_GET_SB_SIZE)
+                    .and_then(SuperblockSize::from_aom),
+                show_existing_frame: self
+                    .query::<i32>(AOMD_GET_SHOW_EXISTING_FRAME_FLAG)
+                    .map(|v| v != 0),
+                s_frame: self
+                    .query::<aom_s_frame_info>(AOMD_GET_S_FRAME_INFO)
+                    .map(|v| SFrameInfo {
+                        is_s_frame: v.is_s_frame != 0,
+                        is_s_frame_at_altref: v.is_s_frame_at_altref != 0,
+                    }),
+                screen_content_tools: self
+                    .query::<aom_screen_content_tools_info>(AOMD_GET_SCREEN_CONTENT_TOOLS_INFO)
+                    .map(|v| ScreenContentTools {
+                        allow_screen_content_tools: v.allow_screen_content_tools != 0,
+                        allow_intrabc: v.allow_intrabc != 0,
+                        force_integer_mv: v.force_integer_mv != 0,
+                    }),
+            }
+        }
+    }
+
+    // Like the frame flags, the controls describe the last decoded frame and
+    // hold for every image of the same decode call.
+    pub(crate) fn frame_info(&mut self, img: &aom_image_t) -> DecodedFrameInfo {
+        use self::aom_dec_control_id::*;
+
+        unsafe {
+            DecodedFrameInfo {
+                spatial_id: img.spatial_id.max(0) as u32,
+                temporal_id: img.temporal_id.max(0) as u32,
+                show_existing_frame: self
+                    .query::<i32>(AOMD_GET_SHOW_EXISTING_FRAME_FLAG)
+                    .is_some_and(|v| v != 0),
+                corrupted: self
+                    .query::<i32>(AOMD_GET_FRAME_CORRUPTED)
+                    .is_some_and(|v| v != 0),
+                flags: self
+                    .query::<i32>(AOMD_GET_FRAME_FLAGS)
+                    .map_or(FrameFlags::empty(), |v| FrameFlags::from_bits(v as _)),
+                resolution_changed: false,
+            }
+        }
+    }
+}
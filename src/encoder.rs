@@ -2,43 +2,307 @@
 //!
 //!
 
-pub use crate::encoder_config::{AV1EncoderConfig, AomUsage, BitstreamProfile, TileCodingMode};
+#[allow(deprecated)]
+pub use crate::encoder_config::to_aom_rational;
+pub use crate::encoder_config::{
+    rational_to_raw, supported_usages, AV1EncoderConfig, AomUsage, BitDepth, BitstreamProfile,
+    ComplexityBudget, ControlValue, EncodePass, KeyframeFiltering, KeyframeMode, RateControl,
+    RealtimeOptions, SuperresMode, TileCodingMode, Timebase, TrellisQuant,
+};
+pub use crate::encoder_keyframes::KeyframePolicy;
+pub use crate::encoder_log::FrameLogEntry;
+pub use crate::encoder_pipeline::{FrameSender, PacketReceiver};
+pub use crate::encoder_references::{Reference, ReferenceControl};
+pub use crate::encoder_simulcast::Simulcast;
+pub use crate::encoder_stats::{EncoderStats, StatsSnapshot};
+pub use crate::encoder_tools::{CodingTool, EncoderCfgOptions};
+pub use crate::encoder_twopass::TwoPassStats;
 
-use crate::common::AOMCodec;
+use crate::common::{
+    check, destroy, destroy_on_drop, AOMCodec, AomError, AomErrorKind, AomImage, AomImageView,
+    FrameFlags, FrameKind,
+};
+use crate::encoder_config::validate_control;
+use crate::encoder_keyframes::KeyframeScheduler;
+use crate::encoder_log::FrameLog;
 use crate::ffi::*;
+use crate::image::{img_to_frame, is_high_bitdepth, new_frame};
+#[cfg(any(test, feature = "metrics"))]
+use crate::metrics::{self, QualityReport};
+use crate::obu::{ObuIter, ObuType, SequenceHeader};
 
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CString;
 use std::mem::{self, MaybeUninit};
+use std::ops::{BitOr, BitOrAssign};
+use std::os::raw;
 use std::ptr;
+use std::slice;
 
-use av_data::frame::{Frame, FrameBufferConv, MediaKind};
+use av_data::frame::{Frame, FrameBufferConv, FrameType, MediaKind};
 use av_data::packet::Packet;
-use av_data::pixel::formats::YUV420;
-use av_data::pixel::Formaton;
+use av_data::pixel::formats::{YUV420, YUV422, YUV444};
+use av_data::pixel::{
+    ChromaLocation, Chromaton, ColorPrimaries, Formaton, MatrixCoefficients, TransferCharacteristic,
+};
+use av_data::rational::Rational64;
 
+/// Quality of an encoded frame, as reported by libaom
+///
+/// The arrays are indexed as total, Y, U and V. For high bit depth input
+/// the values are computed at the input bit depth: the `psnr_hbd` arrays
+/// libaom keeps internally are not part of the `aom_psnr_pkt` of the bound
+/// headers, fields may be added once they are.
 #[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub struct PSNR {
+    /// Number of samples compared
     pub samples: [u32; 4],
+    /// Sum of the squared errors
     pub sse: [u64; 4],
+    /// Peak signal to noise ratio, in dB
     pub psnr: [f64; 4],
 }
 
+/// Per-frame encoding flags
+///
+/// They match the `AOM_EFLAG_*` constants and can be combined with `|`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct EncodeFlags(aom_enc_frame_flags_t);
+
+macro_rules! encode_flags {
+    ($($(#[$doc:meta])* $name:ident = $val:ident,)*) => {
+        impl EncodeFlags {
+            $(
+                $(#[$doc])*
+                pub const $name: EncodeFlags = EncodeFlags($val as aom_enc_frame_flags_t);
+            )*
+        }
+    };
+}
+
+encode_flags! {
+    /// Force the frame to be a keyframe
+    FORCE_KF = AOM_EFLAG_FORCE_KF,
+    /// Do not reference the last frame
+    NO_REF_LAST = AOM_EFLAG_NO_REF_LAST,
+    /// Do not reference the last2 frame
+    NO_REF_LAST2 = AOM_EFLAG_NO_REF_LAST2,
+    /// Do not reference the last3 frame
+    NO_REF_LAST3 = AOM_EFLAG_NO_REF_LAST3,
+    /// Do not reference the golden frame
+    NO_REF_GF = AOM_EFLAG_NO_REF_GF,
+    /// Do not reference the altref frame
+    NO_REF_ARF = AOM_EFLAG_NO_REF_ARF,
+    /// Do not reference the backward frame
+    NO_REF_BWD = AOM_EFLAG_NO_REF_BWD,
+    /// Do not reference the altref2 frame
+    NO_REF_ARF2 = AOM_EFLAG_NO_REF_ARF2,
+    /// Do not update the last frame
+    NO_UPD_LAST = AOM_EFLAG_NO_UPD_LAST,
+    /// Do not update the golden frame
+    NO_UPD_GF = AOM_EFLAG_NO_UPD_GF,
+    /// Do not update the altref frame
+    NO_UPD_ARF = AOM_EFLAG_NO_UPD_ARF,
+    /// Do not update the entropy contexts
+    NO_UPD_ENTROPY = AOM_EFLAG_NO_UPD_ENTROPY,
+    /// Do not use the motion vectors of the reference frames
+    NO_REF_FRAME_MVS = AOM_EFLAG_NO_REF_FRAME_MVS,
+    /// Code the frame in error resilient mode
+    ERROR_RESILIENT = AOM_EFLAG_ERROR_RESILIENT,
+    /// Code the frame as a switch frame
+    SET_S_FRAME = AOM_EFLAG_SET_S_FRAME,
+    /// Do not use a primary reference frame
+    SET_PRIMARY_REF_NONE = AOM_EFLAG_SET_PRIMARY_REF_NONE,
+}
+
+impl EncodeFlags {
+    /// No flag set
+    pub const fn empty() -> EncodeFlags {
+        EncodeFlags(0)
+    }
+
+    /// Raw `aom_enc_frame_flags_t` value
+    pub const fn bits(self) -> aom_enc_frame_flags_t {
+        self.0
+    }
+
+    /// Wrap a raw `aom_enc_frame_flags_t` value
+    pub const fn from_bits(bits: aom_enc_frame_flags_t) -> EncodeFlags {
+        EncodeFlags(bits)
+    }
+
+    /// All the flags of `other` are set
+    pub const fn contains(self, other: EncodeFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for EncodeFlags {
+    type Output = EncodeFlags;
+
+    fn bitor(self, rhs: EncodeFlags) -> EncodeFlags {
+        EncodeFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for EncodeFlags {
+    fn bitor_assign(&mut self, rhs: EncodeFlags) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Frame counters of an encoder
+///
+/// Returned by [`AV1Encoder::stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Frames sent to the encoder
+    pub frames_submitted: u64,
+    /// Compressed frame packets retrieved
+    pub packets_emitted: u64,
+    /// Frames consumed by the encoder without producing a packet
+    ///
+    /// A frame is known to be dropped once a packet with a later timestamp
+    /// is emitted or, if `rc_dropframe_thresh` is set, once the encoder
+    /// holds more frames than its lag after every packet is retrieved.
+    pub frames_dropped: u64,
+    /// Frames dropped between the last emitted packet and the previous one
+    pub dropped_before_last: u64,
+}
+
+/// Per-packet information about the coded frames
+///
+/// Returned by [`AV1Encoder::get_packet_with_info`] for the compressed
+/// frame packets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EncodedFrameInfo {
+    /// The `AOM_FRAME_*` flags of the packet
+    pub flags: FrameFlags,
+    /// The packet shows a frame coded earlier through `show_existing_frame`
+    pub show_existing_frame: bool,
+    /// The packet also carries frames that are not shown, e.g. an altref
+    /// coded ahead of the frame shown by the temporal unit
+    pub hidden_frames: u32,
+}
+
+// Whether the frame header starting `payload` shows a frame: either
+// show_existing_frame or, after the frame_type, show_frame is set. A
+// reduced still picture header has neither, its frame is shown.
+fn frame_shown(payload: &[u8], reduced_still_picture_header: bool) -> bool {
+    match payload.first() {
+        _ if reduced_still_picture_header => true,
+        Some(b) => b & 0x80 != 0 || b & 0x10 != 0,
+        None => true,
+    }
+}
+
+impl EncodedFrameInfo {
+    // A frame header without tile data can only show an existing frame,
+    // libaom ends the temporal units with the shown frame.
+    fn new(flags: aom_codec_frame_flags_t, data: &[u8], annexb: bool) -> Self {
+        let obus = if annexb {
+            ObuIter::annexb(data)
+        } else {
+            ObuIter::new(data)
+        };
+        let mut reduced_still_picture_header = false;
+        let mut hidden_frames = 0;
+        let mut last = None;
+
+        for obu in obus.filter_map(Result::ok) {
+            match obu.header.obu_type {
+                ObuType::SequenceHeader => {
+                    reduced_still_picture_header = SequenceHeader::parse_payload(obu.payload)
+                        .is_ok_and(|sh| sh.reduced_still_picture_header);
+                }
+                t @ (ObuType::FrameHeader | ObuType::Frame) => {
+                    if !frame_shown(obu.payload, reduced_still_picture_header) {
+                        hidden_frames += 1;
+                    }
+                    last = Some(t);
+                }
+                ObuType::TileGroup => last = Some(ObuType::TileGroup),
+                _ => {}
+            }
+        }
+
+        EncodedFrameInfo {
+            flags: FrameFlags::from_bits(flags),
+            show_existing_frame: last == Some(ObuType::FrameHeader),
+            hidden_frames,
+        }
+    }
+
+    /// How the shown frame is coded, see [`FrameKind`]
+    pub fn kind(&self) -> FrameKind {
+        FrameKind::classify(self.flags, self.show_existing_frame)
+    }
+
+    /// The packet carries a frame that is not shown
+    ///
+    /// Containers flagging such frames, e.g. Matroska, can tell them apart
+    /// even if libaom bundles them with the next shown frame.
+    pub fn has_hidden_frame(&self) -> bool {
+        self.hidden_frames > 0
+    }
+
+    /// No other frame references the frames of the packet
+    ///
+    /// It matches `AOM_FRAME_IS_DROPPABLE`.
+    pub fn is_droppable(&self) -> bool {
+        self.flags.contains(FrameFlags::DROPPABLE)
+    }
+}
+
 /// Safe wrapper around `aom_codec_cx_pkt`
+///
+/// The metadata attached to the images, e.g. with `aom_img_add_metadata`,
+/// has no packet kind of its own: libaom writes it as OBUs in the
+/// compressed frame packets.
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum AOMPacket {
+    /// Compressed frame, `AOM_CODEC_CX_FRAME_PKT`
     Packet(Packet),
+    /// Two-pass statistics of a first pass, `AOM_CODEC_STATS_PKT`
     Stats(Vec<u8>),
+    /// First pass macroblock statistics, `AOM_CODEC_FPMB_STATS_PKT`
     MBStats(Vec<u8>),
+    /// Quality of a frame, `AOM_CODEC_PSNR_PKT`, with `AOM_CODEC_USE_PSNR`
     PSNR(PSNR),
+    /// Algorithm specific data, `AOM_CODEC_CUSTOM_PKT`
+    ///
+    /// The AV1 encoder of libaom does not output any, the kind is kept
+    /// for the encoders built on the same interface.
     Custom(Vec<u8>),
+    /// Packet of a kind this crate does not know about
+    ///
+    /// `data` holds the raw bytes of the packet payload union.
+    Unknown {
+        /// The raw `aom_codec_cx_pkt_kind`
+        kind: u32,
+        /// The payload union, as bytes
+        data: Vec<u8>,
+    },
 }
 
-fn to_buffer(buf: aom_fixed_buf_t) -> Vec<u8> {
-    let mut v: Vec<u8> = Vec::with_capacity(buf.sz);
-    unsafe {
-        ptr::copy_nonoverlapping(buf.buf as *const u8, v.as_mut_ptr(), buf.sz);
-        v.set_len(buf.sz);
+/// View the packet data as a slice, empty if libaom returned no buffer
+///
+/// # Safety
+///
+/// A non-null `buf` must be valid for `sz` bytes while the slice is in use.
+unsafe fn packet_data<'a>(buf: *const raw::c_void, sz: usize) -> &'a [u8] {
+    if buf.is_null() || sz == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(buf as *const u8, sz)
     }
-    v
+}
+
+fn to_buffer(buf: aom_fixed_buf_t) -> Vec<u8> {
+    unsafe { packet_data(buf.buf, buf.sz) }.to_vec()
 }
 
 impl AOMPacket {
@@ -46,12 +310,15 @@ impl AOMPacket {
         match pkt.kind {
             aom_codec_cx_pkt_kind::AOM_CODEC_CX_FRAME_PKT => {
                 let f = unsafe { pkt.data.frame };
-                let mut p = Packet::with_capacity(f.sz);
-                unsafe {
-                    ptr::copy_nonoverlapping(f.buf as *const u8, p.data.as_mut_ptr(), f.sz);
-                    p.data.set_len(f.sz);
-                }
+                let data = unsafe { packet_data(f.buf, f.sz) };
+                let mut p = Packet::with_capacity(data.len());
+                p.data.extend_from_slice(data);
                 p.t.pts = Some(f.pts);
+                // The duration is a c_ulong, 32-bit on Windows
+                #[allow(clippy::unnecessary_cast)]
+                {
+                    p.t.duration = Some(f.duration as u64);
+                }
                 p.is_key = (f.flags & AOM_FRAME_IS_KEY) != 0;
 
                 AOMPacket::Packet(p)
@@ -77,7 +344,36 @@ impl AOMPacket {
                 let b = to_buffer(unsafe { pkt.data.raw });
                 AOMPacket::Custom(b)
             }
-            _ => panic!("No packet defined"),
+            // The enums are signed on Windows
+            #[allow(clippy::unnecessary_cast)]
+            kind => AOMPacket::Unknown {
+                kind: kind as u32,
+                data: unsafe { pkt.data.pad }.iter().map(|&b| b as u8).collect(),
+            },
+        }
+    }
+
+    /// The raw `aom_codec_cx_pkt_kind` of the packet
+    // The enums are signed on Windows
+    #[allow(clippy::unnecessary_cast)]
+    pub fn kind(&self) -> u32 {
+        match self {
+            AOMPacket::Packet(_) => aom_codec_cx_pkt_kind::AOM_CODEC_CX_FRAME_PKT as u32,
+            AOMPacket::Stats(_) => aom_codec_cx_pkt_kind::AOM_CODEC_STATS_PKT as u32,
+            AOMPacket::MBStats(_) => aom_codec_cx_pkt_kind::AOM_CODEC_FPMB_STATS_PKT as u32,
+            AOMPacket::PSNR(_) => aom_codec_cx_pkt_kind::AOM_CODEC_PSNR_PKT as u32,
+            AOMPacket::Custom(_) => aom_codec_cx_pkt_kind::AOM_CODEC_CUSTOM_PKT as u32,
+            AOMPacket::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// The bytes carried by the packet, `None` for [`AOMPacket::PSNR`]
+    pub fn payload(&self) -> Option<&[u8]> {
+        match self {
+            AOMPacket::Packet(p) => Some(&p.data),
+            AOMPacket::Stats(b) | AOMPacket::MBStats(b) | AOMPacket::Custom(b) => Some(b),
+            AOMPacket::Unknown { data, .. } => Some(data),
+            AOMPacket::PSNR(_) => None,
         }
     }
 }
@@ -96,44 +392,181 @@ fn map_fmt_to_img(img: &mut aom_image, fmt: &Formaton) {
     img.mc = fmt.get_matrix() as u32;
 }
 
+// The format without its color description
+fn layout(fmt: &Formaton) -> Formaton {
+    Formaton {
+        primaries: ColorPrimaries::Unspecified,
+        xfer: TransferCharacteristic::Unspecified,
+        matrix: MatrixCoefficients::Unspecified,
+        chroma_location: ChromaLocation::Unspecified,
+        ..*fmt
+    }
+}
+
+// The format as an 8-bit one, the chroma of the av-data 10-bit formats
+// does not have the depth and offsets of its 8-bit counterpart
+fn plain_layout(fmt: &Formaton) -> Formaton {
+    let mut f = layout(fmt);
+
+    for (i, c) in f.comp_info.iter_mut().enumerate() {
+        if let Some(c) = c {
+            *c = Chromaton {
+                depth: 8,
+                comp_offs: i as u8,
+                ..*c
+            };
+        }
+    }
+
+    f
+}
+
 // TODO: Extend
-fn map_formaton(img: &mut aom_image, fmt: &Formaton) {
-    if fmt == YUV420 {
-        img.fmt = aom_img_fmt::AOM_IMG_FMT_I420;
+fn map_formaton(img: &mut aom_image, fmt: &Formaton) -> Result<(), AomError> {
+    use aom_img_fmt::*;
+
+    let depth = fmt.get_chromaton(0).map_or(8, |c| c.get_depth());
+    let high = match depth {
+        8 => false,
+        10 | 12 => true,
+        _ => {
+            return Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "unsupported frame bit depth",
+            ))
+        }
+    };
+
+    let plain = plain_layout(fmt);
+    let (aom_fmt, high_fmt, bps, x_chroma_shift, y_chroma_shift) = if plain == *YUV420 {
+        (AOM_IMG_FMT_I420, AOM_IMG_FMT_I42016, 12, 1, 1)
+    } else if plain == *YUV422 {
+        (AOM_IMG_FMT_I422, AOM_IMG_FMT_I42216, 16, 1, 0)
+    } else if plain == *YUV444 {
+        (AOM_IMG_FMT_I444, AOM_IMG_FMT_I44416, 24, 0, 0)
     } else {
-        unimplemented!();
-    }
-    img.bit_depth = 8;
-    img.bps = 12;
-    img.x_chroma_shift = 1;
-    img.y_chroma_shift = 1;
+        return Err(AomError::new(
+            AomErrorKind::InvalidParam,
+            "unsupported frame pixel format",
+        ));
+    };
+    img.fmt = if high { high_fmt } else { aom_fmt };
+    img.bit_depth = depth as u32;
+    // The samples deeper than 8 bits take 2 bytes
+    img.bps = if high { bps * 2 } else { bps };
+    img.x_chroma_shift = x_chroma_shift;
+    img.y_chroma_shift = y_chroma_shift;
     map_fmt_to_img(img, fmt);
+
+    Ok(())
 }
 
-fn img_from_frame(frame: &Frame) -> aom_image {
+pub(crate) fn img_from_frame(frame: &Frame) -> Result<aom_image, AomError> {
+    let invalid = |detail| AomError::new(AomErrorKind::InvalidParam, detail);
     let mut img: aom_image = unsafe { mem::zeroed() };
 
-    if let MediaKind::Video(ref v) = frame.kind {
-        map_formaton(&mut img, &v.format);
-        img.w = v.width as u32;
-        img.h = v.height as u32;
-        img.d_w = v.width as u32;
-        img.d_h = v.height as u32;
+    let v = match frame.kind {
+        MediaKind::Video(ref v) => v,
+        _ => return Err(invalid("not a video frame")),
+    };
+
+    map_formaton(&mut img, &v.format)?;
+    img.w = v.width as u32;
+    img.h = v.height as u32;
+    img.d_w = v.width as u32;
+    img.d_h = v.height as u32;
+
+    let planes = frame.buf.count();
+    if planes != v.format.get_num_comp() || planes > img.planes.len() {
+        return Err(invalid("the frame planes do not match its format"));
     }
-    // populate the buffers
-    for i in 0..frame.buf.count() {
-        let s: &[u8] = frame.buf.as_slice(i).unwrap();
+
+    // populate the buffers, libaom reads stride x height bytes per plane
+    let bytes = if is_high_bitdepth(&img) { 2 } else { 1 };
+    for (i, c) in v.format.iter().flatten().enumerate() {
+        let s: &[u8] = frame
+            .buf
+            .as_slice(i)
+            .map_err(|_| invalid("missing frame plane"))?;
+        let stride = frame
+            .buf
+            .linesize(i)
+            .map_err(|_| invalid("missing frame plane"))?;
+
+        if stride < c.get_width(v.width) * bytes
+            || stride > i32::MAX as usize
+            || s.len() < stride * c.get_height(v.height)
+        {
+            return Err(invalid("frame plane smaller than the frame size"));
+        }
+
         img.planes[i] = s.as_ptr() as *mut u8;
-        img.stride[i] = frame.buf.linesize(i).unwrap() as i32;
+        img.stride[i] = stride as i32;
+    }
+
+    Ok(img)
+}
+
+// Copy `img` into `cache`, reallocated when the geometry changes
+fn filter_copy<'a>(
+    cache: &'a mut Option<AomImage>,
+    img: &aom_image,
+) -> Result<&'a mut AomImage, AomError> {
+    match cache {
+        Some(copy) if copy.same_geometry(img) => unsafe { copy.copy_raw(img) },
+        _ => *cache = unsafe { AomImage::copy_from_raw(img) },
     }
 
-    img
+    cache
+        .as_mut()
+        .ok_or_else(|| AomError::new(AomErrorKind::MemError, "cannot allocate the image"))
 }
 
 /// AV1 Encoder
 pub struct AV1Encoder {
     pub(crate) ctx: aom_codec_ctx_t,
     pub(crate) iter: aom_codec_iter_t,
+    // Used to tell if the encoder holds more frames than the lag allows
+    lag_in_frames: u32,
+    drop_frames: bool,
+    // The CQ level sets the quantizer, see encode_intra_with_q
+    fixed_quality: bool,
+    pub(crate) cq_level: i32,
+    // Last value of every control applied, in order, replayed by reinit
+    controls: Vec<(aome_enc_control_id::Type, ControlValue)>,
+    flags: aom_codec_flags_t,
+    stats: FrameStats,
+    dropped_since_packet: u64,
+    // Timestamps of the frames not output yet
+    pending_pts: VecDeque<i64>,
+    // Packets not retrieved before the next encode call
+    pending_packets: VecDeque<(AOMPacket, Option<EncodedFrameInfo>)>,
+    // Data attached to the frames not output yet, by pts
+    user_data: HashMap<i64, Box<dyn Any + Send>>,
+    // The packets are Annex B temporal units
+    annexb: bool,
+    pub(crate) timebase: (i32, i32),
+    // Configuration in use, reapplied by adapt_to_fps
+    cfg: aom_codec_enc_cfg,
+    // Frame rate and frames submitted at the last adaptation
+    adapted_fps: Option<(f64, u64)>,
+    // Of the frames without duration
+    default_duration: u64,
+    validate_pts: bool,
+    rescale_timestamps: bool,
+    // Stamped on the packets
+    stream_index: isize,
+    // Checked against the first frame
+    chroma_subsampling: Option<(bool, bool)>,
+    // Otherwise taken from the first frame
+    color_description: bool,
+    last_pts: Option<i64>,
+    pub(crate) packet_stats: Option<Box<EncoderStats>>,
+    pub(crate) keyframes: Option<Box<KeyframeScheduler>>,
+    pub(crate) frame_log: Option<Box<FrameLog>>,
+    frame_filter: Option<Box<dyn FnMut(&mut AomImage) + Send>>,
+    // Reused by the frame filter while the frame geometry does not change
+    filtered: Option<AomImage>,
 }
 
 unsafe impl Send for AV1Encoder {} // TODO: Make sure it cannot be abused
@@ -142,14 +575,48 @@ impl AV1Encoder {
     /// Create a new encoder using the provided configuration
     ///
     /// You may use `get_encoder` instead.
-    pub fn new(cfg: &mut AV1EncoderConfig) -> Result<AV1Encoder, aom_codec_err_t::Type> {
-        let mut ctx = MaybeUninit::uninit();
+    pub fn new(cfg: &mut AV1EncoderConfig) -> Result<AV1Encoder, AomError> {
+        AV1Encoder::with_flags_raw(cfg, 0)
+    }
+
+    /// Create a new encoder using the provided configuration and
+    /// initialization flags
+    #[deprecated(since = "0.4.0", note = "use with_flags_raw")]
+    pub fn with_flags(
+        cfg: &mut AV1EncoderConfig,
+        flags: aom_codec_flags_t,
+    ) -> Result<AV1Encoder, AomError> {
+        AV1Encoder::with_flags_raw(cfg, flags)
+    }
+
+    /// Create a new encoder using the provided configuration and
+    /// initialization flags (e.g. `AOM_CODEC_USE_PSNR`)
+    ///
+    /// `AOM_CODEC_USE_HIGHBITDEPTH` is added if the configured bit depth
+    /// is above 8.
+    pub fn with_flags_raw(
+        cfg: &mut AV1EncoderConfig,
+        mut flags: aom_codec_flags_t,
+    ) -> Result<AV1Encoder, AomError> {
+        cfg.check_superres()?;
+
+        if !cfg.iface.is_encoder() {
+            return Err(AomError::InterfaceUnavailable { codec: "encoder" });
+        }
+
+        // Zeroed so the error detail can be read if the initialization fails
+        let mut ctx = MaybeUninit::zeroed();
+
+        if cfg.cfg_raw().g_bit_depth > 8 {
+            flags |= AOM_CODEC_USE_HIGHBITDEPTH as aom_codec_flags_t;
+        }
+
         let ret = unsafe {
             aom_codec_enc_init_ver(
                 ctx.as_mut_ptr(),
-                aom_codec_av1_cx(),
-                cfg.cfg(),
-                0,
+                cfg.iface.as_raw(),
+                cfg.cfg_raw(),
+                flags,
                 AOM_ENCODER_ABI_VERSION as i32,
             )
         };
@@ -160,324 +627,3744 @@ impl AV1Encoder {
                 let mut enc = AV1Encoder {
                     ctx,
                     iter: ptr::null(),
+                    lag_in_frames: cfg.g_lag_in_frames,
+                    drop_frames: cfg.rc_dropframe_thresh > 0,
+                    fixed_quality: cfg.has_fixed_quality(),
+                    // libaom default
+                    cq_level: 10,
+                    controls: Vec::new(),
+                    flags,
+                    stats: FrameStats::default(),
+                    dropped_since_packet: 0,
+                    pending_pts: VecDeque::new(),
+                    pending_packets: VecDeque::new(),
+                    user_data: HashMap::new(),
+                    annexb: cfg.save_as_annexb != 0,
+                    timebase: (cfg.g_timebase.num, cfg.g_timebase.den),
+                    cfg: *cfg.cfg_raw(),
+                    adapted_fps: None,
+                    default_duration: 1,
+                    validate_pts: cfg.validate_pts,
+                    rescale_timestamps: cfg.rescale_timestamps,
+                    stream_index: 0,
+                    chroma_subsampling: cfg.chroma_subsampling,
+                    color_description: cfg.color_description,
+                    last_pts: None,
+                    packet_stats: None,
+                    keyframes: None,
+                    frame_log: None,
+                    frame_filter: None,
+                    filtered: None,
                 };
 
                 // Apparently aom 2.0 would crash if a CPUUSED is not set explicitly.
-                enc.control(aome_enc_control_id::AOME_SET_CPUUSED, 2)
-                    .expect("Cannot set CPUUSED");
+                enc.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, 2)?;
+                cfg.apply_controls(&mut enc)?;
 
                 Ok(enc)
             }
-            _ => Err(ret),
+            _ => Err(AomError::from_ctx(unsafe { ctx.assume_init_ref() }, ret)),
         }
     }
 
     /// Update the encoder parameters after-creation
     ///
+    /// In debug builds, or with the `validate-controls` feature, the values
+    /// out of the range of the known controls are rejected beforehand.
+    ///
     /// It calls `aom_codec_control_`
-    pub fn control(
-        &mut self,
-        id: aome_enc_control_id::Type,
-        val: i32,
-    ) -> Result<(), aom_codec_err_t::Type> {
+    pub fn control_raw(&mut self, id: aome_enc_control_id::Type, val: i32) -> Result<(), AomError> {
+        validate_control(id, val as i64)?;
+
         let ret = unsafe { aom_codec_control(&mut self.ctx, id as i32, val) };
 
-        match ret {
-            aom_codec_err_t::AOM_CODEC_OK => Ok(()),
-            _ => Err(ret),
+        check(&self.ctx, ret)?;
+
+        self.applied_control(id, ControlValue::Int(val));
+
+        Ok(())
+    }
+
+    pub(crate) fn applied_control(&mut self, id: aome_enc_control_id::Type, val: ControlValue) {
+        if id == aome_enc_control_id::AOME_SET_CQ_LEVEL {
+            if let ControlValue::Int(v) = val {
+                self.cq_level = v;
+            }
+        }
+
+        match self.controls.iter_mut().find(|(i, _)| *i == id) {
+            Some(c) => c.1 = val,
+            None => self.controls.push((id, val)),
         }
     }
 
-    // TODO: Cache the image information
-    //
-    /// Send an uncompressed frame to the encoder
+    /// Update the encoder parameters after-creation
+    #[deprecated(since = "0.4.0", note = "use control_raw")]
+    pub fn control(&mut self, id: aome_enc_control_id::Type, val: i32) -> Result<(), AomError> {
+        self.control_raw(id, val)
+    }
+
+    /// Set an option by its `aomenc` name, e.g. `"cpu-used"`
     ///
-    /// Call [`get_packet`] to receive the compressed data.
+    /// It reaches the options that have no control id yet.
     ///
-    /// It calls `aom_codec_encode`.
+    /// It calls `aom_codec_set_option`.
+    pub fn set_option_str(&mut self, name: &str, value: &str) -> Result<(), AomError> {
+        let nul = |_| AomError::new(AomErrorKind::InvalidParam, "option with a nul byte");
+        let name = CString::new(name).map_err(nul)?;
+        let value = CString::new(value).map_err(nul)?;
+
+        let ret = unsafe { aom_codec_set_option(&mut self.ctx, name.as_ptr(), value.as_ptr()) };
+
+        check(&self.ctx, ret)
+    }
+
+    /// Apply a new configuration to the running encoder
     ///
-    /// [`get_packet`]: #method.get_packet
-    pub fn encode(&mut self, frame: &Frame) -> Result<(), aom_codec_err_t::Type> {
-        let img = img_from_frame(frame);
+    /// libaom refuses the changes it cannot apply mid-stream, e.g. a
+    /// different lag or a frame size larger than the initial one. The
+    /// staged controls are applied again.
+    ///
+    /// It calls `aom_codec_enc_config_set`.
+    pub fn set_config(&mut self, cfg: &AV1EncoderConfig) -> Result<(), AomError> {
+        cfg.check_superres()?;
 
-        let ret = unsafe { aom_codec_encode(&mut self.ctx, &img, frame.t.pts.unwrap(), 1, 0) };
+        let ret = unsafe { aom_codec_enc_config_set(&mut self.ctx, cfg.cfg_raw()) };
 
-        self.iter = ptr::null();
+        check(&self.ctx, ret)?;
+        cfg.apply_controls(self)?;
 
-        match ret {
-            aom_codec_err_t::AOM_CODEC_OK => Ok(()),
-            _ => Err(ret),
-        }
+        self.lag_in_frames = cfg.g_lag_in_frames;
+        self.drop_frames = cfg.rc_dropframe_thresh > 0;
+        self.fixed_quality = cfg.has_fixed_quality();
+        self.cfg = *cfg.cfg_raw();
+        self.adapted_fps = None;
+        self.default_duration = 1;
+        self.annexb = cfg.save_as_annexb != 0;
+        self.validate_pts = cfg.validate_pts;
+        self.rescale_timestamps = cfg.rescale_timestamps;
+
+        Ok(())
     }
 
-    /// Notify the encoder that no more data will be sent
+    /// Destroy the encoder and create it again with `cfg`
     ///
-    /// Call [`get_packet`] to receive the compressed data.
+    /// For the changes [`set_config`] refuses, e.g. a frame size larger
+    /// than the initial one. The controls applied so far, staged or set with
+    /// [`control_raw`], are applied again unless `cfg` stages them. The
+    /// stream index, the frame filter, the keyframe policy and the stats
+    /// recording are kept, the recorded stats start over.
     ///
-    /// It calls `aom_codec_encode` with NULL arguments.
+    /// The new stream starts with a keyframe, the frames and packets still
+    /// held by the encoder are dropped: [`flush`] it first.
     ///
-    /// [`get_packet`]: #method.get_packet
-    pub fn flush(&mut self) -> Result<(), aom_codec_err_t::Type> {
-        let ret = unsafe { aom_codec_encode(&mut self.ctx, ptr::null_mut(), 0, 1, 0) };
+    /// [`set_config`]: AV1Encoder::set_config
+    /// [`control_raw`]: AV1Encoder::control_raw
+    /// [`flush`]: AV1Encoder::flush
+    pub fn reinit(&mut self, cfg: &mut AV1EncoderConfig) -> Result<(), AomError> {
+        let mut enc = AV1Encoder::with_flags_raw(cfg, self.flags)?;
 
-        self.iter = ptr::null();
+        for &(id, val) in &self.controls {
+            if cfg.stages(id) {
+                continue;
+            }
 
-        match ret {
-            aom_codec_err_t::AOM_CODEC_OK => Ok(()),
-            _ => Err(ret),
+            let ret = unsafe {
+                match val {
+                    ControlValue::Int(v) => aom_codec_control(&mut enc.ctx, id as i32, v),
+                    ControlValue::UInt(v) => aom_codec_control(&mut enc.ctx, id as i32, v),
+                }
+            };
+
+            check(&enc.ctx, ret)?;
+            enc.applied_control(id, val);
         }
+
+        enc.stream_index = self.stream_index;
+        enc.frame_filter = self.frame_filter.take();
+        enc.set_keyframe_policy(self.keyframe_policy().cloned());
+        if self.packet_stats.is_some() {
+            enc.enable_stats();
+        }
+        enc.frame_log = self.frame_log.take();
+
+        *self = enc;
+
+        Ok(())
     }
 
-    /// Retrieve the compressed data
+    /// Quantizer of the last encoded frame, in the 0-63 range
     ///
-    /// To be called until it returns `None`.
+    /// It matches `AOME_GET_LAST_QUANTIZER_64`.
+    pub fn last_quantizer(&mut self) -> Result<i32, AomError> {
+        let mut q = 0i32;
+        let ret = unsafe {
+            aom_codec_control(
+                &mut self.ctx,
+                aome_enc_control_id::AOME_GET_LAST_QUANTIZER_64 as i32,
+                &mut q as *mut i32,
+            )
+        };
+
+        check(&self.ctx, ret)?;
+
+        Ok(q)
+    }
+
+    /// Quantizer index of the last encoded frame, in the 0-255 range
     ///
-    /// It calls `aom_codec_get_cx_data`.
-    pub fn get_packet(&mut self) -> Option<AOMPacket> {
-        let pkt = unsafe { aom_codec_get_cx_data(&mut self.ctx, &mut self.iter) };
+    /// It matches `AOME_GET_LAST_QUANTIZER`.
+    pub fn last_qindex(&mut self) -> Result<i32, AomError> {
+        let mut q = 0i32;
+        let ret = unsafe {
+            aom_codec_control(
+                &mut self.ctx,
+                aome_enc_control_id::AOME_GET_LAST_QUANTIZER as i32,
+                &mut q as *mut i32,
+            )
+        };
 
-        if pkt.is_null() {
-            None
-        } else {
-            Some(AOMPacket::new(unsafe { *pkt }))
-        }
+        check(&self.ctx, ret)?;
+
+        Ok(q)
     }
-}
 
-impl Drop for AV1Encoder {
-    fn drop(&mut self) {
-        unsafe { aom_codec_destroy(&mut self.ctx) };
+    // libaom keeps the planes until the next encode call
+    fn new_frame_raw(&mut self) -> Result<aom_image_t, AomError> {
+        let mut img: aom_image_t = unsafe { mem::zeroed() };
+        let ret = unsafe {
+            aom_codec_control(
+                &mut self.ctx,
+                aom_com_control_id::AV1_GET_NEW_FRAME_IMAGE as i32,
+                &mut img as *mut aom_image_t,
+            )
+        };
+
+        check(&self.ctx, ret)?;
+
+        Ok(img)
     }
-}
 
-impl AOMCodec for AV1Encoder {
-    fn get_context(&mut self) -> &mut aom_codec_ctx {
-        &mut self.ctx
+    /// Copy the last shown frame the encoder reconstructed
+    ///
+    /// It is the frame a decoder outputs, e.g. to measure the quality
+    /// without decoding the packets. It fails if no frame was shown yet.
+    ///
+    /// It matches `AV1_GET_NEW_FRAME_IMAGE`.
+    pub fn new_frame_image(&mut self) -> Result<Frame, AomError> {
+        self.with_new_frame_image(|img| img.to_frame())?
+            .ok_or_else(|| AomError::new(AomErrorKind::Error, "unsupported image format"))
     }
-}
 
-#[cfg(feature = "codec-trait")]
-mod encoder_trait {
-    use super::*;
-    use av_codec::encoder::*;
-    use av_codec::error::*;
-    use av_data::frame::ArcFrame;
-    use av_data::params::{CodecParams, MediaKind, VideoInfo};
-    use av_data::value::Value;
+    /// Borrow the last shown frame the encoder reconstructed
+    ///
+    /// Unlike [`new_frame_image`] nothing is copied, the image is only
+    /// valid in `f`.
+    ///
+    /// [`new_frame_image`]: AV1Encoder::new_frame_image
+    pub fn with_new_frame_image<R>(
+        &mut self,
+        f: impl FnOnce(&AomImageView) -> R,
+    ) -> Result<R, AomError> {
+        let img = self.new_frame_raw()?;
 
-    pub struct Des {
-        descr: Descr,
+        Ok(f(&unsafe { AomImageView::new(&img) }))
     }
 
-    pub struct Enc {
-        cfg: AV1EncoderConfig,
-        enc: Option<AV1Encoder>,
+    /// Number of frames sent to the encoder and not output yet
+    ///
+    /// Meaningful after retrieving every available packet with
+    /// [`get_packet`], every compressed frame packet is assumed to contain
+    /// a single shown frame.
+    ///
+    /// [`get_packet`]: #method.get_packet
+    pub fn frames_held(&self) -> u64 {
+        self.pending_pts.len() as u64
     }
 
-    impl Descriptor for Des {
-        type OutputEncoder = Enc;
+    /// Stream timebase units, `g_timebase`, in which the packet timestamps
+    /// are expressed
+    pub fn timebase(&self) -> Rational64 {
+        let (num, den) = self.timebase;
 
-        fn create(&self) -> Self::OutputEncoder {
-            Enc {
-                cfg: AV1EncoderConfig::new().unwrap(),
-                enc: None,
-            }
-        }
+        Rational64::new(num as i64, den as i64)
+    }
 
-        fn describe(&self) -> &Descr {
-            &self.descr
-        }
+    /// Stream index stamped on the compressed frame packets, 0 by default
+    pub fn set_stream_index(&mut self, index: isize) {
+        self.stream_index = index;
     }
 
-    impl Encoder for Enc {
-        fn configure(&mut self) -> Result<()> {
-            if self.enc.is_none() {
-                self.cfg
-                    .get_encoder()
-                    .map(|enc| {
-                        self.enc = Some(enc);
-                    })
-                    .map_err(|_err| Error::ConfigurationIncomplete)
-            } else {
-                unimplemented!()
-            }
-        }
+    /// The stream index stamped on the packets
+    pub fn stream_index(&self) -> isize {
+        self.stream_index
+    }
 
-        // TODO: have it as default impl?
-        fn get_extradata(&self) -> Option<Vec<u8>> {
-            None
-        }
+    /// Run `filter` on every frame before encoding it, e.g. to draw an
+    /// overlay
+    ///
+    /// The frames are shared, the filter works on a copy of their planes
+    /// in an image reused as long as the frame size and format do not
+    /// change. `None` removes the filter.
+    pub fn set_frame_filter(&mut self, filter: Option<Box<dyn FnMut(&mut AomImage) + Send>>) {
+        self.frame_filter = filter;
 
-        fn send_frame(&mut self, frame: &ArcFrame) -> Result<()> {
-            let enc = self.enc.as_mut().unwrap();
-            enc.encode(frame).map_err(|_| unimplemented!())
+        if self.frame_filter.is_none() {
+            self.filtered = None;
         }
+    }
 
-        fn receive_packet(&mut self) -> Result<Packet> {
-            let enc = self.enc.as_mut().unwrap();
+    /// Follow a change of the actual frame rate, e.g. of a screen capture
+    ///
+    /// It is meant for frames stamped with their index, in a timebase
+    /// matching the nominal frame rate. libaom derives the frame rate from
+    /// the timestamps, it would keep spending the bits of a frame at the
+    /// nominal rate on fewer frames per second. The target bitrate is
+    /// scaled by the nominal rate over `fps` and the frames without
+    /// duration last as many timebase units.
+    ///
+    /// The changes within 10% of the current rate, or coming less than half
+    /// a second of frames after the previous one, are ignored so the rate
+    /// control is not unsettled. It returns whether the change was applied.
+    ///
+    /// It calls `aom_codec_enc_config_set`.
+    pub fn adapt_to_fps(&mut self, fps: f64) -> Result<bool, AomError> {
+        let invalid = |detail| Err(AomError::new(AomErrorKind::InvalidParam, detail));
 
-            if let Some(p) = enc.get_packet() {
-                match p {
-                    AOMPacket::Packet(pkt) => Ok(pkt),
-                    _ => unimplemented!(),
-                }
-            } else {
-                Err(Error::MoreDataNeeded)
-            }
+        if !fps.is_finite() || fps <= 0.0 {
+            return invalid("invalid frame rate");
         }
 
-        fn flush(&mut self) -> Result<()> {
-            let enc = self.enc.as_mut().unwrap();
-            enc.flush().map_err(|_| unimplemented!())
+        if self.cfg.g_pass != aom_enc_pass::AOM_RC_ONE_PASS {
+            return invalid("frame rate adaptation in a multi-pass encoding");
         }
 
-        fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
-            match (key, val) {
-                ("w", Value::U64(v)) => self.cfg.g_w = v as u32,
-                ("h", Value::U64(v)) => self.cfg.g_h = v as u32,
-                ("qmin", Value::U64(v)) => self.cfg.rc_min_quantizer = v as u32,
-                ("qmax", Value::U64(v)) => self.cfg.rc_max_quantizer = v as u32,
-                ("timebase", Value::Pair(num, den)) => {
-                    self.cfg.g_timebase.num = num as i32;
-                    self.cfg.g_timebase.den = den as i32;
-                }
-                _ => unimplemented!(),
-            }
+        let (num, den) = self.timebase;
+        let nominal = den as f64 / num as f64;
+        let (current, since) = match self.adapted_fps {
+            Some((current, frames)) => (current, self.stats.frames_submitted - frames),
+            None => (nominal, u64::MAX),
+        };
 
-            Ok(())
+        if (fps - current).abs() <= current * 0.1 || (since as f64) < current / 2.0 {
+            return Ok(false);
         }
 
-        fn get_params(&self) -> Result<CodecParams> {
-            use std::sync::Arc;
-            Ok(CodecParams {
-                kind: Some(MediaKind::Video(VideoInfo {
-                    height: self.cfg.g_h as usize,
-                    width: self.cfg.g_w as usize,
-                    format: Some(Arc::new(*YUV420)), // TODO: support more formats
-                })),
-                codec_id: Some("av1".to_owned()),
-                extradata: None,
-                bit_rate: 0, // TODO: expose the information
-                convergence_window: 0,
-                delay: 0,
-            })
+        let mut cfg = self.cfg;
+        let bitrate = self.cfg.rc_target_bitrate as f64 * nominal / fps;
+        cfg.rc_target_bitrate = (bitrate.round() as u32).max(1);
+
+        let ret = unsafe { aom_codec_enc_config_set(&mut self.ctx, &cfg) };
+
+        check(&self.ctx, ret)?;
+
+        self.adapted_fps = Some((fps, self.stats.frames_submitted));
+        self.default_duration = ((nominal / fps).round() as u64).max(1);
+
+        Ok(true)
+    }
+
+    /// Count the frames sent, output and dropped so far
+    ///
+    /// libaom drops frames when `rc_dropframe_thresh` is set and the rate
+    /// control runs out of budget.
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// The encoder holds more frames than the configured lag
+    ///
+    /// In low-latency mode, see [`AV1EncoderConfig::low_latency`], every
+    /// frame sent should produce a packet and this is never true.
+    ///
+    /// If `rc_dropframe_thresh` is set the extra frames are counted as
+    /// dropped instead, see [`stats`].
+    ///
+    /// [`stats`]: #method.stats
+    pub fn is_holding_frames(&self) -> bool {
+        self.frames_held() > self.lag_in_frames as u64
+    }
+
+    // TODO: Cache the image information
+    //
+    /// Send an uncompressed frame to the encoder
+    ///
+    /// Call [`get_packet`] to receive the compressed data.
+    ///
+    /// It calls `aom_codec_encode`.
+    ///
+    /// [`get_packet`]: #method.get_packet
+    pub fn encode(&mut self, frame: &Frame) -> Result<(), AomError> {
+        self.encode_with_flags(frame, EncodeFlags::empty())
+    }
+
+    /// Send an uncompressed frame to the encoder with per-frame flags
+    ///
+    /// The frame lasts `frame.t.duration` timebase units, 1 if unset. The
+    /// [`KeyframePolicy`], if any, may add [`EncodeFlags::FORCE_KF`].
+    ///
+    /// The timestamps are rescaled and checked as configured with
+    /// [`AV1EncoderConfig::rescale_timestamps`] and
+    /// [`AV1EncoderConfig::validate_pts`].
+    ///
+    /// It calls `aom_codec_encode`.
+    pub fn encode_with_flags(&mut self, frame: &Frame, flags: EncodeFlags) -> Result<(), AomError> {
+        let img = img_from_frame(frame)?;
+
+        if self.stats.frames_submitted == 0 {
+            self.check_chroma_subsampling(&img)?;
+
+            if let MediaKind::Video(ref v) = frame.kind {
+                self.set_color_description(&v.format)?;
+            }
+        }
+
+        let (pts, duration) = self.timestamps(frame)?;
+        let flags = flags | self.keyframe_flags(pts);
+
+        self.stash_packets();
+
+        let img = match self.frame_filter {
+            Some(ref mut filter) => {
+                let copy = filter_copy(&mut self.filtered, &img)?;
+                filter(copy);
+                copy.raw()
+            }
+            None => &img,
+        };
+
+        let ret = unsafe { aom_codec_encode(&mut self.ctx, img, pts, duration as _, flags.bits()) };
+
+        self.iter = ptr::null();
+
+        check(&self.ctx, ret)?;
+
+        self.stats.frames_submitted += 1;
+        self.pending_pts.push_back(pts);
+        self.last_pts = Some(pts);
+
+        Ok(())
+    }
+
+    /// Send an uncompressed frame coded as a keyframe at the quantizer `q`,
+    /// 0 to 63
+    ///
+    /// The CQ level is set to `q` for this frame only, the previous one is
+    /// restored once the frame is encoded. It needs the
+    /// [`AomUsage::AllIntra`] usage or the [`RateControl::Q`] or
+    /// [`RateControl::Cq`] rate control, and no lag so the frame is coded
+    /// right away.
+    ///
+    /// It calls `aom_codec_control` with `AOME_SET_CQ_LEVEL`.
+    pub fn encode_intra_with_q(&mut self, frame: &Frame, q: u32) -> Result<(), AomError> {
+        let invalid = |detail| Err(AomError::new(AomErrorKind::InvalidParam, detail));
+
+        if q > 63 {
+            return invalid("quantizer out of the 0-63 range");
+        }
+
+        if !self.fixed_quality {
+            return invalid("per-frame quantizer without all intra usage or Q or CQ rate control");
+        }
+
+        if self.lag_in_frames > 0 {
+            return invalid("per-frame quantizer with lag");
+        }
+
+        let previous = self.cq_level;
+
+        self.control_raw(aome_enc_control_id::AOME_SET_CQ_LEVEL, q as i32)?;
+
+        let encoded = self.encode_with_flags(frame, EncodeFlags::FORCE_KF);
+        let restored = self.control_raw(aome_enc_control_id::AOME_SET_CQ_LEVEL, previous);
+
+        encoded.and(restored)
+    }
+
+    /// Send an uncompressed frame restricting the references it uses
+    ///
+    /// See [`ReferenceControl::flags`].
+    pub fn encode_with_references(
+        &mut self,
+        frame: &Frame,
+        refs: &ReferenceControl,
+    ) -> Result<(), AomError> {
+        self.encode_with_flags(frame, refs.flags()?)
+    }
+
+    /// Set the references and refreshed buffers of the next frame
+    ///
+    /// See [`ReferenceControl::svc_ref_frame_config_raw`].
+    ///
+    /// It calls `aom_codec_control` with `AV1E_SET_SVC_REF_FRAME_CONFIG`.
+    pub fn set_svc_ref_frame_config_raw(
+        &mut self,
+        cfg: &mut aom_svc_ref_frame_config_t,
+    ) -> Result<(), AomError> {
+        let ret = unsafe {
+            aom_codec_control(
+                &mut self.ctx,
+                aome_enc_control_id::AV1E_SET_SVC_REF_FRAME_CONFIG as i32,
+                cfg as *mut aom_svc_ref_frame_config_t,
+            )
+        };
+
+        check(&self.ctx, ret)
+    }
+
+    /// Send an uncompressed frame along with data to get back with its packet
+    ///
+    /// The data is returned by [`get_packet_with_user_data`] with the packet
+    /// of the same pts, whatever the frames held by the encoder. It is
+    /// dropped with the frame if the frame is dropped, if the packet is
+    /// retrieved by another method or with the encoder.
+    ///
+    /// [`get_packet_with_user_data`]: AV1Encoder::get_packet_with_user_data
+    pub fn encode_with_user_data<T: Send + 'static>(
+        &mut self,
+        frame: &Frame,
+        data: T,
+    ) -> Result<(), AomError> {
+        self.encode(frame)?;
+
+        if let Some(pts) = self.last_pts {
+            self.user_data.insert(pts, Box::new(data));
+        }
+
+        Ok(())
+    }
+
+    /// Notify the encoder that no more data will be sent
+    ///
+    /// Call [`get_packet`] to receive the compressed data.
+    ///
+    /// It calls `aom_codec_encode` with NULL arguments.
+    ///
+    /// [`get_packet`]: #method.get_packet
+    pub fn flush(&mut self) -> Result<(), AomError> {
+        self.stash_packets();
+
+        let ret = unsafe { aom_codec_encode(&mut self.ctx, ptr::null_mut(), 0, 1, 0) };
+
+        self.iter = ptr::null();
+
+        check(&self.ctx, ret)
+    }
+
+    /// Flush the encoder, retrieve every packet and destroy it
+    ///
+    /// Dropping the encoder discards the frames it still holds, e.g. up to
+    /// `lag_in_frames` of them.
+    ///
+    /// It calls `aom_codec_destroy`.
+    pub fn close(mut self) -> Result<Vec<AOMPacket>, AomError> {
+        let mut packets = Vec::new();
+
+        loop {
+            self.flush()?;
+
+            let before = packets.len();
+            while let Some(p) = self.get_packet() {
+                packets.push(p);
+            }
+
+            if packets.len() == before {
+                break;
+            }
+        }
+
+        destroy(&mut self.ctx)?;
+
+        Ok(packets)
+    }
+
+    /// Retrieve the reconstruction of the last frame shown
+    ///
+    /// It is the frame a decoder would output, handy to measure the quality
+    /// with the helpers of the `metrics` module. `None` if the last frame
+    /// encoded is not shown or its format is not supported.
+    ///
+    /// It calls `aom_codec_get_preview_frame`.
+    pub fn get_preview_frame(&mut self) -> Option<Frame> {
+        let img = unsafe { aom_codec_get_preview_frame(&mut self.ctx) };
+
+        if img.is_null() {
+            return None;
+        }
+
+        img_to_frame(unsafe { *img }, FrameType::OTHER, |v| new_frame(v, None))
+    }
+
+    /// Measure the quality of the last frame shown against its `source`
+    ///
+    /// The reconstruction of [`get_preview_frame`] is compared in place,
+    /// brought back to the input bit depth, so the PSNR matches the one of
+    /// the PSNR packets without `AOM_CODEC_USE_PSNR`, which changes some
+    /// encoder decisions. `source` is the frame just encoded, the lag must
+    /// be 0 for the last frame shown to be that one.
+    ///
+    /// [`get_preview_frame`]: AV1Encoder::get_preview_frame
+    #[cfg(any(test, feature = "metrics"))]
+    pub fn measure_frame_quality(&mut self, source: &Frame) -> Result<QualityReport, AomError> {
+        let img = unsafe { aom_codec_get_preview_frame(&mut self.ctx) };
+
+        if img.is_null() {
+            return Err(AomError::new(AomErrorKind::Error, "no frame shown yet"));
+        }
+
+        metrics::measure(source, &unsafe { AomImageView::new(&*img) })
+    }
+
+    /// Retrieve the global headers of the stream
+    ///
+    /// They consist of the sequence header OBU, as stored in the codec
+    /// configuration of most containers.
+    ///
+    /// It calls `aom_codec_get_global_headers`.
+    pub fn global_headers(&mut self) -> Option<Vec<u8>> {
+        extern "C" {
+            fn free(ptr: *mut std::os::raw::c_void);
+        }
+
+        unsafe {
+            let buf = aom_codec_get_global_headers(&mut self.ctx);
+
+            if buf.is_null() {
+                return None;
+            }
+
+            let headers = if (*buf).buf.is_null() {
+                None
+            } else {
+                Some(to_buffer(*buf))
+            };
+
+            // Both the struct and the data are allocated with malloc
+            free((*buf).buf);
+            free(buf as *mut std::os::raw::c_void);
+
+            headers
+        }
+    }
+
+    /// Retrieve the compressed data
+    ///
+    /// To be called until it returns `None`. The packets not retrieved
+    /// before calling [`encode`] or [`flush`] again are kept and returned
+    /// first.
+    ///
+    /// It calls `aom_codec_get_cx_data`.
+    ///
+    /// [`encode`]: #method.encode
+    /// [`flush`]: #method.flush
+    pub fn get_packet(&mut self) -> Option<AOMPacket> {
+        self.get_packet_with_info().map(|(p, _)| p)
+    }
+
+    /// Retrieve the compressed data along with its [`EncodedFrameInfo`]
+    ///
+    /// The information is `None` for the packets other than
+    /// [`AOMPacket::Packet`].
+    pub fn get_packet_with_info(&mut self) -> Option<(AOMPacket, Option<EncodedFrameInfo>)> {
+        self.pending_packets
+            .pop_front()
+            .or_else(|| self.next_packet())
+            .inspect(|(pkt, _)| {
+                self.take_user_data(pkt);
+            })
+    }
+
+    /// Retrieve the compressed data along with the data given to
+    /// [`encode_with_user_data`] for its frame
+    ///
+    /// The data is `None` for the packets other than [`AOMPacket::Packet`],
+    /// for the frames sent without data and if it is not a `T`.
+    ///
+    /// [`encode_with_user_data`]: AV1Encoder::encode_with_user_data
+    pub fn get_packet_with_user_data<T: 'static>(&mut self) -> Option<(AOMPacket, Option<T>)> {
+        let (pkt, _) = self
+            .pending_packets
+            .pop_front()
+            .or_else(|| self.next_packet())?;
+        let data = self
+            .take_user_data(&pkt)
+            .and_then(|data| (data as Box<dyn Any>).downcast().ok())
+            .map(|data| *data);
+
+        Some((pkt, data))
+    }
+}
+
+impl AV1Encoder {
+    // The pts and duration of the frame, in g_timebase units
+    fn timestamps(&self, frame: &Frame) -> Result<(i64, u64), AomError> {
+        let invalid = |detail| AomError::new(AomErrorKind::InvalidParam, detail);
+
+        let t = if self.rescale_timestamps {
+            Timebase::new(self.timebase()).rescale_time_info(&frame.t)
+        } else {
+            frame.t.clone()
+        };
+
+        let pts = t.pts.ok_or_else(|| invalid("frame without pts"))?;
+
+        if self.validate_pts && self.last_pts.is_some_and(|last| pts <= last) {
+            return Err(invalid("non-increasing pts"));
+        }
+
+        Ok((pts, t.duration.unwrap_or(self.default_duration)))
+    }
+
+    // libaom discards the packets of the previous call on the next
+    // aom_codec_encode, keep them around.
+    fn stash_packets(&mut self) {
+        while let Some(pkt) = self.next_packet() {
+            self.pending_packets.push_back(pkt);
+        }
+    }
+
+    fn next_packet(&mut self) -> Option<(AOMPacket, Option<EncodedFrameInfo>)> {
+        let pkt = unsafe { aom_codec_get_cx_data(&mut self.ctx, &mut self.iter) };
+
+        if pkt.is_null() {
+            self.count_drops();
+            None
+        } else {
+            let raw = unsafe { *pkt };
+            let mut pkt = AOMPacket::new(raw);
+            let mut info = None;
+
+            if let AOMPacket::Packet(ref mut p) = pkt {
+                let flags = unsafe { raw.data.frame.flags };
+                let pts = p.t.pts.unwrap_or_default();
+
+                // The packets come in decode order, each one is decoded no
+                // later than the oldest frame still held
+                p.t.dts = Some(self.pending_pts.front().map_or(pts, |&held| held.min(pts)));
+                p.t.timebase = Some(self.timebase());
+                p.stream_index = self.stream_index;
+
+                info = Some(EncodedFrameInfo::new(flags, &p.data, self.annexb));
+                self.count_packet(pts);
+
+                if self.packet_stats.is_some() {
+                    let quantizer = self.last_quantizer().ok();
+
+                    if let Some(stats) = self.packet_stats.as_deref_mut() {
+                        stats.record(p, quantizer);
+                    }
+                }
+
+                if self.frame_log.is_some() {
+                    let qindex = self.last_qindex().ok();
+
+                    if let Some(log) = self.frame_log.as_deref_mut() {
+                        log.record(p, qindex, FrameFlags::from_bits(flags));
+                    }
+                }
+            }
+
+            Some((pkt, info))
+        }
+    }
+
+    // The sequence header is written along with the first frame
+    fn set_color_description(&mut self, fmt: &Formaton) -> Result<(), AomError> {
+        let specified = fmt.get_primaries() != ColorPrimaries::Unspecified
+            || fmt.get_xfer() != TransferCharacteristic::Unspecified
+            || fmt.get_matrix() != MatrixCoefficients::Unspecified;
+
+        if self.color_description || !specified {
+            return Ok(());
+        }
+
+        self.control_raw(
+            aome_enc_control_id::AV1E_SET_COLOR_PRIMARIES,
+            fmt.get_primaries() as i32,
+        )?;
+        self.control_raw(
+            aome_enc_control_id::AV1E_SET_TRANSFER_CHARACTERISTICS,
+            fmt.get_xfer() as i32,
+        )?;
+        self.control_raw(
+            aome_enc_control_id::AV1E_SET_MATRIX_COEFFICIENTS,
+            fmt.get_matrix() as i32,
+        )?;
+        self.color_description = true;
+
+        Ok(())
+    }
+
+    fn check_chroma_subsampling(&self, img: &aom_image) -> Result<(), AomError> {
+        let subsampling = (
+            img.monochrome != 0 || img.x_chroma_shift != 0,
+            img.monochrome != 0 || img.y_chroma_shift != 0,
+        );
+
+        match self.chroma_subsampling {
+            Some(expected) if expected != subsampling => Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "the frame chroma subsampling differs from the configured one",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn take_user_data(&mut self, pkt: &AOMPacket) -> Option<Box<dyn Any + Send>> {
+        match pkt {
+            AOMPacket::Packet(p) => self.user_data.remove(&p.t.pts?),
+            _ => None,
+        }
+    }
+
+    // The frames are output in timestamp order, the earlier ones still
+    // pending were dropped
+    fn count_packet(&mut self, pts: i64) {
+        while let Some(&p) = self.pending_pts.front() {
+            if p > pts {
+                break;
+            }
+
+            self.pending_pts.pop_front();
+
+            if p < pts {
+                self.dropped_since_packet += 1;
+                self.user_data.remove(&p);
+            }
+        }
+
+        self.stats.packets_emitted += 1;
+        self.stats.frames_dropped += self.dropped_since_packet;
+        self.stats.dropped_before_last = self.dropped_since_packet;
+        self.dropped_since_packet = 0;
+    }
+
+    // Once drained, an encoder allowed to drop frames holds at most its lag
+    fn count_drops(&mut self) {
+        if !self.drop_frames {
+            return;
+        }
+
+        while self.pending_pts.len() > self.lag_in_frames as usize {
+            if let Some(p) = self.pending_pts.pop_front() {
+                self.user_data.remove(&p);
+            }
+            self.dropped_since_packet += 1;
+            self.stats.frames_dropped += 1;
+        }
+    }
+}
+
+// The frames held by the encoder are lost, see AV1Encoder::close
+impl Drop for AV1Encoder {
+    fn drop(&mut self) {
+        destroy_on_drop(&mut self.ctx);
+    }
+}
+
+impl AOMCodec for AV1Encoder {
+    fn get_context(&mut self) -> &mut aom_codec_ctx {
+        &mut self.ctx
+    }
+}
+
+#[cfg(feature = "codec-trait")]
+mod encoder_trait {
+    use super::*;
+    use av_codec::encoder::*;
+    use av_codec::error::*;
+    use av_data::frame::{ArcFrame, FrameType};
+    use av_data::params::{CodecParams, MediaKind, VideoInfo};
+    use av_data::value::Value;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    pub struct Des {
+        descr: Descr,
+    }
+
+    /// Encoder created by [`AV1_DESCR`]
+    pub struct Enc {
+        pub(crate) cfg: AV1EncoderConfig,
+        enc: Option<AV1Encoder>,
+        // Applied as a control once the encoder is created
+        pub(crate) cpu_used: Option<i32>,
+        // Unknown string options, passed to libaom once the encoder is created
+        options: Vec<(String, String)>,
+        format: Arc<Formaton>,
+        extradata: Option<Vec<u8>>,
+        psnr: bool,
+        flushing: bool,
+        // Configuration and PSNR flag the current encoder was created with
+        applied: Option<(AV1EncoderConfig, bool)>,
+        // Packets drained from a previous encoder
+        pending: VecDeque<Packet>,
+        // Non-frame packets, the trait has no way to return them
+        metrics: Mutex<Vec<AOMPacket>>,
+    }
+
+    impl Enc {
+        /// Return the PSNR and stats packets produced since the last call
+        ///
+        /// The PSNR packets are produced if the `psnr` option is set, one
+        /// per encoded frame.
+        pub fn take_metrics(&self) -> Vec<AOMPacket> {
+            std::mem::take(&mut *self.metrics.lock().unwrap())
+        }
+    }
+
+    // The other fields can be changed on the running encoder
+    fn same_geometry(a: &aom_codec_enc_cfg, b: &aom_codec_enc_cfg) -> bool {
+        a.g_w == b.g_w
+            && a.g_h == b.g_h
+            && a.g_profile == b.g_profile
+            && a.g_bit_depth == b.g_bit_depth
+            && a.g_input_bit_depth == b.g_input_bit_depth
+            && a.monochrome == b.monochrome
+            && a.g_usage == b.g_usage
+            && a.g_pass == b.g_pass
+            && a.g_lag_in_frames == b.g_lag_in_frames
+            && a.g_timebase.num == b.g_timebase.num
+            && a.g_timebase.den == b.g_timebase.den
+    }
+
+    impl Enc {
+        // Derive the profile and the bit depth from the pixel format
+        fn set_format(&mut self, format: Arc<Formaton>) -> Result<()> {
+            let luma = format.get_chromaton(0).ok_or(Error::InvalidData)?;
+            let depth = luma.get_depth() as u32;
+            let monochrome = format.get_num_comp() == 1;
+            let subsampling = match format.get_chromaton(1) {
+                Some(c) if !monochrome => c.get_subsampling(),
+                _ => (1, 1),
+            };
+
+            let profile = match (subsampling, depth) {
+                ((1, 1), 8 | 10) => BitstreamProfile::Profile0,
+                ((0, 0), 8 | 10) => BitstreamProfile::Profile1,
+                ((1, 0), 8 | 10) | (_, 12) => BitstreamProfile::Profile2,
+                _ => return Err(Error::Unsupported(format!("{} format", format))),
+            };
+
+            self.cfg.g_profile = profile as u32;
+            self.cfg.g_bit_depth = depth as aom_bit_depth_t;
+            self.cfg.g_input_bit_depth = depth;
+            self.cfg.monochrome = monochrome as u32;
+            self.format = format;
+
+            Ok(())
+        }
+    }
+
+    impl Descriptor for Des {
+        type OutputEncoder = Enc;
+
+        fn create(&self) -> Self::OutputEncoder {
+            Enc {
+                cfg: AV1EncoderConfig::new().unwrap(),
+                enc: None,
+                cpu_used: None,
+                options: Vec::new(),
+                format: Arc::new(*YUV420),
+                extradata: None,
+                psnr: false,
+                flushing: false,
+                applied: None,
+                pending: VecDeque::new(),
+                metrics: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn describe(&self) -> &Descr {
+            &self.descr
+        }
+    }
+
+    impl Encoder for Enc {
+        fn configure(&mut self) -> Result<()> {
+            if let (Some(enc), Some((cfg, psnr))) = (self.enc.as_mut(), self.applied.as_ref()) {
+                if *psnr == self.psnr && same_geometry(cfg, &self.cfg) {
+                    enc.set_config(&self.cfg)?;
+
+                    if let Some(speed) = self.cpu_used {
+                        enc.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, speed)?;
+                    }
+
+                    for (name, value) in &self.options {
+                        enc.set_option_str(name, value)?;
+                    }
+
+                    self.applied = Some((self.cfg.clone(), self.psnr));
+
+                    return Ok(());
+                }
+
+                // A new encoder is needed, keep the frames of the old one
+                self.flush()?;
+                let mut drained = VecDeque::new();
+                loop {
+                    match self.receive_packet() {
+                        Ok(pkt) => drained.push_back(pkt),
+                        Err(Error::MoreDataNeeded) => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                self.pending = drained;
+                self.flushing = false;
+                self.enc = None;
+            }
+
+            let flags = if self.psnr {
+                AOM_CODEC_USE_PSNR as aom_codec_flags_t
+            } else {
+                0
+            };
+            let mut enc = AV1Encoder::with_flags_raw(&mut self.cfg, flags)?;
+
+            if let Some(speed) = self.cpu_used {
+                enc.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, speed)?;
+            }
+
+            for (name, value) in &self.options {
+                enc.set_option_str(name, value)?;
+            }
+
+            self.extradata = enc.global_headers();
+            self.enc = Some(enc);
+            self.applied = Some((self.cfg.clone(), self.psnr));
+
+            Ok(())
+        }
+
+        fn get_extradata(&self) -> Option<Vec<u8>> {
+            self.extradata.clone()
+        }
+
+        fn send_frame(&mut self, frame: &ArcFrame) -> Result<()> {
+            let enc = self.enc.as_mut().ok_or(Error::ConfigurationIncomplete)?;
+
+            let flags = match frame.kind {
+                av_data::frame::MediaKind::Video(ref info) if info.frame_type == FrameType::I => {
+                    EncodeFlags::FORCE_KF
+                }
+                _ => EncodeFlags::empty(),
+            };
+
+            enc.encode_with_flags(frame, flags).map_err(Error::from)
+        }
+
+        fn receive_packet(&mut self) -> Result<Packet> {
+            let enc = self.enc.as_mut().ok_or(Error::ConfigurationIncomplete)?;
+
+            if let Some(pkt) = self.pending.pop_front() {
+                return Ok(pkt);
+            }
+
+            loop {
+                // Only the compressed frames are forwarded, the stats and
+                // PSNR packets are kept aside
+                while let Some(p) = enc.get_packet() {
+                    match p {
+                        AOMPacket::Packet(pkt) => return Ok(pkt),
+                        p => self.metrics.get_mut().unwrap().push(p),
+                    }
+                }
+
+                if !self.flushing {
+                    return Err(Error::MoreDataNeeded);
+                }
+
+                // Every flush call outputs at most the next shown frame,
+                // keep calling it until the lagged frames are drained
+                enc.flush()?;
+
+                match enc.get_packet() {
+                    Some(AOMPacket::Packet(pkt)) => return Ok(pkt),
+                    Some(p) => self.metrics.get_mut().unwrap().push(p),
+                    None => return Err(Error::MoreDataNeeded),
+                }
+            }
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            let enc = self.enc.as_mut().ok_or(Error::ConfigurationIncomplete)?;
+            self.flushing = true;
+            enc.flush().map_err(Error::from)
+        }
+
+        fn set_option<'a>(&mut self, key: &str, val: Value<'a>) -> Result<()> {
+            match (key, val) {
+                ("w", Value::U64(v)) => self.cfg.g_w = v as u32,
+                ("h", Value::U64(v)) => self.cfg.g_h = v as u32,
+                ("qmin", Value::U64(v)) => self.cfg.rc_min_quantizer = v as u32,
+                ("qmax", Value::U64(v)) => self.cfg.rc_max_quantizer = v as u32,
+                ("timebase", Value::Pair(num, den)) => {
+                    self.cfg.g_timebase.num = num as i32;
+                    self.cfg.g_timebase.den = den as i32;
+                }
+                // In bits per second, libaom wants kilobits
+                ("b", Value::U64(v)) | ("bitrate", Value::U64(v)) => {
+                    self.cfg.rc_target_bitrate = (v / 1000) as u32;
+                    self.cfg.rc_end_usage = aom_rc_mode::AOM_VBR;
+                }
+                ("threads", Value::U64(v)) => self.cfg.g_threads = v as u32,
+                ("kf-max-dist", Value::U64(v)) | ("keyint", Value::U64(v)) => {
+                    self.cfg.kf_max_dist = v as u32
+                }
+                ("speed", Value::U64(v)) | ("cpu-used", Value::U64(v)) => {
+                    self.cpu_used = Some(v as i32)
+                }
+                ("usage", Value::U64(v)) => {
+                    self.cfg.g_usage = match v {
+                        0 => AomUsage::GoodQuality,
+                        1 => AomUsage::RealTime,
+                        2 => AomUsage::AllIntra,
+                        _ => return Err(Error::InvalidData),
+                    } as u32
+                }
+                ("usage", Value::Str(v)) => {
+                    self.cfg.g_usage = match v {
+                        "good" => AomUsage::GoodQuality,
+                        "realtime" => AomUsage::RealTime,
+                        "allintra" => AomUsage::AllIntra,
+                        _ => return Err(Error::InvalidData),
+                    } as u32
+                }
+                ("lag-in-frames", Value::U64(v)) => self.cfg.g_lag_in_frames = v as u32,
+                ("format", Value::Formaton(f)) => self.set_format(f)?,
+                ("psnr", Value::Bool(v)) => self.psnr = v,
+                ("trellis", Value::U64(v)) => {
+                    let trellis = match v {
+                        0 => TrellisQuant::Enabled,
+                        1 => TrellisQuant::Disabled,
+                        2 => TrellisQuant::DisabledInRdSearch,
+                        3 => TrellisQuant::DisabledInYrdEstimate,
+                        _ => return Err(Error::InvalidData),
+                    };
+                    self.cfg = self.cfg.clone().trellis_quant(trellis);
+                }
+                // Known key, unexpected value
+                (
+                    "w" | "h" | "qmin" | "qmax" | "timebase" | "b" | "bitrate" | "threads"
+                    | "kf-max-dist" | "keyint" | "speed" | "cpu-used" | "usage" | "lag-in-frames"
+                    | "format" | "psnr" | "trellis",
+                    _,
+                ) => return Err(Error::InvalidData),
+                // Left to libaom, with the aomenc option names
+                (_, Value::Str(v)) => self.options.push((key.to_owned(), v.to_owned())),
+                _ => return Err(Error::Unsupported(format!("{} key", key))),
+            }
+
+            Ok(())
+        }
+
+        fn get_params(&self) -> Result<CodecParams> {
+            Ok(CodecParams {
+                kind: Some(MediaKind::Video(VideoInfo {
+                    height: self.cfg.g_h as usize,
+                    width: self.cfg.g_w as usize,
+                    format: Some(self.format.clone()),
+                })),
+                codec_id: Some("av1".to_owned()),
+                extradata: self.extradata.clone(),
+                bit_rate: self.cfg.rc_target_bitrate as usize * 1000,
+                convergence_window: 0,
+                delay: self.cfg.g_lag_in_frames as usize,
+            })
+        }
+
+        fn set_params(&mut self, params: &CodecParams) -> Result<()> {
+            if let Some(MediaKind::Video(ref info)) = params.kind {
+                self.cfg.g_w = info.width as u32;
+                self.cfg.g_h = info.height as u32;
+                if let Some(ref format) = info.format {
+                    self.set_format(format.clone())?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// AV1 Encoder
+    ///
+    /// To be used with [av-codec](https://docs.rs/av-codec) `Encoder Context`.
+    ///
+    /// A frame with the [`FrameType::I`] type is coded as a keyframe, the
+    /// frame duration is taken from its `TimeInfo`.
+    ///
+    /// [`FrameType::I`]: av_data::frame::FrameType::I
+    pub const AV1_DESCR: &Des = &Des {
+        descr: Descr {
+            codec: "av1",
+            name: "aom",
+            desc: "libaom AV1 encoder",
+            mime: "video/AV1",
+        },
+    };
+}
+
+#[cfg(feature = "codec-trait")]
+pub use self::encoder_trait::{Enc as AV1CodecEncoder, AV1_DESCR};
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    #[test]
+    fn init() {
+        let mut c = AV1EncoderConfig::new().unwrap();
+        let mut e = c.get_encoder().unwrap();
+        println!("{}", e.error_to_str());
+    }
+
+    use av_data::rational::*;
+    use av_data::timeinfo::TimeInfo;
+    pub fn setup(w: u32, h: u32, t: &TimeInfo) -> AV1Encoder {
+        if (w % 2) != 0 || (h % 2) != 0 {
+            panic!("Invalid frame size: w: {} h: {}", w, h);
+        }
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(w)
+            .height(h)
+            .timebase(t.timebase.unwrap())
+            .rc_min_quantizer(0)
+            .rc_min_quantizer(0)
+            .threads(4)
+            .pass(EncodePass::OnePass)
+            .rc_end_usage(RateControl::Cq);
+
+        let mut enc = cfg.get_encoder().unwrap();
+
+        enc.control_raw(aome_enc_control_id::AOME_SET_CQ_LEVEL, 4)
+            .unwrap();
+        enc.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, 2)
+            .unwrap();
+
+        enc
+    }
+
+    pub fn setup_frame(w: u32, h: u32, t: &TimeInfo) -> Frame {
+        use av_data::frame::*;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+
+        let v = VideoInfo::new(
+            w as usize,
+            h as usize,
+            false,
+            FrameType::OTHER,
+            Arc::new(*formats::YUV420),
+        );
+
+        Frame::new_default_frame(v, Some(t.clone()))
+    }
+
+    #[test]
+    fn encode() {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+
+        let w = 200;
+        let h = 200;
+
+        let t = TimeInfo {
+            pts: Some(0),
+            dts: Some(0),
+            duration: Some(1),
+            timebase: Some(Rational64::new(1, 1000)),
+            user_private: None,
+        };
+
+        let mut e = setup(w, h, &t);
+        let frames = MovingBox::new(w as usize, h as usize, &Arc::new(*formats::YUV420), &t);
+
+        let mut keyframe = 0;
+        let mut inter = Vec::new();
+        for f in frames.take(100) {
+            e.encode(&f).unwrap();
+
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    if pkt.is_key {
+                        keyframe = keyframe.max(pkt.data.len());
+                    } else {
+                        inter.push(pkt.data.len());
+                    }
+                }
+            }
+        }
+
+        // The motion is predicted, the inter frames are much smaller
+        assert!(keyframe > 0 && !inter.is_empty());
+        assert!(inter.iter().sum::<usize>() / inter.len() < keyframe / 2);
+    }
+
+    #[test]
+    fn encode_low_latency() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .noise_sensitivity(3)
+            .denoise_noise_level(10);
+        #[cfg(aom_ge_3_4)]
+        {
+            cfg = cfg.dnl_denoising(false);
+        }
+        let mut e = cfg.get_encoder().unwrap();
+        e.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, 8)
+            .unwrap();
+
+        // The denoiser is refused outside of the realtime usage
+        let err = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .noise_sensitivity(3)
+            .get_encoder()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+
+        let mut f = setup_frame(64, 64, &t);
+
+        for i in 0..50 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+
+            let mut packets = Vec::new();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    packets.push(pkt.t.pts.unwrap());
+                }
+            }
+
+            assert_eq!(packets, vec![i]);
+            assert_eq!(e.frames_held(), 0);
+            assert!(!e.is_holding_frames());
+        }
+    }
+
+    #[test]
+    fn encode_dropped_frames() {
+        use crate::testing::noise;
+        use av_data::pixel::formats::YUV420;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(128)
+            .height(128)
+            .timebase(t.timebase.unwrap())
+            .rc_end_usage(RateControl::Cbr)
+            .rc_target_bitrate(1)
+            .rc_dropframe_thresh(90)
+            .rc_buf_sz(100)
+            .rc_buf_initial_sz(50)
+            .rc_buf_optimal_sz(50);
+        let mut e = cfg.get_encoder().unwrap();
+        e.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, 8)
+            .unwrap();
+
+        // Noise is expensive to code
+        let format = Arc::new(*YUV420);
+        for i in 0..60 {
+            let mut f = noise(128, 128, &format, i as u64);
+            f.t = TimeInfo {
+                pts: Some(i),
+                ..t.clone()
+            };
+            e.encode(&f).unwrap();
+            while e.get_packet().is_some() {}
+        }
+
+        let stats = e.stats();
+        assert_eq!(stats.frames_submitted, 60);
+        assert!(stats.frames_dropped > 0);
+        assert_eq!(
+            stats.packets_emitted + stats.frames_dropped + e.frames_held(),
+            60
+        );
+    }
+
+    #[test]
+    fn encode_stats() {
+        use crate::testing::luma_noise;
+        use av_data::pixel::formats::YUV420;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(128)
+            .height(128)
+            .timebase(t.timebase.unwrap())
+            .rc_end_usage(RateControl::Cbr)
+            .rc_target_bitrate(1000);
+        let mut e = cfg.get_encoder().unwrap();
+        e.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, 8)
+            .unwrap();
+
+        assert!(e.encoder_stats().is_none());
+        e.enable_stats();
+
+        let format = Arc::new(*YUV420);
+
+        for i in 0..100 {
+            // Noise, so the rate control has to spend the budget
+            let mut f = luma_noise(128, 128, &format, i as u64, 255);
+            f.t = TimeInfo {
+                pts: Some(i),
+                ..t.clone()
+            };
+            e.encode(&f).unwrap();
+            while e.get_packet().is_some() {}
+        }
+
+        let snap = e.encoder_stats().unwrap().snapshot();
+        assert_eq!(snap.packets, 100);
+        assert!(snap.keyframes >= 1);
+        assert!((snap.duration - 100.0 / 30.0).abs() < 1e-6);
+        assert!(
+            snap.bitrate > 500_000.0 && snap.bitrate < 1_500_000.0,
+            "{}",
+            snap.bitrate
+        );
+        let q = snap.average_quantizer.unwrap();
+        assert!((0.0..=63.0).contains(&q));
+
+        let last = e.encoder_stats().unwrap().snapshot_last(1.0);
+        assert_eq!(last.packets, 30);
+
+        e.encoder_stats_mut().unwrap().reset();
+        assert_eq!(e.encoder_stats().unwrap().snapshot().packets, 0);
+    }
+
+    #[test]
+    fn frame_log() {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats::YUV420;
+        use std::sync::{Arc, Mutex};
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .kf_max_dist(1000)
+            .cpu_used(8);
+        let mut e = cfg.get_encoder().unwrap();
+        let format = Arc::new(*YUV420);
+
+        assert!(e.take_frame_log().is_empty());
+        e.enable_frame_log(64);
+
+        let streamed = Arc::new(Mutex::new(Vec::new()));
+        let mut keys = Vec::new();
+
+        for (i, f) in MovingBox::new(64, 64, &format, &t).take(50).enumerate() {
+            let flags = if i == 20 || i == 40 {
+                EncodeFlags::FORCE_KF
+            } else {
+                EncodeFlags::empty()
+            };
+            e.encode_with_flags(&f, flags).unwrap();
+
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(p) = p {
+                    if p.is_key {
+                        keys.push(p.t.pts.unwrap());
+                    }
+                }
+            }
+
+            // The last frames go to a callback instead
+            if i == 44 {
+                let log = e.take_frame_log();
+
+                assert_eq!(log.len(), 45);
+                assert_eq!(
+                    log.iter()
+                        .filter(|l| l.is_key)
+                        .map(|l| l.pts)
+                        .collect::<Vec<_>>(),
+                    keys
+                );
+                assert!(log.windows(2).all(|w| w[0].pts < w[1].pts));
+                for l in &log {
+                    assert!(l.size > 0);
+                    assert!((0..=255).contains(&l.qindex.unwrap()));
+                    assert_eq!(l.is_key, l.flags.contains(FrameFlags::KEY));
+                }
+
+                let streamed = Arc::clone(&streamed);
+                e.set_frame_log_callback(move |l| streamed.lock().unwrap().push(l.pts));
+            }
+        }
+
+        assert_eq!(keys, [0, 20, 40]);
+        assert!(e.take_frame_log().is_empty());
+        assert_eq!(*streamed.lock().unwrap(), (45..50).collect::<Vec<_>>());
+
+        // The ring keeps the most recent entries
+        e.enable_frame_log(2);
+        for f in MovingBox::new(64, 64, &format, &t).skip(50).take(3) {
+            e.encode(&f).unwrap();
+            while e.get_packet().is_some() {}
+        }
+        let pts: Vec<_> = e.take_frame_log().iter().map(|l| l.pts).collect();
+        assert_eq!(pts, [51, 52]);
+    }
+
+    #[test]
+    fn encode_pipeline() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap());
+        let mut e = cfg.get_encoder().unwrap();
+        e.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, 8)
+            .unwrap();
+
+        let (tx, rx) = e.into_pipeline(4);
+
+        let producer = std::thread::spawn(move || {
+            for i in 0..100 {
+                let mut f = setup_frame(64, 64, &t);
+                f.t.pts = Some(i);
+                tx.send(f).unwrap();
+            }
+            tx.finish();
+        });
+
+        let pts: Vec<_> = rx
+            .filter_map(|p| match p.unwrap() {
+                AOMPacket::Packet(pkt) => pkt.t.pts,
+                _ => None,
+            })
+            .collect();
+
+        producer.join().unwrap();
+
+        assert_eq!(pts, (0..100).collect::<Vec<_>>());
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn encode_codec_trait_options() {
+        use super::AV1_DESCR;
+        use av_codec::common::CodecList;
+        use av_codec::encoder::*;
+        use av_codec::error::*;
+
+        let encoders = Codecs::from_list(&[AV1_DESCR]);
+        let mut ctx = Context::by_name(&encoders, "av1").unwrap();
+
+        ctx.set_option("w", 64u64).unwrap();
+        ctx.set_option("h", 64u64).unwrap();
+        ctx.set_option("timebase", (1, 1000)).unwrap();
+        ctx.set_option("b", 500_000u64).unwrap();
+        ctx.set_option("threads", 4u64).unwrap();
+        ctx.set_option("keyint", 60u64).unwrap();
+        ctx.set_option("cpu-used", 6u64).unwrap();
+        ctx.set_option("usage", "realtime").unwrap();
+        ctx.set_option("lag-in-frames", 0u64).unwrap();
+
+        {
+            let enc = ctx.encoder();
+            assert_eq!(enc.cfg.rc_target_bitrate, 500);
+            assert_eq!(enc.cfg.rc_end_usage, aom_rc_mode::AOM_VBR);
+            assert_eq!(enc.cfg.g_threads, 4);
+            assert_eq!(enc.cfg.kf_max_dist, 60);
+            assert_eq!(enc.cpu_used, Some(6));
+            assert_eq!(enc.cfg.g_usage, AomUsage::RealTime as u32);
+            assert_eq!(enc.cfg.g_lag_in_frames, 0);
+        }
+
+        ctx.set_option("bitrate", 1_000_000u64).unwrap();
+        ctx.set_option("kf-max-dist", 30u64).unwrap();
+        ctx.set_option("speed", 8u64).unwrap();
+        ctx.set_option("usage", 0u64).unwrap();
+
+        {
+            let enc = ctx.encoder();
+            assert_eq!(enc.cfg.rc_target_bitrate, 1000);
+            assert_eq!(enc.cfg.kf_max_dist, 30);
+            assert_eq!(enc.cpu_used, Some(8));
+            assert_eq!(enc.cfg.g_usage, AomUsage::GoodQuality as u32);
+        }
+
+        assert!(matches!(
+            ctx.set_option("threads", "many"),
+            Err(Error::InvalidData)
+        ));
+        assert!(matches!(
+            ctx.set_option("usage", "fast"),
+            Err(Error::InvalidData)
+        ));
+        assert!(matches!(
+            ctx.set_option("no-such-option", 1u64),
+            Err(Error::Unsupported(_))
+        ));
+
+        ctx.configure().unwrap();
+
+        // The string options are forwarded to libaom
+        ctx.set_option("enable-cdef", "0").unwrap();
+        ctx.configure().unwrap();
+        ctx.set_option("no-such-option", "1").unwrap();
+        assert!(ctx.configure().is_err());
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn encode_codec_trait_errors() {
+        use super::AV1_DESCR;
+        use av_codec::common::CodecList;
+        use av_codec::encoder::*;
+        use av_codec::error::*;
+
+        let encoders = Codecs::from_list(&[AV1_DESCR]);
+        let mut ctx = Context::by_name(&encoders, "av1").unwrap();
+
+        let t = TimeInfo {
+            pts: Some(0),
+            ..Default::default()
+        };
+        let f = std::sync::Arc::new(setup_frame(64, 64, &t));
+
+        assert!(matches!(
+            ctx.send_frame(&f),
+            Err(Error::ConfigurationIncomplete)
+        ));
+
+        // libaom copes with odd sizes, an inverted quantizer range is
+        // rejected instead
+        ctx.set_option("w", 64u64).unwrap();
+        ctx.set_option("h", 64u64).unwrap();
+        ctx.set_option("timebase", (1, 1000)).unwrap();
+        ctx.set_option("qmin", 40u64).unwrap();
+        ctx.set_option("qmax", 20u64).unwrap();
+
+        assert!(matches!(ctx.configure(), Err(Error::ConfigurationInvalid)));
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn encode_codec_trait_psnr_flush() {
+        use super::AV1_DESCR;
+        use av_codec::common::CodecList;
+        use av_codec::encoder::*;
+        use av_codec::error::*;
+        use std::sync::Arc;
+
+        let encoders = Codecs::from_list(&[AV1_DESCR]);
+        let mut ctx = Context::by_name(&encoders, "av1").unwrap();
+
+        ctx.set_option("w", 64u64).unwrap();
+        ctx.set_option("h", 64u64).unwrap();
+        ctx.set_option("timebase", (1, 1000)).unwrap();
+        ctx.set_option("cpu-used", 8u64).unwrap();
+        ctx.set_option("lag-in-frames", 10u64).unwrap();
+        ctx.set_option("psnr", true).unwrap();
+
+        ctx.configure().unwrap();
+
+        let mut t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        let mut packets = 0;
+        for i in 0..20 {
+            t.pts = Some(i);
+            let f = Arc::new(setup_frame(64, 64, &t));
+            ctx.send_frame(&f).unwrap();
+
+            while ctx.receive_packet().is_ok() {
+                packets += 1;
+            }
+        }
+
+        // The lag keeps frames in the encoder
+        assert!(packets < 20);
+
+        ctx.flush().unwrap();
+        loop {
+            match ctx.receive_packet() {
+                Ok(_) => packets += 1,
+                Err(Error::MoreDataNeeded) => break,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+
+        assert_eq!(packets, 20);
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn encode_codec_trait_metrics() {
+        use super::AV1_DESCR;
+        use av_codec::common::CodecList;
+        use av_codec::encoder::*;
+        use std::sync::Arc;
+
+        let encoders = Codecs::from_list(&[AV1_DESCR]);
+        let mut ctx = Context::by_name(&encoders, "av1").unwrap();
+
+        ctx.set_option("w", 64u64).unwrap();
+        ctx.set_option("h", 64u64).unwrap();
+        ctx.set_option("timebase", (1, 1000)).unwrap();
+        ctx.set_option("cpu-used", 8u64).unwrap();
+        ctx.set_option("psnr", true).unwrap();
+        ctx.configure().unwrap();
+
+        let mut t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        for i in 0..10 {
+            t.pts = Some(i);
+            ctx.send_frame(&Arc::new(setup_frame(64, 64, &t))).unwrap();
+            while ctx.receive_packet().is_ok() {}
+        }
+
+        ctx.flush().unwrap();
+        while ctx.receive_packet().is_ok() {}
+
+        let psnr = ctx
+            .encoder()
+            .take_metrics()
+            .into_iter()
+            .filter(|p| matches!(p, AOMPacket::PSNR(_)))
+            .count();
+
+        assert_eq!(psnr, 10);
+        assert!(ctx.encoder().take_metrics().is_empty());
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[cfg(feature = "decoder")]
+    #[test]
+    fn encode_codec_trait_reconfigure() {
+        use super::AV1_DESCR;
+        use crate::decoder::AV1Decoder;
+        use av_codec::common::CodecList;
+        use av_codec::encoder::*;
+        use std::sync::Arc;
+
+        let encoders = Codecs::from_list(&[AV1_DESCR]);
+        let mut ctx = Context::by_name(&encoders, "av1").unwrap();
+
+        ctx.set_option("w", 200u64).unwrap();
+        ctx.set_option("h", 200u64).unwrap();
+        ctx.set_option("timebase", (1, 1000)).unwrap();
+        ctx.set_option("cpu-used", 8u64).unwrap();
+        ctx.set_option("trellis", 2u64).unwrap();
+        assert!(ctx.set_option("trellis", 4u64).is_err());
+        ctx.configure().unwrap();
+
+        let mut t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        let mut packets = Vec::new();
+        let mut send = |ctx: &mut Context<_>, w, h, pts| {
+            t.pts = Some(pts);
+            let f = Arc::new(setup_frame(w, h, &t));
+            ctx.send_frame(&f).unwrap();
+        };
+
+        for i in 0..5 {
+            send(&mut ctx, 200, 200, i);
+            while let Ok(p) = ctx.receive_packet() {
+                packets.push(p);
+            }
+        }
+
+        // Only the rate control changes, the encoder is kept
+        ctx.set_option("b", 300_000u64).unwrap();
+        ctx.configure().unwrap();
+        assert_eq!(ctx.encoder().cfg.rc_target_bitrate, 300);
+
+        ctx.set_option("w", 320u64).unwrap();
+        ctx.set_option("h", 240u64).unwrap();
+        ctx.configure().unwrap();
+
+        for i in 5..10 {
+            send(&mut ctx, 320, 240, i);
+            while let Ok(p) = ctx.receive_packet() {
+                packets.push(p);
+            }
+        }
+
+        ctx.flush().unwrap();
+        while let Ok(p) = ctx.receive_packet() {
+            packets.push(p);
+        }
+
+        assert_eq!(packets.len(), 10);
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut sizes = Vec::new();
+        for p in packets {
+            d.decode(&p.data, None).unwrap();
+            while let Some((f, _)) = d.get_frame() {
+                if let MediaKind::Video(ref info) = f.kind {
+                    sizes.push((info.width, info.height));
+                }
+            }
+        }
+
+        let mut expected = vec![(200, 200); 5];
+        expected.extend(vec![(320, 240); 5]);
+        assert_eq!(sizes, expected);
+    }
+
+    #[test]
+    fn hidden_frames() {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats::YUV420;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(35)
+            .push_control_raw(
+                aome_enc_control_id::AOME_SET_ENABLEAUTOALTREF,
+                ControlValue::UInt(1),
+            );
+        let mut e = cfg.get_encoder().unwrap();
+        let format = Arc::new(*YUV420);
+        let mut pts = Vec::new();
+        let mut hidden = 0;
+
+        let mut drain = |e: &mut AV1Encoder| {
+            while let Some((p, info)) = e.get_packet_with_info() {
+                if let AOMPacket::Packet(p) = p {
+                    let info = info.unwrap();
+                    if info.has_hidden_frame() {
+                        // Bundled with the frame the temporal unit shows
+                        assert!(!info.show_existing_frame);
+                        hidden += 1;
+                    }
+                    pts.push(p.t.pts.unwrap());
+                }
+            }
+        };
+
+        // Moving content, so the encoder uses altrefs
+        for f in MovingBox::new(64, 64, &format, &t).take(40) {
+            e.encode(&f).unwrap();
+            drain(&mut e);
+        }
+
+        while e.frames_held() > 0 {
+            e.flush().unwrap();
+            drain(&mut e);
+        }
+
+        assert!(hidden > 0);
+        // One packet per shown frame, with its pts
+        assert_eq!(pts, (0..40).collect::<Vec<_>>());
+
+        let shown = [0x12, 0x00, 0x0a, 0x00, 0x32, 0x01, 0x10];
+        let hidden = [0x12, 0x00, 0x32, 0x01, 0x00, 0x32, 0x01, 0x10];
+        assert_eq!(EncodedFrameInfo::new(0, &shown, false).hidden_frames, 0);
+        assert_eq!(EncodedFrameInfo::new(0, &hidden, false).hidden_frames, 1);
+    }
+
+    #[test]
+    fn reference_control() {
+        let golden = ReferenceControl::new()
+            .only_reference(&[Reference::Golden])
+            .no_update(&[Reference::Last]);
+        let flags = golden.flags().unwrap();
+        assert!(flags.contains(EncodeFlags::NO_REF_LAST | EncodeFlags::NO_REF_ARF));
+        assert!(flags.contains(EncodeFlags::NO_UPD_LAST));
+        assert!(!flags.contains(EncodeFlags::NO_REF_GF));
+        assert!(!flags.contains(EncodeFlags::NO_UPD_GF));
+
+        let err = |refs: ReferenceControl| refs.flags().unwrap_err().kind().is_invalid_param();
+        assert!(err(golden.no_reference(&[Reference::Golden])));
+        assert!(err(golden.update(&[Reference::Golden])));
+        assert!(err(ReferenceControl::new().no_update(&[Reference::Last2])));
+        assert!(err(ReferenceControl::new()
+            .update(&[Reference::Last])
+            .no_update(&[Reference::Last])));
+
+        let svc = golden
+            .no_update(&[Reference::Last2])
+            .update(&[Reference::Golden])
+            .svc_ref_frame_config_raw([0, 1, 2, 3, 4, 5, 6])
+            .unwrap();
+        assert_eq!(svc.reference, [0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(svc.refresh, [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert!(golden
+            .update(&[Reference::Golden])
+            .svc_ref_frame_config_raw([0, 0, 0, 0, 0, 0, 0])
+            .is_err());
+        assert!(golden
+            .svc_ref_frame_config_raw([8, 0, 0, 0, 0, 0, 0])
+            .is_err());
+    }
+
+    #[cfg(feature = "decoder")]
+    #[test]
+    fn encode_intra_only() {
+        use crate::decoder::AV1Decoder;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .kf_mode(KeyframeMode::Disabled)
+            .cpu_used(8);
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 64, &t);
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut types = Vec::new();
+
+        for i in 0..6 {
+            f.t.pts = Some(i);
+            let refs = match i {
+                4 => ReferenceControl::new().intra_only(),
+                _ => ReferenceControl::new(),
+            };
+            e.encode_with_references(&f, &refs).unwrap();
+
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(p) = p {
+                    d.decode(&p.data, None).unwrap();
+                    while let Some((f, _)) = d.get_frame() {
+                        types.push(f.kind.get_video_info().unwrap().frame_type);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(types.len(), 6);
+        assert_eq!(types[0], FrameType::I);
+        assert_eq!(types[3], FrameType::P);
+        assert_eq!(types[4], FrameType::I);
+    }
+
+    #[test]
+    fn encode_force_keyframe() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0);
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 64, &t);
+
+        let mut keys = Vec::new();
+        for i in 0..10 {
+            f.t.pts = Some(i);
+            f.t.duration = Some(2);
+            let flags = if i == 7 {
+                EncodeFlags::FORCE_KF
+            } else {
+                EncodeFlags::empty()
+            };
+            e.encode_with_flags(&f, flags).unwrap();
+
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    if pkt.is_key {
+                        keys.push(pkt.t.pts.unwrap());
+                    }
+                }
+            }
+        }
+
+        // libaom may code the following frame as a keyframe as well
+        assert_eq!(keys[..2], [0, 7]);
+        assert!((EncodeFlags::FORCE_KF | EncodeFlags::NO_REF_LAST).contains(EncodeFlags::FORCE_KF));
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn encode_codec_trait_force_keyframe() {
+        use super::AV1_DESCR;
+        use av_codec::common::CodecList;
+        use av_codec::encoder::*;
+        use av_data::frame::FrameType;
+        use std::sync::Arc;
+
+        let encoders = Codecs::from_list(&[AV1_DESCR]);
+        let mut ctx = Context::by_name(&encoders, "av1").unwrap();
+
+        ctx.set_option("w", 64u64).unwrap();
+        ctx.set_option("h", 64u64).unwrap();
+        ctx.set_option("timebase", (1, 1000)).unwrap();
+        ctx.set_option("lag-in-frames", 0u64).unwrap();
+        ctx.configure().unwrap();
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        let mut keys = Vec::new();
+        for i in 0..10 {
+            let mut f = setup_frame(64, 64, &t);
+            f.t.pts = Some(i);
+            if i == 7 {
+                if let MediaKind::Video(ref mut info) = f.kind {
+                    info.frame_type = FrameType::I;
+                }
+            }
+            ctx.send_frame(&Arc::new(f)).unwrap();
+
+            while let Ok(p) = ctx.receive_packet() {
+                if p.is_key {
+                    keys.push(p.t.pts.unwrap());
+                }
+            }
+        }
+
+        // libaom may code the following frame as a keyframe as well
+        assert_eq!(keys[..2], [0, 7]);
+    }
+
+    #[cfg(all(test, feature = "codec-trait"))]
+    #[test]
+    fn encode_codec_trait_params() {
+        use super::AV1_DESCR;
+        use av_codec::common::CodecList;
+        use av_codec::encoder::*;
+        use av_data::frame::VideoInfo;
+        use av_data::params::MediaKind;
+        use av_data::pixel::formats::YUV444_10;
+        use std::sync::Arc;
+
+        let encoders = Codecs::from_list(&[AV1_DESCR]);
+        let mut ctx = Context::by_name(&encoders, "av1").unwrap();
+
+        ctx.set_option("w", 64u64).unwrap();
+        ctx.set_option("h", 64u64).unwrap();
+        ctx.set_option("timebase", (1, 1000)).unwrap();
+        ctx.set_option("b", 400_000u64).unwrap();
+        ctx.set_option("lag-in-frames", 10u64).unwrap();
+        ctx.set_option("qmin", 0u64).unwrap();
+        ctx.set_option("qmax", 0u64).unwrap();
+        ctx.set_option("format", Arc::new(*YUV444_10)).unwrap();
+
+        ctx.configure().unwrap();
+
+        {
+            let enc = ctx.encoder();
+            assert_eq!(enc.cfg.g_profile, BitstreamProfile::Profile1 as u32);
+            assert_eq!(enc.cfg.g_bit_depth, 10);
+        }
+
+        let params = ctx.get_params().unwrap();
+        match params.kind {
+            Some(MediaKind::Video(ref info)) => {
+                assert_eq!(info.format.as_deref(), Some(YUV444_10));
+            }
+            _ => panic!("Not video params"),
+        }
+        assert_eq!(params.bit_rate, 400_000);
+        assert_eq!(params.delay, 10);
+
+        let extradata = ctx.get_extradata().unwrap();
+        assert!(!extradata.is_empty());
+        assert_eq!(params.extradata, Some(extradata));
+
+        // A 10-bit frame, 2 bytes per sample
+        let t = TimeInfo {
+            pts: Some(0),
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let v = VideoInfo::new(64, 64, false, FrameType::OTHER, Arc::new(*YUV444_10));
+        let mut f = new_frame(v, Some(t));
+        for i in 0..3 {
+            let stride = f.buf.linesize(i).unwrap();
+            let data: &mut [u8] = f.buf.as_mut_slice(i).unwrap();
+            for (y, row) in data.chunks_mut(stride).take(64).enumerate() {
+                for (x, s) in row[..128].chunks_exact_mut(2).enumerate() {
+                    let sample = if i == 0 { (x + y) as u16 * 8 } else { 512 };
+                    s.copy_from_slice(&sample.to_ne_bytes());
+                }
+            }
+        }
+        let f = Arc::new(f);
+
+        ctx.send_frame(&f).unwrap();
+        ctx.flush().unwrap();
+        let mut packets = Vec::new();
+        while let Ok(p) = ctx.receive_packet() {
+            packets.push(p);
+        }
+        assert_eq!(packets.len(), 1);
+
+        // The lossless encoding gives the samples back
+        #[cfg(feature = "decoder")]
+        {
+            use crate::decoder::AV1Decoder;
+
+            let mut d = AV1Decoder::<()>::new().unwrap();
+            d.decode(&packets[0].data, None).unwrap();
+            let (out, _) = d.get_frame().unwrap();
+            match out.kind {
+                av_data::frame::MediaKind::Video(ref info) => {
+                    assert_eq!(info.format.get_chromaton(0).unwrap().get_depth(), 10);
+                }
+                _ => panic!("Not a video frame"),
+            }
+
+            for i in 0..3 {
+                let (a, b) = (f.buf.linesize(i).unwrap(), out.buf.linesize(i).unwrap());
+                let src: &[u8] = f.buf.as_slice(i).unwrap();
+                let dec: &[u8] = out.buf.as_slice(i).unwrap();
+                for (s, d) in src.chunks(a).zip(dec.chunks(b)).take(64) {
+                    assert_eq!(s[..128], d[..128]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_packet_kind() {
+        let mut raw: aom_codec_cx_pkt = unsafe { mem::zeroed() };
+        raw.kind = 42;
+        unsafe { raw.data.pad[0] = 7 };
+
+        match AOMPacket::new(raw) {
+            AOMPacket::Unknown { kind, data } => {
+                assert_eq!(kind, 42);
+                assert_eq!(data[0], 7);
+            }
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    #[allow(clippy::unnecessary_cast)]
+    fn packet_kinds() {
+        let data = [1u8, 2, 3];
+        let buf = aom_fixed_buf_t {
+            buf: data.as_ptr() as *mut raw::c_void,
+            sz: data.len(),
+        };
+        let packet = |kind, fill: &dyn Fn(&mut aom_codec_cx_pkt)| {
+            let mut raw: aom_codec_cx_pkt = unsafe { mem::zeroed() };
+            raw.kind = kind;
+            fill(&mut raw);
+
+            AOMPacket::new(raw)
+        };
+
+        let p = packet(aom_codec_cx_pkt_kind::AOM_CODEC_CX_FRAME_PKT, &|raw| {
+            raw.data.frame.buf = buf.buf;
+            raw.data.frame.sz = buf.sz;
+            raw.data.frame.pts = 5;
+            raw.data.frame.flags = AOM_FRAME_IS_KEY as aom_codec_frame_flags_t;
+        });
+        assert!(matches!(&p, AOMPacket::Packet(p) if p.is_key && p.t.pts == Some(5)));
+        assert_eq!(p.payload(), Some(&data[..]));
+
+        let p = packet(aom_codec_cx_pkt_kind::AOM_CODEC_STATS_PKT, &|raw| {
+            raw.data.twopass_stats = buf;
+        });
+        assert!(matches!(p, AOMPacket::Stats(_)));
+        assert_eq!(p.payload(), Some(&data[..]));
+
+        let p = packet(aom_codec_cx_pkt_kind::AOM_CODEC_FPMB_STATS_PKT, &|raw| {
+            raw.data.firstpass_mb_stats = buf;
+        });
+        assert!(matches!(p, AOMPacket::MBStats(_)));
+        assert_eq!(p.payload(), Some(&data[..]));
+
+        let p = packet(aom_codec_cx_pkt_kind::AOM_CODEC_PSNR_PKT, &|raw| {
+            raw.data.psnr.psnr = [40.0, 41.0, 42.0, 43.0];
+        });
+        assert!(matches!(&p, AOMPacket::PSNR(q) if q.psnr[3] == 43.0));
+        assert_eq!(p.payload(), None);
+
+        let p = packet(aom_codec_cx_pkt_kind::AOM_CODEC_CUSTOM_PKT, &|raw| {
+            raw.data.raw = buf;
+        });
+        assert!(matches!(p, AOMPacket::Custom(_)));
+        assert_eq!(p.payload(), Some(&data[..]));
+
+        for kind in [
+            aom_codec_cx_pkt_kind::AOM_CODEC_CX_FRAME_PKT,
+            aom_codec_cx_pkt_kind::AOM_CODEC_STATS_PKT,
+            aom_codec_cx_pkt_kind::AOM_CODEC_FPMB_STATS_PKT,
+            aom_codec_cx_pkt_kind::AOM_CODEC_PSNR_PKT,
+            aom_codec_cx_pkt_kind::AOM_CODEC_CUSTOM_PKT,
+            42,
+        ] {
+            assert_eq!(packet(kind, &|_| {}).kind(), kind as u32);
+        }
+    }
+
+    #[test]
+    fn null_packet_buffer() {
+        let mut raw: aom_codec_cx_pkt = unsafe { mem::zeroed() };
+        raw.kind = aom_codec_cx_pkt_kind::AOM_CODEC_CX_FRAME_PKT;
+        raw.data.frame.sz = 16;
+
+        match AOMPacket::new(raw) {
+            AOMPacket::Packet(p) => assert!(p.data.is_empty()),
+            p => panic!("Unexpected packet {:?}", p),
+        }
+
+        raw.kind = aom_codec_cx_pkt_kind::AOM_CODEC_STATS_PKT;
+        raw.data.twopass_stats.sz = 16;
+        match AOMPacket::new(raw) {
+            AOMPacket::Stats(b) => assert!(b.is_empty()),
+            p => panic!("Unexpected packet {:?}", p),
+        }
+    }
+
+    #[test]
+    fn img_from_odd_frames() {
+        use av_data::frame::{FrameBuffer, FrameError, FrameType, VideoInfo};
+        use av_data::pixel::formats::YUV444;
+        use std::sync::Arc;
+
+        struct OddBuffer(Vec<(Vec<u8>, usize)>);
+
+        impl FrameBuffer for OddBuffer {
+            fn linesize(&self, idx: usize) -> Result<usize, FrameError> {
+                self.0.get(idx).map(|p| p.1).ok_or(FrameError::InvalidIndex)
+            }
+            fn count(&self) -> usize {
+                self.0.len()
+            }
+            fn as_slice_inner(&self, idx: usize) -> Result<&[u8], FrameError> {
+                self.0
+                    .get(idx)
+                    .map(|p| p.0.as_slice())
+                    .ok_or(FrameError::InvalidIndex)
+            }
+            fn as_mut_slice_inner(&mut self, idx: usize) -> Result<&mut [u8], FrameError> {
+                self.0
+                    .get_mut(idx)
+                    .map(|p| p.0.as_mut_slice())
+                    .ok_or(FrameError::InvalidIndex)
+            }
+        }
+
+        let t = TimeInfo {
+            pts: Some(0),
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut e = setup(64, 64, &t);
+        let frame = |planes: Vec<(Vec<u8>, usize)>| {
+            let mut f = setup_frame(64, 64, &t);
+            f.buf = Box::new(OddBuffer(planes));
+            f
+        };
+        let plane = |stride: usize, rows: usize| (vec![0u8; stride * rows], stride);
+
+        let good = frame(vec![plane(64, 64), plane(32, 32), plane(32, 32)]);
+        assert!(img_from_frame(&good).is_ok());
+        e.encode(&good).unwrap();
+
+        let odd = [
+            // Alpha plane
+            vec![plane(64, 64), plane(32, 32), plane(32, 32), plane(64, 64)],
+            // Missing plane
+            vec![plane(64, 64), plane(32, 32)],
+            // Short plane
+            vec![plane(64, 64), plane(32, 31), plane(32, 32)],
+            // Stride smaller than the width
+            vec![plane(64, 64), plane(32, 32), plane(16, 64)],
+            vec![],
+        ];
+
+        for planes in odd {
+            let f = frame(planes);
+            let err = img_from_frame(&f).err().unwrap();
+            assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+            assert!(e.encode(&f).is_err());
+        }
+
+        let v = VideoInfo::new(64, 64, false, FrameType::OTHER, Arc::new(*YUV444));
+        let f = Frame::new_default_frame(v, Some(t.clone()));
+        assert!(e.encode(&f).is_err());
+    }
+
+    #[test]
+    fn encode_without_draining() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0);
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 64, &t);
+        let mut pts = Vec::new();
+
+        let mut drain = |e: &mut AV1Encoder| {
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(p) = p {
+                    pts.push(p.t.pts.unwrap());
+                }
+            }
+        };
+
+        for i in 0..10 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            // The packets of the other calls are kept for the next drain
+            if i % 3 == 0 {
+                drain(&mut e, &mut received);
+            }
+        }
+
+        e.flush().unwrap();
+        drain(&mut e, &mut received);
+
+        assert_eq!(pts, (0..10).collect::<Vec<_>>());
+        assert_eq!(e.stats().packets_emitted, 10);
+    }
+
+    #[test]
+    fn encode_keyframe_policy() {
+        use std::time::Duration;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0)
+            .kf_max_dist(1000);
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 64, &t);
+        let mut keys = Vec::new();
+
+        e.set_keyframe_policy(Some(KeyframePolicy::EveryDuration(Duration::from_secs(4))));
+        assert!(e.keyframe_policy().is_some());
+
+        for i in 0..360 {
+            if i == 300 {
+                e.set_keyframe_policy(Some(KeyframePolicy::AtPts(vec![330])));
+            }
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(p) = p {
+                    if p.is_key {
+                        keys.push(p.t.pts.unwrap());
+                    }
+                }
+            }
+        }
+
+        assert_eq!(keys, [0, 120, 240, 330]);
+
+        e.set_keyframe_policy(None);
+        assert!(e.keyframe_policy().is_none());
+    }
+
+    #[test]
+    fn encode_error_detail() {
+        use crate::common::AomErrorKind;
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .rc_min_quantizer(40)
+            .rc_max_quantizer(20);
+
+        let err = cfg.get_encoder().err().unwrap();
+        assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+        assert!(err.detail().unwrap().contains("rc_min_quantizer"));
+        assert!(err.to_string().contains("rc_min_quantizer"));
+    }
+
+    #[test]
+    fn encode_rescaled_timestamps() {
+        let ms = Rational64::new(1, 1000);
+        let t = TimeInfo {
+            timebase: Some(ms),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 30))
+            .lag_in_frames(0)
+            .rescale_timestamps(true)
+            .validate_pts(true);
+
+        let tb = cfg.encoder_timebase();
+        assert_eq!(tb.as_rational(), Rational64::new(1, 30));
+        assert_eq!(tb.rescale(1000, ms), 30);
+        assert_eq!(tb.rescale(-33, ms), -1);
+        assert_eq!(tb.rescale_duration(1, ms), 1);
+
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 64, &t);
+        let mut out = Vec::new();
+
+        for i in 0..30 {
+            f.t.pts = Some(i * 1000 / 30);
+            f.t.duration = Some(33);
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(p) = p {
+                    out.push((p.t.pts.unwrap(), p.t.duration.unwrap()));
+                }
+            }
+        }
+
+        let expected: Vec<_> = (0..30).map(|i| (i, 1)).collect();
+        assert_eq!(out, expected);
+
+        // 967ms rounds to 29, sent already
+        f.t.pts = Some(967);
+        let err = e.encode(&f).err().unwrap();
+        assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+
+        f.t.pts = None;
+        assert!(e.encode(&f).is_err());
+
+        f.t.pts = Some(1000);
+        e.encode(&f).unwrap();
+    }
+
+    #[test]
+    fn encode_without_pts() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut e = setup(64, 64, &t);
+        let f = setup_frame(64, 64, &t);
+
+        let err = e.encode(&f).err().unwrap();
+        assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+    }
+
+    #[test]
+    fn timebase_rational() {
+        let tb = rational_to_raw(Rational64::new(2_000_000_000, 4_000_000_000)).unwrap();
+        assert_eq!((tb.num, tb.den), (1, 2));
+        assert!(rational_to_raw(Rational64::new(1, 1 << 40)).is_err());
+
+        let cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .g_timebase_rational(Rational64::new(1, 90000))
+            .unwrap();
+        assert_eq!(cfg.get_timebase(), Rational64::new(1, 90000));
+
+        let err = cfg
+            .g_timebase_rational(Rational64::new(1, 3_000_000_000))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+    }
+
+    #[test]
+    fn safe_settings() {
+        let cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .bit_depth(BitDepth::Ten)
+            .error_resilient(true)
+            .pass(EncodePass::LastPass)
+            .rc_end_usage(RateControl::Q)
+            .rc_superres_mode(SuperresMode::QThresh)
+            .kf_mode(KeyframeMode::Disabled);
+
+        assert_eq!(cfg.g_bit_depth, aom_bit_depth::AOM_BITS_10);
+        assert_eq!(cfg.g_error_resilient, AOM_ERROR_RESILIENT_DEFAULT);
+        assert_eq!(cfg.g_pass, aom_enc_pass::AOM_RC_LAST_PASS);
+        assert_eq!(cfg.rc_end_usage, aom_rc_mode::AOM_Q);
+        assert_eq!(
+            cfg.rc_superres_mode,
+            aom_superres_mode::AOM_SUPERRES_QTHRESH
+        );
+        assert_eq!(cfg.kf_mode, aom_kf_mode::AOM_KF_DISABLED);
+    }
+
+    #[test]
+    fn close() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(35);
+        let mut f = setup_frame(64, 64, &t);
+
+        // Dropped with the frames held
+        let mut e = cfg.get_encoder().unwrap();
+        for i in 0..10 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+        }
+        assert!(e.frames_held() > 0);
+        drop(e);
+
+        let mut e = cfg.get_encoder().unwrap();
+        for i in 0..10 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+        }
+        let pts: Vec<_> = e
+            .close()
+            .unwrap()
+            .into_iter()
+            .filter_map(|p| match p {
+                AOMPacket::Packet(p) => p.t.pts,
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(pts, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn user_data() {
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(16)
+            .cpu_used(8);
+        let mut f = setup_frame(64, 64, &t);
+        let token = Arc::new(());
+
+        let mut e = cfg.get_encoder().unwrap();
+        let mut received = Vec::new();
+        let drain = |e: &mut AV1Encoder, received: &mut Vec<u64>| {
+            while let Some((p, data)) = e.get_packet_with_user_data::<(u64, Arc<()>)>() {
+                if let AOMPacket::Packet(p) = p {
+                    let (seq, _) = data.unwrap();
+                    assert_eq!(seq, p.t.pts.unwrap() as u64 * 10);
+                    received.push(seq);
+                }
+            }
+        };
+
+        for i in 0..20 {
+            f.t.pts = Some(i);
+            e.encode_with_user_data(&f, (i as u64 * 10, Arc::clone(&token)))
+                .unwrap();
+            drain(&mut e, &mut received);
+        }
+        // The lag holds the frames back
+        assert!(received.len() < 20);
+
+        e.flush().unwrap();
+        drain(&mut e, &mut received);
+        assert_eq!(received, (0..20).map(|i| i * 10).collect::<Vec<_>>());
+        assert_eq!(Arc::strong_count(&token), 1);
+
+        // Dropped along with the frames held
+        let mut e = cfg.get_encoder().unwrap();
+        for i in 0..10 {
+            f.t.pts = Some(i);
+            e.encode_with_user_data(&f, Arc::clone(&token)).unwrap();
+        }
+        assert!(Arc::strong_count(&token) > 1);
+        drop(e);
+        assert_eq!(Arc::strong_count(&token), 1);
+    }
+
+    #[test]
+    fn simulcast() {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats::YUV420;
+        use std::collections::BTreeSet;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let rendition = |w, h| {
+            AV1EncoderConfig::new()
+                .unwrap()
+                .width(w)
+                .height(h)
+                .timebase(t.timebase.unwrap())
+                .lag_in_frames(0)
+                .kf_max_dist(8)
+                .cpu_used(8)
+        };
+
+        let mut s = Simulcast::new(&[rendition(640, 360), rendition(320, 180)]).unwrap();
+        let frames = MovingBox::new(1280, 720, &Arc::new(*YUV420), &t);
+        let mut keys = [BTreeSet::new(), BTreeSet::new()];
+        let mut count = [0, 0];
+
+        // Moving content, so the encoders do not skip the frames
+        for (i, f) in frames.take(20).enumerate() {
+            let flags = if i == 13 {
+                EncodeFlags::FORCE_KF
+            } else {
+                EncodeFlags::empty()
+            };
+            s.encode_with_flags(&f, flags).unwrap();
+
+            while let Some((idx, p)) = s.get_packet() {
+                if let AOMPacket::Packet(p) = p {
+                    count[idx] += 1;
+                    if p.is_key {
+                        keys[idx].insert(p.t.pts.unwrap());
+                    }
+                }
+            }
+        }
+
+        assert_eq!(count, [20, 20]);
+        assert_eq!(keys[0], keys[1]);
+        assert_eq!(keys[0], [0, 8, 13].into_iter().collect());
+    }
+
+    #[test]
+    fn encode_deterministic() {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats::YUV420;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        let encode = || {
+            let mut e = AV1EncoderConfig::new()
+                .unwrap()
+                .width(128)
+                .height(128)
+                .timebase(t.timebase.unwrap())
+                .threads(8)
+                .deterministic()
+                .get_encoder()
+                .unwrap();
+            for f in MovingBox::new(128, 128, &Arc::new(*YUV420), &t).take(20) {
+                e.encode(&f).unwrap();
+            }
+
+            e.close()
+                .unwrap()
+                .into_iter()
+                .filter_map(|p| match p {
+                    AOMPacket::Packet(p) => Some(p.data),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = encode();
+        assert_eq!(first.len(), 20);
+        assert_eq!(first, encode());
+    }
+
+    #[test]
+    fn two_pass_chunks() {
+        use crate::testing::moving_box;
+        use av_data::pixel::formats::YUV420;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .rc_end_usage(RateControl::Vbr)
+            .rc_target_bitrate(100)
+            .cpu_used(6);
+
+        let format = Arc::new(*YUV420);
+        let frame = |i: i64| {
+            let mut f = moving_box(64, 64, &format, i as usize);
+            f.t = TimeInfo {
+                pts: Some(i),
+                ..t.clone()
+            };
+            f
+        };
+
+        // First pass over each half of the clip, as separate workers would
+        let chunks = [0..10, 10..20]
+            .into_iter()
+            .map(|range| {
+                let mut e = cfg
+                    .clone()
+                    .pass(EncodePass::FirstPass)
+                    .get_encoder()
+                    .unwrap();
+                for i in range {
+                    e.encode(&frame(i)).unwrap();
+                }
+
+                let mut stats = TwoPassStats::new();
+                for p in e.close().unwrap() {
+                    if let AOMPacket::Stats(b) = p {
+                        stats.push(&b).unwrap();
+                    }
+                }
+                stats
+            })
+            .collect::<Vec<_>>();
+
+        // A record per frame and the EOS one
+        assert!(chunks.iter().all(|c| c.len() == 11));
+
+        let stats = TwoPassStats::merge(chunks).unwrap();
+        assert_eq!(stats.len(), 21);
+
+        let mut e = cfg
+            .clone()
+            .pass(EncodePass::LastPass)
+            .rc_twopass_stats_in_raw(stats.as_fixed_buf_raw())
+            .get_encoder()
+            .unwrap();
+        for i in 0..20 {
+            e.encode(&frame(i)).unwrap();
+        }
+
+        let packets = e
+            .close()
+            .unwrap()
+            .into_iter()
+            .filter(|p| matches!(p, AOMPacket::Packet(_)))
+            .count();
+        assert_eq!(packets, 20);
+
+        let mut bad = TwoPassStats::new();
+        bad.push(&[0; 16]).unwrap();
+        assert!(bad.push(&[0; 24]).unwrap_err().kind().is_invalid_param());
+        assert!(bad.push(&[0; 7]).unwrap_err().kind().is_invalid_param());
+    }
+
+    #[test]
+    fn runtime_version_mismatch() {
+        use crate::common::{
+            check_runtime_version, headers_version, runtime_version, MOCK_RUNTIME_VERSION,
+        };
+
+        assert!(runtime_version() >= (3, 0, 0));
+        check_runtime_version().unwrap();
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .cpu_used(6);
+
+        MOCK_RUNTIME_VERSION.with(|v| v.set(Some((2, 0, 0))));
+
+        let err = cfg.get_encoder().err().unwrap();
+        assert_eq!(err, AomError::UnsupportedByLibaom { needs: "3.0.0" });
+        assert_eq!(err.kind(), AomErrorKind::UnsupFeature);
+        assert!(err
+            .to_string()
+            .ends_with("needs libaom 3.0.0, running 2.0.0"));
+
+        let err = check_runtime_version().unwrap_err();
+        assert_eq!(err.kind(), AomErrorKind::AbiMismatch);
+
+        let (major, minor, _) = headers_version();
+        MOCK_RUNTIME_VERSION.with(|v| v.set(Some((major + 1, minor, 0))));
+        assert!(check_runtime_version().is_err());
+
+        MOCK_RUNTIME_VERSION.with(|v| v.set(None));
+        cfg.get_encoder().unwrap();
+    }
+
+    #[test]
+    fn iface() {
+        use crate::common::Iface;
+
+        let iface = Iface::av1_encoder().unwrap();
+        assert!(iface.name().contains("AV1"));
+        assert!(iface.is_encoder());
+        assert!(!iface.is_decoder());
+        assert!(unsafe { Iface::from_raw(ptr::null()) }.is_none());
+
+        let mut cfg = unsafe {
+            AV1EncoderConfig::new()
+                .unwrap()
+                .width(64)
+                .height(64)
+                .timebase(Rational64::new(1, 1000))
+                .with_iface(iface.as_raw())
+        };
+        assert_eq!(cfg.iface(), iface);
+
+        let t = TimeInfo {
+            pts: Some(0),
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut e = cfg.get_encoder().unwrap();
+        e.encode(&setup_frame(64, 64, &t)).unwrap();
+    }
+
+    #[test]
+    fn interface_unavailable() {
+        use crate::common::{encoder_available, MOCK_MISSING_CAPS};
+
+        assert!(encoder_available());
+
+        let mut cfg = AV1EncoderConfig::new().unwrap().width(64).height(64);
+
+        // A decoder only libaom
+        MOCK_MISSING_CAPS.with(|c| c.set(AOM_CODEC_CAP_ENCODER as aom_codec_caps_t));
+
+        assert!(!encoder_available());
+        let err = AV1EncoderConfig::new().err().unwrap();
+        assert_eq!(err, AomError::InterfaceUnavailable { codec: "encoder" });
+        assert_eq!(err.kind(), AomErrorKind::Incapable);
+        assert!(err
+            .to_string()
+            .ends_with("no AV1 encoder interface, libaom may be built without it"));
+        assert_eq!(cfg.get_encoder().err(), Some(err));
+
+        #[cfg(feature = "decoder")]
+        {
+            use crate::common::decoder_available;
+            use crate::decoder::AV1Decoder;
+
+            assert!(decoder_available());
+            AV1Decoder::<()>::new().unwrap();
+
+            MOCK_MISSING_CAPS.with(|c| c.set(AOM_CODEC_CAP_DECODER as aom_codec_caps_t));
+            assert!(!decoder_available());
+            let err = AV1Decoder::<()>::new().err().unwrap();
+            assert_eq!(err, AomError::InterfaceUnavailable { codec: "decoder" });
+        }
+
+        MOCK_MISSING_CAPS.with(|c| c.set(0));
+        cfg.get_encoder().unwrap();
+    }
+
+    #[test]
+    fn supported_usages() {
+        use crate::common::runtime_version;
+
+        let usages = super::supported_usages();
+
+        #[cfg(feature = "realtime-only")]
+        assert_eq!(usages, [AomUsage::RealTime]);
+        #[cfg(not(feature = "realtime-only"))]
+        {
+            assert_eq!(usages[..2], [AomUsage::GoodQuality, AomUsage::RealTime]);
+            assert!(usages.len() <= 3);
+            assert_eq!(
+                usages.contains(&AomUsage::AllIntra),
+                runtime_version() >= (3, 1, 0)
+            );
+        }
+
+        for usage in usages {
+            let cfg = AV1EncoderConfig::new_with_usage(usage).unwrap();
+            assert_eq!(cfg.g_usage, usage as u32);
+        }
+    }
+
+    #[test]
+    fn set_option_str() {
+        let mut e = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .get_encoder()
+            .unwrap();
+
+        e.set_option_str("cpu-used", "8").unwrap();
+        e.set_option_str("enable-cdef", "0").unwrap();
+
+        assert!(e.set_option_str("no-such-option", "1").is_err());
+        let err = e.set_option_str("cpu\0used", "8").err().unwrap();
+        assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+    }
+
+    #[cfg(any(debug_assertions, feature = "validate-controls"))]
+    #[test]
+    fn validate_controls() {
+        use crate::common::AomErrorKind;
+        use aome_enc_control_id::*;
+
+        let t = TimeInfo::default();
+        let mut e = setup(64, 64, &t);
+
+        e.control_raw(AOME_SET_SHARPNESS, 7).unwrap();
+
+        let err = e.control_raw(AOME_SET_SHARPNESS, 42).err().unwrap();
+        assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+        assert_eq!(
+            err.detail().unwrap(),
+            "AOME_SET_SHARPNESS: 42 out of range 0..=7"
+        );
+
+        // Not in the table, left to libaom
+        e.control_raw(AV1E_SET_ENABLE_DUAL_FILTER, 1).unwrap();
+
+        // Checked by libaom only
+        e.control_raw(AOME_SET_CPUUSED, 6).unwrap();
+        assert!(e.control_raw(AOME_SET_CPUUSED, -1).is_err());
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .tile_columns(9);
+        let err = cfg.get_encoder().err().unwrap();
+        assert!(err.detail().unwrap().starts_with("AV1E_SET_TILE_COLUMNS"));
+    }
+
+    #[test]
+    fn quality_controls() {
+        use aome_enc_control_id::*;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        for (tpl, kf, boost) in [
+            (true, KeyframeFiltering::Disabled, false),
+            (false, KeyframeFiltering::Enabled, true),
+            (true, KeyframeFiltering::Overlay, true),
+        ] {
+            let mut cfg = AV1EncoderConfig::new()
+                .unwrap()
+                .width(64)
+                .height(64)
+                .timebase(t.timebase.unwrap())
+                .cpu_used(6)
+                .tpl_model(tpl)
+                .keyframe_filtering(kf)
+                .frame_periodic_boost(boost);
+
+            assert_eq!(
+                &cfg.controls_raw()[1..],
+                &[
+                    (AV1E_SET_ENABLE_TPL_MODEL, ControlValue::UInt(tpl as u32)),
+                    (
+                        AV1E_SET_ENABLE_KEYFRAME_FILTERING,
+                        ControlValue::UInt(kf as u32)
+                    ),
+                    (
+                        AV1E_SET_FRAME_PERIODIC_BOOST,
+                        ControlValue::UInt(boost as u32)
+                    ),
+                ]
+            );
+
+            let mut e = cfg.get_encoder().unwrap();
+            let mut f = setup_frame(64, 64, &t);
+            for i in 0..5 {
+                f.t.pts = Some(i);
+                e.encode(&f).unwrap();
+            }
+            let packets = e.close().unwrap();
+            assert!(packets.iter().any(|p| matches!(p, AOMPacket::Packet(_))));
+        }
+    }
+
+    #[test]
+    fn trellis_quant() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        for trellis in [
+            TrellisQuant::Enabled,
+            TrellisQuant::Disabled,
+            TrellisQuant::DisabledInRdSearch,
+            TrellisQuant::DisabledInYrdEstimate,
+        ] {
+            let mut cfg = AV1EncoderConfig::low_latency()
+                .unwrap()
+                .width(64)
+                .height(64)
+                .timebase(t.timebase.unwrap())
+                .cpu_used(8)
+                .trellis_quant(trellis);
+            let mut e = cfg.get_encoder().unwrap();
+            let mut f = setup_frame(64, 64, &t);
+
+            for i in 0..3 {
+                f.t.pts = Some(i);
+                e.encode(&f).unwrap();
+                assert!(matches!(e.get_packet(), Some(AOMPacket::Packet(_))));
+            }
+        }
+    }
+
+    #[cfg(feature = "decoder")]
+    #[test]
+    fn encode_superres() {
+        use crate::decoder::AV1Decoder;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(128)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .cpu_used(6)
+            .rc_superres_mode(SuperresMode::Fixed)
+            .rc_superres_denominator(16)
+            .rc_superres_kf_denominator(16);
+
+        let mut f = setup_frame(128, 64, &t);
+        let mut e = cfg.clone().get_encoder().unwrap();
+        for i in 0..5 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+        }
+
+        // The frames are coded at half the width and upscaled back
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut sizes = Vec::new();
+        for p in e.close().unwrap() {
+            if let AOMPacket::Packet(p) = p {
+                d.decode(&p.data, None).unwrap();
+                while let Some((f, _)) = d.get_frame() {
+                    let info = f.kind.get_video_info().unwrap();
+                    sizes.push((info.width, info.height));
+                }
+            }
+        }
+        assert_eq!(sizes, vec![(128, 64); 5]);
+
+        let invalid = |mut cfg: AV1EncoderConfig| {
+            cfg.get_encoder()
+                .err()
+                .is_some_and(|e| e.kind().is_invalid_param())
+        };
+        assert!(invalid(cfg.clone().rc_superres_denominator(17)));
+        assert!(invalid(
+            cfg.clone()
+                .rc_superres_mode(SuperresMode::QThresh)
+                .rc_superres_qthresh(40)
+        ));
+        assert!(!invalid(
+            cfg.rc_superres_mode(SuperresMode::QThresh)
+                .rc_superres_denominator(8)
+                .rc_superres_kf_denominator(8)
+                .rc_superres_qthresh(40)
+        ));
+    }
+
+    #[cfg(feature = "decoder")]
+    #[test]
+    fn color_description() {
+        use crate::decoder::AV1Decoder;
+        use av_data::frame::VideoInfo;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let bt709 = (
+            ColorPrimaries::BT709,
+            TransferCharacteristic::BT709,
+            MatrixCoefficients::BT709,
+        );
+        let bt2020 = (
+            ColorPrimaries::BT2020,
+            TransferCharacteristic::BT2020Ten,
+            MatrixCoefficients::BT2020NonConstantLuminance,
+        );
+
+        type Description = (ColorPrimaries, TransferCharacteristic, MatrixCoefficients);
+
+        let decoded = |cfg: AV1EncoderConfig, (cp, tc, mc): Description| {
+            let mut fmt = *formats::YUV420;
+            fmt.primaries = cp;
+            fmt.xfer = tc;
+            fmt.matrix = mc;
+            let info = VideoInfo::new(64, 64, false, FrameType::OTHER, Arc::new(fmt));
+            let mut f = Frame::new_default_frame(info, Some(t.clone()));
+
+            let mut e = cfg
+                .width(64)
+                .height(64)
+                .timebase(t.timebase.unwrap())
+                .cpu_used(8)
+                .get_encoder()
+                .unwrap();
+            f.t.pts = Some(0);
+            e.encode(&f).unwrap();
+
+            let mut d = AV1Decoder::<()>::new().unwrap();
+            for p in e.close().unwrap() {
+                if let AOMPacket::Packet(p) = p {
+                    d.decode(&p.data, None).unwrap();
+                }
+            }
+            d.flush().unwrap();
+
+            let (f, _) = d.get_frame().unwrap();
+            let fmt = &f.kind.get_video_info().unwrap().format;
+            (fmt.get_primaries(), fmt.get_xfer(), fmt.get_matrix())
+        };
+
+        // Taken from the frames
+        let cfg = AV1EncoderConfig::low_latency().unwrap();
+        assert_eq!(decoded(cfg.clone(), bt709), bt709);
+        assert_eq!(decoded(cfg.clone(), bt2020), bt2020);
+
+        // The configured one wins
+        let cfg = cfg.color_description(bt2020.0, bt2020.1, bt2020.2);
+        assert_eq!(decoded(cfg, bt709), bt2020);
+    }
+
+    #[test]
+    fn packet_timestamps() {
+        use crate::testing::moving_box;
+        use av_data::pixel::formats::YUV420;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(35)
+            .cpu_used(6);
+        let mut e = cfg.get_encoder().unwrap();
+        e.set_stream_index(3);
+        let format = Arc::new(*YUV420);
+
+        for i in 0..40 {
+            // Moving content, so the encoder uses altrefs
+            let mut f = moving_box(64, 64, &format, i);
+            f.t = TimeInfo {
+                pts: Some(i as i64 * 40),
+                ..t.clone()
+            };
+            e.encode(&f).unwrap();
         }
 
-        fn set_params(&mut self, params: &CodecParams) -> Result<()> {
-            if let Some(MediaKind::Video(ref info)) = params.kind {
-                self.cfg.g_w = info.width as u32;
-                self.cfg.g_h = info.height as u32;
+        let mut dts = Vec::new();
+        for p in e.close().unwrap() {
+            if let AOMPacket::Packet(p) = p {
+                assert_eq!(p.stream_index, 3);
+                assert_eq!(p.t.timebase, t.timebase);
+                assert!(p.t.dts.unwrap() <= p.t.pts.unwrap());
+                dts.push(p.t.dts.unwrap());
             }
-            Ok(())
         }
+
+        assert_eq!(dts.len(), 40);
+        assert!(dts.windows(2).all(|w| w[0] < w[1]));
     }
 
-    /// AV1 Encoder
-    ///
-    /// To be used with [av-codec](https://docs.rs/av-codec) `Encoder Context`.
-    pub const AV1_DESCR: &Des = &Des {
-        descr: Descr {
-            codec: "av1",
-            name: "aom",
-            desc: "libaom AV1 encoder",
-            mime: "video/AV1",
-        },
-    };
-}
+    #[test]
+    fn encoder_cfg_options() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let tools = EncoderCfgOptions::new()
+            .superblock_size(Some(64))
+            .partition_sizes(8, 32)
+            .disable(&[
+                CodingTool::Cdef,
+                CodingTool::LoopRestoration,
+                CodingTool::WarpedMotion,
+                CodingTool::GlobalMotion,
+                CodingTool::Palette,
+            ])
+            .reduced_tx_type_set(true);
 
-#[cfg(feature = "codec-trait")]
-pub use self::encoder_trait::AV1_DESCR;
+        let raw = tools.to_raw().unwrap();
+        assert_eq!(raw.init_by_cfg_file, 1);
+        assert_eq!(raw.super_block_size, 64);
+        assert_eq!((raw.min_partition_size, raw.max_partition_size), (8, 32));
+        assert_eq!((raw.disable_cdef, raw.disable_lr), (1, 1));
+        assert_eq!((raw.disable_obmc, raw.disable_cfl), (0, 0));
+        assert_eq!(raw.reduced_tx_type_set, 1);
+        assert!(tools.is_disabled(CodingTool::Palette));
 
-#[cfg(test)]
-pub(crate) mod tests {
-    use super::*;
-    #[test]
-    fn init() {
-        let mut c = AV1EncoderConfig::new().unwrap();
-        let mut e = c.get_encoder().unwrap();
-        println!("{}", e.error_to_str());
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .cpu_used(8)
+            .encoder_cfg_options(&tools)
+            .unwrap();
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 64, &t);
+
+        for i in 0..3 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            assert!(matches!(e.get_packet(), Some(AOMPacket::Packet(_))));
+        }
+
+        let err = |opts: EncoderCfgOptions| opts.to_raw().unwrap_err().kind().is_invalid_param();
+        assert!(err(EncoderCfgOptions::new().superblock_size(Some(32))));
+        assert!(err(EncoderCfgOptions::new().partition_sizes(6, 64)));
+        assert!(err(EncoderCfgOptions::new().partition_sizes(64, 16)));
+        assert!(err(EncoderCfgOptions::new()
+            .superblock_size(Some(64))
+            .partition_sizes(4, 128)));
+        assert!(AV1EncoderConfig::new()
+            .unwrap()
+            .encoder_cfg_options(&EncoderCfgOptions::new().partition_sizes(256, 256))
+            .is_err());
     }
 
-    use av_data::rational::*;
-    use av_data::timeinfo::TimeInfo;
-    pub fn setup(w: u32, h: u32, t: &TimeInfo) -> AV1Encoder {
-        if (w % 2) != 0 || (h % 2) != 0 {
-            panic!("Invalid frame size: w: {} h: {}", w, h);
+    #[cfg(feature = "decoder")]
+    #[test]
+    fn complexity_budget() {
+        use crate::decoder::AV1Decoder;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let budget = ComplexityBudget::new()
+            .reduced_reference_set(true)
+            .screen_content(true)
+            .intrabc(false)
+            .palette(false);
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .cpu_used(6)
+            .complexity_budget(budget);
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 64, &t);
+
+        for i in 0..10 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+        }
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut decoded = 0;
+        for p in e.close().unwrap() {
+            if let AOMPacket::Packet(p) = p {
+                d.decode(&p.data, None).unwrap();
+                while d.get_frame().is_some() {
+                    decoded += 1;
+                }
+            }
         }
+        assert_eq!(decoded, 10);
+    }
+
+    #[cfg(feature = "decoder")]
+    #[test]
+    fn frame_filter() {
+        use crate::decoder::AV1Decoder;
+        use av_data::frame::FrameBufferConv;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
         let mut cfg = AV1EncoderConfig::new()
             .unwrap()
-            .width(w)
-            .height(h)
+            .width(64)
+            .height(64)
             .timebase(t.timebase.unwrap())
-            .rc_min_quantizer(0)
-            .rc_min_quantizer(0)
-            .threads(4)
-            .pass(aom_enc_pass::AOM_RC_ONE_PASS)
-            .rc_end_usage(aom_rc_mode::AOM_CQ);
+            .cpu_used(8)
+            .push_control_raw(aome_enc_control_id::AV1E_SET_LOSSLESS, ControlValue::Int(1));
+        let mut e = cfg.get_encoder().unwrap();
 
-        let mut enc = cfg.get_encoder().unwrap();
+        // White 16x16 block at 8,8
+        e.set_frame_filter(Some(Box::new(|img: &mut AomImage| {
+            let stride = img.stride(0);
+            for row in img.plane_mut(0).chunks_mut(stride).skip(8).take(16) {
+                row[8..24].fill(255);
+            }
+        })));
 
-        enc.control(aome_enc_control_id::AOME_SET_CQ_LEVEL, 4)
-            .unwrap();
-        enc.control(aome_enc_control_id::AOME_SET_CPUUSED, 2)
-            .unwrap();
+        let mut f = setup_frame(64, 64, &t);
+        for i in 0..3 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+        }
 
-        enc
+        // The frame itself is left untouched
+        let y: &[u8] = f.buf.as_slice(0).unwrap();
+        assert!(y.iter().all(|&v| v == 0));
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut decoded = 0;
+        for p in e.close().unwrap() {
+            if let AOMPacket::Packet(p) = p {
+                d.decode(&p.data, None).unwrap();
+                while let Some((f, _)) = d.get_frame() {
+                    let stride = f.buf.linesize(0).unwrap();
+                    let y: &[u8] = f.buf.as_slice(0).unwrap();
+
+                    for (r, row) in y.chunks(stride).take(64).enumerate() {
+                        for (c, &v) in row[..64].iter().enumerate() {
+                            let inside = (8..24).contains(&r) && (8..24).contains(&c);
+                            assert_eq!(v, if inside { 255 } else { 0 }, "at {},{}", c, r);
+                        }
+                    }
+                    decoded += 1;
+                }
+            }
+        }
+        assert_eq!(decoded, 3);
     }
 
-    pub fn setup_frame(w: u32, h: u32, t: &TimeInfo) -> Frame {
-        use av_data::frame::*;
+    #[test]
+    fn encode_intra_with_q() {
+        use crate::testing::noise;
         use av_data::pixel::formats;
         use std::sync::Arc;
 
-        let v = VideoInfo::new(
-            w as usize,
-            h as usize,
-            false,
-            FrameType::OTHER,
-            Arc::new(*formats::YUV420),
-        );
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new_with_usage(AomUsage::AllIntra)
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0)
+            .cpu_used(6);
+        let mut e = cfg.get_encoder().unwrap();
+        let format = Arc::new(*formats::YUV420);
 
-        Frame::new_default_frame(v, Some(t.clone()))
+        let mut sizes = Vec::new();
+        for (i, q) in [10, 30, 50].into_iter().enumerate() {
+            let mut f = noise(64, 64, &format, 1);
+            f.t = TimeInfo {
+                pts: Some(i as i64),
+                ..t.clone()
+            };
+            e.encode_intra_with_q(&f, q).unwrap();
+            assert_eq!(e.cq_level, 10);
+
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    assert!(pkt.is_key);
+                    sizes.push(pkt.data.len());
+                }
+            }
+        }
+
+        assert_eq!(sizes.len(), 3);
+        assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2], "{:?}", sizes);
+
+        let f = setup_frame(64, 64, &t);
+        let invalid = |e: &mut AV1Encoder, q| {
+            e.encode_intra_with_q(&f, q)
+                .unwrap_err()
+                .kind()
+                .is_invalid_param()
+        };
+        assert!(invalid(&mut e, 64));
+
+        let mut vbr = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .lag_in_frames(0)
+            .rc_end_usage(RateControl::Vbr);
+        assert!(invalid(&mut vbr.get_encoder().unwrap(), 30));
+
+        // All intra takes no lag, the good quality usage does
+        let mut lagged = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .lag_in_frames(5)
+            .rc_end_usage(RateControl::Q);
+        assert!(invalid(&mut lagged.get_encoder().unwrap(), 30));
     }
 
     #[test]
-    fn encode() {
-        let w = 200;
-        let h = 200;
+    fn new_frame_image() {
+        use crate::metrics::psnr;
+        use crate::testing::moving_box;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
 
         let t = TimeInfo {
             pts: Some(0),
-            dts: Some(0),
-            duration: Some(1),
             timebase: Some(Rational64::new(1, 1000)),
-            user_private: None,
+            ..Default::default()
         };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0)
+            .rc_end_usage(RateControl::Q)
+            .cpu_used(6)
+            .push_control_raw(
+                aome_enc_control_id::AOME_SET_CQ_LEVEL,
+                ControlValue::Int(20),
+            );
+        let mut e = cfg.get_encoder().unwrap();
 
-        let mut e = setup(w, h, &t);
-        let mut f = setup_frame(w, h, &t);
+        let mut f = moving_box(64, 64, &Arc::new(*formats::YUV420), 0);
+        f.t = t;
+        e.encode(&f).unwrap();
 
-        let mut out = 0;
-        // TODO write some pattern
-        for i in 0..100 {
-            e.encode(&f).unwrap();
-            f.t.pts = Some(i);
-            // println!("{:#?}", f);
-            loop {
-                let p = e.get_packet();
+        let recon = e.new_frame_image().unwrap();
+        let p = psnr(&f, &recon).unwrap();
+        assert!(p.psnr[0] > 30.0 && p.psnr[0] < 100.0, "{:?}", p.psnr);
 
-                if p.is_none() {
-                    break;
-                } else {
-                    out = 1;
-                    // println!("{:#?}", p.unwrap());
+        // The same samples, borrowed
+        let stride = recon.buf.linesize(0).unwrap();
+        let luma: &[u8] = recon.buf.as_slice(0).unwrap();
+        e.with_new_frame_image(|img| {
+            assert_eq!((img.width(), img.height()), (64, 64));
+            for (a, b) in img.plane(0).chunks(img.stride(0)).zip(luma.chunks(stride)) {
+                assert_eq!(a[..64], b[..64]);
+            }
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn adapt_to_fps() {
+        use crate::testing::noise;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(160)
+            .height(90)
+            .timebase(t.timebase.unwrap())
+            .rc_end_usage(RateControl::Cbr)
+            .rc_target_bitrate(500)
+            .cpu_used(8);
+        let mut e = cfg.get_encoder().unwrap();
+        let format = Arc::new(*formats::YUV420);
+
+        // Capture rate and frames of 2 seconds, the frames are stamped
+        // with their index
+        let mut index = 0;
+        let mut rates = Vec::new();
+        for (fps, frames) in [(30.0, 60), (5.0, 10), (30.0, 60)] {
+            assert_eq!(e.adapt_to_fps(fps).unwrap(), index > 0);
+
+            let mut bytes = 0;
+            for i in 0..frames {
+                let mut f = noise(160, 90, &format, index);
+                f.t = TimeInfo {
+                    pts: Some(index as i64),
+                    ..t.clone()
+                };
+                e.encode(&f).unwrap();
+                index += 1;
+
+                while let Some(p) = e.get_packet() {
+                    // Skip the first second, it holds the keyframe
+                    if let AOMPacket::Packet(pkt) = p {
+                        if i >= frames / 2 {
+                            bytes += pkt.data.len();
+                        }
+                    }
                 }
             }
+
+            rates.push(bytes as f64 * 8.0 / 1000.0);
         }
 
-        if out != 1 {
-            panic!("No packet produced");
+        // The last second of every phase is close to 500 kbps
+        for rate in &rates {
+            assert!((250.0..750.0).contains(rate), "{:?}", rates);
+        }
+
+        // Too small or too close to the previous change
+        assert!(!e.adapt_to_fps(31.0).unwrap());
+        assert!(e.adapt_to_fps(0.0).unwrap_err().kind().is_invalid_param());
+    }
+
+    #[test]
+    fn reinit() {
+        use crate::testing::noise;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0)
+            .rc_end_usage(RateControl::Q);
+        let format = Arc::new(*formats::YUV420);
+
+        // Quantizer of the keyframe coding a w x w noise frame
+        let keyframe_q = |e: &mut AV1Encoder, w: u32| {
+            let mut f = noise(w as usize, w as usize, &format, 1);
+            f.t = TimeInfo {
+                pts: Some(0),
+                ..t.clone()
+            };
+            e.encode(&f).unwrap();
+            e.last_quantizer().unwrap()
+        };
+
+        let controls = |e: &mut AV1Encoder| {
+            e.control_raw(aome_enc_control_id::AOME_SET_CQ_LEVEL, 40)
+                .unwrap();
+            e.control_raw(aome_enc_control_id::AOME_SET_CPUUSED, 6)
+                .unwrap();
+        };
+
+        let mut e = cfg.clone().get_encoder().unwrap();
+        controls(&mut e);
+        keyframe_q(&mut e, 64);
+
+        let mut large = cfg.clone().width(128).height(128);
+        e.reinit(&mut large).unwrap();
+        assert_eq!(e.cq_level, 40);
+        let q = keyframe_q(&mut e, 128);
+
+        // As an encoder created with the same controls, not with the
+        // default CQ level
+        let mut fresh = large.clone().get_encoder().unwrap();
+        controls(&mut fresh);
+        assert_eq!(keyframe_q(&mut fresh, 128), q);
+
+        let mut default = large.clone().get_encoder().unwrap();
+        assert!(keyframe_q(&mut default, 128) < q);
+
+        // The staged controls of the new configuration win
+        let mut staged = large.push_control_raw(
+            aome_enc_control_id::AOME_SET_CQ_LEVEL,
+            ControlValue::Int(20),
+        );
+        e.reinit(&mut staged).unwrap();
+        assert_eq!(e.cq_level, 20);
+    }
+
+    #[cfg(feature = "realtime-only")]
+    #[test]
+    fn realtime_only_default_usage() {
+        use crate::testing::moving_box;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+
+        let cfg = AV1EncoderConfig::new().unwrap();
+        assert_eq!(cfg.g_usage, AomUsage::RealTime as u32);
+
+        let t = TimeInfo {
+            pts: Some(0),
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let mut e = cfg
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .get_encoder()
+            .unwrap();
+        let mut f = moving_box(64, 64, &Arc::new(*formats::YUV420), 0);
+        f.t = t;
+        e.encode(&f).unwrap();
+        e.flush().unwrap();
+        assert!(std::iter::from_fn(|| e.get_packet()).any(|p| matches!(p, AOMPacket::Packet(_))));
+    }
+
+    #[test]
+    fn realtime_options() {
+        use crate::testing::MovingBox;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let options = RealtimeOptions::new().undershoot_pct(50);
+        #[cfg(aom_ge_3_9)]
+        let options = options.max_consecutive_drops(2);
+
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(160)
+            .height(90)
+            .timebase(t.timebase.unwrap())
+            .rc_end_usage(RateControl::Cbr)
+            .rc_target_bitrate(200)
+            .rc_dropframe_thresh(30)
+            .cpu_used(8)
+            .realtime_options(options);
+        assert_eq!(cfg.rc_undershoot_pct, 50);
+
+        let mut e = cfg.get_encoder().unwrap();
+        let format = Arc::new(*formats::YUV420);
+
+        // Generous, 100 small frames take a fraction of it
+        let start = Instant::now();
+        for f in MovingBox::new(160, 90, &format, &t).take(100) {
+            e.encode(&f).unwrap();
+            while e.get_packet().is_some() {}
+        }
+        assert!(start.elapsed() < Duration::from_secs(20));
+    }
+
+    #[test]
+    fn chroma_subsampling() {
+        use av_data::frame::VideoInfo;
+        use av_data::pixel::formats;
+        use std::sync::Arc;
+
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .profile(BitstreamProfile::Profile1)
+            .cpu_used(6)
+            .chroma_subsampling(false, false);
+
+        let info = VideoInfo::new(64, 64, false, FrameType::OTHER, Arc::new(*formats::YUV444));
+        let mut f = Frame::new_default_frame(info, Some(t.clone()));
+
+        let mut e = cfg.get_encoder().unwrap();
+        for i in 0..3 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
         }
+        let packets = e.close().unwrap();
+        assert!(packets.iter().any(|p| matches!(p, AOMPacket::Packet(_))));
+
+        // A 4:2:0 frame contradicts the configuration
+        let mut e = cfg.get_encoder().unwrap();
+        let err = e.encode(&setup_frame(64, 64, &t)).err().unwrap();
+        assert!(err.kind().is_invalid_param());
+    }
+
+    #[test]
+    fn staged_controls() {
+        use crate::common::AomErrorKind;
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .cpu_used(8)
+            .tile_columns(1)
+            .row_mt(true)
+            .push_control_raw(
+                aome_enc_control_id::AOME_SET_ENABLEAUTOALTREF,
+                ControlValue::UInt(0),
+            );
+
+        assert_eq!(cfg.controls_raw().len(), 4);
+        assert_eq!(
+            cfg.controls_raw()[0],
+            (aome_enc_control_id::AOME_SET_CPUUSED, ControlValue::Int(8))
+        );
+        assert!(cfg.get_encoder().is_ok());
+
+        let mut cfg = cfg.cq_level(255).tile_rows(1);
+        let err = cfg.get_encoder().err().unwrap();
+        assert_eq!(err.kind(), AomErrorKind::InvalidParam);
+        assert!(err.detail().unwrap().starts_with("AOME_SET_CQ_LEVEL"));
     }
 
     #[cfg(all(test, feature = "codec-trait"))]
@@ -3,9 +3,12 @@
 use core::mem::MaybeUninit;
 use core::ops::{Deref, DerefMut};
 
+use av_data::pixel::{ColorPrimaries, MatrixCoefficients, TransferCharacteristic};
 use av_data::rational::Rational64;
+use av_data::timeinfo::TimeInfo;
 
-use crate::encoder::AV1Encoder;
+use crate::common::{check, require_version, AomError, AomErrorKind, Iface};
+use crate::encoder::{AV1Encoder, EncoderCfgOptions};
 use crate::ffi::*;
 
 /// Encoder configuration structure
@@ -13,6 +16,7 @@ use crate::ffi::*;
 /// This structure contains the encoder settings that have common representations
 /// across all codecs. This doesn't imply that all codecs support all features,
 /// however.
+#[derive(Clone)]
 pub struct AV1EncoderConfig {
     /// Public for backwards compatibility
     ///
@@ -21,48 +25,386 @@ pub struct AV1EncoderConfig {
     /// i.e. `*config` instead of `config.cfg`,
     /// or `config.g_usage` instead of `config.cfg.g_usage`.
     pub cfg: aom_codec_enc_cfg,
+    // Applied in order once the encoder is created
+    controls: Vec<(aome_enc_control_id::Type, ControlValue)>,
+    // Checks and conversions done by AV1Encoder before aom_codec_encode
+    pub(crate) validate_pts: bool,
+    pub(crate) rescale_timestamps: bool,
+    pub(crate) chroma_subsampling: Option<(bool, bool)>,
+    pub(crate) color_description: bool,
+    pub(crate) iface: Iface,
+}
+
+/// Argument of a control staged with [`AV1EncoderConfig::push_control_raw`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlValue {
+    /// Passed as an `int`
+    Int(i32),
+    /// Passed as an `unsigned int`
+    UInt(u32),
+}
+
+macro_rules! control_names {
+    ($($(#[$attr:meta])* $id:ident,)*) => {
+        fn control_name(id: aome_enc_control_id::Type) -> String {
+            use self::aome_enc_control_id::*;
+
+            match id {
+                $($(#[$attr])* $id => stringify!($id).to_owned(),)*
+                _ => format!("control {}", id),
+            }
+        }
+    };
+}
+
+control_names! {
+    AOME_SET_CPUUSED,
+    AOME_SET_ENABLEAUTOALTREF,
+    AOME_SET_SHARPNESS,
+    AOME_SET_STATIC_THRESHOLD,
+    AOME_SET_ARNR_MAXFRAMES,
+    AOME_SET_ARNR_STRENGTH,
+    AOME_SET_TUNING,
+    AOME_SET_CQ_LEVEL,
+    AOME_SET_MAX_INTRA_BITRATE_PCT,
+    AOME_SET_NUMBER_SPATIAL_LAYERS,
+    AOME_SET_SPATIAL_LAYER_ID,
+    AV1E_SET_MAX_INTER_BITRATE_PCT,
+    AV1E_SET_GF_CBR_BOOST_PCT,
+    AV1E_SET_LOSSLESS,
+    AV1E_SET_ROW_MT,
+    AV1E_SET_TILE_COLUMNS,
+    AV1E_SET_TILE_ROWS,
+    AV1E_SET_ENABLE_TPL_MODEL,
+    AV1E_SET_ENABLE_KEYFRAME_FILTERING,
+    AV1E_SET_FRAME_PERIODIC_BOOST,
+    AV1E_SET_DISABLE_TRELLIS_QUANT,
+    AV1E_SET_FRAME_PARALLEL_DECODING,
+    AV1E_SET_ERROR_RESILIENT_MODE,
+    AV1E_SET_AQ_MODE,
+    AV1E_SET_NOISE_SENSITIVITY,
+    AV1E_SET_TUNE_CONTENT,
+    AV1E_SET_COLOR_PRIMARIES,
+    AV1E_SET_TRANSFER_CHARACTERISTICS,
+    AV1E_SET_MATRIX_COEFFICIENTS,
+    AV1E_SET_CHROMA_SAMPLE_POSITION,
+    AV1E_SET_CHROMA_SUBSAMPLING_X,
+    AV1E_SET_CHROMA_SUBSAMPLING_Y,
+    AV1E_SET_COLOR_RANGE,
+    AV1E_SET_MIN_GF_INTERVAL,
+    AV1E_SET_MAX_GF_INTERVAL,
+    AV1E_SET_ENABLE_CDEF,
+    AV1E_SET_ENABLE_RESTORATION,
+    AV1E_SET_DENOISE_NOISE_LEVEL,
+    AV1E_SET_SINGLE_TILE_DECODING,
+    AV1E_SET_REDUCED_REFERENCE_SET,
+    AV1E_SET_ENABLE_INTRABC,
+    AV1E_SET_ENABLE_PALETTE,
+    #[cfg(aom_ge_3_4)]
+    AV1E_SET_ENABLE_DNL_DENOISING,
+    #[cfg(aom_ge_3_9)]
+    AV1E_SET_MAX_CONSEC_FRAME_DROP_CBR,
+}
+
+// Values libaom accepts for the controls taking a bounded integer, the
+// other controls are passed through unchecked, AOME_SET_CPUUSED included
+// since its range depends on the usage, the version and the build
+const CONTROL_RANGES: &[(aome_enc_control_id::Type, i64, i64)] = {
+    use self::aome_enc_control_id::*;
+
+    &[
+        (AOME_SET_ENABLEAUTOALTREF, 0, 2),
+        (AOME_SET_SHARPNESS, 0, 7),
+        (AOME_SET_ARNR_MAXFRAMES, 0, 15),
+        (AOME_SET_ARNR_STRENGTH, 0, 6),
+        (AOME_SET_CQ_LEVEL, 0, 63),
+        (AOME_SET_NUMBER_SPATIAL_LAYERS, 1, 4),
+        (AOME_SET_SPATIAL_LAYER_ID, 0, 3),
+        (AV1E_SET_LOSSLESS, 0, 1),
+        (AV1E_SET_ROW_MT, 0, 1),
+        (AV1E_SET_TILE_COLUMNS, 0, 6),
+        (AV1E_SET_TILE_ROWS, 0, 6),
+        (AV1E_SET_ENABLE_TPL_MODEL, 0, 1),
+        (AV1E_SET_ENABLE_KEYFRAME_FILTERING, 0, 2),
+        (AV1E_SET_FRAME_PERIODIC_BOOST, 0, 1),
+        (AV1E_SET_DISABLE_TRELLIS_QUANT, 0, 3),
+        (AV1E_SET_FRAME_PARALLEL_DECODING, 0, 1),
+        (AV1E_SET_ERROR_RESILIENT_MODE, 0, 1),
+        (AV1E_SET_AQ_MODE, 0, 3),
+        (AV1E_SET_NOISE_SENSITIVITY, 0, 6),
+        (AV1E_SET_COLOR_PRIMARIES, 0, 255),
+        (AV1E_SET_TRANSFER_CHARACTERISTICS, 0, 255),
+        (AV1E_SET_MATRIX_COEFFICIENTS, 0, 255),
+        (AV1E_SET_CHROMA_SAMPLE_POSITION, 0, 3),
+        (AV1E_SET_CHROMA_SUBSAMPLING_X, 0, 1),
+        (AV1E_SET_CHROMA_SUBSAMPLING_Y, 0, 1),
+        (AV1E_SET_COLOR_RANGE, 0, 1),
+        (AV1E_SET_ENABLE_RESTORATION, 0, 1),
+        (AV1E_SET_SINGLE_TILE_DECODING, 0, 1),
+        (AV1E_SET_REDUCED_REFERENCE_SET, 0, 1),
+        (AV1E_SET_ENABLE_INTRABC, 0, 1),
+        (AV1E_SET_ENABLE_PALETTE, 0, 1),
+        #[cfg(aom_ge_3_9)]
+        (AV1E_SET_MAX_CONSEC_FRAME_DROP_CBR, 0, i32::MAX as i64),
+    ]
+};
+
+/// Reject the values out of the range of a known control
+///
+/// Checked in debug builds and with the `validate-controls` feature, the
+/// release builds leave it to libaom.
+#[cfg(any(debug_assertions, feature = "validate-controls"))]
+pub(crate) fn validate_control(id: aome_enc_control_id::Type, val: i64) -> Result<(), AomError> {
+    match CONTROL_RANGES.iter().find(|&&(control, ..)| control == id) {
+        Some(&(_, min, max)) if !(min..=max).contains(&val) => Err(AomError::Codec {
+            kind: AomErrorKind::InvalidParam,
+            detail: Some(format!(
+                "{}: {} out of range {}..={}",
+                control_name(id),
+                val,
+                min,
+                max
+            )),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(any(debug_assertions, feature = "validate-controls")))]
+pub(crate) fn validate_control(_id: aome_enc_control_id::Type, _val: i64) -> Result<(), AomError> {
+    Ok(())
+}
+
+// First libaom release supporting the controls introduced after 3.0.0, the
+// oldest release the bindings accept
+const CONTROL_VERSIONS: &[(aome_enc_control_id::Type, &str)] = &[
+    #[cfg(aom_ge_3_4)]
+    (aome_enc_control_id::AV1E_SET_ENABLE_DNL_DENOISING, "3.4.0"),
+    #[cfg(aom_ge_3_9)]
+    (
+        aome_enc_control_id::AV1E_SET_MAX_CONSEC_FRAME_DROP_CBR,
+        "3.9.0",
+    ),
+];
+
+fn control_introduced_in(id: aome_enc_control_id::Type) -> &'static str {
+    CONTROL_VERSIONS
+        .iter()
+        .find(|&&(control, _)| control == id)
+        .map_or("3.0.0", |&(_, version)| version)
 }
 
 unsafe impl Send for AV1EncoderConfig {} // TODO: Make sure it cannot be abused
 
 impl AV1EncoderConfig {
     /// Create a new default configuration with default usage
-    pub fn new() -> Result<AV1EncoderConfig, aom_codec_err_t::Type> {
+    ///
+    /// See [`AomUsage`] for the default usage.
+    pub fn new() -> Result<AV1EncoderConfig, AomError> {
         Self::new_with_usage(AomUsage::default())
     }
 
     /// Create a new default configuration with a particular usage
     ///
     /// For example, running in [`AomUsage::RealTime`] mode
-    pub fn new_with_usage(usage: AomUsage) -> Result<AV1EncoderConfig, aom_codec_err_t::Type> {
+    pub fn new_with_usage(usage: AomUsage) -> Result<AV1EncoderConfig, AomError> {
+        let iface =
+            Iface::av1_encoder().ok_or(AomError::InterfaceUnavailable { codec: "encoder" })?;
         let mut cfg = MaybeUninit::uninit();
-        let ret = unsafe {
-            aom_codec_enc_config_default(aom_codec_av1_cx(), cfg.as_mut_ptr(), usage as u32)
-        };
+        let ret =
+            unsafe { aom_codec_enc_config_default(iface.as_raw(), cfg.as_mut_ptr(), usage as u32) };
 
         match ret {
             aom_codec_err_t::AOM_CODEC_OK => {
                 let cfg = unsafe { cfg.assume_init() };
-                Ok(AV1EncoderConfig { cfg })
+                Ok(AV1EncoderConfig {
+                    cfg,
+                    controls: Vec::new(),
+                    validate_pts: false,
+                    rescale_timestamps: false,
+                    chroma_subsampling: None,
+                    color_description: false,
+                    iface,
+                })
             }
-            _ => Err(ret),
+            // The only failure once the interface and the structure are set
+            aom_codec_err_t::AOM_CODEC_INVALID_PARAM => Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                &format!("usage {:?} unsupported by this libaom", usage),
+            )),
+            _ => Err(ret.into()),
         }
     }
 
+    /// Create the encoder with `iface` instead of the AV1 one
+    ///
+    /// For the builds of libaom exposing other interfaces, the
+    /// configuration is kept as is and must suit the interface.
+    ///
+    /// # Safety
+    ///
+    /// `iface` must be a libaom encoder interface, valid for the lifetime of
+    /// the program.
+    ///
+    /// # Panics
+    ///
+    /// If `iface` is null.
+    pub unsafe fn with_iface(mut self, iface: *const aom_codec_iface_t) -> Self {
+        self.iface = Iface::from_raw(iface).expect("null codec interface");
+        self
+    }
+
+    /// The interface the encoder is created with
+    pub fn iface(&self) -> Iface {
+        self.iface
+    }
+
+    /// Create a configuration for low-latency encoding
+    ///
+    /// It uses [`AomUsage::RealTime`] with no lag, so every frame sent to the
+    /// encoder produces a packet right away, see
+    /// [`AV1Encoder::is_holding_frames`]. Error resilience is left disabled,
+    /// enable it with [`error_resilient`] for lossy links.
+    ///
+    /// [`error_resilient`]: AV1EncoderConfig::error_resilient
+    pub fn low_latency() -> Result<AV1EncoderConfig, AomError> {
+        Ok(Self::new_with_usage(AomUsage::RealTime)?
+            .lag_in_frames(0)
+            .error_resilient(false))
+    }
+
     /// Return a newly allocated `AV1Encoder` using the current configuration
-    pub fn get_encoder(&mut self) -> Result<AV1Encoder, aom_codec_err_t::Type> {
+    pub fn get_encoder(&mut self) -> Result<AV1Encoder, AomError> {
         AV1Encoder::new(self)
     }
 
     /// Get a reference to the underlying config structure
-    pub fn cfg(&self) -> &aom_codec_enc_cfg {
+    pub fn cfg_raw(&self) -> &aom_codec_enc_cfg {
         &self.cfg
     }
 
     /// Get a mutable reference to the underlying config structure
-    pub fn cfg_mut(&mut self) -> &mut aom_codec_enc_cfg {
+    pub fn cfg_raw_mut(&mut self) -> &mut aom_codec_enc_cfg {
         &mut self.cfg
     }
+
+    /// Get a reference to the underlying config structure
+    #[deprecated(since = "0.4.0", note = "use cfg_raw")]
+    pub fn cfg(&self) -> &aom_codec_enc_cfg {
+        self.cfg_raw()
+    }
+
+    /// Get a mutable reference to the underlying config structure
+    #[deprecated(since = "0.4.0", note = "use cfg_raw_mut")]
+    pub fn cfg_mut(&mut self) -> &mut aom_codec_enc_cfg {
+        self.cfg_raw_mut()
+    }
+
+    /// Stage a control, applied once the encoder is created
+    ///
+    /// The controls are applied in order right after `aom_codec_enc_init_ver`
+    /// and again on [`AV1Encoder::set_config`]. A failure names the control.
+    pub fn push_control_raw(mut self, id: aome_enc_control_id::Type, val: ControlValue) -> Self {
+        self.controls.push((id, val));
+        self
+    }
+
+    /// Stage a control, applied once the encoder is created
+    #[deprecated(since = "0.4.0", note = "use push_control_raw")]
+    pub fn push_control(self, id: aome_enc_control_id::Type, val: ControlValue) -> Self {
+        self.push_control_raw(id, val)
+    }
+
+    /// The staged controls, in the order they are applied
+    pub fn controls_raw(&self) -> &[(aome_enc_control_id::Type, ControlValue)] {
+        &self.controls
+    }
+
+    /// The staged controls, in the order they are applied
+    #[deprecated(since = "0.4.0", note = "use controls_raw")]
+    pub fn controls(&self) -> &[(aome_enc_control_id::Type, ControlValue)] {
+        self.controls_raw()
+    }
+
+    // The CQ level sets the quantizer
+    pub(crate) fn has_fixed_quality(&self) -> bool {
+        self.cfg.g_usage == AomUsage::AllIntra as u32
+            || matches!(
+                self.cfg.rc_end_usage,
+                aom_rc_mode::AOM_Q | aom_rc_mode::AOM_CQ
+            )
+    }
+
+    // The denominators only apply to the fixed mode, libaom ignores them
+    // otherwise. The default ones, 8, do not scale.
+    pub(crate) fn check_superres(&self) -> Result<(), AomError> {
+        let invalid = |detail| Err(AomError::new(AomErrorKind::InvalidParam, detail));
+        let denominators = [
+            self.cfg.rc_superres_denominator,
+            self.cfg.rc_superres_kf_denominator,
+        ];
+
+        if self.cfg.rc_superres_mode == aom_superres_mode::AOM_SUPERRES_FIXED {
+            if denominators.iter().any(|d| !(8..=16).contains(d)) {
+                return invalid("superres denominator out of the 8-16 range");
+            }
+        } else if denominators.iter().any(|&d| d != 8) {
+            return invalid("superres denominators need the fixed superres mode");
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn apply_controls(&self, enc: &mut AV1Encoder) -> Result<(), AomError> {
+        for &(id, val) in &self.controls {
+            let v = match val {
+                ControlValue::Int(v) => v as i64,
+                ControlValue::UInt(v) => v as i64,
+            };
+
+            require_version(control_introduced_in(id))?;
+            validate_control(id, v)?;
+
+            // The denoiser only runs in realtime usage
+            if id == aome_enc_control_id::AV1E_SET_NOISE_SENSITIVITY
+                && v != 0
+                && self.cfg.g_usage != AomUsage::RealTime as u32
+            {
+                return Err(AomError::new(
+                    AomErrorKind::InvalidParam,
+                    "AV1E_SET_NOISE_SENSITIVITY: realtime usage only",
+                ));
+            }
+
+            let ret = unsafe {
+                match val {
+                    ControlValue::Int(v) => aom_codec_control(&mut enc.ctx, id as i32, v),
+                    ControlValue::UInt(v) => aom_codec_control(&mut enc.ctx, id as i32, v),
+                }
+            };
+
+            check(&enc.ctx, ret).map_err(|err| {
+                let name = control_name(id);
+                let detail = match err.detail() {
+                    Some(detail) => format!("{}: {}", name, detail),
+                    None => name,
+                };
+
+                AomError::Codec {
+                    kind: err.kind(),
+                    detail: Some(detail),
+                }
+            })?;
+
+            enc.applied_control(id, val);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn stages(&self, id: aome_enc_control_id::Type) -> bool {
+        self.controls.iter().any(|&(i, _)| i == id)
+    }
 }
 
 /// # Generic settings
@@ -144,11 +486,12 @@ impl AV1EncoderConfig {
     }
 
     /// Bit-depth of the codec
-    ///
-    /// This value identifies the bit_depth of the codec,
-    /// Only certain bit-depths are supported as identified in the
-    /// aom_bit_depth_t enum.
-    pub fn bit_depth(mut self, val: aom_bit_depth_t) -> Self {
+    pub fn bit_depth(self, val: BitDepth) -> Self {
+        self.bit_depth_raw(val.to_aom())
+    }
+
+    /// Bit-depth of the codec, as an `aom_bit_depth_t`
+    pub fn bit_depth_raw(mut self, val: aom_bit_depth_t) -> Self {
         self.cfg.g_bit_depth = val;
         self
     }
@@ -174,18 +517,67 @@ impl AV1EncoderConfig {
     /// re-encoding video from containers with absolute time timestamps, the
     /// \ref RECOMMENDED method is to set the timebase to that of the parent
     /// container or multimedia framework (ex: 1/1000 for ms, as in FLV).
+    ///
+    /// The terms are truncated to 32 bits, [`g_timebase_rational`] checks
+    /// them.
+    ///
+    /// [`g_timebase_rational`]: AV1EncoderConfig::g_timebase_rational
     pub fn timebase(mut self, val: Rational64) -> Self {
         self.cfg.g_timebase.num = *val.numer() as i32;
         self.cfg.g_timebase.den = *val.denom() as i32;
         self
     }
 
+    /// Stream timebase units, see [`timebase`]
+    ///
+    /// It fails if the reduced fraction does not fit the 32-bit
+    /// `aom_rational`.
+    ///
+    /// ```
+    /// use av_data::rational::Rational64;
+    /// use libaom::encoder::{AV1EncoderConfig, AomUsage};
+    ///
+    /// let mut cfg = AV1EncoderConfig::new_with_usage(AomUsage::RealTime)?
+    ///     .width(640)
+    ///     .height(480)
+    ///     .g_timebase_rational(Rational64::new(1001, 30000))?
+    ///     .lag_in_frames(0)
+    ///     .cpu_used(8)
+    ///     .cq_level(30);
+    ///
+    /// assert_eq!(cfg.get_timebase(), Rational64::new(1001, 30000));
+    /// let enc = cfg.get_encoder()?;
+    /// assert_eq!(enc.timebase(), Rational64::new(1001, 30000));
+    /// # Ok::<(), libaom::common::AomError>(())
+    /// ```
+    ///
+    /// [`timebase`]: AV1EncoderConfig::timebase
+    pub fn g_timebase_rational(mut self, val: Rational64) -> Result<Self, AomError> {
+        self.cfg.g_timebase = rational_to_raw(val)?;
+        Ok(self)
+    }
+
+    /// Stream timebase units, `g_timebase`
+    pub fn get_timebase(&self) -> Rational64 {
+        Rational64::new(
+            self.cfg.g_timebase.num as i64,
+            self.cfg.g_timebase.den as i64,
+        )
+    }
+
     /// Enable error resilient modes.
     ///
-    /// The error resilient bitfield indicates to the encoder which features
-    /// it should enable to take measures for streaming over lossy or noisy
-    /// links.
-    pub fn error_resilient(mut self, val: aom_codec_er_flags_t) -> Self {
+    /// The encoder takes measures for streaming over lossy or noisy links.
+    pub fn error_resilient(self, val: bool) -> Self {
+        self.error_resilient_raw(if val {
+            AOM_ERROR_RESILIENT_DEFAULT as aom_codec_er_flags_t
+        } else {
+            0
+        })
+    }
+
+    /// Enable error resilient modes, as an `AOM_ERROR_RESILIENT_*` bitfield
+    pub fn error_resilient_raw(mut self, val: aom_codec_er_flags_t) -> Self {
         self.cfg.g_error_resilient = val;
         self
     }
@@ -193,8 +585,13 @@ impl AV1EncoderConfig {
     /// Multi-pass Encoding Mode
     ///
     /// This value should be set to the current phase for multi-pass encoding.
-    /// For single pass, set to #AOM_RC_ONE_PASS.
-    pub fn pass(mut self, val: aom::aom_enc_pass::Type) -> Self {
+    /// For single pass, set to [`EncodePass::OnePass`].
+    pub fn pass(self, val: EncodePass) -> Self {
+        self.pass_raw(val.to_aom())
+    }
+
+    /// Multi-pass Encoding Mode, as an `aom_enc_pass`
+    pub fn pass_raw(mut self, val: aom::aom_enc_pass::Type) -> Self {
         self.cfg.g_pass = val;
         self
     }
@@ -274,7 +671,12 @@ impl AV1EncoderConfig {
     /// Similar to spatial resampling, frame super-resolution integrates
     /// upscaling after the encode/decode process. Taking control of upscaling and
     /// using restoration filters should allow it to outperform normal resizing.
-    pub fn rc_superres_mode(mut self, val: aom::aom_superres_mode::Type) -> Self {
+    pub fn rc_superres_mode(self, val: SuperresMode) -> Self {
+        self.rc_superres_mode_raw(val.to_aom())
+    }
+
+    /// Frame super-resolution scaling mode, as an `aom_superres_mode`
+    pub fn rc_superres_mode_raw(mut self, val: aom::aom_superres_mode::Type) -> Self {
         self.cfg.rc_superres_mode = val;
         self
     }
@@ -287,7 +689,8 @@ impl AV1EncoderConfig {
     ///
     /// Valid denominators are 8 to 16.
     ///
-    /// Used only by AOM_SUPERRES_FIXED.
+    /// Used only by [`SuperresMode::Fixed`], the encoder creation fails if
+    /// it is set with another mode.
     pub fn rc_superres_denominator(mut self, val: u32) -> Self {
         self.cfg.rc_superres_denominator = val;
         self
@@ -300,6 +703,9 @@ impl AV1EncoderConfig {
     /// this forces superres to reduce scaling.
     ///
     /// Valid denominators are 8 - 16 for now.
+    ///
+    /// Used only by [`SuperresMode::Fixed`], the encoder creation fails if
+    /// it is set with another mode.
     pub fn rc_superres_kf_denominator(mut self, val: u32) -> Self {
         self.cfg.rc_superres_kf_denominator = val;
         self
@@ -334,7 +740,12 @@ impl AV1EncoderConfig {
     /// mode should be used, or whether it will be played back on a high
     /// bandwidth link, as from a local disk, where higher variations in
     /// bitrate are acceptable.
-    pub fn rc_end_usage(mut self, val: aom::aom_rc_mode::Type) -> Self {
+    pub fn rc_end_usage(self, val: RateControl) -> Self {
+        self.rc_end_usage_raw(val.to_aom())
+    }
+
+    /// Rate control algorithm to use, as an `aom_rc_mode`
+    pub fn rc_end_usage_raw(mut self, val: aom::aom_rc_mode::Type) -> Self {
         self.cfg.rc_end_usage = val;
         self
     }
@@ -342,21 +753,35 @@ impl AV1EncoderConfig {
     /// Two-pass stats buffer.
     ///
     /// A buffer containing all of the stats packets produced in the first
-    /// pass, concatenated.
-    pub fn rc_twopass_stats_in(mut self, val: aom_fixed_buf_t) -> Self {
+    /// pass, concatenated, e.g. [`TwoPassStats::as_fixed_buf_raw`].
+    ///
+    /// [`TwoPassStats::as_fixed_buf_raw`]: crate::encoder::TwoPassStats::as_fixed_buf_raw
+    pub fn rc_twopass_stats_in_raw(mut self, val: aom_fixed_buf_t) -> Self {
         self.cfg.rc_twopass_stats_in = val;
         self
     }
 
+    /// Two-pass stats buffer.
+    #[deprecated(since = "0.4.0", note = "use rc_twopass_stats_in_raw")]
+    pub fn rc_twopass_stats_in(self, val: aom_fixed_buf_t) -> Self {
+        self.rc_twopass_stats_in_raw(val)
+    }
+
     /// first pass mb stats buffer.
     ///
     /// A buffer containing all of the first pass mb stats packets produced
     /// in the first pass, concatenated.
-    pub fn rc_firstpass_mb_stats_in(mut self, val: aom_fixed_buf_t) -> Self {
+    pub fn rc_firstpass_mb_stats_in_raw(mut self, val: aom_fixed_buf_t) -> Self {
         self.cfg.rc_firstpass_mb_stats_in = val;
         self
     }
 
+    /// first pass mb stats buffer.
+    #[deprecated(since = "0.4.0", note = "use rc_firstpass_mb_stats_in_raw")]
+    pub fn rc_firstpass_mb_stats_in(self, val: aom_fixed_buf_t) -> Self {
+        self.rc_firstpass_mb_stats_in_raw(val)
+    }
+
     /// Target data rate
     ///
     /// Target bitrate to use for this stream, in kilobits per second.
@@ -489,6 +914,33 @@ impl AV1EncoderConfig {
     }
 }
 
+/// # Timestamp settings
+impl AV1EncoderConfig {
+    /// Reject the frames whose pts is not larger than the previous one
+    ///
+    /// libaom requires strictly increasing timestamps. The frames without
+    /// pts are always rejected.
+    pub fn validate_pts(mut self, val: bool) -> Self {
+        self.validate_pts = val;
+        self
+    }
+
+    /// Rescale the frame timestamps into `g_timebase`
+    ///
+    /// The pts and the duration of the frames carrying a `timebase` in
+    /// their [`TimeInfo`] are converted, see [`Timebase::rescale_time_info`].
+    /// The other frames are assumed to be in `g_timebase` units already.
+    pub fn rescale_timestamps(mut self, val: bool) -> Self {
+        self.rescale_timestamps = val;
+        self
+    }
+
+    /// The configured `g_timebase`
+    pub fn encoder_timebase(&self) -> Timebase {
+        Timebase::from_aom(self.cfg.g_timebase)
+    }
+}
+
 /// # Keyframing settings (kf)
 impl AV1EncoderConfig {
     /// Option to enable forward reference key frame
@@ -502,7 +954,12 @@ impl AV1EncoderConfig {
     /// This value indicates whether the encoder should place keyframes at a
     /// fixed interval, or determine the optimal placement automatically
     /// (as governed by the `kf_min_dist` and `kf_max_dist` parameters)
-    pub fn kf_mode(mut self, val: aom::aom_kf_mode::Type) -> Self {
+    pub fn kf_mode(self, val: KeyframeMode) -> Self {
+        self.kf_mode_raw(val.to_aom())
+    }
+
+    /// Keyframe placement mode, as an `aom_kf_mode`
+    pub fn kf_mode_raw(mut self, val: aom::aom_kf_mode::Type) -> Self {
         self.cfg.kf_mode = val;
         self
     }
@@ -653,10 +1110,312 @@ impl AV1EncoderConfig {
     }
 
     /// Options defined per config file
-    pub fn encoder_cfg(mut self, val: cfg_options_t) -> Self {
+    pub fn encoder_cfg_raw(mut self, val: cfg_options_t) -> Self {
         self.cfg.encoder_cfg = val;
         self
     }
+
+    /// Options defined per config file
+    #[deprecated(since = "0.4.0", note = "use encoder_cfg_raw")]
+    pub fn encoder_cfg(self, val: cfg_options_t) -> Self {
+        self.encoder_cfg_raw(val)
+    }
+
+    /// Restrict the coding tools and partitions, as `aomenc --cfg` does
+    ///
+    /// It fails if `val` holds conflicting settings, see
+    /// [`EncoderCfgOptions::to_raw`].
+    pub fn encoder_cfg_options(self, val: &EncoderCfgOptions) -> Result<Self, AomError> {
+        Ok(self.encoder_cfg_raw(val.to_raw()?))
+    }
+}
+
+/// # Encoder controls
+///
+/// They are staged with [`AV1EncoderConfig::push_control_raw`].
+impl AV1EncoderConfig {
+    /// Speed preset, the higher the faster
+    ///
+    /// It matches `AOME_SET_CPUUSED`, the encoder defaults to 2.
+    pub fn cpu_used(self, val: i32) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AOME_SET_CPUUSED,
+            ControlValue::Int(val),
+        )
+    }
+
+    /// Quality level for the constrained and constant quality modes, 0-63
+    ///
+    /// It matches `AOME_SET_CQ_LEVEL`.
+    pub fn cq_level(self, val: u32) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AOME_SET_CQ_LEVEL,
+            ControlValue::UInt(val),
+        )
+    }
+
+    /// Number of tile columns, in log2 units
+    ///
+    /// It matches `AV1E_SET_TILE_COLUMNS`.
+    pub fn tile_columns(self, val: u32) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_TILE_COLUMNS,
+            ControlValue::UInt(val),
+        )
+    }
+
+    /// Number of tile rows, in log2 units
+    ///
+    /// It matches `AV1E_SET_TILE_ROWS`.
+    pub fn tile_rows(self, val: u32) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_TILE_ROWS,
+            ControlValue::UInt(val),
+        )
+    }
+
+    /// Make every tile decodable on its own, without the other tiles of
+    /// the frame
+    ///
+    /// Only meaningful with [`TileCodingMode::LargeScale`], it lets the
+    /// decoder extract a single tile, see [`TileDecoding`].
+    ///
+    /// It matches `AV1E_SET_SINGLE_TILE_DECODING`.
+    ///
+    /// [`TileDecoding`]: crate::decoder::TileDecoding
+    pub fn single_tile_decoding(self, val: bool) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_SINGLE_TILE_DECODING,
+            ControlValue::UInt(val as u32),
+        )
+    }
+
+    /// Row based multi-threading
+    ///
+    /// It matches `AV1E_SET_ROW_MT`.
+    pub fn row_mt(self, val: bool) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_ROW_MT,
+            ControlValue::UInt(val as u32),
+        )
+    }
+
+    /// Temporal dependency model, the quantizers follow how much the
+    /// following frames reference each block
+    ///
+    /// It matches `AV1E_SET_ENABLE_TPL_MODEL`, enabled by default. Disabling
+    /// it speeds up the encoding at the expense of the quality.
+    pub fn tpl_model(self, val: bool) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_ENABLE_TPL_MODEL,
+            ControlValue::UInt(val as u32),
+        )
+    }
+
+    /// Temporal filtering of the keyframes
+    ///
+    /// The filter reads the frames following the keyframe, the encoder
+    /// holds them before coding it: the latency grows up to `g_lag_in_frames`
+    /// frames.
+    ///
+    /// It matches `AV1E_SET_ENABLE_KEYFRAME_FILTERING`.
+    pub fn keyframe_filtering(self, val: KeyframeFiltering) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_ENABLE_KEYFRAME_FILTERING,
+            ControlValue::UInt(val.to_aom()),
+        )
+    }
+
+    /// Periodic quality boost of the frames
+    ///
+    /// It matches `AV1E_SET_FRAME_PERIODIC_BOOST`, disabled by default.
+    pub fn frame_periodic_boost(self, val: bool) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_FRAME_PERIODIC_BOOST,
+            ControlValue::UInt(val as u32),
+        )
+    }
+
+    /// Color description of the sequence header
+    ///
+    /// If unset, the encoder takes the one of the first frame encoded, if
+    /// its format has any.
+    ///
+    /// It matches `AV1E_SET_COLOR_PRIMARIES`,
+    /// `AV1E_SET_TRANSFER_CHARACTERISTICS` and
+    /// `AV1E_SET_MATRIX_COEFFICIENTS`.
+    pub fn color_description(
+        mut self,
+        primaries: ColorPrimaries,
+        transfer: TransferCharacteristic,
+        matrix: MatrixCoefficients,
+    ) -> Self {
+        self.color_description = true;
+
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_COLOR_PRIMARIES,
+            ControlValue::Int(primaries as i32),
+        )
+        .push_control_raw(
+            aome_enc_control_id::AV1E_SET_TRANSFER_CHARACTERISTICS,
+            ControlValue::Int(transfer as i32),
+        )
+        .push_control_raw(
+            aome_enc_control_id::AV1E_SET_MATRIX_COEFFICIENTS,
+            ControlValue::Int(matrix as i32),
+        )
+    }
+
+    /// Signal the chroma subsampling explicitly, horizontally and vertically
+    ///
+    /// Some AVIF validators want it spelled out, e.g. 0/0 for 4:4:4. The
+    /// first frame encoded must have a matching format, the encoding fails
+    /// otherwise. Monochrome frames count as subsampled both ways.
+    ///
+    /// It matches `AV1E_SET_CHROMA_SUBSAMPLING_X` and
+    /// `AV1E_SET_CHROMA_SUBSAMPLING_Y`.
+    pub fn chroma_subsampling(mut self, x: bool, y: bool) -> Self {
+        self.chroma_subsampling = Some((x, y));
+
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_CHROMA_SUBSAMPLING_X,
+            ControlValue::UInt(x as u32),
+        )
+        .push_control_raw(
+            aome_enc_control_id::AV1E_SET_CHROMA_SUBSAMPLING_Y,
+            ControlValue::UInt(y as u32),
+        )
+    }
+
+    /// Where the trellis quantization is applied
+    ///
+    /// It matches `AV1E_SET_DISABLE_TRELLIS_QUANT`, skipping it speeds up
+    /// the encoding at the expense of the quality.
+    pub fn trellis_quant(self, val: TrellisQuant) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_DISABLE_TRELLIS_QUANT,
+            ControlValue::Int(val.to_aom()),
+        )
+    }
+
+    /// Temporal denoiser strength, 0 (disabled) to 6
+    ///
+    /// It denoises the source before encoding, e.g. noisy camera content at
+    /// low bitrates. The denoiser only runs in [`AomUsage::RealTime`] usage,
+    /// the encoder creation fails with other usages.
+    ///
+    /// It matches `AV1E_SET_NOISE_SENSITIVITY`.
+    pub fn noise_sensitivity(self, level: u8) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_NOISE_SENSITIVITY,
+            ControlValue::UInt(level as u32),
+        )
+    }
+
+    /// Strength of the noise estimated and synthesized as film grain, 0
+    /// disables it
+    ///
+    /// It matches `AV1E_SET_DENOISE_NOISE_LEVEL`.
+    pub fn denoise_noise_level(self, level: i32) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_DENOISE_NOISE_LEVEL,
+            ControlValue::Int(level),
+        )
+    }
+
+    /// Encode the noise estimated by [`denoise_noise_level`] as film grain
+    /// with the source denoised, the default, or keep the noise in the
+    /// source and only add the grain
+    ///
+    /// It needs libaom 3.4.0 or newer.
+    ///
+    /// It matches `AV1E_SET_ENABLE_DNL_DENOISING`.
+    ///
+    /// [`denoise_noise_level`]: AV1EncoderConfig::denoise_noise_level
+    #[cfg(aom_ge_3_4)]
+    pub fn dnl_denoising(self, val: bool) -> Self {
+        self.push_control_raw(
+            aome_enc_control_id::AV1E_SET_ENABLE_DNL_DENOISING,
+            ControlValue::Int(val as i32),
+        )
+    }
+
+    /// Restrict the tools costly to decode
+    ///
+    /// See [`ComplexityBudget`], the settings it leaves unset are not
+    /// staged.
+    pub fn complexity_budget(mut self, val: ComplexityBudget) -> Self {
+        use self::aome_enc_control_id::*;
+
+        let screen_content = val.screen_content.map(|screen| {
+            if screen {
+                aom_tune_content::AOM_CONTENT_SCREEN
+            } else {
+                aom_tune_content::AOM_CONTENT_DEFAULT
+            }
+        });
+        let controls = [
+            (AV1E_SET_REDUCED_REFERENCE_SET, val.reduced_reference_set),
+            (AV1E_SET_ENABLE_INTRABC, val.intrabc),
+            (AV1E_SET_ENABLE_PALETTE, val.palette),
+        ];
+
+        if let Some(content) = screen_content {
+            self = self.push_control_raw(AV1E_SET_TUNE_CONTENT, ControlValue::Int(content as i32));
+        }
+
+        for (id, enabled) in controls {
+            if let Some(enabled) = enabled {
+                self = self.push_control_raw(id, ControlValue::Int(enabled as i32));
+            }
+        }
+
+        self
+    }
+
+    /// Bound the realtime encoding, see [`RealtimeOptions`]
+    ///
+    /// The settings it leaves unset are not changed.
+    pub fn realtime_options(mut self, val: RealtimeOptions) -> Self {
+        if let Some(pct) = val.undershoot_pct {
+            self.cfg.rc_undershoot_pct = pct;
+        }
+
+        #[cfg(aom_ge_3_9)]
+        if let Some(frames) = val.max_consecutive_drops {
+            self = self.push_control_raw(
+                aome_enc_control_id::AV1E_SET_MAX_CONSEC_FRAME_DROP_CBR,
+                ControlValue::Int(frames as i32),
+            );
+        }
+
+        self
+    }
+
+    /// Produce the same packets for the same input and configuration
+    ///
+    /// The encoder output depends on how the work is split between
+    /// threads, it runs on a single thread without row based
+    /// multi-threading. The noise sensitivity and the denoiser are disabled
+    /// as well, they depend on the previous frames in ways that change with
+    /// the threading.
+    ///
+    /// The other settings are deterministic, e.g. the film grain seed, and
+    /// libaom does not read the clock. The results still depend on the
+    /// libaom version and on the SIMD code selected at runtime.
+    pub fn deterministic(mut self) -> Self {
+        self.cfg.g_threads = 1;
+
+        self.row_mt(false)
+            .push_control_raw(
+                aome_enc_control_id::AV1E_SET_NOISE_SENSITIVITY,
+                ControlValue::UInt(0),
+            )
+            .push_control_raw(
+                aome_enc_control_id::AV1E_SET_DENOISE_NOISE_LEVEL,
+                ControlValue::Int(0),
+            )
+    }
 }
 
 impl Deref for AV1EncoderConfig {
@@ -675,21 +1434,41 @@ impl DerefMut for AV1EncoderConfig {
 
 /// Algorithm specific usage type
 ///
-/// In this case, AV1 usage types.
-#[derive(Default, Clone, Copy, Debug)]
+/// In this case, AV1 usage types, see [`supported_usages`].
+///
+/// The default is [`AomUsage::GoodQuality`], [`AomUsage::RealTime`] with
+/// the `realtime-only` feature, the only usage of libaom built that way.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u32)]
 pub enum AomUsage {
     /// [`AOM_USAGE_GOOD_QUALITY`]
-    #[default]
+    #[cfg_attr(not(feature = "realtime-only"), default)]
     GoodQuality = 0,
 
     /// [`AOM_USAGE_REALTIME`]
+    #[cfg_attr(feature = "realtime-only", default)]
     RealTime = 1,
 
     /// [`AOM_USAGE_ALL_INTRA`]
     AllIntra = 2,
 }
 
+/// The usages the libaom loaded at runtime supports
+///
+/// [`AomUsage::AllIntra`] needs libaom 3.1.0 or newer. Every usage is
+/// probed with `aom_codec_enc_config_default`, the list is empty if libaom
+/// is built without the encoder.
+pub fn supported_usages() -> Vec<AomUsage> {
+    [
+        AomUsage::GoodQuality,
+        AomUsage::RealTime,
+        AomUsage::AllIntra,
+    ]
+    .into_iter()
+    .filter(|&usage| AV1EncoderConfig::new_with_usage(usage).is_ok())
+    .collect()
+}
+
 /// Tile coding mode
 #[derive(Default, Clone, Copy, Debug)]
 #[repr(u32)]
@@ -717,3 +1496,375 @@ pub enum BitstreamProfile {
     /// - 12-bit `4:0:0`, `4:2:2` and `4:4:4`
     Profile2 = 2,
 }
+
+/// Bit depth of the codec, `g_bit_depth`
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    /// 8 bits
+    #[default]
+    Eight,
+    /// 10 bits
+    Ten,
+    /// 12 bits
+    Twelve,
+}
+
+impl BitDepth {
+    fn to_aom(self) -> aom_bit_depth_t {
+        match self {
+            BitDepth::Eight => aom_bit_depth::AOM_BITS_8,
+            BitDepth::Ten => aom_bit_depth::AOM_BITS_10,
+            BitDepth::Twelve => aom_bit_depth::AOM_BITS_12,
+        }
+    }
+}
+
+/// Multi-pass encoding phase, `g_pass`
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodePass {
+    /// Single pass encoding
+    #[default]
+    OnePass,
+    /// First pass of a two-pass encoding, producing the statistics
+    FirstPass,
+    /// Last pass of a two-pass encoding, consuming the statistics
+    LastPass,
+}
+
+impl EncodePass {
+    fn to_aom(self) -> aom_enc_pass::Type {
+        match self {
+            EncodePass::OnePass => aom_enc_pass::AOM_RC_ONE_PASS,
+            EncodePass::FirstPass => aom_enc_pass::AOM_RC_FIRST_PASS,
+            EncodePass::LastPass => aom_enc_pass::AOM_RC_LAST_PASS,
+        }
+    }
+}
+
+/// Rate control mode, `rc_end_usage`
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateControl {
+    /// Variable bitrate
+    #[default]
+    Vbr,
+    /// Constant bitrate
+    Cbr,
+    /// Constrained quality, bounded by the target bitrate
+    Cq,
+    /// Constant quality
+    Q,
+}
+
+impl RateControl {
+    fn to_aom(self) -> aom_rc_mode::Type {
+        match self {
+            RateControl::Vbr => aom_rc_mode::AOM_VBR,
+            RateControl::Cbr => aom_rc_mode::AOM_CBR,
+            RateControl::Cq => aom_rc_mode::AOM_CQ,
+            RateControl::Q => aom_rc_mode::AOM_Q,
+        }
+    }
+}
+
+/// Keyframe placement mode, `kf_mode`
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyframeMode {
+    /// Placed by the encoder, within `kf_min_dist` and `kf_max_dist`
+    #[default]
+    Auto,
+    /// Only the forced keyframes
+    Disabled,
+}
+
+impl KeyframeMode {
+    fn to_aom(self) -> aom_kf_mode::Type {
+        match self {
+            KeyframeMode::Auto => aom_kf_mode::AOM_KF_AUTO,
+            KeyframeMode::Disabled => aom_kf_mode::AOM_KF_DISABLED,
+        }
+    }
+}
+
+/// Frame super-resolution mode, `rc_superres_mode`
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuperresMode {
+    /// No super-resolution
+    #[default]
+    None,
+    /// Fixed denominators, `rc_superres_denominator` and
+    /// `rc_superres_kf_denominator`
+    Fixed,
+    /// Random denominators, for testing
+    Random,
+    /// Used above the `rc_superres_qthresh` quantizers
+    QThresh,
+    /// Chosen by the encoder
+    Auto,
+}
+
+impl SuperresMode {
+    fn to_aom(self) -> aom_superres_mode::Type {
+        match self {
+            SuperresMode::None => aom_superres_mode::AOM_SUPERRES_NONE,
+            SuperresMode::Fixed => aom_superres_mode::AOM_SUPERRES_FIXED,
+            SuperresMode::Random => aom_superres_mode::AOM_SUPERRES_RANDOM,
+            SuperresMode::QThresh => aom_superres_mode::AOM_SUPERRES_QTHRESH,
+            SuperresMode::Auto => aom_superres_mode::AOM_SUPERRES_AUTO,
+        }
+    }
+}
+
+/// Keyframe temporal filtering, `AV1E_SET_ENABLE_KEYFRAME_FILTERING`
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyframeFiltering {
+    /// The keyframes are coded as they are
+    Disabled,
+    /// The keyframes are filtered
+    #[default]
+    Enabled,
+    /// The filtered keyframes are followed by an overlay frame, coding the
+    /// difference with the source
+    Overlay,
+}
+
+impl KeyframeFiltering {
+    fn to_aom(self) -> u32 {
+        match self {
+            KeyframeFiltering::Disabled => 0,
+            KeyframeFiltering::Enabled => 1,
+            KeyframeFiltering::Overlay => 2,
+        }
+    }
+}
+
+/// Trellis quantization stages, `AV1E_SET_DISABLE_TRELLIS_QUANT`
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrellisQuant {
+    /// Applied everywhere
+    #[default]
+    Enabled,
+    /// Never applied
+    Disabled,
+    /// Skipped in the rate-distortion search, applied when coding
+    DisabledInRdSearch,
+    /// Skipped when estimating the luma rate and distortion
+    DisabledInYrdEstimate,
+}
+
+impl TrellisQuant {
+    fn to_aom(self) -> i32 {
+        match self {
+            TrellisQuant::Enabled => 0,
+            TrellisQuant::Disabled => 1,
+            TrellisQuant::DisabledInRdSearch => 2,
+            TrellisQuant::DisabledInYrdEstimate => 3,
+        }
+    }
+}
+
+/// Tools restricted to bound the decoding cost, e.g. for embedded decoders
+///
+/// Every setting is left to libaom unless set, they are staged by
+/// [`AV1EncoderConfig::complexity_budget`].
+///
+/// | Setting | Control | Decoder savings |
+/// |---|---|---|
+/// | [`reduced_reference_set`] | `AV1E_SET_REDUCED_REFERENCE_SET` | At most 4 of the 7 references per frame, less memory bandwidth in motion compensation |
+/// | [`screen_content`] | `AV1E_SET_TUNE_CONTENT` | `false` leaves the screen content tools to the content detection, `true` forces them |
+/// | [`intrabc`] | `AV1E_SET_ENABLE_INTRABC` | No intra block copy, whose frames keep an unfiltered copy and cannot run the loop filters in parallel |
+/// | [`palette`] | `AV1E_SET_ENABLE_PALETTE` | No palette color cache and index map decoding |
+///
+/// The intra block copy and palette tools are only used on screen
+/// content.
+///
+/// [`reduced_reference_set`]: ComplexityBudget::reduced_reference_set
+/// [`screen_content`]: ComplexityBudget::screen_content
+/// [`intrabc`]: ComplexityBudget::intrabc
+/// [`palette`]: ComplexityBudget::palette
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComplexityBudget {
+    reduced_reference_set: Option<bool>,
+    screen_content: Option<bool>,
+    intrabc: Option<bool>,
+    palette: Option<bool>,
+}
+
+impl ComplexityBudget {
+    /// Leave every setting to libaom
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use at most 4 references per frame
+    pub fn reduced_reference_set(mut self, val: bool) -> Self {
+        self.reduced_reference_set = Some(val);
+        self
+    }
+
+    /// Tune for screen content, overriding the content detection
+    pub fn screen_content(mut self, val: bool) -> Self {
+        self.screen_content = Some(val);
+        self
+    }
+
+    /// Allow the intra block copy
+    pub fn intrabc(mut self, val: bool) -> Self {
+        self.intrabc = Some(val);
+        self
+    }
+
+    /// Allow the palette mode
+    pub fn palette(mut self, val: bool) -> Self {
+        self.palette = Some(val);
+        self
+    }
+}
+
+/// Realtime rate control settings
+///
+/// libaom has no per-frame deadline, the encoding time is set by the
+/// [`AomUsage::RealTime`] usage and the speed. These settings bound how the
+/// one-pass CBR rate control reacts when a frame overshoots. Every setting
+/// is left to libaom unless set, they are applied by
+/// [`AV1EncoderConfig::realtime_options`].
+///
+/// | Setting | Libaom | Effect |
+/// |---|---|---|
+/// | [`undershoot_pct`] | `rc_undershoot_pct` | How far below the target the frames may go to recover from an overshoot |
+/// | [`max_consecutive_drops`] | `AV1E_SET_MAX_CONSEC_FRAME_DROP_CBR`, libaom 3.9.0 | Frames the frame dropper may drop in a row |
+///
+/// The frame dropper only runs with a non-zero [`rc_dropframe_thresh`].
+///
+/// [`undershoot_pct`]: RealtimeOptions::undershoot_pct
+/// [`max_consecutive_drops`]: RealtimeOptions::max_consecutive_drops
+/// [`rc_dropframe_thresh`]: AV1EncoderConfig::rc_dropframe_thresh
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RealtimeOptions {
+    undershoot_pct: Option<u32>,
+    #[cfg(aom_ge_3_9)]
+    max_consecutive_drops: Option<u32>,
+}
+
+impl RealtimeOptions {
+    /// Leave every setting to libaom
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Undershoot allowed, in percent of the target bitrate, from 0 to 100
+    pub fn undershoot_pct(mut self, val: u32) -> Self {
+        self.undershoot_pct = Some(val);
+        self
+    }
+
+    /// Frames dropped in a row at most, 0 does not limit them
+    ///
+    /// It needs libaom 3.9.0 or newer.
+    #[cfg(aom_ge_3_9)]
+    pub fn max_consecutive_drops(mut self, val: u32) -> Self {
+        self.max_consecutive_drops = Some(val);
+        self
+    }
+}
+
+/// Convert a `Rational64` to the `aom_rational` libaom uses
+///
+/// It fails if the reduced fraction does not fit in 32 bits. Neither type
+/// belongs to this crate, hence a function instead of a `TryFrom`
+/// implementation.
+#[deprecated(since = "0.4.0", note = "use rational_to_raw")]
+pub fn to_aom_rational(val: Rational64) -> Result<aom_rational_t, AomError> {
+    rational_to_raw(val)
+}
+
+/// Convert a `Rational64` to the `aom_rational` libaom uses
+///
+/// It fails if the reduced fraction does not fit in 32 bits.
+pub fn rational_to_raw(val: Rational64) -> Result<aom_rational_t, AomError> {
+    let val = val.reduced();
+
+    match (i32::try_from(*val.numer()), i32::try_from(*val.denom())) {
+        (Ok(num), Ok(den)) => Ok(aom_rational_t { num, den }),
+        _ => Err(AomError::new(
+            AomErrorKind::InvalidParam,
+            "rational out of the 32-bit range",
+        )),
+    }
+}
+
+/// Timebase of an encoder, `g_timebase`
+///
+/// It converts the timestamps expressed in other timebases, rounding to
+/// the nearest unit. The rate control assumes the pts are in this timebase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timebase {
+    num: i64,
+    den: i64,
+}
+
+impl Timebase {
+    /// Wrap a timebase, in seconds
+    pub fn new(val: Rational64) -> Self {
+        Timebase {
+            num: *val.numer(),
+            den: *val.denom(),
+        }
+    }
+
+    pub(crate) fn from_aom(val: aom_rational_t) -> Self {
+        Timebase {
+            num: val.num as i64,
+            den: val.den as i64,
+        }
+    }
+
+    /// The timebase as a rational number
+    pub fn as_rational(&self) -> Rational64 {
+        Rational64::new(self.num, self.den)
+    }
+
+    fn convert(&self, val: i128, from: Rational64) -> i128 {
+        let mul = *from.numer() as i128 * self.den as i128;
+        let div = *from.denom() as i128 * self.num as i128;
+
+        // Degenerate timebases are left alone
+        if mul <= 0 || div <= 0 {
+            return val;
+        }
+
+        let n = val * mul;
+        if n >= 0 {
+            (n + div / 2) / div
+        } else {
+            (n - div / 2) / div
+        }
+    }
+
+    /// Rescale a timestamp expressed in `from` units
+    pub fn rescale(&self, ts: i64, from: Rational64) -> i64 {
+        self.convert(ts as i128, from)
+            .clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    /// Rescale a duration expressed in `from` units, at least 1
+    pub fn rescale_duration(&self, duration: u64, from: Rational64) -> u64 {
+        self.convert(duration as i128, from)
+            .clamp(1, u64::MAX as i128) as u64
+    }
+
+    /// Rescale the pts, dts and duration of `t` if it carries a timebase
+    pub fn rescale_time_info(&self, t: &TimeInfo) -> TimeInfo {
+        let from = match t.timebase {
+            Some(from) => from,
+            None => return t.clone(),
+        };
+
+        TimeInfo {
+            pts: t.pts.map(|v| self.rescale(v, from)),
+            dts: t.dts.map(|v| self.rescale(v, from)),
+            duration: t.duration.map(|v| self.rescale_duration(v, from)),
+            timebase: Some(self.as_rational()),
+            user_private: t.user_private.clone(),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+#![deny(missing_docs)]
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::encoder::{AV1Encoder, EncodeFlags};
+
+/// When the encoder forces keyframes on its own
+///
+/// Attached with [`AV1Encoder::set_keyframe_policy`], it complements
+/// `kf_max_dist` with a placement driven by the frame timestamps, e.g. to
+/// start every segment of an adaptive streaming rendition with a keyframe.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyframePolicy {
+    /// Every `n` frames, starting with the first one sent
+    EveryNFrames(u64),
+    /// Every time the timestamps cross a multiple of the duration, counted
+    /// from the first frame sent
+    EveryDuration(Duration),
+    /// On the first frame at or after each timestamp, in ascending order
+    AtPts(Vec<i64>),
+}
+
+#[derive(Debug)]
+enum State {
+    Frames { n: u64, count: u64 },
+    Duration { ticks: i64, next: Option<i64> },
+    Pts(VecDeque<i64>),
+}
+
+#[derive(Debug)]
+pub(crate) struct KeyframeScheduler {
+    policy: KeyframePolicy,
+    state: State,
+}
+
+impl KeyframeScheduler {
+    pub(crate) fn new(policy: KeyframePolicy, timebase: (i32, i32)) -> Self {
+        let state = match policy {
+            KeyframePolicy::EveryNFrames(n) => State::Frames { n, count: 0 },
+            KeyframePolicy::EveryDuration(d) => {
+                let (num, den) = timebase;
+                let ticks = d.as_nanos() as i128 * den as i128 / (num as i128 * 1_000_000_000);
+
+                State::Duration {
+                    ticks: ticks.clamp(0, i64::MAX as i128) as i64,
+                    next: None,
+                }
+            }
+            KeyframePolicy::AtPts(ref pts) => {
+                let mut pts = pts.clone();
+                pts.sort_unstable();
+                State::Pts(pts.into())
+            }
+        };
+
+        KeyframeScheduler { policy, state }
+    }
+
+    pub(crate) fn policy(&self) -> &KeyframePolicy {
+        &self.policy
+    }
+
+    // Whether the frame at `pts` must be a keyframe
+    pub(crate) fn fires(&mut self, pts: i64) -> bool {
+        match self.state {
+            State::Frames { n, ref mut count } => {
+                let fire = n > 0 && *count % n == 0;
+                *count += 1;
+                fire
+            }
+            State::Duration {
+                ticks,
+                ref mut next,
+            } => match *next {
+                _ if ticks == 0 => false,
+                Some(boundary) if pts < boundary => false,
+                Some(mut boundary) => {
+                    // Skip the boundaries crossed by a gap in the timestamps
+                    while boundary <= pts {
+                        boundary += ticks;
+                    }
+                    *next = Some(boundary);
+                    true
+                }
+                None => {
+                    *next = Some(pts + ticks);
+                    true
+                }
+            },
+            State::Pts(ref mut pending) => {
+                let mut fire = false;
+                while pending.front().map_or(false, |&p| p <= pts) {
+                    pending.pop_front();
+                    fire = true;
+                }
+                fire
+            }
+        }
+    }
+}
+
+impl AV1Encoder {
+    /// Force keyframes according to `policy`, `None` removes it
+    ///
+    /// It can be replaced at any time, the new policy counts the frames and
+    /// the durations from the next frame sent.
+    pub fn set_keyframe_policy(&mut self, policy: Option<KeyframePolicy>) {
+        self.keyframes = policy.map(|p| Box::new(KeyframeScheduler::new(p, self.timebase)));
+    }
+
+    /// The keyframe policy in use
+    pub fn keyframe_policy(&self) -> Option<&KeyframePolicy> {
+        self.keyframes.as_ref().map(|k| &k.policy)
+    }
+
+    pub(crate) fn keyframe_flags(&mut self, pts: i64) -> EncodeFlags {
+        match self.keyframes.as_deref_mut() {
+            Some(k) if k.fires(pts) => EncodeFlags::FORCE_KF,
+            _ => EncodeFlags::empty(),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+#![deny(missing_docs)]
+
+use std::collections::VecDeque;
+
+use av_data::packet::Packet;
+
+use crate::common::FrameFlags;
+use crate::encoder::AV1Encoder;
+
+/// A compressed frame packet, as recorded by the frame log
+///
+/// See [`AV1Encoder::enable_frame_log`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FrameLogEntry {
+    /// Timestamp of the packet, in `g_timebase` units
+    pub pts: i64,
+    /// Size of the packet, in bytes
+    pub size: usize,
+    /// The packet is a keyframe
+    pub is_key: bool,
+    /// Quantizer index of the frame, in the 0-255 range, `None` if libaom
+    /// did not report it
+    pub qindex: Option<i32>,
+    /// The `AOM_FRAME_*` flags of the packet
+    pub flags: FrameFlags,
+}
+
+pub(crate) enum FrameLog {
+    // The most recent entries, at most `capacity`
+    Ring {
+        entries: VecDeque<FrameLogEntry>,
+        capacity: usize,
+    },
+    Callback(Box<dyn FnMut(&FrameLogEntry) + Send>),
+}
+
+impl FrameLog {
+    pub(crate) fn record(&mut self, pkt: &Packet, qindex: Option<i32>, flags: FrameFlags) {
+        let entry = FrameLogEntry {
+            pts: pkt.t.pts.unwrap_or_default(),
+            size: pkt.data.len(),
+            is_key: pkt.is_key,
+            qindex,
+            flags,
+        };
+
+        match self {
+            FrameLog::Ring { entries, capacity } => {
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            }
+            FrameLog::Callback(f) => f(&entry),
+        }
+    }
+}
+
+impl AV1Encoder {
+    /// Record the last `capacity` compressed frame packets, see
+    /// [`take_frame_log`]
+    ///
+    /// The quantizer index is queried after every packet, see
+    /// [`last_qindex`]. Nothing is recorded unless this or
+    /// [`set_frame_log_callback`] is called, it replaces the callback.
+    ///
+    /// [`take_frame_log`]: AV1Encoder::take_frame_log
+    /// [`last_qindex`]: AV1Encoder::last_qindex
+    /// [`set_frame_log_callback`]: AV1Encoder::set_frame_log_callback
+    pub fn enable_frame_log(&mut self, capacity: usize) {
+        assert!(capacity > 0, "empty frame log");
+
+        self.frame_log = Some(Box::new(FrameLog::Ring {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }));
+    }
+
+    /// Call `f` on every compressed frame packet as it is retrieved
+    ///
+    /// It replaces the log of [`enable_frame_log`].
+    ///
+    /// [`enable_frame_log`]: AV1Encoder::enable_frame_log
+    pub fn set_frame_log_callback(&mut self, f: impl FnMut(&FrameLogEntry) + Send + 'static) {
+        self.frame_log = Some(Box::new(FrameLog::Callback(Box::new(f))));
+    }
+
+    /// Stop recording the packets, the entries not taken are lost
+    pub fn disable_frame_log(&mut self) {
+        self.frame_log = None;
+    }
+
+    /// Take the entries recorded since the last call, oldest first
+    ///
+    /// It is empty if the packets go to a callback.
+    pub fn take_frame_log(&mut self) -> Vec<FrameLogEntry> {
+        match self.frame_log.as_deref_mut() {
+            Some(FrameLog::Ring { entries, .. }) => entries.drain(..).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+#![deny(missing_docs)]
+
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+use av_data::frame::Frame;
+
+use crate::common::{AomError, AomErrorKind};
+use crate::encoder::{AOMPacket, AV1Encoder, EncodeFlags};
+
+/// Sending half of an encoder pipeline
+///
+/// Created by [`AV1Encoder::into_pipeline`]. Dropping every sender, or
+/// calling [`finish`], flushes the encoder: the remaining packets are
+/// delivered to the [`PacketReceiver`] before it stops.
+///
+/// [`finish`]: FrameSender::finish
+#[derive(Clone)]
+pub struct FrameSender {
+    tx: SyncSender<(Frame, EncodeFlags)>,
+}
+
+impl FrameSender {
+    /// Queue a frame for encoding
+    ///
+    /// It blocks while the queue is full and fails if the encoder stopped,
+    /// the reason is reported by the [`PacketReceiver`].
+    pub fn send(&self, frame: Frame) -> Result<(), AomError> {
+        self.send_with_flags(frame, EncodeFlags::empty())
+    }
+
+    /// Queue a frame for encoding with per-frame flags
+    ///
+    /// See [`AV1Encoder::encode_with_flags`].
+    pub fn send_with_flags(&self, frame: Frame, flags: EncodeFlags) -> Result<(), AomError> {
+        self.tx
+            .send((frame, flags))
+            .map_err(|_| AomError::new(AomErrorKind::Error, "the encoder pipeline is closed"))
+    }
+
+    /// Notify the encoder that no more frames will be sent
+    ///
+    /// The encoder is flushed once every clone of the sender is gone.
+    pub fn finish(self) {}
+}
+
+/// Receiving half of an encoder pipeline
+///
+/// It yields the packets in the order libaom produces them, it stops after
+/// the encoder is flushed or right after reporting an error.
+pub struct PacketReceiver {
+    rx: Receiver<Result<AOMPacket, AomError>>,
+}
+
+impl PacketReceiver {
+    /// Wait for the next packet
+    ///
+    /// It returns `None` once the encoder is done.
+    pub fn recv(&self) -> Option<Result<AOMPacket, AomError>> {
+        self.rx.recv().ok()
+    }
+
+    /// Return the next packet if one is available
+    pub fn try_recv(&self) -> Option<Result<AOMPacket, AomError>> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Iterator for PacketReceiver {
+    type Item = Result<AOMPacket, AomError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv()
+    }
+}
+
+// Forward the available packets, None if the receiver is gone
+fn drain(enc: &mut AV1Encoder, tx: &SyncSender<Result<AOMPacket, AomError>>) -> Option<usize> {
+    let mut count = 0;
+
+    while let Some(pkt) = enc.get_packet() {
+        tx.send(Ok(pkt)).ok()?;
+        count += 1;
+    }
+
+    Some(count)
+}
+
+fn run(
+    mut enc: AV1Encoder,
+    frames: Receiver<(Frame, EncodeFlags)>,
+    packets: SyncSender<Result<AOMPacket, AomError>>,
+) {
+    for (frame, flags) in frames {
+        if let Err(err) = enc.encode_with_flags(&frame, flags) {
+            let _ = packets.send(Err(err));
+            return;
+        }
+
+        if drain(&mut enc, &packets).is_none() {
+            return;
+        }
+    }
+
+    // Every flush call outputs at most the next shown frame
+    loop {
+        if let Err(err) = enc.flush() {
+            let _ = packets.send(Err(err));
+            return;
+        }
+
+        match drain(&mut enc, &packets) {
+            Some(0) | None => return,
+            Some(_) => {}
+        }
+    }
+}
+
+impl AV1Encoder {
+    /// Move the encoder to a worker thread
+    ///
+    /// The frames sent through the [`FrameSender`] are encoded by the worker
+    /// and the packets are delivered to the [`PacketReceiver`], each queue
+    /// holds up to `queue_len` items.
+    pub fn into_pipeline(self, queue_len: usize) -> (FrameSender, PacketReceiver) {
+        let (frame_tx, frame_rx) = mpsc::sync_channel(queue_len);
+        let (packet_tx, packet_rx) = mpsc::sync_channel(queue_len);
+
+        thread::spawn(move || run(self, frame_rx, packet_tx));
+
+        (
+            FrameSender { tx: frame_tx },
+            PacketReceiver { rx: packet_rx },
+        )
+    }
+}
@@ -0,0 +1,259 @@
+#![deny(missing_docs)]
+
+use std::mem;
+
+use crate::common::{AomError, AomErrorKind};
+use crate::encoder::EncodeFlags;
+use crate::ffi::*;
+
+/// A reference frame, as named by libaom
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reference {
+    /// `LAST_FRAME`
+    Last,
+    /// `LAST2_FRAME`
+    Last2,
+    /// `LAST3_FRAME`
+    Last3,
+    /// `GOLDEN_FRAME`
+    Golden,
+    /// `BWDREF_FRAME`
+    Backward,
+    /// `ALTREF2_FRAME`
+    Altref2,
+    /// `ALTREF_FRAME`
+    Altref,
+}
+
+impl Reference {
+    /// Every reference, in the libaom order
+    pub const ALL: [Reference; 7] = [
+        Reference::Last,
+        Reference::Last2,
+        Reference::Last3,
+        Reference::Golden,
+        Reference::Backward,
+        Reference::Altref2,
+        Reference::Altref,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn no_ref_flag(self) -> EncodeFlags {
+        match self {
+            Reference::Last => EncodeFlags::NO_REF_LAST,
+            Reference::Last2 => EncodeFlags::NO_REF_LAST2,
+            Reference::Last3 => EncodeFlags::NO_REF_LAST3,
+            Reference::Golden => EncodeFlags::NO_REF_GF,
+            Reference::Backward => EncodeFlags::NO_REF_BWD,
+            Reference::Altref2 => EncodeFlags::NO_REF_ARF2,
+            Reference::Altref => EncodeFlags::NO_REF_ARF,
+        }
+    }
+
+    // Only the last, golden and altref updates have a flag
+    fn no_update_flag(self) -> Option<EncodeFlags> {
+        match self {
+            Reference::Last => Some(EncodeFlags::NO_UPD_LAST),
+            Reference::Golden => Some(EncodeFlags::NO_UPD_GF),
+            Reference::Altref => Some(EncodeFlags::NO_UPD_ARF),
+            _ => None,
+        }
+    }
+}
+
+fn mask(refs: &[Reference]) -> u8 {
+    refs.iter().fold(0, |m, r| m | r.bit())
+}
+
+/// Which references a frame may predict from and update
+///
+/// By default libaom decides. The usage is translated to the per-frame
+/// [`EncodeFlags`] with [`flags`] or, for the references the flags cannot
+/// express, to an `AV1E_SET_SVC_REF_FRAME_CONFIG` value with
+/// [`svc_ref_frame_config_raw`].
+///
+/// ```
+/// use libaom::encoder::{Reference, ReferenceControl};
+///
+/// // Long-term reference: predict from golden only, keep it untouched
+/// let refs = ReferenceControl::new()
+///     .only_reference(&[Reference::Golden])
+///     .no_update(&[Reference::Golden]);
+///
+/// assert!(refs.flags().is_ok());
+/// ```
+///
+/// [`flags`]: ReferenceControl::flags
+/// [`svc_ref_frame_config_raw`]: ReferenceControl::svc_ref_frame_config_raw
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReferenceControl {
+    // Bits indexed by Reference
+    no_ref: u8,
+    only_ref: u8,
+    no_update: u8,
+    refresh: u8,
+}
+
+impl ReferenceControl {
+    /// Leave every decision to libaom
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Do not predict from any reference, the frame is coded intra
+    pub fn intra_only(self) -> Self {
+        self.no_reference(&Reference::ALL)
+    }
+
+    /// Do not predict from `refs`
+    pub fn no_reference(mut self, refs: &[Reference]) -> Self {
+        self.no_ref |= mask(refs);
+        self
+    }
+
+    /// Predict from `refs` only
+    pub fn only_reference(mut self, refs: &[Reference]) -> Self {
+        self.only_ref |= mask(refs);
+        self
+    }
+
+    /// Do not update `refs` with the frame
+    ///
+    /// Only the last, golden and altref references can be kept by
+    /// [`flags`], the others need [`svc_ref_frame_config_raw`].
+    ///
+    /// [`flags`]: ReferenceControl::flags
+    /// [`svc_ref_frame_config_raw`]: ReferenceControl::svc_ref_frame_config_raw
+    pub fn no_update(mut self, refs: &[Reference]) -> Self {
+        self.no_update |= mask(refs);
+        self
+    }
+
+    /// Update `refs` with the frame
+    ///
+    /// Only honoured by [`svc_ref_frame_config_raw`], the flags can only
+    /// prevent the updates.
+    ///
+    /// [`svc_ref_frame_config_raw`]: ReferenceControl::svc_ref_frame_config_raw
+    pub fn update(mut self, refs: &[Reference]) -> Self {
+        self.refresh |= mask(refs);
+        self
+    }
+
+    // The references that cannot be predicted from
+    fn disabled(&self) -> u8 {
+        let others = if self.only_ref != 0 {
+            !self.only_ref & 0x7f
+        } else {
+            0
+        };
+
+        self.no_ref | others
+    }
+
+    fn validate(&self) -> Result<(), AomError> {
+        let invalid = |detail| Err(AomError::new(AomErrorKind::InvalidParam, detail));
+
+        if self.only_ref & self.no_ref != 0 {
+            return invalid("reference both required and excluded");
+        }
+
+        if self.refresh & self.no_update != 0 {
+            return invalid("reference both updated and kept");
+        }
+
+        Ok(())
+    }
+
+    /// The per-frame flags for [`AV1Encoder::encode_with_flags`]
+    ///
+    /// It fails on contradictory usages, on explicit updates and on kept
+    /// references that have no flag.
+    ///
+    /// [`AV1Encoder::encode_with_flags`]: crate::encoder::AV1Encoder::encode_with_flags
+    pub fn flags(&self) -> Result<EncodeFlags, AomError> {
+        self.validate()?;
+
+        if self.refresh != 0 {
+            return Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "explicit updates need the svc reference config",
+            ));
+        }
+
+        let disabled = self.disabled();
+        let mut flags = EncodeFlags::empty();
+
+        for r in Reference::ALL {
+            if disabled & r.bit() != 0 {
+                flags |= r.no_ref_flag();
+            }
+
+            if self.no_update & r.bit() != 0 {
+                flags |= r.no_update_flag().ok_or_else(|| {
+                    AomError::new(
+                        AomErrorKind::InvalidParam,
+                        "no flag keeping the reference, use the svc reference config",
+                    )
+                })?;
+            }
+        }
+
+        Ok(flags)
+    }
+
+    /// Describe the usage as an `AV1E_SET_SVC_REF_FRAME_CONFIG` value
+    ///
+    /// `ref_idx` maps every reference, in the [`Reference::ALL`] order, to
+    /// one of the 8 reference buffers. The buffers of the references
+    /// explicitly updated are refreshed, none is otherwise.
+    ///
+    /// The value is applied with [`AV1Encoder::set_svc_ref_frame_config_raw`]
+    /// before encoding the frame.
+    ///
+    /// [`AV1Encoder::set_svc_ref_frame_config_raw`]: crate::encoder::AV1Encoder::set_svc_ref_frame_config_raw
+    pub fn svc_ref_frame_config_raw(
+        &self,
+        ref_idx: [i32; 7],
+    ) -> Result<aom_svc_ref_frame_config_t, AomError> {
+        self.validate()?;
+
+        if ref_idx.iter().any(|&idx| !(0..8).contains(&idx)) {
+            return Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "reference buffer index out of range",
+            ));
+        }
+
+        let disabled = self.disabled();
+        let mut cfg: aom_svc_ref_frame_config_t = unsafe { mem::zeroed() };
+
+        for (i, r) in Reference::ALL.into_iter().enumerate() {
+            let slot = ref_idx[i] as usize;
+
+            cfg.reference[i] = (disabled & r.bit() == 0) as i32;
+            cfg.ref_idx[i] = ref_idx[i];
+
+            if self.refresh & r.bit() != 0 {
+                cfg.refresh[slot] = 1;
+            }
+        }
+
+        // A buffer shared with a kept reference is not refreshed
+        for (i, r) in Reference::ALL.into_iter().enumerate() {
+            let slot = ref_idx[i] as usize;
+
+            if self.no_update & r.bit() != 0 && cfg.refresh[slot] != 0 {
+                return Err(AomError::new(
+                    AomErrorKind::InvalidParam,
+                    "reference buffer both updated and kept",
+                ));
+            }
+        }
+
+        Ok(cfg)
+    }
+}
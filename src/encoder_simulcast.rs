@@ -0,0 +1,232 @@
+#![deny(missing_docs)]
+
+use std::sync::Arc;
+
+use av_data::frame::{Frame, FrameBufferConv, MediaKind, VideoInfo};
+
+use crate::common::{AomError, AomErrorKind};
+use crate::encoder::{
+    AOMPacket, AV1Encoder, AV1EncoderConfig, EncodeFlags, KeyframeMode, KeyframePolicy,
+};
+use crate::encoder_keyframes::KeyframeScheduler;
+
+/// Encoders producing several renditions of the same input
+///
+/// Every rendition is encoded at the size of its configuration, the input
+/// frames are scaled down with a bilinear filter, only the 8-bit ones can be.
+///
+/// The renditions place their keyframes together: libaom does not place
+/// them on its own, they are forced on every rendition at once, on the
+/// frames flagged with [`EncodeFlags::FORCE_KF`], by the
+/// [`KeyframePolicy`] and every `kf_max_dist` frames of the first
+/// configuration.
+pub struct Simulcast {
+    encoders: Vec<AV1Encoder>,
+    sizes: Vec<(usize, usize)>,
+    keyframes: Option<KeyframeScheduler>,
+    // Forced keyframe interval, in frames
+    kf_interval: u64,
+    since_keyframe: Option<u64>,
+    // Rendition whose packets are being retrieved
+    current: usize,
+}
+
+impl Simulcast {
+    /// Create an encoder per configuration
+    ///
+    /// The configurations are left untouched, the encoders are created with
+    /// the automatic keyframe placement disabled.
+    pub fn new(cfgs: &[AV1EncoderConfig]) -> Result<Simulcast, AomError> {
+        let first = cfgs
+            .first()
+            .ok_or_else(|| AomError::new(AomErrorKind::InvalidParam, "no rendition configured"))?;
+        let kf_interval = (first.kf_max_dist as u64).max(1);
+
+        let encoders = cfgs
+            .iter()
+            .map(|cfg| cfg.clone().kf_mode(KeyframeMode::Disabled).get_encoder())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Simulcast {
+            encoders,
+            sizes: cfgs
+                .iter()
+                .map(|cfg| (cfg.g_w as usize, cfg.g_h as usize))
+                .collect(),
+            keyframes: None,
+            kf_interval,
+            since_keyframe: None,
+            current: 0,
+        })
+    }
+
+    /// The encoders, in the order of the configurations
+    pub fn encoders(&self) -> &[AV1Encoder] {
+        &self.encoders
+    }
+
+    /// The encoder of a rendition
+    pub fn encoder_mut(&mut self, idx: usize) -> Option<&mut AV1Encoder> {
+        self.encoders.get_mut(idx)
+    }
+
+    /// Force keyframes on every rendition according to `policy`
+    ///
+    /// The timestamps are in the timebase of the first configuration, see
+    /// [`AV1Encoder::set_keyframe_policy`].
+    pub fn set_keyframe_policy(&mut self, policy: Option<KeyframePolicy>) {
+        let timebase = self.encoders[0].timebase;
+
+        self.keyframes = policy.map(|p| KeyframeScheduler::new(p, timebase));
+    }
+
+    /// The keyframe policy in use
+    pub fn keyframe_policy(&self) -> Option<&KeyframePolicy> {
+        self.keyframes.as_ref().map(|k| k.policy())
+    }
+
+    /// Send an uncompressed frame to every rendition
+    ///
+    /// Call [`get_packet`] to receive the compressed data.
+    ///
+    /// [`get_packet`]: Simulcast::get_packet
+    pub fn encode(&mut self, frame: &Frame) -> Result<(), AomError> {
+        self.encode_with_flags(frame, EncodeFlags::empty())
+    }
+
+    /// Send an uncompressed frame to every rendition with per-frame flags
+    ///
+    /// See [`AV1Encoder::encode_with_flags`].
+    pub fn encode_with_flags(
+        &mut self,
+        frame: &Frame,
+        mut flags: EncodeFlags,
+    ) -> Result<(), AomError> {
+        let pts = frame.t.pts.unwrap_or_default();
+        let policy = self.keyframes.as_mut().is_some_and(|k| k.fires(pts));
+        let due = self
+            .since_keyframe
+            .map_or(true, |count| count + 1 >= self.kf_interval);
+
+        if due || policy || flags.contains(EncodeFlags::FORCE_KF) {
+            flags |= EncodeFlags::FORCE_KF;
+            self.since_keyframe = Some(0);
+        } else {
+            self.since_keyframe = self.since_keyframe.map(|count| count + 1);
+        }
+
+        self.current = 0;
+
+        for (enc, &(w, h)) in self.encoders.iter_mut().zip(&self.sizes) {
+            match scale_frame(frame, w, h)? {
+                Some(scaled) => enc.encode_with_flags(&scaled, flags)?,
+                None => enc.encode_with_flags(frame, flags)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Notify every rendition that no more data will be sent
+    pub fn flush(&mut self) -> Result<(), AomError> {
+        self.current = 0;
+
+        for enc in &mut self.encoders {
+            enc.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Retrieve the compressed data, tagged with the rendition index
+    ///
+    /// To be called until it returns `None`, the packets of a rendition
+    /// are returned before the ones of the next.
+    pub fn get_packet(&mut self) -> Option<(usize, AOMPacket)> {
+        while let Some(enc) = self.encoders.get_mut(self.current) {
+            if let Some(pkt) = enc.get_packet() {
+                return Some((self.current, pkt));
+            }
+
+            self.current += 1;
+        }
+
+        self.current = 0;
+
+        None
+    }
+}
+
+/// Scale `frame` to `w`x`h`, `None` if it has the right size already
+fn scale_frame(frame: &Frame, w: usize, h: usize) -> Result<Option<Frame>, AomError> {
+    let invalid = |detail| AomError::new(AomErrorKind::InvalidParam, detail);
+
+    let v = match frame.kind {
+        MediaKind::Video(ref v) => v,
+        _ => return Err(invalid("not a video frame")),
+    };
+
+    if (v.width, v.height) == (w, h) {
+        return Ok(None);
+    }
+
+    if v.format.get_chromaton(0).map_or(8, |c| c.get_depth()) > 8 {
+        return Err(invalid("only 8-bit frames can be scaled"));
+    }
+
+    let info = VideoInfo::new(w, h, v.flipped, v.frame_type.clone(), Arc::clone(&v.format));
+    let mut scaled = Frame::new_default_frame(info, Some(frame.t.clone()));
+
+    for (i, c) in v.format.iter().flatten().enumerate() {
+        let src = frame
+            .buf
+            .as_slice(i)
+            .map_err(|_| invalid("missing frame plane"))?;
+        let src_stride = frame
+            .buf
+            .linesize(i)
+            .map_err(|_| invalid("missing frame plane"))?;
+        let (sw, sh) = (c.get_width(v.width), c.get_height(v.height));
+
+        if sw == 0 || sh == 0 || src_stride < sw || src.len() < src_stride * (sh - 1) + sw {
+            return Err(invalid("frame plane smaller than the frame size"));
+        }
+
+        let dst_stride = scaled.buf.linesize(i).unwrap();
+        let (dw, dh) = (c.get_width(w), c.get_height(h));
+        let dst: &mut [u8] = scaled.buf.as_mut_slice(i).unwrap();
+
+        scale_plane((src, src_stride, sw, sh), (dst, dst_stride, dw, dh));
+    }
+
+    Ok(Some(scaled))
+}
+
+// Source position of the destination sample `pos`, in 1/256 units, the
+// sample centers are aligned
+fn source_pos(pos: usize, src: usize, dst: usize) -> (usize, usize, usize) {
+    let p = ((2 * pos + 1) * src * 256 / (2 * dst)).saturating_sub(128);
+    let p0 = (p >> 8).min(src - 1);
+
+    (p0, (p0 + 1).min(src - 1), p & 0xff)
+}
+
+// Bilinear interpolation of 8-bit samples
+fn scale_plane(
+    (src, src_stride, sw, sh): (&[u8], usize, usize, usize),
+    (dst, dst_stride, dw, dh): (&mut [u8], usize, usize, usize),
+) {
+    for y in 0..dh {
+        let (y0, y1, wy) = source_pos(y, sh, dh);
+        let row0 = &src[y0 * src_stride..];
+        let row1 = &src[y1 * src_stride..];
+
+        for (x, out) in dst[y * dst_stride..][..dw].iter_mut().enumerate() {
+            let (x0, x1, wx) = source_pos(x, sw, dw);
+            let top = row0[x0] as usize * (256 - wx) + row0[x1] as usize * wx;
+            let bottom = row1[x0] as usize * (256 - wx) + row1[x1] as usize * wx;
+
+            *out = ((top * (256 - wy) + bottom * wy + (1 << 15)) >> 16) as u8;
+        }
+    }
+}
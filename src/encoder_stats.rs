@@ -0,0 +1,151 @@
+#![deny(missing_docs)]
+
+use av_data::packet::Packet;
+
+use crate::encoder::AV1Encoder;
+
+#[derive(Clone, Copy, Debug)]
+struct PacketRecord {
+    pts: i64,
+    duration: u64,
+    size: usize,
+    is_key: bool,
+    quantizer: Option<i32>,
+}
+
+/// Summary of the packets recorded by [`EncoderStats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StatsSnapshot {
+    /// Number of compressed frame packets
+    pub packets: u64,
+    /// Total size of the packets, in bytes
+    pub bytes: u64,
+    /// Number of keyframe packets
+    pub keyframes: u64,
+    /// Average quantizer, in the 0-63 range, `None` if libaom did not
+    /// report it
+    pub average_quantizer: Option<f64>,
+    /// Time covered by the packets, in seconds
+    pub duration: f64,
+    /// Average bitrate over `duration`, in bits per second
+    pub bitrate: f64,
+}
+
+/// Per-packet accounting of an encoder
+///
+/// Enabled with [`AV1Encoder::enable_stats`], it records the packets
+/// retrieved with [`get_packet`].
+///
+/// [`get_packet`]: AV1Encoder::get_packet
+#[derive(Clone, Debug)]
+pub struct EncoderStats {
+    timebase: (i32, i32),
+    records: Vec<PacketRecord>,
+}
+
+impl EncoderStats {
+    fn new(timebase: (i32, i32)) -> Self {
+        EncoderStats {
+            timebase,
+            records: Vec::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, pkt: &Packet, quantizer: Option<i32>) {
+        self.records.push(PacketRecord {
+            pts: pkt.t.pts.unwrap_or_default(),
+            duration: pkt.t.duration.unwrap_or(1),
+            size: pkt.data.len(),
+            is_key: pkt.is_key,
+            quantizer,
+        });
+    }
+
+    fn seconds(&self, ticks: i64) -> f64 {
+        let (num, den) = self.timebase;
+
+        ticks as f64 * num as f64 / den as f64
+    }
+
+    fn summarize(&self, records: &[PacketRecord]) -> StatsSnapshot {
+        let (first, last) = match (records.first(), records.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return StatsSnapshot::default(),
+        };
+
+        let bytes: u64 = records.iter().map(|r| r.size as u64).sum();
+        let quantizers: Vec<_> = records.iter().filter_map(|r| r.quantizer).collect();
+        let duration = self.seconds(last.pts + last.duration as i64 - first.pts);
+
+        StatsSnapshot {
+            packets: records.len() as u64,
+            bytes,
+            keyframes: records.iter().filter(|r| r.is_key).count() as u64,
+            average_quantizer: if quantizers.is_empty() {
+                None
+            } else {
+                Some(quantizers.iter().sum::<i32>() as f64 / quantizers.len() as f64)
+            },
+            duration,
+            bitrate: if duration > 0.0 {
+                bytes as f64 * 8.0 / duration
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Summarize every packet recorded
+    pub fn snapshot(&self) -> StatsSnapshot {
+        self.summarize(&self.records)
+    }
+
+    /// Summarize the packets of the last `seconds`
+    pub fn snapshot_last(&self, seconds: f64) -> StatsSnapshot {
+        let end = match self.records.last() {
+            Some(last) => self.seconds(last.pts + last.duration as i64),
+            None => return StatsSnapshot::default(),
+        };
+
+        let start = self
+            .records
+            .iter()
+            .position(|r| end - self.seconds(r.pts) <= seconds)
+            .unwrap_or(self.records.len());
+
+        self.summarize(&self.records[start..])
+    }
+
+    /// Forget the packets recorded so far
+    pub fn reset(&mut self) {
+        self.records.clear();
+    }
+}
+
+impl AV1Encoder {
+    /// Start recording the size, type and quantizer of every packet
+    ///
+    /// The quantizer is queried after every compressed frame packet, see
+    /// [`last_quantizer`]. Nothing is recorded unless this is called.
+    ///
+    /// [`last_quantizer`]: AV1Encoder::last_quantizer
+    pub fn enable_stats(&mut self) {
+        if self.packet_stats.is_none() {
+            self.packet_stats = Some(Box::new(EncoderStats::new(self.timebase)));
+        }
+    }
+
+    /// Packet accounting, `None` unless [`enable_stats`] was called
+    ///
+    /// [`enable_stats`]: AV1Encoder::enable_stats
+    pub fn encoder_stats(&self) -> Option<&EncoderStats> {
+        self.packet_stats.as_deref()
+    }
+
+    /// Mutable packet accounting, e.g. to [`reset`] it
+    ///
+    /// [`reset`]: EncoderStats::reset
+    pub fn encoder_stats_mut(&mut self) -> Option<&mut EncoderStats> {
+        self.packet_stats.as_deref_mut()
+    }
+}
@@ -0,0 +1,217 @@
+#![deny(missing_docs)]
+
+use std::mem;
+
+use crate::common::{AomError, AomErrorKind};
+use crate::ffi::*;
+
+/// A coding tool `cfg_options_t` can disable
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CodingTool {
+    /// AB partitions, `disable_ab_partition_type`
+    AbPartition,
+    /// Rectangular partitions, `disable_rect_partition_type`
+    RectPartition,
+    /// 1:4 and 4:1 partitions, `disable_1to4_partition_type`
+    OneToFourPartition,
+    /// Flip and identity transforms, `disable_flip_idtx`
+    FlipIdtx,
+    /// Constrained directional enhancement filter, `disable_cdef`
+    Cdef,
+    /// Loop restoration, `disable_lr`
+    LoopRestoration,
+    /// Overlapped block motion compensation, `disable_obmc`
+    Obmc,
+    /// Warped motion, `disable_warp_motion`
+    WarpedMotion,
+    /// Global motion, `disable_global_motion`
+    GlobalMotion,
+    /// Distance weighted compound, `disable_dist_wtd_comp`
+    DistWtdCompound,
+    /// Difference weighted compound, `disable_diff_wtd_comp`
+    DiffWtdCompound,
+    /// Inter-intra compound, `disable_inter_intra_comp`
+    InterIntraCompound,
+    /// Masked compound, `disable_masked_comp`
+    MaskedCompound,
+    /// One sided compound, `disable_one_sided_comp`
+    OneSidedCompound,
+    /// Palette mode, `disable_palette`
+    Palette,
+    /// Intra block copy, `disable_intrabc`
+    IntraBc,
+    /// Chroma from luma, `disable_cfl`
+    Cfl,
+    /// Smooth intra modes, `disable_smooth_intra`
+    SmoothIntra,
+    /// Filter intra, `disable_filter_intra`
+    FilterIntra,
+    /// Dual interpolation filter, `disable_dual_filter`
+    DualFilter,
+    /// Intra angle delta, `disable_intra_angle_delta`
+    IntraAngleDelta,
+    /// Intra edge filter, `disable_intra_edge_filter`
+    IntraEdgeFilter,
+    /// 64x64 transforms, `disable_tx_64x64`
+    Tx64x64,
+    /// Smooth inter-intra, `disable_smooth_inter_intra`
+    SmoothInterIntra,
+    /// Inter-inter wedge, `disable_inter_inter_wedge`
+    InterInterWedge,
+    /// Inter-intra wedge, `disable_inter_intra_wedge`
+    InterIntraWedge,
+    /// Paeth intra, `disable_paeth_intra`
+    PaethIntra,
+    /// Trellis quantization, `disable_trellis_quant`
+    TrellisQuant,
+    /// Reference frame motion vectors, `disable_ref_frame_mv`
+    RefFrameMv,
+}
+
+/// Safe builder of the `cfg_options_t` set with
+/// [`AV1EncoderConfig::encoder_cfg_options`]
+///
+/// libaom only reads the structure when its `init_by_cfg_file` is set, as
+/// `aomenc --cfg` does, and then takes every field as is: the defaults here
+/// are the libaom ones, a dynamic superblock size, 4x4 to 128x128
+/// partitions and every tool enabled.
+///
+/// [`AV1EncoderConfig::encoder_cfg_options`]: crate::encoder::AV1EncoderConfig::encoder_cfg_options
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncoderCfgOptions {
+    superblock_size: Option<u32>,
+    partition_sizes: (u32, u32),
+    disabled: Vec<CodingTool>,
+    reduced_reference_set: bool,
+    reduced_tx_type_set: bool,
+}
+
+impl Default for EncoderCfgOptions {
+    fn default() -> Self {
+        EncoderCfgOptions {
+            superblock_size: None,
+            partition_sizes: (4, 128),
+            disabled: Vec::new(),
+            reduced_reference_set: false,
+            reduced_tx_type_set: false,
+        }
+    }
+}
+
+impl EncoderCfgOptions {
+    /// The libaom defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Superblock size, 64 or 128, `None` lets the encoder choose
+    pub fn superblock_size(mut self, val: Option<u32>) -> Self {
+        self.superblock_size = val;
+        self
+    }
+
+    /// Smallest and largest partition sizes, powers of two from 4 to 128
+    pub fn partition_sizes(mut self, min: u32, max: u32) -> Self {
+        self.partition_sizes = (min, max);
+        self
+    }
+
+    /// Disable `tools`
+    pub fn disable(mut self, tools: &[CodingTool]) -> Self {
+        self.disabled.extend_from_slice(tools);
+        self
+    }
+
+    /// Use at most 4 reference frames
+    pub fn reduced_reference_set(mut self, val: bool) -> Self {
+        self.reduced_reference_set = val;
+        self
+    }
+
+    /// Use a reduced set of transform types
+    pub fn reduced_tx_type_set(mut self, val: bool) -> Self {
+        self.reduced_tx_type_set = val;
+        self
+    }
+
+    /// Whether `tool` is disabled
+    pub fn is_disabled(&self, tool: CodingTool) -> bool {
+        self.disabled.contains(&tool)
+    }
+
+    /// Build the `cfg_options_t`, with `init_by_cfg_file` set
+    ///
+    /// It fails on the sizes libaom does not support and on the partitions
+    /// larger than the superblock.
+    pub fn to_raw(&self) -> Result<cfg_options_t, AomError> {
+        let invalid = |detail| Err(AomError::new(AomErrorKind::InvalidParam, detail));
+        let (min, max) = self.partition_sizes;
+
+        if !matches!(self.superblock_size, None | Some(64) | Some(128)) {
+            return invalid("superblock size other than 64 or 128");
+        }
+
+        let size = |v: u32| v.is_power_of_two() && (4..=128).contains(&v);
+        if !size(min) || !size(max) {
+            return invalid("partition size out of the 4-128 powers of two");
+        }
+
+        if min > max {
+            return invalid("minimum partition size above the maximum");
+        }
+
+        if self.superblock_size.is_some_and(|sb| max > sb) {
+            return invalid("maximum partition size above the superblock size");
+        }
+
+        let mut raw: cfg_options_t = unsafe { mem::zeroed() };
+
+        raw.init_by_cfg_file = 1;
+        raw.super_block_size = self.superblock_size.unwrap_or(0);
+        raw.min_partition_size = min;
+        raw.max_partition_size = max;
+        raw.reduced_reference_set = self.reduced_reference_set as u32;
+        raw.reduced_tx_type_set = self.reduced_tx_type_set as u32;
+
+        for tool in &self.disabled {
+            *disable_flag(&mut raw, *tool) = 1;
+        }
+
+        Ok(raw)
+    }
+}
+
+fn disable_flag(raw: &mut cfg_options_t, tool: CodingTool) -> &mut u32 {
+    match tool {
+        CodingTool::AbPartition => &mut raw.disable_ab_partition_type,
+        CodingTool::RectPartition => &mut raw.disable_rect_partition_type,
+        CodingTool::OneToFourPartition => &mut raw.disable_1to4_partition_type,
+        CodingTool::FlipIdtx => &mut raw.disable_flip_idtx,
+        CodingTool::Cdef => &mut raw.disable_cdef,
+        CodingTool::LoopRestoration => &mut raw.disable_lr,
+        CodingTool::Obmc => &mut raw.disable_obmc,
+        CodingTool::WarpedMotion => &mut raw.disable_warp_motion,
+        CodingTool::GlobalMotion => &mut raw.disable_global_motion,
+        CodingTool::DistWtdCompound => &mut raw.disable_dist_wtd_comp,
+        CodingTool::DiffWtdCompound => &mut raw.disable_diff_wtd_comp,
+        CodingTool::InterIntraCompound => &mut raw.disable_inter_intra_comp,
+        CodingTool::MaskedCompound => &mut raw.disable_masked_comp,
+        CodingTool::OneSidedCompound => &mut raw.disable_one_sided_comp,
+        CodingTool::Palette => &mut raw.disable_palette,
+        CodingTool::IntraBc => &mut raw.disable_intrabc,
+        CodingTool::Cfl => &mut raw.disable_cfl,
+        CodingTool::SmoothIntra => &mut raw.disable_smooth_intra,
+        CodingTool::FilterIntra => &mut raw.disable_filter_intra,
+        CodingTool::DualFilter => &mut raw.disable_dual_filter,
+        CodingTool::IntraAngleDelta => &mut raw.disable_intra_angle_delta,
+        CodingTool::IntraEdgeFilter => &mut raw.disable_intra_edge_filter,
+        CodingTool::Tx64x64 => &mut raw.disable_tx_64x64,
+        CodingTool::SmoothInterIntra => &mut raw.disable_smooth_inter_intra,
+        CodingTool::InterInterWedge => &mut raw.disable_inter_inter_wedge,
+        CodingTool::InterIntraWedge => &mut raw.disable_inter_intra_wedge,
+        CodingTool::PaethIntra => &mut raw.disable_paeth_intra,
+        CodingTool::TrellisQuant => &mut raw.disable_trellis_quant,
+        CodingTool::RefFrameMv => &mut raw.disable_ref_frame_mv,
+    }
+}
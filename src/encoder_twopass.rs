@@ -0,0 +1,131 @@
+#![deny(missing_docs)]
+
+use std::os::raw;
+
+use crate::common::{AomError, AomErrorKind};
+use crate::ffi::*;
+
+/// First pass statistics, as fed to the last pass
+///
+/// libaom produces one [`AOMPacket::Stats`] per frame, each holding a
+/// `FIRSTPASS_STATS` record, followed on flush by a record accumulating
+/// the whole pass, the end of stream (EOS) one. The last pass expects all
+/// of them concatenated, the EOS record last.
+///
+/// The record layout is private to libaom, the size is taken from the
+/// first packet and every record is handled as an array of `f64`.
+///
+/// [`AOMPacket::Stats`]: crate::encoder::AOMPacket::Stats
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TwoPassStats {
+    record_size: usize,
+    data: Vec<u8>,
+}
+
+impl TwoPassStats {
+    /// Create an empty set of statistics
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the payload of a stats packet
+    ///
+    /// The payloads must all have the same size, a multiple of 8 bytes.
+    pub fn push(&mut self, record: &[u8]) -> Result<(), AomError> {
+        let invalid = |detail| AomError::new(AomErrorKind::InvalidParam, detail);
+
+        if record.is_empty() || record.len() % 8 != 0 {
+            return Err(invalid("not a first pass stats record"));
+        }
+
+        if self.record_size == 0 {
+            self.record_size = record.len();
+        } else if self.record_size != record.len() {
+            return Err(invalid("stats record size mismatch"));
+        }
+
+        self.data.extend_from_slice(record);
+
+        Ok(())
+    }
+
+    /// Merge the statistics of consecutive chunks of the same clip
+    ///
+    /// The records are concatenated in order, the EOS record of every chunk
+    /// is dropped and replaced by one summing them, as libaom accumulates
+    /// its own. Empty chunks are skipped.
+    pub fn merge(chunks: Vec<TwoPassStats>) -> Result<TwoPassStats, AomError> {
+        let mut merged = TwoPassStats::new();
+        let mut eos: Vec<f64> = Vec::new();
+
+        for chunk in chunks.iter().filter(|c| !c.is_empty()) {
+            let (records, last) = chunk.data.split_at(chunk.data.len() - chunk.record_size);
+
+            for record in records.chunks_exact(chunk.record_size) {
+                merged.push(record)?;
+            }
+
+            let values = last
+                .chunks_exact(8)
+                .map(|v| f64::from_ne_bytes(v.try_into().unwrap()));
+
+            if eos.is_empty() {
+                eos.extend(values);
+            } else if eos.len() * 8 == chunk.record_size {
+                eos.iter_mut().zip(values).for_each(|(sum, v)| *sum += v);
+            } else {
+                return Err(AomError::new(
+                    AomErrorKind::InvalidParam,
+                    "stats record size mismatch",
+                ));
+            }
+        }
+
+        if !eos.is_empty() {
+            let last: Vec<u8> = eos.iter().flat_map(|v| v.to_ne_bytes()).collect();
+            merged.push(&last)?;
+        }
+
+        Ok(merged)
+    }
+
+    /// Number of records, the EOS one included
+    pub fn len(&self) -> usize {
+        self.data.len().checked_div(self.record_size).unwrap_or(0)
+    }
+
+    /// Whether no record was pushed
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The records, in order
+    pub fn records(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks_exact(self.record_size.max(1))
+    }
+
+    /// The concatenated records
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Describe the records for [`AV1EncoderConfig::rc_twopass_stats_in_raw`]
+    ///
+    /// The buffer is borrowed by the encoder: the statistics must outlive
+    /// the encoder created with it and must not be modified meanwhile.
+    ///
+    /// [`AV1EncoderConfig::rc_twopass_stats_in_raw`]: crate::encoder::AV1EncoderConfig::rc_twopass_stats_in_raw
+    pub fn as_fixed_buf_raw(&self) -> aom_fixed_buf_t {
+        aom_fixed_buf_t {
+            buf: self.data.as_ptr() as *mut raw::c_void,
+            sz: self.data.len(),
+        }
+    }
+
+    /// Describe the records for the last pass
+    #[deprecated(since = "0.4.0", note = "use as_fixed_buf_raw")]
+    pub fn as_fixed_buf(&self) -> aom_fixed_buf_t {
+        self.as_fixed_buf_raw()
+    }
+}
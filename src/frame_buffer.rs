@@ -0,0 +1,393 @@
+#![deny(missing_docs)]
+
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex, Weak};
+
+use av_data::frame::{Frame, FrameBuffer, FrameError, MediaKind, VideoInfo};
+use av_data::pixel::Formaton;
+
+use crate::ffi::*;
+use crate::image::new_frame;
+
+/// Caller-provided storage for the decoded frames
+///
+/// Once installed with [`AV1Decoder::with_allocator`], libaom asks for a
+/// buffer every time it needs a new frame (output or reference) and gives it
+/// back when it is done with it.
+///
+/// The [`Frame`]s returned by the decoder point straight into those buffers,
+/// so a buffer is handed back to [`release`] only once both libaom and every
+/// `Frame` referencing it are done with it. Since libaom keeps reading its
+/// reference frames, the pixels are shared read-only: the first mutable
+/// access to a zero-copy `Frame` copies its planes into memory owned by the
+/// `Frame` itself.
+///
+/// The allocator is owned by the decoder and by the frames still referencing
+/// its buffers, so it lives as long as the last of them.
+///
+/// [`AV1Decoder::with_allocator`]: crate::decoder::AV1Decoder::with_allocator
+/// [`Frame`]: av_data::frame::Frame
+/// [`release`]: FrameBufferAllocator::release
+pub trait FrameBufferAllocator: Send {
+    /// Provide a buffer of at least `min_size` bytes
+    ///
+    /// The content does not need to be cleared. Returning `None`, or a
+    /// smaller buffer, makes the current decode call fail.
+    fn get(&mut self, min_size: usize) -> Option<Box<[u8]>>;
+    /// Take back a buffer previously provided by [`get`]
+    ///
+    /// [`get`]: FrameBufferAllocator::get
+    fn release(&mut self, buf: Box<[u8]>);
+}
+
+pub(crate) type SharedAllocator = Arc<Mutex<dyn FrameBufferAllocator>>;
+
+/// A buffer on loan from the allocator
+///
+/// libaom and the zero-copy frames hold a reference each, the buffer goes
+/// back to the allocator when the last one is dropped.
+struct PooledBuffer {
+    data: *mut u8,
+    len: usize,
+    alloc: SharedAllocator,
+}
+
+// The buffer is written by libaom only before being handed out as a frame.
+unsafe impl Send for PooledBuffer {}
+unsafe impl Sync for PooledBuffer {}
+
+impl PooledBuffer {
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buf = unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.data, self.len)) };
+
+        if let Ok(mut alloc) = self.alloc.lock() {
+            alloc.release(buf);
+        }
+    }
+}
+
+pub(crate) unsafe extern "C" fn get_frame_buffer(
+    priv_: *mut c_void,
+    min_size: usize,
+    fb: *mut aom_codec_frame_buffer_t,
+) -> c_int {
+    let alloc = &*(priv_ as *const SharedAllocator);
+
+    let mut a = match alloc.lock() {
+        Ok(a) => a,
+        Err(_) => return -1,
+    };
+
+    match a.get(min_size) {
+        Some(buf) if buf.len() >= min_size => {
+            drop(a);
+            let len = buf.len();
+            let data = Box::into_raw(buf) as *mut u8;
+            let pooled = Arc::new(PooledBuffer {
+                data,
+                len,
+                alloc: alloc.clone(),
+            });
+
+            (*fb).data = data;
+            (*fb).size = len;
+            (*fb).priv_ = Box::into_raw(Box::new(pooled)) as *mut c_void;
+
+            0
+        }
+        Some(buf) => {
+            a.release(buf);
+            -1
+        }
+        None => -1,
+    }
+}
+
+pub(crate) unsafe extern "C" fn release_frame_buffer(
+    _priv: *mut c_void,
+    fb: *mut aom_codec_frame_buffer_t,
+) -> c_int {
+    let pooled = (*fb).priv_ as *mut Arc<PooledBuffer>;
+
+    if !pooled.is_null() {
+        let _ = Box::from_raw(pooled);
+        (*fb).priv_ = ptr::null_mut();
+    }
+
+    0
+}
+
+enum Storage {
+    Shared(Arc<PooledBuffer>),
+    Owned(Box<[u8]>),
+}
+
+struct PlaneRange {
+    offset: usize,
+    len: usize,
+    stride: usize,
+}
+
+/// Frame planes referencing a buffer provided by a [`FrameBufferAllocator`]
+pub(crate) struct PooledFrameBuffer {
+    storage: Storage,
+    planes: Vec<PlaneRange>,
+}
+
+impl PooledFrameBuffer {
+    /// Wrap the planes of `img` without copying them
+    ///
+    /// `img.fb_priv` must come from [`get_frame_buffer`], `None` is returned
+    /// if the planes are not stored in that buffer.
+    pub(crate) unsafe fn from_img(img: &aom_image_t) -> Option<PooledFrameBuffer> {
+        let pooled = (img.fb_priv as *const Arc<PooledBuffer>).as_ref()?;
+
+        let start = pooled.data as usize;
+        let end = start + pooled.len;
+
+        let planes = (0..3)
+            .map(|i| {
+                let stride = img.stride[i];
+                let height = if i == 0 {
+                    img.d_h
+                } else {
+                    (img.d_h + img.y_chroma_shift) >> img.y_chroma_shift
+                } as usize;
+                let plane = img.planes[i] as usize;
+
+                if stride <= 0 || plane < start || plane >= end {
+                    return None;
+                }
+
+                let offset = plane - start;
+                let len = (stride as usize * height).min(pooled.len - offset);
+
+                Some(PlaneRange {
+                    offset,
+                    len,
+                    stride: stride as usize,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(PooledFrameBuffer {
+            storage: Storage::Shared(pooled.clone()),
+            planes,
+        })
+    }
+
+    fn data(&self) -> &[u8] {
+        match self.storage {
+            Storage::Shared(ref pooled) => pooled.as_slice(),
+            Storage::Owned(ref buf) => buf,
+        }
+    }
+}
+
+impl FrameBuffer for PooledFrameBuffer {
+    fn linesize(&self, idx: usize) -> Result<usize, FrameError> {
+        self.planes
+            .get(idx)
+            .map(|p| p.stride)
+            .ok_or(FrameError::InvalidIndex)
+    }
+
+    fn count(&self) -> usize {
+        self.planes.len()
+    }
+
+    fn as_slice_inner(&self, idx: usize) -> Result<&[u8], FrameError> {
+        let p = self.planes.get(idx).ok_or(FrameError::InvalidIndex)?;
+
+        Ok(&self.data()[p.offset..p.offset + p.len])
+    }
+
+    fn as_mut_slice_inner(&mut self, idx: usize) -> Result<&mut [u8], FrameError> {
+        let p = self.planes.get(idx).ok_or(FrameError::InvalidIndex)?;
+
+        if let Storage::Shared(ref pooled) = self.storage {
+            self.storage = Storage::Owned(pooled.as_slice().into());
+        }
+
+        match self.storage {
+            Storage::Owned(ref mut buf) => Ok(&mut buf[p.offset..p.offset + p.len]),
+            Storage::Shared(_) => unreachable!(),
+        }
+    }
+}
+
+/// Frame pool statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FramePoolStats {
+    /// Frames served from a recycled buffer
+    pub hits: usize,
+    /// Frames that required a new allocation
+    pub misses: usize,
+}
+
+struct FramePoolInner {
+    free: Vec<(PoolKey, Box<dyn FrameBuffer>)>,
+    // The buffers dropped past it are freed
+    capacity: usize,
+    stats: FramePoolStats,
+}
+
+// The 8 reference frames of AV1 plus a few frames in flight, the output
+// frames are seldom held longer
+const DEFAULT_CAPACITY: usize = 8 + 4;
+
+impl Default for FramePoolInner {
+    fn default() -> Self {
+        FramePoolInner {
+            free: Vec::new(),
+            capacity: DEFAULT_CAPACITY,
+            stats: FramePoolStats::default(),
+        }
+    }
+}
+
+type PoolKey = (usize, usize, Formaton);
+
+/// Recycle the decoded frame allocations
+///
+/// Once the decoder is created with [`AV1Decoder::with_frame_pool`], the
+/// buffer of every [`Frame`] it returns goes back to the pool when the
+/// `Frame` is dropped and is reused by the next frame with the same
+/// geometry and pixel format.
+///
+/// At most 12 free buffers are kept by default, see [`with_capacity`]. The
+/// pool is cheap to clone, all the clones share the same buffers.
+///
+/// [`with_capacity`]: FramePool::with_capacity
+/// [`AV1Decoder::with_frame_pool`]: crate::decoder::AV1Decoder::with_frame_pool
+/// [`Frame`]: av_data::frame::Frame
+#[derive(Clone, Default)]
+pub struct FramePool {
+    inner: Arc<Mutex<FramePoolInner>>,
+}
+
+impl FramePool {
+    /// Create an empty pool
+    pub fn new() -> FramePool {
+        FramePool::default()
+    }
+
+    /// Create an empty pool keeping at most `capacity` free buffers
+    ///
+    /// The buffers returned once the pool is full are freed.
+    pub fn with_capacity(capacity: usize) -> FramePool {
+        FramePool {
+            inner: Arc::new(Mutex::new(FramePoolInner {
+                capacity,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Return the hits and misses counted so far
+    pub fn stats(&self) -> FramePoolStats {
+        self.inner.lock().map(|p| p.stats).unwrap_or_default()
+    }
+
+    /// Drop the free buffers of the `width`x`height` frames
+    ///
+    /// Called once the stream size changes, they are unlikely to be used
+    /// again.
+    pub(crate) fn evict(&self, width: usize, height: usize) {
+        if let Ok(mut p) = self.inner.lock() {
+            p.free.retain(|((w, h, _), _)| (*w, *h) != (width, height));
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn free_count(&self) -> usize {
+        self.inner.lock().unwrap().free.len()
+    }
+
+    /// Get a frame matching `info`, reusing a previous allocation if possible
+    ///
+    /// The content of a recycled frame is left as is.
+    pub(crate) fn get(&self, info: VideoInfo) -> Frame {
+        let key = (info.width, info.height, *info.format);
+
+        let recycled = self.inner.lock().ok().and_then(|mut p| {
+            let buf = p
+                .free
+                .iter()
+                .position(|(k, _)| *k == key)
+                .map(|i| p.free.swap_remove(i).1);
+
+            if buf.is_some() {
+                p.stats.hits += 1;
+            } else {
+                p.stats.misses += 1;
+            }
+
+            buf
+        });
+
+        let buf = recycled.unwrap_or_else(|| new_frame(info.clone(), None).buf);
+        let kind = MediaKind::Video(info);
+
+        Frame {
+            kind,
+            buf: Box::new(RecycledFrameBuffer {
+                buf: Some(buf),
+                key,
+                pool: Arc::downgrade(&self.inner),
+            }),
+            t: Default::default(),
+        }
+    }
+}
+
+/// Frame buffer going back to its pool on drop
+struct RecycledFrameBuffer {
+    buf: Option<Box<dyn FrameBuffer>>,
+    key: PoolKey,
+    pool: Weak<Mutex<FramePoolInner>>,
+}
+
+impl RecycledFrameBuffer {
+    fn buf(&self) -> &dyn FrameBuffer {
+        self.buf.as_deref().unwrap()
+    }
+}
+
+impl Drop for RecycledFrameBuffer {
+    fn drop(&mut self) {
+        if let (Some(pool), Some(buf)) = (self.pool.upgrade(), self.buf.take()) {
+            if let Ok(mut p) = pool.lock() {
+                if p.free.len() < p.capacity {
+                    p.free.push((self.key, buf));
+                }
+            }
+        }
+    }
+}
+
+impl FrameBuffer for RecycledFrameBuffer {
+    fn linesize(&self, idx: usize) -> Result<usize, FrameError> {
+        self.buf().linesize(idx)
+    }
+
+    fn count(&self) -> usize {
+        self.buf().count()
+    }
+
+    fn as_slice_inner(&self, idx: usize) -> Result<&[u8], FrameError> {
+        self.buf().as_slice_inner(idx)
+    }
+
+    fn as_mut_slice_inner(&mut self, idx: usize) -> Result<&mut [u8], FrameError> {
+        self.buf.as_deref_mut().unwrap().as_mut_slice_inner(idx)
+    }
+}
@@ -0,0 +1,448 @@
+#![deny(missing_docs)]
+
+use std::ptr::{self, NonNull};
+use std::slice;
+use std::sync::Arc;
+
+use av_data::frame::{Frame, FrameBufferConv, FrameType, MediaKind, VideoInfo};
+use av_data::pixel::formats::{YUV420, YUV422, YUV444};
+use av_data::pixel::{
+    Chromaton, ColorPrimaries, Formaton, FromPrimitive, MatrixCoefficients, TransferCharacteristic,
+};
+use av_data::timeinfo::TimeInfo;
+
+use crate::ffi::*;
+
+/// Image allocated by libaom
+///
+/// It wraps an `aom_image_t` owning its planes, used wherever libaom
+/// copies pictures in and out of the codec (e.g. the reference frames).
+pub struct AomImage {
+    img: NonNull<aom_image_t>,
+}
+
+// The image owns its planes.
+unsafe impl Send for AomImage {}
+unsafe impl Sync for AomImage {}
+
+impl AomImage {
+    /// Allocate a `width`x`height` image in the `fmt` pixel format
+    ///
+    /// It calls `aom_img_alloc`.
+    pub fn new(fmt: aom_img_fmt_t, width: u32, height: u32) -> Option<AomImage> {
+        let img = unsafe { aom_img_alloc(ptr::null_mut(), fmt, width, height, 32) };
+
+        NonNull::new(img).map(|img| AomImage { img })
+    }
+
+    /// Allocate a `width`x`height` image surrounded by `border` pixels
+    ///
+    /// Some controls copying the codec frames require the image to have
+    /// the same border as the codec.
+    ///
+    /// It calls `aom_img_alloc_with_border`.
+    pub fn new_with_border(
+        fmt: aom_img_fmt_t,
+        width: u32,
+        height: u32,
+        border: u32,
+    ) -> Option<AomImage> {
+        let img = unsafe {
+            aom_img_alloc_with_border(ptr::null_mut(), fmt, width, height, 32, 32, border)
+        };
+
+        NonNull::new(img).map(|img| AomImage { img })
+    }
+
+    /// Allocate an image with the same geometry and format as `src` and
+    /// copy its content
+    ///
+    /// # Safety
+    ///
+    /// The planes of `src` must be valid for its geometry.
+    pub(crate) unsafe fn copy_from_raw(src: &aom_image_t) -> Option<AomImage> {
+        let mut img = AomImage::new(src.fmt, src.d_w, src.d_h)?;
+
+        img.copy_raw(src);
+
+        Some(img)
+    }
+
+    /// Copy the content of `src`
+    ///
+    /// # Safety
+    ///
+    /// The planes of `src` must be valid for its geometry, which must be
+    /// the one of the image, see [`same_geometry`].
+    ///
+    /// [`same_geometry`]: AomImage::same_geometry
+    pub(crate) unsafe fn copy_raw(&mut self, src: &aom_image_t) {
+        {
+            let dst = self.raw_mut();
+
+            dst.bit_depth = src.bit_depth;
+            dst.cp = src.cp;
+            dst.tc = src.tc;
+            dst.mc = src.mc;
+            dst.monochrome = src.monochrome;
+            dst.csp = src.csp;
+            dst.range = src.range;
+        }
+
+        for i in 0..3 {
+            let rows = self.plane_height(i);
+            let row_bytes = self.plane_row_bytes(i);
+            let dst_stride = self.stride(i);
+            let src_stride = src.stride[i] as usize;
+            let dst = self.raw().planes[i];
+
+            for r in 0..rows {
+                ptr::copy_nonoverlapping(
+                    src.planes[i].add(r * src_stride),
+                    dst.add(r * dst_stride),
+                    row_bytes,
+                );
+            }
+        }
+    }
+
+    pub(crate) fn raw(&self) -> &aom_image_t {
+        unsafe { self.img.as_ref() }
+    }
+
+    pub(crate) fn raw_mut(&mut self) -> &mut aom_image_t {
+        unsafe { self.img.as_mut() }
+    }
+
+    /// Borrow the image
+    pub fn view(&self) -> AomImageView<'_> {
+        AomImageView { img: self.raw() }
+    }
+
+    /// Pixel format
+    pub fn format(&self) -> aom_img_fmt_t {
+        self.view().format()
+    }
+
+    /// Displayed width
+    pub fn width(&self) -> u32 {
+        self.view().width()
+    }
+
+    /// Displayed height
+    pub fn height(&self) -> u32 {
+        self.view().height()
+    }
+
+    /// Bit depth of the samples
+    pub fn bit_depth(&self) -> u32 {
+        self.view().bit_depth()
+    }
+
+    /// The samples are stored in 16 bits
+    pub fn is_high_bitdepth(&self) -> bool {
+        self.view().is_high_bitdepth()
+    }
+
+    /// Distance in bytes between two rows of the plane `idx`
+    pub fn stride(&self, idx: usize) -> usize {
+        self.view().stride(idx)
+    }
+
+    /// Number of rows of the plane `idx`
+    pub fn plane_height(&self, idx: usize) -> usize {
+        self.view().plane_height(idx)
+    }
+
+    /// Number of meaningful bytes in every row of the plane `idx`
+    pub fn plane_row_bytes(&self, idx: usize) -> usize {
+        self.view().plane_row_bytes(idx)
+    }
+
+    /// Content of the plane `idx`, rows are [`stride`] bytes apart
+    ///
+    /// [`stride`]: AomImage::stride
+    pub fn plane(&self, idx: usize) -> &[u8] {
+        self.view().plane(idx)
+    }
+
+    /// Mutable content of the plane `idx`
+    pub fn plane_mut(&mut self, idx: usize) -> &mut [u8] {
+        assert!(idx < 3);
+        let len = self.view().plane_len(idx);
+        unsafe { slice::from_raw_parts_mut(self.raw_mut().planes[idx], len) }
+    }
+
+    /// Check that `other` has the same format and dimensions
+    pub(crate) fn same_geometry(&self, other: &aom_image_t) -> bool {
+        let img = self.raw();
+
+        img.fmt == other.fmt
+            && img.d_w == other.d_w
+            && img.d_h == other.d_h
+            && img.x_chroma_shift == other.x_chroma_shift
+            && img.y_chroma_shift == other.y_chroma_shift
+    }
+}
+
+impl Drop for AomImage {
+    fn drop(&mut self) {
+        unsafe { aom_img_free(self.img.as_ptr()) };
+    }
+}
+
+/// Image borrowed from an [`AomImage`] or from libaom
+///
+/// It gives a read-only access to the planes, e.g. of the frame the
+/// encoder reconstructed, see [`AV1Encoder::with_new_frame_image`].
+///
+/// [`AV1Encoder::with_new_frame_image`]: crate::encoder::AV1Encoder::with_new_frame_image
+#[derive(Clone, Copy)]
+pub struct AomImageView<'a> {
+    img: &'a aom_image_t,
+}
+
+impl<'a> AomImageView<'a> {
+    /// Borrow an image libaom keeps
+    ///
+    /// # Safety
+    ///
+    /// The planes of `img` must be valid for its geometry, as long as the
+    /// view.
+    pub(crate) unsafe fn new(img: &'a aom_image_t) -> Self {
+        AomImageView { img }
+    }
+
+    /// Pixel format
+    pub fn format(&self) -> aom_img_fmt_t {
+        self.img.fmt
+    }
+
+    /// Displayed width
+    pub fn width(&self) -> u32 {
+        self.img.d_w
+    }
+
+    /// Displayed height
+    pub fn height(&self) -> u32 {
+        self.img.d_h
+    }
+
+    /// Bit depth of the samples
+    pub fn bit_depth(&self) -> u32 {
+        self.img.bit_depth
+    }
+
+    /// The samples are stored in 16 bits
+    pub fn is_high_bitdepth(&self) -> bool {
+        is_high_bitdepth(self.img)
+    }
+
+    /// Distance in bytes between two rows of the plane `idx`
+    pub fn stride(&self, idx: usize) -> usize {
+        self.img.stride[idx] as usize
+    }
+
+    /// Number of rows of the plane `idx`
+    pub fn plane_height(&self, idx: usize) -> usize {
+        let img = self.img;
+
+        if idx == 0 {
+            img.d_h as usize
+        } else {
+            ((img.d_h + img.y_chroma_shift) >> img.y_chroma_shift) as usize
+        }
+    }
+
+    /// Number of meaningful bytes in every row of the plane `idx`
+    pub fn plane_row_bytes(&self, idx: usize) -> usize {
+        let img = self.img;
+
+        let w = if idx == 0 {
+            img.d_w
+        } else {
+            (img.d_w + img.x_chroma_shift) >> img.x_chroma_shift
+        } as usize;
+
+        if self.is_high_bitdepth() {
+            w * 2
+        } else {
+            w
+        }
+    }
+
+    fn plane_len(&self, idx: usize) -> usize {
+        match self.plane_height(idx) {
+            0 => 0,
+            h => (h - 1) * self.stride(idx) + self.plane_row_bytes(idx),
+        }
+    }
+
+    /// Content of the plane `idx`, rows are [`stride`] bytes apart
+    ///
+    /// [`stride`]: AomImageView::stride
+    pub fn plane(&self, idx: usize) -> &'a [u8] {
+        assert!(idx < 3);
+        unsafe { slice::from_raw_parts(self.img.planes[idx], self.plane_len(idx)) }
+    }
+
+    /// Copy the display rectangle into a new [`Frame`]
+    ///
+    /// `None` if the format has no [`Frame`] equivalent.
+    pub fn to_frame(&self) -> Option<Frame> {
+        img_to_frame(*self.img, FrameType::OTHER, |v| new_frame(v, None))
+    }
+}
+
+// The format enum is signed on Windows
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn is_high_bitdepth(img: &aom_image_t) -> bool {
+    img.fmt as u32 & AOM_IMG_FMT_HIGHBITDEPTH != 0
+}
+
+/// The same format with every component `depth` bits deep
+///
+/// Only the luma depth of a format is meaningful to this crate, the chroma
+/// of the 10-bit formats of av-data, e.g. `YUV420_10`, is not described as
+/// 10-bit.
+pub fn with_depth(fmt: &Formaton, depth: u8) -> Formaton {
+    let mut f = *fmt;
+
+    for c in f.comp_info.iter_mut().flatten() {
+        *c = Chromaton { depth, ..*c };
+    }
+
+    f
+}
+
+/// Allocate a frame for `info`, as [`Frame::new_default_frame`] does
+///
+/// The samples deeper than 8 bits take 2 bytes, in the native byte order
+/// as libaom stores them, while the av-data frames size such rows as if the
+/// samples were packed.
+pub fn new_frame(info: VideoInfo, t: Option<TimeInfo>) -> Frame {
+    let depth = info.format.get_chromaton(0).map_or(8, |c| c.get_depth());
+
+    if depth <= 8 {
+        return Frame::new_default_frame(info, t);
+    }
+
+    let storage = VideoInfo {
+        format: Arc::new(with_depth(&info.format, 16)),
+        ..info.clone()
+    };
+    let mut f = Frame::new_default_frame(storage, t);
+    f.kind = MediaKind::Video(info);
+
+    f
+}
+
+// The color enums are signed on Windows
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn video_info_from_img(img: &aom_image_t, frame_type: FrameType) -> Option<VideoInfo> {
+    let f = match img.fmt {
+        aom_img_fmt::AOM_IMG_FMT_I420 | aom_img_fmt::AOM_IMG_FMT_I42016 => YUV420,
+        aom_img_fmt::AOM_IMG_FMT_I422 | aom_img_fmt::AOM_IMG_FMT_I42216 => YUV422,
+        aom_img_fmt::AOM_IMG_FMT_I444 | aom_img_fmt::AOM_IMG_FMT_I44416 => YUV444,
+        _ => return None,
+    };
+
+    // The 16-bit buffers of 8-bit content are converted to 8-bit frames
+    let mut f = match (is_high_bitdepth(img), img.bit_depth) {
+        (_, 8) => *f,
+        (true, depth @ (10 | 12)) => with_depth(f, depth as u8),
+        _ => return None,
+    };
+
+    // The Formaton setters work on a copy
+    if let Some(cp) = ColorPrimaries::from_u32(img.cp as u32) {
+        f.primaries = cp;
+    }
+    if let Some(tc) = TransferCharacteristic::from_u32(img.tc as u32) {
+        f.xfer = tc;
+    }
+    if let Some(mc) = MatrixCoefficients::from_u32(img.mc as u32) {
+        f.matrix = mc;
+    }
+
+    Some(VideoInfo::new(
+        img.d_w as usize,
+        img.d_h as usize,
+        false,
+        frame_type,
+        Arc::new(f),
+    ))
+}
+
+/// Copy the display rectangle of `img` into a frame allocated by `new_frame`
+pub(crate) fn img_to_frame(
+    img: aom_image_t,
+    frame_type: FrameType,
+    new_frame: impl FnOnce(VideoInfo) -> Frame,
+) -> Option<Frame> {
+    let v = video_info_from_img(&img, frame_type)?;
+    let bytes = if is_high_bitdepth(&img) { 2 } else { 1 };
+    // The 16-bit samples are kept as is unless the content is 8-bit
+    let narrow = bytes == 2 && img.bit_depth == 8;
+
+    // The planes point to the top left corner of the display rectangle,
+    // only d_w x d_h samples are copied, the chroma size is rounded up.
+    // Every row copied must fit in the stride.
+    let planes = v
+        .format
+        .iter()
+        .flatten()
+        .zip(img.planes.iter().zip(img.stride.iter()))
+        .enumerate()
+        .map(|(i, (c, (&plane, &stride)))| {
+            let (x_shift, y_shift) = if i == 0 {
+                (0, 0)
+            } else {
+                (img.x_chroma_shift, img.y_chroma_shift)
+            };
+            let width = ((img.d_w + x_shift) >> x_shift) as usize;
+            let height = ((img.d_h + y_shift) >> y_shift) as usize;
+
+            if width != c.get_width(v.width) || height != c.get_height(v.height) {
+                return None;
+            }
+
+            if plane.is_null() || stride <= 0 || (stride as usize) < width * bytes || height == 0 {
+                return None;
+            }
+
+            let len = stride as usize * (height - 1) + width * bytes;
+            let src = unsafe { slice::from_raw_parts(plane as *const u8, len) };
+
+            Some((src, stride as usize, width, height))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut f = new_frame(v);
+
+    let row_bytes = |width| if narrow { width } else { width * bytes };
+
+    for (i, (src, stride, width, height)) in planes.into_iter().enumerate() {
+        let linesize = f.buf.linesize(i).ok()?;
+        if linesize < row_bytes(width) {
+            return None;
+        }
+        let dst: &mut [u8] = f.buf.as_mut_slice(i).ok()?;
+
+        for (d, s) in dst
+            .chunks_mut(linesize)
+            .zip(src.chunks(stride))
+            .take(height)
+        {
+            if narrow {
+                for (d, s) in d[..width].iter_mut().zip(s.chunks_exact(2)) {
+                    *d = u16::from_ne_bytes([s[0], s[1]]) as u8;
+                }
+            } else {
+                d[..row_bytes(width)].copy_from_slice(&s[..row_bytes(width)]);
+            }
+        }
+    }
+
+    Some(f)
+}
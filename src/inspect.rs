@@ -0,0 +1,272 @@
+#![deny(missing_docs)]
+
+use std::ffi::CStr;
+use std::mem::MaybeUninit;
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+
+use crate::common::{AomError, AomErrorKind};
+use crate::decoder::AV1Decoder;
+use crate::ffi::*;
+
+/// Bits spent on a single syntax element
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccountingSymbol {
+    /// Horizontal position, in 4x4 blocks
+    pub x: i16,
+    /// Vertical position, in 4x4 blocks
+    pub y: i16,
+    /// Index of the syntax element name in [`Accounting::names`]
+    pub id: usize,
+    /// Bits spent, in 1/8 of bit
+    pub bits: u32,
+    /// Number of symbols coded
+    pub samples: u32,
+}
+
+/// Bit accounting of a decoded frame
+///
+/// It is a copy of the `Accounting` struct filled by libaom.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Accounting {
+    /// Names of the syntax elements
+    pub names: Vec<String>,
+    /// Bits spent per syntax element and position
+    pub symbols: Vec<AccountingSymbol>,
+}
+
+impl Accounting {
+    unsafe fn from_raw(acct: &crate::ffi::Accounting) -> Accounting {
+        let syms = &acct.syms;
+
+        let names = syms.strs[..syms.num_strs.max(0) as usize]
+            .iter()
+            .map(|s| {
+                if s.is_null() {
+                    String::new()
+                } else {
+                    CStr::from_ptr(*s).to_string_lossy().into_owned()
+                }
+            })
+            .collect();
+
+        let symbols = if syms.syms.is_null() {
+            Vec::new()
+        } else {
+            slice::from_raw_parts(syms.syms, syms.num_syms.max(0) as usize)
+                .iter()
+                .map(|s| AccountingSymbol {
+                    x: s.context.x,
+                    y: s.context.y,
+                    id: s.id as usize,
+                    bits: s.bits,
+                    samples: s.samples,
+                })
+                .collect()
+        };
+
+        Accounting { names, symbols }
+    }
+
+    /// Name of the syntax element `sym` accounts for
+    pub fn name(&self, sym: &AccountingSymbol) -> Option<&str> {
+        self.names.get(sym.id).map(|s| s.as_str())
+    }
+
+    /// Total number of bits spent on the frame
+    pub fn total_bits(&self) -> f64 {
+        self.symbols.iter().map(|s| s.bits as u64).sum::<u64>() as f64 / 8.0
+    }
+}
+
+/// Film grain synthesis parameters of a frame
+///
+/// It mirrors `aom_film_grain_t`, the meaning of every field is described
+/// in the section 6.8.20 of the AV1 specification.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilmGrainParams {
+    /// The parameters are new and not loaded from a reference frame
+    pub update_parameters: bool,
+    /// Piecewise-linear luma scaling function, as (value, scaling) points
+    pub scaling_points_y: Vec<(i32, i32)>,
+    /// Cb scaling function points
+    pub scaling_points_cb: Vec<(i32, i32)>,
+    /// Cr scaling function points
+    pub scaling_points_cr: Vec<(i32, i32)>,
+    /// Shift applied to the scaling function
+    pub scaling_shift: i32,
+    /// Lag of the auto-regressive filter
+    pub ar_coeff_lag: i32,
+    /// Luma auto-regressive coefficients
+    pub ar_coeffs_y: Vec<i32>,
+    /// Cb auto-regressive coefficients, the last one applies to the luma
+    pub ar_coeffs_cb: Vec<i32>,
+    /// Cr auto-regressive coefficients, the last one applies to the luma
+    pub ar_coeffs_cr: Vec<i32>,
+    /// Shift applied to the auto-regressive coefficients
+    pub ar_coeff_shift: i32,
+    /// Cb multiplier of the chroma component
+    pub cb_mult: i32,
+    /// Cb multiplier of the average luma
+    pub cb_luma_mult: i32,
+    /// Cb offset
+    pub cb_offset: i32,
+    /// Cr multiplier of the chroma component
+    pub cr_mult: i32,
+    /// Cr multiplier of the average luma
+    pub cr_luma_mult: i32,
+    /// Cr offset
+    pub cr_offset: i32,
+    /// The grain blocks overlap
+    pub overlap_flag: bool,
+    /// Clip the output to the restricted range
+    pub clip_to_restricted_range: bool,
+    /// Bit depth the parameters apply to
+    pub bit_depth: u32,
+    /// The chroma scaling is inferred from the luma one
+    pub chroma_scaling_from_luma: bool,
+    /// Shift applied to the Gaussian random numbers
+    pub grain_scale_shift: i32,
+    /// Seed of the pseudo-random generator
+    pub random_seed: u16,
+}
+
+impl FilmGrainParams {
+    fn from_aom(g: &aom_film_grain_t) -> Option<FilmGrainParams> {
+        if g.apply_grain == 0 {
+            return None;
+        }
+
+        fn points(p: &[[i32; 2]], n: i32) -> Vec<(i32, i32)> {
+            p[..(n.max(0) as usize).min(p.len())]
+                .iter()
+                .map(|v| (v[0], v[1]))
+                .collect()
+        }
+
+        let lag = g.ar_coeff_lag.clamp(0, 3) as usize;
+        let num_pos_luma = 2 * lag * (lag + 1);
+        let num_pos_chroma = num_pos_luma + (g.num_y_points > 0) as usize;
+
+        Some(FilmGrainParams {
+            update_parameters: g.update_parameters != 0,
+            scaling_points_y: points(&g.scaling_points_y, g.num_y_points),
+            scaling_points_cb: points(&g.scaling_points_cb, g.num_cb_points),
+            scaling_points_cr: points(&g.scaling_points_cr, g.num_cr_points),
+            scaling_shift: g.scaling_shift,
+            ar_coeff_lag: g.ar_coeff_lag,
+            ar_coeffs_y: g.ar_coeffs_y[..num_pos_luma].to_vec(),
+            ar_coeffs_cb: g.ar_coeffs_cb[..num_pos_chroma].to_vec(),
+            ar_coeffs_cr: g.ar_coeffs_cr[..num_pos_chroma].to_vec(),
+            ar_coeff_shift: g.ar_coeff_shift,
+            cb_mult: g.cb_mult,
+            cb_luma_mult: g.cb_luma_mult,
+            cb_offset: g.cb_offset,
+            cr_mult: g.cr_mult,
+            cr_luma_mult: g.cr_luma_mult,
+            cr_offset: g.cr_offset,
+            overlap_flag: g.overlap_flag != 0,
+            clip_to_restricted_range: g.clip_to_restricted_range != 0,
+            bit_depth: g.bit_depth,
+            chroma_scaling_from_luma: g.chroma_scaling_from_luma != 0,
+            grain_scale_shift: g.grain_scale_shift,
+            random_seed: g.random_seed,
+        })
+    }
+}
+
+/// Inspection state, boxed so libaom can keep a pointer to it
+pub(crate) struct Inspection {
+    // Refreshed on every decode call, the decoder may have moved
+    pub(crate) ctx: *mut aom_codec_ctx,
+    frames: Vec<Accounting>,
+    film_grain: Option<FilmGrainParams>,
+}
+
+unsafe extern "C" fn inspect_frame(decoder: *mut c_void, priv_: *mut c_void) {
+    let insp = &mut *(priv_ as *mut Inspection);
+
+    let mut grain = MaybeUninit::<aom_film_grain_t>::zeroed();
+    insp.film_grain = match aom_sys_inspect_film_grain(decoder, grain.as_mut_ptr()) {
+        0 => FilmGrainParams::from_aom(grain.assume_init_ref()),
+        _ => None,
+    };
+
+    if insp.ctx.is_null() {
+        return;
+    }
+
+    let mut acct: *mut crate::ffi::Accounting = ptr::null_mut();
+    let ret = aom_codec_control(
+        insp.ctx,
+        aom_dec_control_id::AV1_GET_ACCOUNTING as i32,
+        &mut acct as *mut *mut crate::ffi::Accounting,
+    );
+
+    if ret == aom_codec_err_t::AOM_CODEC_OK {
+        if let Some(acct) = acct.as_ref() {
+            insp.frames.push(Accounting::from_raw(acct));
+        }
+    }
+}
+
+impl<T> AV1Decoder<T> {
+    /// Collect the bit accounting of every decoded frame
+    ///
+    /// It must be called before the first [`decode`] call and requires
+    /// libaom to be built with `CONFIG_ACCOUNTING` and `CONFIG_INSPECTION`.
+    ///
+    /// It matches `AV1_SET_INSPECTION_CALLBACK`.
+    ///
+    /// [`decode`]: AV1Decoder::decode
+    pub fn enable_inspection(&mut self) -> Result<(), AomError> {
+        if self.started {
+            return Err(AomError::new(
+                AomErrorKind::Error,
+                "the inspection must be enabled before decoding",
+            ));
+        }
+
+        let mut insp = Box::new(Inspection {
+            ctx: ptr::null_mut(),
+            frames: Vec::new(),
+            film_grain: None,
+        });
+
+        let mut init = aom_inspect_init {
+            inspect_cb: Some(inspect_frame),
+            inspect_ctx: &mut *insp as *mut Inspection as *mut c_void,
+        };
+
+        unsafe {
+            self.control_ptr(aom_dec_control_id::AV1_SET_INSPECTION_CALLBACK, &mut init)?;
+        }
+
+        self.inspection = Some(insp);
+
+        Ok(())
+    }
+
+    /// Return the accounting of the frames decoded since the last call
+    ///
+    /// The frames are in decoding order, hidden frames included.
+    pub fn take_accounting(&mut self) -> Vec<Accounting> {
+        self.inspection
+            .as_deref_mut()
+            .map(|i| std::mem::take(&mut i.frames))
+            .unwrap_or_default()
+    }
+
+    /// Film grain parameters of the last decoded frame
+    ///
+    /// It returns `None` if the frame has no film grain or if the inspection
+    /// is not enabled, see [`enable_inspection`]. Combined with
+    /// [`set_skip_film_grain`] it lets the caller synthesize the grain.
+    ///
+    /// [`enable_inspection`]: AV1Decoder::enable_inspection
+    /// [`set_skip_film_grain`]: AV1Decoder::set_skip_film_grain
+    pub fn film_grain_params(&self) -> Option<FilmGrainParams> {
+        self.inspection.as_ref()?.film_grain.clone()
+    }
+}
@@ -0,0 +1,262 @@
+//! Minimal IVF reader and writer
+//!
+//! IVF is the container used by `aomenc` and `aomdec`, a 32 bytes file
+//! header is followed by the frames, each prefixed by a 12 bytes header.
+
+#![deny(missing_docs)]
+
+use std::io::{self, Read, Write};
+
+use av_data::packet::Packet;
+use av_data::rational::Rational64;
+
+/// IVF fourcc of AV1 streams
+pub const AV1_FOURCC: [u8; 4] = *b"AV01";
+
+const SIGNATURE: &[u8; 4] = b"DKIF";
+const HEADER_LEN: u16 = 32;
+
+/// Content of the IVF file header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IvfHeader {
+    /// Codec fourcc
+    pub fourcc: [u8; 4],
+    /// Frame width
+    pub width: u16,
+    /// Frame height
+    pub height: u16,
+    /// Timebase of the frame timestamps
+    pub timebase: Rational64,
+    /// Number of frames, writers often leave it to 0
+    pub frames: u32,
+}
+
+/// IVF writer
+pub struct IvfWriter<W: Write> {
+    inner: W,
+    frames: u32,
+}
+
+impl<W: Write> IvfWriter<W> {
+    /// Write the file header
+    ///
+    /// The frame count is left to 0, readers do not rely on it.
+    pub fn new(
+        mut inner: W,
+        fourcc: [u8; 4],
+        width: u16,
+        height: u16,
+        timebase: Rational64,
+    ) -> io::Result<Self> {
+        let mut hdr = [0u8; HEADER_LEN as usize];
+
+        hdr[0..4].copy_from_slice(SIGNATURE);
+        hdr[6..8].copy_from_slice(&HEADER_LEN.to_le_bytes());
+        hdr[8..12].copy_from_slice(&fourcc);
+        hdr[12..14].copy_from_slice(&width.to_le_bytes());
+        hdr[14..16].copy_from_slice(&height.to_le_bytes());
+        hdr[16..20].copy_from_slice(&(*timebase.denom() as u32).to_le_bytes());
+        hdr[20..24].copy_from_slice(&(*timebase.numer() as u32).to_le_bytes());
+
+        inner.write_all(&hdr)?;
+
+        Ok(IvfWriter { inner, frames: 0 })
+    }
+
+    /// Write a frame
+    ///
+    /// Packets without a pts are stored with their index as timestamp.
+    pub fn write_packet(&mut self, pkt: &Packet) -> io::Result<()> {
+        let pts = pkt.t.pts.unwrap_or(self.frames as i64);
+        let size = u32::try_from(pkt.data.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "packet too large"))?;
+
+        self.inner.write_all(&size.to_le_bytes())?;
+        self.inner.write_all(&pts.to_le_bytes())?;
+        self.inner.write_all(&pkt.data)?;
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Return the underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// IVF reader
+///
+/// It yields the frame data alongside its timestamp.
+pub struct IvfReader<R: Read> {
+    inner: R,
+    header: IvfHeader,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<R: Read> IvfReader<R> {
+    /// Read the file header
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut hdr = [0u8; HEADER_LEN as usize];
+
+        inner.read_exact(&mut hdr)?;
+
+        if &hdr[0..4] != SIGNATURE {
+            return Err(invalid("not an IVF file"));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([hdr[i], hdr[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([hdr[i], hdr[i + 1], hdr[i + 2], hdr[i + 3]]);
+
+        let header_len = u16_at(6);
+        if header_len < HEADER_LEN {
+            return Err(invalid("invalid IVF header size"));
+        }
+
+        let (den, num) = (u32_at(16), u32_at(20));
+        if den == 0 || num == 0 {
+            return Err(invalid("invalid IVF timebase"));
+        }
+
+        // Skip the extra header bytes, if any
+        io::copy(
+            &mut (&mut inner).take((header_len - HEADER_LEN) as u64),
+            &mut io::sink(),
+        )?;
+
+        let header = IvfHeader {
+            fourcc: [hdr[8], hdr[9], hdr[10], hdr[11]],
+            width: u16_at(12),
+            height: u16_at(14),
+            timebase: Rational64::new(num as i64, den as i64),
+            frames: u32_at(24),
+        };
+
+        Ok(IvfReader { inner, header })
+    }
+
+    /// File header
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+
+    /// Read the next frame, `None` at the end of the file
+    pub fn read_frame(&mut self) -> io::Result<Option<(Vec<u8>, i64)>> {
+        let mut hdr = [0u8; 12];
+
+        match self.inner.read(&mut hdr[..1])? {
+            0 => return Ok(None),
+            _ => self.inner.read_exact(&mut hdr[1..])?,
+        }
+
+        let size = u32::from_le_bytes([hdr[0], hdr[1], hdr[2], hdr[3]]);
+        let mut pts = [0u8; 8];
+        pts.copy_from_slice(&hdr[4..]);
+
+        let mut data = Vec::new();
+        (&mut self.inner).take(size as u64).read_to_end(&mut data)?;
+        if data.len() != size as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(Some((data, i64::from_le_bytes(pts))))
+    }
+}
+
+impl<R: Read> Iterator for IvfReader<R> {
+    type Item = io::Result<(Vec<u8>, i64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+#[cfg(all(test, feature = "encoder", feature = "decoder"))]
+mod tests {
+    use super::*;
+    use crate::decoder::AV1Decoder;
+    use crate::encoder::tests::setup_frame;
+    use crate::encoder::{AOMPacket, AV1EncoderConfig};
+    use av_data::timeinfo::TimeInfo;
+
+    #[test]
+    fn ivf_roundtrip() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 30)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(48)
+            .timebase(t.timebase.unwrap());
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 48, &t);
+        let mut packets = Vec::new();
+
+        for i in 0..10 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    packets.push(pkt);
+                }
+            }
+        }
+        loop {
+            e.flush().unwrap();
+            let mut done = true;
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    packets.push(pkt);
+                    done = false;
+                }
+            }
+            if done {
+                break;
+            }
+        }
+
+        let mut w = IvfWriter::new(Vec::new(), AV1_FOURCC, 64, 48, Rational64::new(1, 30)).unwrap();
+        for pkt in &packets {
+            w.write_packet(pkt).unwrap();
+        }
+        let written: Vec<_> = packets
+            .into_iter()
+            .map(|pkt| (pkt.data, pkt.t.pts.unwrap()))
+            .collect();
+
+        let file = w.into_inner();
+        assert_eq!(&file[0..4], b"DKIF");
+
+        let r = IvfReader::new(file.as_slice()).unwrap();
+        assert_eq!(
+            *r.header(),
+            IvfHeader {
+                fourcc: AV1_FOURCC,
+                width: 64,
+                height: 48,
+                timebase: Rational64::new(1, 30),
+                frames: 0,
+            }
+        );
+
+        let read: Vec<_> = r.map(|f| f.unwrap()).collect();
+        assert_eq!(read, written);
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut decoded = 0;
+        for (data, _) in &read {
+            d.decode(data, None).unwrap();
+            decoded += d.frames().count();
+        }
+        assert_eq!(decoded, 10);
+
+        assert!(IvfReader::new(&b"RIFF"[..]).is_err());
+        let mut truncated = IvfReader::new(&file[..file.len() - 1]).unwrap();
+        assert!(truncated.any(|f| f.is_err()));
+    }
+}
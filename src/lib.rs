@@ -2,8 +2,49 @@
 
 extern crate aom_sys as ffi;
 
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 pub mod common;
+#[cfg(feature = "decoder")]
 pub mod decoder;
+#[cfg(feature = "encoder")]
 pub mod encoder;
+#[cfg(any(test, feature = "ivf"))]
+pub mod ivf;
+#[cfg(all(feature = "encoder", any(test, feature = "metrics")))]
+pub mod metrics;
+pub mod obu;
+pub mod prelude;
+#[cfg(feature = "encoder")]
+pub mod segment;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(any(test, feature = "y4m"))]
+pub mod y4m;
 
+#[cfg(feature = "decoder")]
+mod decoder_info;
+#[cfg(feature = "encoder")]
 mod encoder_config;
+#[cfg(feature = "encoder")]
+mod encoder_keyframes;
+#[cfg(feature = "encoder")]
+mod encoder_log;
+#[cfg(feature = "encoder")]
+mod encoder_pipeline;
+#[cfg(feature = "encoder")]
+mod encoder_references;
+#[cfg(feature = "encoder")]
+mod encoder_simulcast;
+#[cfg(feature = "encoder")]
+mod encoder_stats;
+#[cfg(feature = "encoder")]
+mod encoder_tools;
+#[cfg(feature = "encoder")]
+mod encoder_twopass;
+#[cfg(feature = "decoder")]
+mod frame_buffer;
+mod image;
+#[cfg(feature = "accounting")]
+mod inspect;
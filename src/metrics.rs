@@ -0,0 +1,481 @@
+//! Objective quality metrics
+//!
+//! Pure-Rust PSNR and SSIM between two 8-bit frames of the same size and
+//! format, e.g. an input frame and the [`AV1Encoder::get_preview_frame`]
+//! reconstruction, so the quality can be measured without asking libaom
+//! for the PSNR packets.
+//!
+//! [`AV1Encoder::measure_frame_quality`] does both on the reconstruction
+//! in place, whatever its bit depth.
+//!
+//! [`AV1Encoder::get_preview_frame`]: crate::encoder::AV1Encoder::get_preview_frame
+//! [`AV1Encoder::measure_frame_quality`]: crate::encoder::AV1Encoder::measure_frame_quality
+
+#![deny(missing_docs)]
+
+use std::borrow::Cow;
+
+use av_data::frame::{Frame, FrameBufferConv, MediaKind, VideoInfo};
+
+use crate::common::{AomError, AomErrorKind, AomImageView};
+use crate::encoder::PSNR;
+
+// Value reported for identical planes, as libaom does
+const MAX_PSNR: f64 = 100.0;
+const PEAK: f64 = 255.0;
+
+// SSIM window size and step
+const WINDOW: usize = 8;
+const STEP: usize = 4;
+
+// A plane: samples, stride, width and height
+type Plane<'a> = (&'a [u8], usize, usize, usize);
+
+fn invalid(detail: &'static str) -> AomError {
+    AomError::new(AomErrorKind::InvalidParam, detail)
+}
+
+fn eight_bit(v: &VideoInfo) -> bool {
+    v.format
+        .get_chromaton(0)
+        .is_some_and(|c| c.get_depth() <= 8)
+}
+
+// The plane `i` of `frame`, `w`x`h` samples
+fn frame_plane(frame: &Frame, i: usize, w: usize, h: usize) -> Result<Plane<'_>, AomError> {
+    let data: &[u8] = frame
+        .buf
+        .as_slice(i)
+        .map_err(|_| invalid("missing frame plane"))?;
+    let stride = frame
+        .buf
+        .linesize(i)
+        .map_err(|_| invalid("missing frame plane"))?;
+
+    if w == 0 || h == 0 || stride < w || data.len() < stride * (h - 1) + w {
+        return Err(invalid("frame plane smaller than the frame size"));
+    }
+
+    Ok((data, stride, w, h))
+}
+
+// The matching planes of both frames
+fn planes<'a>(
+    reference: &'a Frame,
+    distorted: &'a Frame,
+) -> Result<Vec<(Plane<'a>, Plane<'a>)>, AomError> {
+    let (r, d) = match (&reference.kind, &distorted.kind) {
+        (MediaKind::Video(r), MediaKind::Video(d)) => (r, d),
+        _ => return Err(invalid("not a video frame")),
+    };
+
+    if (r.width, r.height) != (d.width, d.height) {
+        return Err(invalid("frame sizes differ"));
+    }
+
+    if r.format.get_num_comp() != d.format.get_num_comp() {
+        return Err(invalid("frame formats differ"));
+    }
+
+    if !eight_bit(r) || !eight_bit(d) {
+        return Err(invalid("only 8-bit frames are supported"));
+    }
+
+    r.format
+        .iter()
+        .flatten()
+        .zip(d.format.iter().flatten())
+        .enumerate()
+        .map(|(i, (rc, dc))| {
+            let (w, h) = (rc.get_width(r.width), rc.get_height(r.height));
+
+            if (w, h) != (dc.get_width(d.width), dc.get_height(d.height)) {
+                return Err(invalid("frame formats differ"));
+            }
+
+            Ok((
+                frame_plane(reference, i, w, h)?,
+                frame_plane(distorted, i, w, h)?,
+            ))
+        })
+        .collect()
+}
+
+fn sse_to_psnr(samples: u64, sse: u64) -> f64 {
+    if sse == 0 {
+        return MAX_PSNR;
+    }
+
+    (10.0 * (samples as f64 * PEAK * PEAK / sse as f64).log10()).min(MAX_PSNR)
+}
+
+fn plane_sse((r, r_stride, w, h): Plane, (d, d_stride, ..): Plane) -> u64 {
+    r.chunks(r_stride)
+        .zip(d.chunks(d_stride))
+        .take(h)
+        .flat_map(|(r, d)| r[..w].iter().zip(&d[..w]))
+        .map(|(&a, &b)| {
+            let e = a as i64 - b as i64;
+            (e * e) as u64
+        })
+        .sum()
+}
+
+/// Compute the PSNR of `distorted` against `reference`
+///
+/// The result is laid out as the libaom PSNR packet: total, Y, U and V,
+/// identical planes are reported at 100 dB.
+pub fn psnr(reference: &Frame, distorted: &Frame) -> Result<PSNR, AomError> {
+    Ok(planes_psnr(&planes(reference, distorted)?))
+}
+
+fn planes_psnr(planes: &[(Plane, Plane)]) -> PSNR {
+    let mut samples = [0u32; 4];
+    let mut sse = [0u64; 4];
+    let mut psnr = [MAX_PSNR; 4];
+
+    for (i, &(r, d)) in planes.iter().enumerate().take(3) {
+        samples[i + 1] = (r.2 * r.3) as u32;
+        sse[i + 1] = plane_sse(r, d);
+        psnr[i + 1] = sse_to_psnr(samples[i + 1] as u64, sse[i + 1]);
+
+        samples[0] += samples[i + 1];
+        sse[0] += sse[i + 1];
+    }
+
+    psnr[0] = sse_to_psnr(samples[0] as u64, sse[0]);
+
+    PSNR { samples, sse, psnr }
+}
+
+// SSIM of a single window
+fn window_ssim(r: Plane, d: Plane, x: usize, y: usize, w: usize, h: usize) -> f64 {
+    let c1 = (0.01 * PEAK) * (0.01 * PEAK);
+    let c2 = (0.03 * PEAK) * (0.03 * PEAK);
+
+    let (mut sr, mut sd, mut srr, mut sdd, mut srd) = (0u64, 0u64, 0u64, 0u64, 0u64);
+
+    for row in y..y + h {
+        let rr = &r.0[row * r.1 + x..][..w];
+        let dr = &d.0[row * d.1 + x..][..w];
+
+        for (&a, &b) in rr.iter().zip(dr) {
+            let (a, b) = (a as u64, b as u64);
+            sr += a;
+            sd += b;
+            srr += a * a;
+            sdd += b * b;
+            srd += a * b;
+        }
+    }
+
+    let n = (w * h) as f64;
+    let (mr, md) = (sr as f64 / n, sd as f64 / n);
+    let vr = srr as f64 / n - mr * mr;
+    let vd = sdd as f64 / n - md * md;
+    let cov = srd as f64 / n - mr * md;
+
+    ((2.0 * mr * md + c1) * (2.0 * cov + c2)) / ((mr * mr + md * md + c1) * (vr + vd + c2))
+}
+
+fn plane_ssim(r: Plane, d: Plane) -> f64 {
+    let (w, h) = (r.2, r.3);
+    let (ww, wh) = (WINDOW.min(w), WINDOW.min(h));
+
+    let mut sum = 0.0;
+    let mut count = 0;
+
+    for y in (0..=h - wh).step_by(STEP) {
+        for x in (0..=w - ww).step_by(STEP) {
+            sum += window_ssim(r, d, x, y, ww, wh);
+            count += 1;
+        }
+    }
+
+    sum / count as f64
+}
+
+/// Compute the SSIM of `distorted` against `reference`
+///
+/// It is computed over 8x8 windows every 4 samples, the result is laid out
+/// as [`psnr`]: total, Y, U and V. The total weights the planes as libaom
+/// does, 0.8 for the luma and 0.1 for each chroma plane.
+pub fn ssim(reference: &Frame, distorted: &Frame) -> Result<[f64; 4], AomError> {
+    Ok(planes_ssim(&planes(reference, distorted)?))
+}
+
+fn planes_ssim(planes: &[(Plane, Plane)]) -> [f64; 4] {
+    let mut ssim = [1.0; 4];
+
+    for (i, &(r, d)) in planes.iter().enumerate().take(3) {
+        ssim[i + 1] = plane_ssim(r, d);
+    }
+
+    ssim[0] = 0.8 * ssim[1] + 0.1 * (ssim[2] + ssim[3]);
+
+    ssim
+}
+
+/// Quality of an encoded frame, see [`AV1Encoder::measure_frame_quality`]
+///
+/// [`AV1Encoder::measure_frame_quality`]: crate::encoder::AV1Encoder::measure_frame_quality
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct QualityReport {
+    /// PSNR, as computed by [`psnr`]
+    pub psnr: PSNR,
+    /// SSIM, as computed by [`ssim`]
+    pub ssim: [f64; 4],
+}
+
+// The plane `i` of `img` in 8 bits, the 16-bit samples are shifted down to
+// the 8-bit input as libaom does for its PSNR packets
+fn image_plane<'a>(img: &AomImageView<'a>, i: usize) -> (Cow<'a, [u8]>, usize) {
+    let data = img.plane(i);
+    let stride = img.stride(i);
+
+    if !img.is_high_bitdepth() {
+        return (Cow::Borrowed(data), stride);
+    }
+
+    let shift = img.bit_depth().saturating_sub(8);
+    let row_bytes = img.plane_row_bytes(i);
+    let samples = data
+        .chunks(stride)
+        .flat_map(|row| row[..row_bytes].chunks_exact(2))
+        .map(|s| (u16::from_ne_bytes([s[0], s[1]]) >> shift) as u8)
+        .collect();
+
+    (Cow::Owned(samples), row_bytes / 2)
+}
+
+// Compare the 8-bit `source` with the reconstruction `recon`
+pub(crate) fn measure(source: &Frame, recon: &AomImageView) -> Result<QualityReport, AomError> {
+    let v = match &source.kind {
+        MediaKind::Video(v) => v,
+        _ => return Err(invalid("not a video frame")),
+    };
+
+    if !eight_bit(v) {
+        return Err(invalid("only 8-bit frames are supported"));
+    }
+
+    if (v.width, v.height) != (recon.width() as usize, recon.height() as usize) {
+        return Err(invalid("frame sizes differ"));
+    }
+
+    let bytes = if recon.is_high_bitdepth() { 2 } else { 1 };
+    let shape = v
+        .format
+        .iter()
+        .flatten()
+        .take(3)
+        .enumerate()
+        .map(|(i, c)| {
+            let (w, h) = (c.get_width(v.width), c.get_height(v.height));
+
+            if (w * bytes, h) != (recon.plane_row_bytes(i), recon.plane_height(i)) {
+                return Err(invalid("frame formats differ"));
+            }
+
+            Ok((i, w, h))
+        })
+        .collect::<Result<Vec<_>, AomError>>()?;
+
+    let recon_planes: Vec<_> = shape.iter().map(|&(i, ..)| image_plane(recon, i)).collect();
+    let planes = shape
+        .iter()
+        .zip(&recon_planes)
+        .map(|(&(i, w, h), (data, stride))| {
+            Ok((frame_plane(source, i, w, h)?, (&data[..], *stride, w, h)))
+        })
+        .collect::<Result<Vec<_>, AomError>>()?;
+
+    Ok(QualityReport {
+        psnr: planes_psnr(&planes),
+        ssim: planes_ssim(&planes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::AomImage;
+    use crate::encoder::tests::setup_frame;
+    use crate::encoder::{AOMPacket, AV1Encoder, AV1EncoderConfig, BitstreamProfile};
+    use crate::ffi::*;
+    use crate::testing::{moving_box, noise};
+    use av_data::pixel::formats::{YUV420, YUV444};
+    use av_data::rational::Rational64;
+    use av_data::timeinfo::TimeInfo;
+    use std::sync::Arc;
+
+    #[test]
+    fn identical() {
+        let t = TimeInfo::default();
+        let f = noise(64, 48, &Arc::new(*YUV420), 0);
+
+        let p = psnr(&f, &f).unwrap();
+        assert_eq!(p.samples, [64 * 48 * 3 / 2, 64 * 48, 32 * 24, 32 * 24]);
+        assert_eq!(p.sse, [0; 4]);
+        assert_eq!(p.psnr, [MAX_PSNR; 4]);
+
+        for s in ssim(&f, &f).unwrap() {
+            assert!((s - 1.0).abs() < 1e-9);
+        }
+
+        let other = setup_frame(32, 48, &t);
+        assert!(psnr(&f, &other).unwrap_err().kind().is_invalid_param());
+        assert!(ssim(&f, &other).unwrap_err().kind().is_invalid_param());
+    }
+
+    #[test]
+    fn psnr_matches_packet() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::low_latency()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .cpu_used(8);
+        let mut e =
+            AV1Encoder::with_flags_raw(&mut cfg, AOM_CODEC_USE_PSNR as aom_codec_flags_t).unwrap();
+
+        let format = Arc::new(*YUV420);
+        let mut checked = 0;
+
+        for i in 0..5 {
+            let mut f = moving_box(64, 64, &format, i as usize);
+            f.t = TimeInfo {
+                pts: Some(i),
+                ..t.clone()
+            };
+            e.encode(&f).unwrap();
+
+            let preview = e.get_preview_frame().unwrap();
+
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::PSNR(expected) = p {
+                    let computed = psnr(&f, &preview).unwrap();
+
+                    assert_eq!(computed.samples, expected.samples);
+                    for (a, b) in computed.psnr.iter().zip(&expected.psnr) {
+                        assert!((a - b).abs() < 0.01, "{} != {}", a, b);
+                    }
+
+                    let s = ssim(&f, &preview).unwrap();
+                    assert!(s[0] > 0.5 && s[0] <= 1.0);
+
+                    checked += 1;
+                }
+            }
+        }
+
+        assert_eq!(checked, 5);
+    }
+
+    #[test]
+    fn measure_high_bitdepth() {
+        let t = TimeInfo::default();
+        let f = noise(64, 48, &Arc::new(*YUV420), 0);
+
+        // The same samples at 10 bits, with the 2 bits libaom drops set
+        let mut img = AomImage::new(aom_img_fmt::AOM_IMG_FMT_I42016, 64, 48).unwrap();
+        img.raw_mut().bit_depth = 10;
+
+        let fill = |img: &mut AomImage, luma_error: u16| {
+            for i in 0..3 {
+                let src_stride = f.buf.linesize(i).unwrap();
+                let src: &[u8] = f.buf.as_slice(i).unwrap();
+                let (stride, row_bytes) = (img.stride(i), img.plane_row_bytes(i));
+                let error = if i == 0 { luma_error } else { 0 };
+
+                for (d, s) in img
+                    .plane_mut(i)
+                    .chunks_mut(stride)
+                    .zip(src.chunks(src_stride))
+                {
+                    for (d, &s) in d[..row_bytes].chunks_exact_mut(2).zip(s) {
+                        let v = ((s as u16 ^ error) << 2) | 3;
+                        d.copy_from_slice(&v.to_ne_bytes());
+                    }
+                }
+            }
+        };
+
+        fill(&mut img, 0);
+        let report = measure(&f, &img.view()).unwrap();
+        assert_eq!(
+            report.psnr.samples,
+            [64 * 48 * 3 / 2, 64 * 48, 32 * 24, 32 * 24]
+        );
+        assert_eq!(report.psnr.sse, [0; 4]);
+        assert!((report.ssim[0] - 1.0).abs() < 1e-9);
+
+        // Every luma sample one off at 8 bits
+        fill(&mut img, 1);
+        let report = measure(&f, &img.view()).unwrap();
+        assert_eq!(report.psnr.sse, [64 * 48, 64 * 48, 0, 0]);
+        assert!((report.psnr.psnr[1] - 20.0 * 255f64.log10()).abs() < 1e-9);
+
+        let other = setup_frame(32, 48, &t);
+        let err = measure(&other, &img.view()).unwrap_err();
+        assert!(err.kind().is_invalid_param());
+    }
+
+    #[test]
+    fn measure_matches_packet() {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+
+        for (format, profile) in [
+            (YUV420, BitstreamProfile::Profile0),
+            (YUV444, BitstreamProfile::Profile1),
+        ] {
+            let format = Arc::new(*format);
+            let subsampled = format.get_chromaton(1).unwrap().get_subsampling() != (0, 0);
+            let mut cfg = AV1EncoderConfig::low_latency()
+                .unwrap()
+                .width(64)
+                .height(64)
+                .timebase(t.timebase.unwrap())
+                .profile(profile)
+                .chroma_subsampling(subsampled, subsampled)
+                .cpu_used(8);
+            let mut e =
+                AV1Encoder::with_flags_raw(&mut cfg, AOM_CODEC_USE_PSNR as aom_codec_flags_t)
+                    .unwrap();
+            let mut checked = 0;
+
+            for i in 0..5 {
+                let mut f = moving_box(64, 64, &format, i);
+                f.t = TimeInfo {
+                    pts: Some(i as i64),
+                    ..t.clone()
+                };
+                e.encode(&f).unwrap();
+
+                let report = e.measure_frame_quality(&f).unwrap();
+
+                while let Some(p) = e.get_packet() {
+                    if let AOMPacket::PSNR(expected) = p {
+                        assert_eq!(report.psnr.samples, expected.samples);
+                        for (a, b) in report.psnr.psnr.iter().zip(&expected.psnr) {
+                            assert!((a - b).abs() < 0.1, "{} != {}", a, b);
+                        }
+                        assert!(report.ssim[0] > 0.5 && report.ssim[0] <= 1.0);
+
+                        checked += 1;
+                    }
+                }
+            }
+
+            assert_eq!(checked, 5);
+        }
+    }
+}
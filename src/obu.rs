@@ -0,0 +1,826 @@
+//! OBU parsing utilities
+//!
+//! The packets produced by the encoder are temporal units made of
+//! several OBUs (Open Bitstream Units), see the section 5.3 of the AV1
+//! specification.
+
+#![deny(missing_docs)]
+
+use std::error;
+use std::fmt;
+
+/// Error returned while parsing a malformed bitstream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The data ends in the middle of a syntax element
+    UnexpectedEnd,
+    /// A `leb128()` value is longer than 8 bytes or larger than 32 bits
+    InvalidLeb128,
+    /// The forbidden bit of an OBU header is set
+    ForbiddenBit,
+    /// The OBU is not of the expected type
+    UnexpectedObuType(ObuType),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ParseError::UnexpectedEnd => "unexpected end of data",
+            ParseError::InvalidLeb128 => "invalid leb128 value",
+            ParseError::ForbiddenBit => "OBU forbidden bit set",
+            ParseError::UnexpectedObuType(t) => return write!(f, "unexpected OBU type {:?}", t),
+        };
+
+        f.write_str(s)
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// Type of an OBU
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObuType {
+    /// `OBU_SEQUENCE_HEADER`
+    SequenceHeader,
+    /// `OBU_TEMPORAL_DELIMITER`
+    TemporalDelimiter,
+    /// `OBU_FRAME_HEADER`
+    FrameHeader,
+    /// `OBU_TILE_GROUP`
+    TileGroup,
+    /// `OBU_METADATA`
+    Metadata,
+    /// `OBU_FRAME`
+    Frame,
+    /// `OBU_REDUNDANT_FRAME_HEADER`
+    RedundantFrameHeader,
+    /// `OBU_TILE_LIST`
+    TileList,
+    /// `OBU_PADDING`
+    Padding,
+    /// Reserved value
+    Reserved(u8),
+}
+
+impl ObuType {
+    fn from_u8(val: u8) -> ObuType {
+        match val {
+            1 => ObuType::SequenceHeader,
+            2 => ObuType::TemporalDelimiter,
+            3 => ObuType::FrameHeader,
+            4 => ObuType::TileGroup,
+            5 => ObuType::Metadata,
+            6 => ObuType::Frame,
+            7 => ObuType::RedundantFrameHeader,
+            8 => ObuType::TileList,
+            15 => ObuType::Padding,
+            v => ObuType::Reserved(v),
+        }
+    }
+}
+
+/// OBU header fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObuHeader {
+    /// Type of the OBU
+    pub obu_type: ObuType,
+    /// The header carries the temporal and spatial ids
+    pub has_extension: bool,
+    /// The OBU carries its own size
+    pub has_size_field: bool,
+    /// Temporal layer, 0 without extension
+    pub temporal_id: u8,
+    /// Spatial layer, 0 without extension
+    pub spatial_id: u8,
+}
+
+/// A single OBU
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Obu<'a> {
+    /// Parsed header
+    pub header: ObuHeader,
+    /// Payload, header and size field excluded
+    pub payload: &'a [u8],
+    /// The whole OBU, header included
+    pub data: &'a [u8],
+}
+
+/// Read a `leb128()` value, return it alongside its length
+pub(crate) fn read_leb128(data: &[u8]) -> Result<(u64, usize), ParseError> {
+    let mut value = 0u64;
+
+    for i in 0..8 {
+        let byte = *data.get(i).ok_or(ParseError::UnexpectedEnd)?;
+
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+
+        if byte & 0x80 == 0 {
+            if value > u32::MAX as u64 {
+                return Err(ParseError::InvalidLeb128);
+            }
+
+            return Ok((value, i + 1));
+        }
+    }
+
+    Err(ParseError::InvalidLeb128)
+}
+
+fn split_sized(data: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
+    let (size, len) = read_leb128(data)?;
+    let data = &data[len..];
+
+    if (data.len() as u64) < size {
+        return Err(ParseError::UnexpectedEnd);
+    }
+
+    Ok(data.split_at(size as usize))
+}
+
+// Without size field the OBU spans the whole `data`
+fn parse_obu(data: &[u8]) -> Result<(Obu<'_>, usize), ParseError> {
+    let b = *data.first().ok_or(ParseError::UnexpectedEnd)?;
+
+    if b & 0x80 != 0 {
+        return Err(ParseError::ForbiddenBit);
+    }
+
+    let has_extension = b & 0x04 != 0;
+    let has_size_field = b & 0x02 != 0;
+
+    let (temporal_id, spatial_id, mut pos) = if has_extension {
+        let e = *data.get(1).ok_or(ParseError::UnexpectedEnd)?;
+        (e >> 5, (e >> 3) & 0x03, 2)
+    } else {
+        (0, 0, 1)
+    };
+
+    let payload = if has_size_field {
+        let (size, len) = read_leb128(&data[pos..])?;
+        pos += len;
+
+        let rest = &data[pos..];
+        if (rest.len() as u64) < size {
+            return Err(ParseError::UnexpectedEnd);
+        }
+
+        &rest[..size as usize]
+    } else {
+        &data[pos..]
+    };
+
+    let end = pos + payload.len();
+
+    Ok((
+        Obu {
+            header: ObuHeader {
+                obu_type: ObuType::from_u8((b >> 3) & 0x0f),
+                has_extension,
+                has_size_field,
+                temporal_id,
+                spatial_id,
+            },
+            payload,
+            data: &data[..end],
+        },
+        end,
+    ))
+}
+
+/// Sequence header fields needed for container signaling
+///
+/// It holds what the `av1C` box of ISOBMFF and the Matroska `CodecPrivate`
+/// describe, the syntax elements are defined in the section 5.5 of the
+/// specification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SequenceHeader {
+    /// `seq_profile`
+    pub profile: u8,
+    /// `still_picture`
+    pub still_picture: bool,
+    /// `reduced_still_picture_header`
+    pub reduced_still_picture_header: bool,
+    /// `seq_level_idx` of the first operating point
+    pub level_idx: u8,
+    /// `seq_tier` of the first operating point
+    pub tier: u8,
+    /// Initial presentation delay of the first operating point, in frames
+    pub initial_presentation_delay: Option<u8>,
+    /// Maximum frame width
+    pub max_frame_width: u32,
+    /// Maximum frame height
+    pub max_frame_height: u32,
+    /// Bit depth of the samples, 8, 10 or 12
+    pub bit_depth: u8,
+    /// `mono_chrome`
+    pub monochrome: bool,
+    /// `subsampling_x`
+    pub subsampling_x: bool,
+    /// `subsampling_y`
+    pub subsampling_y: bool,
+    /// `chroma_sample_position`
+    pub chroma_sample_position: u8,
+    /// `color_primaries`
+    pub color_primaries: u8,
+    /// `transfer_characteristics`
+    pub transfer_characteristics: u8,
+    /// `matrix_coefficients`
+    pub matrix_coefficients: u8,
+    /// `color_range`
+    pub full_range: bool,
+    /// `film_grain_params_present`
+    pub film_grain_params_present: bool,
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn f(&mut self, bits: u32) -> Result<u32, ParseError> {
+        let mut val = 0;
+
+        for _ in 0..bits {
+            let byte = self
+                .data
+                .get(self.pos / 8)
+                .ok_or(ParseError::UnexpectedEnd)?;
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+
+            val = (val << 1) | bit as u32;
+            self.pos += 1;
+        }
+
+        Ok(val)
+    }
+
+    fn flag(&mut self) -> Result<bool, ParseError> {
+        Ok(self.f(1)? != 0)
+    }
+
+    fn uvlc(&mut self) -> Result<u32, ParseError> {
+        let mut leading_zeros = 0;
+
+        while !self.flag()? {
+            leading_zeros += 1;
+        }
+
+        if leading_zeros >= 32 {
+            return Ok(u32::MAX);
+        }
+
+        Ok(self.f(leading_zeros)? + ((1u64 << leading_zeros) - 1) as u32)
+    }
+}
+
+impl SequenceHeader {
+    /// Parse a whole sequence header OBU
+    ///
+    /// As returned by [`AV1Encoder::global_headers`] or yielded by
+    /// [`ObuIter`].
+    ///
+    /// [`AV1Encoder::global_headers`]: crate::encoder::AV1Encoder::global_headers
+    pub fn parse(obu: &[u8]) -> Result<SequenceHeader, ParseError> {
+        let (obu, _) = parse_obu(obu)?;
+
+        match obu.header.obu_type {
+            ObuType::SequenceHeader => Self::parse_payload(obu.payload),
+            t => Err(ParseError::UnexpectedObuType(t)),
+        }
+    }
+
+    /// Parse the payload of a sequence header OBU
+    pub fn parse_payload(payload: &[u8]) -> Result<SequenceHeader, ParseError> {
+        let mut r = BitReader {
+            data: payload,
+            pos: 0,
+        };
+        let mut sh = SequenceHeader {
+            profile: r.f(3)? as u8,
+            still_picture: r.flag()?,
+            reduced_still_picture_header: r.flag()?,
+            ..Default::default()
+        };
+
+        if sh.reduced_still_picture_header {
+            sh.level_idx = r.f(5)? as u8;
+        } else {
+            let mut buffer_delay_length = 0;
+            let decoder_model_info_present = if r.flag()? {
+                // timing_info()
+                r.f(32)?;
+                r.f(32)?;
+                if r.flag()? {
+                    r.uvlc()?;
+                }
+
+                let present = r.flag()?;
+                if present {
+                    // decoder_model_info()
+                    buffer_delay_length = r.f(5)? + 1;
+                    r.f(32)?;
+                    r.f(5)?;
+                    r.f(5)?;
+                }
+                present
+            } else {
+                false
+            };
+
+            let initial_display_delay_present = r.flag()?;
+            let operating_points = r.f(5)? + 1;
+
+            for i in 0..operating_points {
+                r.f(12)?;
+                let level_idx = r.f(5)? as u8;
+                let tier = if level_idx > 7 { r.f(1)? as u8 } else { 0 };
+
+                if decoder_model_info_present && r.flag()? {
+                    // operating_parameters_info()
+                    r.f(buffer_delay_length)?;
+                    r.f(buffer_delay_length)?;
+                    r.f(1)?;
+                }
+
+                let initial_display_delay = if initial_display_delay_present && r.flag()? {
+                    Some(r.f(4)? as u8 + 1)
+                } else {
+                    None
+                };
+
+                if i == 0 {
+                    sh.level_idx = level_idx;
+                    sh.tier = tier;
+                    sh.initial_presentation_delay = initial_display_delay;
+                }
+            }
+        }
+
+        let width_bits = r.f(4)? + 1;
+        let height_bits = r.f(4)? + 1;
+        sh.max_frame_width = r.f(width_bits)? + 1;
+        sh.max_frame_height = r.f(height_bits)? + 1;
+
+        if !sh.reduced_still_picture_header && r.flag()? {
+            // delta_frame_id_length_minus_2, additional_frame_id_length_minus_1
+            r.f(4)?;
+            r.f(3)?;
+        }
+
+        // use_128x128_superblock, enable_filter_intra, enable_intra_edge_filter
+        r.f(3)?;
+
+        if !sh.reduced_still_picture_header {
+            // enable_interintra_compound, enable_masked_compound,
+            // enable_warped_motion, enable_dual_filter
+            r.f(4)?;
+            let enable_order_hint = r.flag()?;
+            if enable_order_hint {
+                // enable_jnt_comp, enable_ref_frame_mvs
+                r.f(2)?;
+            }
+
+            let force_screen_content_tools = if r.flag()? { 2 } else { r.f(1)? };
+            if force_screen_content_tools > 0 && !r.flag()? {
+                // seq_force_integer_mv
+                r.f(1)?;
+            }
+
+            if enable_order_hint {
+                r.f(3)?;
+            }
+        }
+
+        // enable_superres, enable_cdef, enable_restoration
+        r.f(3)?;
+
+        sh.parse_color_config(&mut r)?;
+
+        sh.film_grain_params_present = r.flag()?;
+
+        Ok(sh)
+    }
+
+    fn parse_color_config(&mut self, r: &mut BitReader) -> Result<(), ParseError> {
+        let high_bitdepth = r.flag()?;
+
+        self.bit_depth = match (self.profile, high_bitdepth) {
+            (2, true) if r.flag()? => 12,
+            (_, true) => 10,
+            (_, false) => 8,
+        };
+
+        self.monochrome = self.profile != 1 && r.flag()?;
+
+        if r.flag()? {
+            self.color_primaries = r.f(8)? as u8;
+            self.transfer_characteristics = r.f(8)? as u8;
+            self.matrix_coefficients = r.f(8)? as u8;
+        } else {
+            // Unspecified
+            self.color_primaries = 2;
+            self.transfer_characteristics = 2;
+            self.matrix_coefficients = 2;
+        }
+
+        if self.monochrome {
+            self.full_range = r.flag()?;
+            self.subsampling_x = true;
+            self.subsampling_y = true;
+            return Ok(());
+        }
+
+        // sRGB
+        if self.color_primaries == 1
+            && self.transfer_characteristics == 13
+            && self.matrix_coefficients == 0
+        {
+            self.full_range = true;
+        } else {
+            self.full_range = r.flag()?;
+
+            match self.profile {
+                0 => {
+                    self.subsampling_x = true;
+                    self.subsampling_y = true;
+                }
+                1 => {}
+                _ if self.bit_depth == 12 => {
+                    self.subsampling_x = r.flag()?;
+                    self.subsampling_y = self.subsampling_x && r.flag()?;
+                }
+                _ => self.subsampling_x = true,
+            }
+
+            if self.subsampling_x && self.subsampling_y {
+                self.chroma_sample_position = r.f(2)? as u8;
+            }
+        }
+
+        // separate_uv_delta_q
+        r.f(1)?;
+
+        Ok(())
+    }
+}
+
+/// Iterator over the OBUs of a buffer
+///
+/// It supports both the low-overhead bitstream format, every OBU carrying
+/// its size, and the length-delimited format described in the Annex B of
+/// the specification. It stops after the first error.
+#[derive(Clone, Debug)]
+pub struct ObuIter<'a> {
+    data: &'a [u8],
+    annexb: bool,
+    temporal_unit: &'a [u8],
+    frame_unit: &'a [u8],
+}
+
+impl<'a> ObuIter<'a> {
+    /// Iterate over low-overhead bitstream format data
+    ///
+    /// It is the default output format of the encoder.
+    pub fn new(data: &'a [u8]) -> Self {
+        ObuIter {
+            data,
+            annexb: false,
+            temporal_unit: &[],
+            frame_unit: &[],
+        }
+    }
+
+    /// Iterate over Annex B temporal units
+    ///
+    /// It is the output format of the encoder if `save_as_annexb` is set.
+    pub fn annexb(data: &'a [u8]) -> Self {
+        ObuIter {
+            annexb: true,
+            ..Self::new(data)
+        }
+    }
+
+    fn next_annexb(&mut self) -> Result<Option<Obu<'a>>, ParseError> {
+        loop {
+            if !self.frame_unit.is_empty() {
+                let (obu, rest) = split_sized(self.frame_unit)?;
+                self.frame_unit = rest;
+
+                return parse_obu(obu).map(|(obu, _)| Some(obu));
+            }
+
+            if !self.temporal_unit.is_empty() {
+                let (frame_unit, rest) = split_sized(self.temporal_unit)?;
+                self.frame_unit = frame_unit;
+                self.temporal_unit = rest;
+                continue;
+            }
+
+            if !self.data.is_empty() {
+                let (temporal_unit, rest) = split_sized(self.data)?;
+                self.temporal_unit = temporal_unit;
+                self.data = rest;
+                continue;
+            }
+
+            return Ok(None);
+        }
+    }
+
+    fn next_obu(&mut self) -> Result<Option<Obu<'a>>, ParseError> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+
+        let (obu, len) = parse_obu(self.data)?;
+        self.data = &self.data[len..];
+
+        Ok(Some(obu))
+    }
+}
+
+impl<'a> Iterator for ObuIter<'a> {
+    type Item = Result<Obu<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ret = if self.annexb {
+            self.next_annexb()
+        } else {
+            self.next_obu()
+        };
+
+        match ret {
+            Ok(obu) => obu.map(Ok),
+            Err(err) => {
+                self.data = &[];
+                self.temporal_unit = &[];
+                self.frame_unit = &[];
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Iterator over the temporal units of a buffer
+///
+/// Created by [`split_temporal_units`] and [`split_temporal_units_annexb`].
+#[derive(Clone, Debug)]
+pub struct TemporalUnits<'a> {
+    data: &'a [u8],
+    annexb: bool,
+}
+
+/// Split low-overhead bitstream format data in temporal units
+///
+/// Every temporal unit starts with a temporal delimiter OBU, the data before
+/// the first one, if any, is returned as a temporal unit of its own. Each
+/// chunk can then be passed to [`AV1Decoder::decode`] with its own
+/// timestamps.
+///
+/// Malformed data is not split further, the remainder is returned as a
+/// single chunk so the decoder reports the error.
+///
+/// [`AV1Decoder::decode`]: crate::decoder::AV1Decoder::decode
+pub fn split_temporal_units(data: &[u8]) -> TemporalUnits<'_> {
+    TemporalUnits {
+        data,
+        annexb: false,
+    }
+}
+
+/// Split Annex B data in temporal units
+///
+/// Every chunk keeps its `temporal_unit_size` prefix.
+pub fn split_temporal_units_annexb(data: &[u8]) -> TemporalUnits<'_> {
+    TemporalUnits { data, annexb: true }
+}
+
+impl TemporalUnits<'_> {
+    fn next_len(&self) -> usize {
+        if self.annexb {
+            return match read_leb128(self.data) {
+                Ok((size, len)) => (len + size as usize).min(self.data.len()),
+                Err(_) => self.data.len(),
+            };
+        }
+
+        let mut pos = 0;
+
+        while pos < self.data.len() {
+            match parse_obu(&self.data[pos..]) {
+                Ok((obu, _)) if pos > 0 && obu.header.obu_type == ObuType::TemporalDelimiter => {
+                    break
+                }
+                Ok((_, len)) => pos += len,
+                Err(_) => return self.data.len(),
+            }
+        }
+
+        pos
+    }
+}
+
+impl<'a> Iterator for TemporalUnits<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let (tu, rest) = self.data.split_at(self.next_len());
+        self.data = rest;
+
+        Some(tu)
+    }
+}
+
+#[cfg(all(test, feature = "encoder"))]
+mod tests {
+    use super::*;
+    use crate::encoder::tests::setup_frame;
+    use crate::encoder::{AOMPacket, AV1EncoderConfig, BitDepth};
+    use av_data::rational::Rational64;
+    use av_data::timeinfo::TimeInfo;
+
+    fn encode(annexb: bool) -> Vec<Vec<u8>> {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(t.timebase.unwrap())
+            .lag_in_frames(0)
+            .save_as_annexb(annexb);
+        let mut e = cfg.get_encoder().unwrap();
+        let mut f = setup_frame(64, 64, &t);
+        let mut packets = Vec::new();
+
+        for i in 0..3 {
+            f.t.pts = Some(i);
+            e.encode(&f).unwrap();
+            while let Some(p) = e.get_packet() {
+                if let AOMPacket::Packet(pkt) = p {
+                    packets.push(pkt.data);
+                }
+            }
+        }
+
+        packets
+    }
+
+    fn types(iter: ObuIter) -> Vec<ObuType> {
+        iter.map(|obu| obu.unwrap().header.obu_type).collect()
+    }
+
+    #[test]
+    fn parse_low_overhead() {
+        let packets = encode(false);
+
+        let key = types(ObuIter::new(&packets[0]));
+        assert_eq!(key[0], ObuType::TemporalDelimiter);
+        assert_eq!(key[1], ObuType::SequenceHeader);
+        assert!(key.contains(&ObuType::Frame));
+
+        let inter = types(ObuIter::new(&packets[1]));
+        assert_eq!(inter[0], ObuType::TemporalDelimiter);
+        assert!(!inter.contains(&ObuType::SequenceHeader));
+
+        let total: usize = ObuIter::new(&packets[0])
+            .map(|obu| obu.unwrap().data.len())
+            .sum();
+        assert_eq!(total, packets[0].len());
+    }
+
+    #[test]
+    fn parse_annexb() {
+        let packets = encode(true);
+
+        let key = types(ObuIter::annexb(&packets[0]));
+        assert_eq!(key[0], ObuType::TemporalDelimiter);
+        assert_eq!(key[1], ObuType::SequenceHeader);
+        assert!(key.contains(&ObuType::Frame));
+    }
+
+    #[test]
+    fn parse_sequence_header() {
+        use crate::encoder::BitstreamProfile;
+
+        let cases = [
+            (BitstreamProfile::Profile0, 8, false, (true, true)),
+            (BitstreamProfile::Profile0, 10, false, (true, true)),
+            (BitstreamProfile::Profile0, 8, true, (true, true)),
+            (BitstreamProfile::Profile1, 8, false, (false, false)),
+            (BitstreamProfile::Profile1, 10, false, (false, false)),
+            (BitstreamProfile::Profile2, 10, false, (true, false)),
+        ];
+
+        for (profile, depth, monochrome, subsampling) in cases {
+            let mut cfg = AV1EncoderConfig::new()
+                .unwrap()
+                .width(320)
+                .height(240)
+                .timebase(Rational64::new(1, 1000))
+                .profile(profile)
+                .bit_depth(if depth == 8 {
+                    BitDepth::Eight
+                } else {
+                    BitDepth::Ten
+                })
+                .input_bit_depth(depth)
+                .monochrome(monochrome);
+            let mut e = cfg.get_encoder().unwrap();
+
+            let sh = SequenceHeader::parse(&e.global_headers().unwrap()).unwrap();
+
+            assert_eq!(sh.profile, profile as u8);
+            assert_eq!(sh.bit_depth as u32, depth);
+            assert_eq!(sh.monochrome, monochrome);
+            assert_eq!((sh.subsampling_x, sh.subsampling_y), subsampling);
+            assert_eq!((sh.max_frame_width, sh.max_frame_height), (320, 240));
+            assert!(!sh.still_picture);
+            assert!(sh.level_idx <= 31);
+        }
+
+        // The iterator yields the same header
+        let packets = encode(false);
+        let obu = ObuIter::new(&packets[0])
+            .map(|obu| obu.unwrap())
+            .find(|obu| obu.header.obu_type == ObuType::SequenceHeader)
+            .unwrap();
+        let sh = SequenceHeader::parse(obu.data).unwrap();
+        assert_eq!((sh.max_frame_width, sh.max_frame_height), (64, 64));
+        assert_eq!(SequenceHeader::parse_payload(obu.payload), Ok(sh));
+
+        assert_eq!(
+            SequenceHeader::parse(&[0x12, 0x00]),
+            Err(ParseError::UnexpectedObuType(ObuType::TemporalDelimiter))
+        );
+    }
+
+    #[test]
+    fn split_low_overhead() {
+        let packets = encode(false);
+        let data = packets.concat();
+
+        let tus: Vec<_> = split_temporal_units(&data).collect();
+        assert_eq!(tus, packets);
+
+        // The first temporal unit lacks its temporal delimiter
+        let (td, _) = parse_obu(&data).unwrap();
+        let tus: Vec<_> = split_temporal_units(&data[td.data.len()..]).collect();
+        assert_eq!(tus.len(), 3);
+        assert_eq!(tus[0], &packets[0][td.data.len()..]);
+        assert_eq!(tus[1..], packets[1..]);
+
+        // Trailing garbage is kept with the last temporal unit
+        let mut data = data;
+        data.push(0x80);
+        let tus: Vec<_> = split_temporal_units(&data).collect();
+        assert_eq!(tus.len(), 3);
+        assert_eq!(tus[2].len(), packets[2].len() + 1);
+    }
+
+    #[test]
+    fn split_annexb() {
+        let packets = encode(true);
+        let data = packets.concat();
+
+        let tus: Vec<_> = split_temporal_units_annexb(&data).collect();
+        assert_eq!(tus, packets);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        // Sequence header with a 9 bytes size
+        let data = [0x0a, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+        let mut iter = ObuIter::new(&data);
+        assert_eq!(iter.next(), Some(Err(ParseError::InvalidLeb128)));
+        assert_eq!(iter.next(), None);
+
+        // Size larger than the data
+        let data = [0x0a, 0x10, 0x00];
+        assert_eq!(
+            ObuIter::new(&data).next(),
+            Some(Err(ParseError::UnexpectedEnd))
+        );
+
+        let data = [0x8a, 0x00];
+        assert_eq!(
+            ObuIter::new(&data).next(),
+            Some(Err(ParseError::ForbiddenBit))
+        );
+
+        // Truncated temporal unit size
+        let data = [0x80];
+        assert_eq!(
+            ObuIter::annexb(&data).next(),
+            Some(Err(ParseError::UnexpectedEnd))
+        );
+    }
+}
@@ -0,0 +1,21 @@
+//! Commonly used types
+//!
+//! ```no_run
+//! use libaom::prelude::*;
+//! ```
+//!
+//! It only holds safe types, the raw libaom ones are reached through the
+//! `_raw` methods and `aom-sys`.
+
+pub use crate::common::{AomError, AomErrorKind, FrameFlags, FrameKind, Iface};
+
+#[cfg(feature = "decoder")]
+pub use crate::decoder::{AV1Decoder, AV1DecoderBuilder, DecodedFrameInfo, StreamProperties};
+
+#[cfg(feature = "encoder")]
+pub use crate::encoder::{
+    AOMPacket, AV1Encoder, AV1EncoderConfig, AomUsage, BitDepth, BitstreamProfile, CodingTool,
+    ComplexityBudget, EncodeFlags, EncodePass, EncodedFrameInfo, EncoderCfgOptions, FrameLogEntry,
+    KeyframeFiltering, KeyframeMode, KeyframePolicy, RateControl, RealtimeOptions, Reference,
+    ReferenceControl, SuperresMode, TileCodingMode, Timebase, TrellisQuant, TwoPassStats, PSNR,
+};
@@ -0,0 +1,176 @@
+//! Segment encoding
+//!
+//! Distributed encoders split a clip at keyframes, encode the segments
+//! independently and concatenate the bitstreams. The result decodes as a
+//! single stream only if every segment starts with a keyframe refreshing
+//! all the reference frames and repeats the same sequence header.
+
+#![deny(missing_docs)]
+
+use crate::common::{AomError, AomErrorKind};
+use crate::encoder::{AV1Encoder, AV1EncoderConfig, ControlValue};
+use crate::ffi::*;
+use crate::obu::{ObuIter, ObuType, SequenceHeader};
+
+/// Configuration shared by the encoders of every segment
+///
+/// The sequence header depends on the content unless pinned: the level is
+/// forced with `AV1E_SET_TARGET_SEQ_LEVEL_IDX` and the maximum frame size
+/// with `g_forced_max_frame_width/height`, defaulting to the frame size.
+///
+/// The first frame of a new encoder is a keyframe refreshing every
+/// reference frame, so each segment can be decoded on its own or after the
+/// previous ones.
+#[derive(Clone)]
+pub struct SegmentConfig {
+    cfg: AV1EncoderConfig,
+}
+
+impl SegmentConfig {
+    /// Pin the sequence header of `cfg`
+    ///
+    /// `level` is the `seq_level_idx` of the first operating point, 0 to 23
+    /// for the levels 2.0 to 7.3 or 31 for the unconstrained one.
+    pub fn new(cfg: &AV1EncoderConfig, level: u8) -> Result<SegmentConfig, AomError> {
+        if level > 23 && level != 31 {
+            return Err(AomError::new(
+                AomErrorKind::InvalidParam,
+                "invalid seq_level_idx",
+            ));
+        }
+
+        let mut cfg = cfg.clone().push_control_raw(
+            aome_enc_control_id::AV1E_SET_TARGET_SEQ_LEVEL_IDX,
+            ControlValue::Int(level as i32),
+        );
+
+        if cfg.g_forced_max_frame_width == 0 {
+            cfg.g_forced_max_frame_width = cfg.g_w;
+        }
+        if cfg.g_forced_max_frame_height == 0 {
+            cfg.g_forced_max_frame_height = cfg.g_h;
+        }
+
+        Ok(SegmentConfig { cfg })
+    }
+
+    /// The configuration the segment encoders are created with
+    pub fn config(&self) -> &AV1EncoderConfig {
+        &self.cfg
+    }
+
+    /// Create the encoder of a new segment
+    pub fn encoder(&self) -> Result<AV1Encoder, AomError> {
+        self.cfg.clone().get_encoder()
+    }
+}
+
+// The first sequence header OBU of a segment
+fn sequence_header(segment: &[u8]) -> Result<&[u8], AomError> {
+    for obu in ObuIter::new(segment) {
+        let obu =
+            obu.map_err(|_| AomError::new(AomErrorKind::InvalidParam, "malformed segment"))?;
+
+        if obu.header.obu_type == ObuType::SequenceHeader {
+            return Ok(obu.payload);
+        }
+    }
+
+    Err(AomError::new(
+        AomErrorKind::InvalidParam,
+        "missing sequence header",
+    ))
+}
+
+/// Check that two segments can be concatenated
+///
+/// The segments are in the low-overhead bitstream format, only their first
+/// temporal unit is needed. The sequence headers must be identical, the
+/// shared one is returned.
+pub fn verify_sequence_headers(a: &[u8], b: &[u8]) -> Result<SequenceHeader, AomError> {
+    let (a, b) = (sequence_header(a)?, sequence_header(b)?);
+
+    if a != b {
+        return Err(AomError::new(
+            AomErrorKind::InvalidParam,
+            "sequence headers differ",
+        ));
+    }
+
+    SequenceHeader::parse_payload(a)
+        .map_err(|_| AomError::new(AomErrorKind::InvalidParam, "malformed sequence header"))
+}
+
+#[cfg(all(test, feature = "decoder"))]
+mod tests {
+    use super::*;
+    use crate::decoder::AV1Decoder;
+    use crate::encoder::AOMPacket;
+    use crate::testing::moving_box;
+    use av_data::pixel::formats::YUV420;
+    use av_data::rational::Rational64;
+    use av_data::timeinfo::TimeInfo;
+    use std::sync::Arc;
+
+    fn encode_segment(seg: &SegmentConfig, frames: std::ops::Range<i64>) -> Vec<Vec<u8>> {
+        let t = TimeInfo {
+            timebase: Some(Rational64::new(1, 1000)),
+            ..Default::default()
+        };
+        let mut e = seg.encoder().unwrap();
+        let format = Arc::new(*YUV420);
+
+        for i in frames {
+            let mut f = moving_box(64, 64, &format, i as usize);
+            f.t = TimeInfo {
+                pts: Some(i),
+                ..t.clone()
+            };
+            e.encode(&f).unwrap();
+        }
+
+        e.close()
+            .unwrap()
+            .into_iter()
+            .filter_map(|p| match p {
+                AOMPacket::Packet(p) => Some(p.data),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stitch_segments() {
+        let cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(64)
+            .height(64)
+            .timebase(Rational64::new(1, 1000))
+            .cpu_used(6);
+        let seg = SegmentConfig::new(&cfg, 8).unwrap();
+        assert_eq!(seg.config().g_forced_max_frame_width, 64);
+
+        let first = encode_segment(&seg, 0..6);
+        let second = encode_segment(&seg, 6..12);
+
+        let sh = verify_sequence_headers(&first[0], &second[0]).unwrap();
+        assert_eq!(sh.level_idx, 8);
+        assert_eq!((sh.max_frame_width, sh.max_frame_height), (64, 64));
+
+        let mut d = AV1Decoder::<()>::new().unwrap();
+        let mut frames = 0;
+        for packet in first.iter().chain(&second) {
+            d.decode(packet, None).unwrap();
+            frames += d.frames().count();
+        }
+        d.flush().unwrap();
+        frames += d.frames().count();
+        assert_eq!(frames, 12);
+
+        // A different level is not stitchable
+        let other = encode_segment(&SegmentConfig::new(&cfg, 9).unwrap(), 0..1);
+        assert!(verify_sequence_headers(&first[0], &other[0]).is_err());
+
+        assert!(SegmentConfig::new(&cfg, 24).is_err());
+    }
+}
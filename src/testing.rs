@@ -0,0 +1,236 @@
+//! Test pattern generators
+//!
+//! Frames with some content for tests and benchmarks, so the rate control
+//! and the motion search have something to work on. The sample values are
+//! given in 8 bits, as YUV, and scaled up in the deeper formats.
+
+#![deny(missing_docs)]
+
+use std::sync::Arc;
+
+use av_data::frame::{Frame, FrameBufferConv, FrameType, VideoInfo};
+use av_data::pixel::Formaton;
+use av_data::timeinfo::TimeInfo;
+
+use crate::common::new_frame;
+
+// Fill every plane of a new frame, `sample` gets the plane index and the
+// coordinates of the sample in luma units
+fn pattern(
+    width: usize,
+    height: usize,
+    format: &Arc<Formaton>,
+    mut sample: impl FnMut(usize, usize, usize) -> u8,
+) -> Frame {
+    let info = VideoInfo::new(width, height, false, FrameType::OTHER, Arc::clone(format));
+    let mut f = new_frame(info, None);
+    // Only the luma depth is meaningful, see `with_depth`
+    let depth = format.get_chromaton(0).map_or(8, |c| c.get_depth());
+
+    for (i, c) in format.iter().flatten().enumerate() {
+        let (h_ss, v_ss) = c.get_subsampling();
+        let (w, h) = (c.get_width(width), c.get_height(height));
+        let stride = f.buf.linesize(i).unwrap();
+        let data: &mut [u8] = f.buf.as_mut_slice(i).unwrap();
+
+        for (y, row) in data.chunks_mut(stride).take(h).enumerate() {
+            if depth > 8 {
+                for (x, s) in row[..w * 2].chunks_exact_mut(2).enumerate() {
+                    let v = u16::from(sample(i, x << h_ss, y << v_ss)) << (depth - 8);
+                    s.copy_from_slice(&v.to_ne_bytes());
+                }
+            } else {
+                for (x, v) in row[..w].iter_mut().enumerate() {
+                    *v = sample(i, x << h_ss, y << v_ss);
+                }
+            }
+        }
+    }
+
+    f
+}
+
+/// A frame of a single `color`
+pub fn solid(width: usize, height: usize, format: &Arc<Formaton>, color: [u8; 3]) -> Frame {
+    pattern(width, height, format, |i, _, _| color[i.min(2)])
+}
+
+// Luma increasing from left to right, blue difference from top to bottom
+fn gradient_sample(i: usize, x: usize, y: usize, width: usize, height: usize) -> u8 {
+    let ramp = |v: usize, len: usize| (v * 255 / len.saturating_sub(1).max(1)).min(255) as u8;
+
+    match i {
+        0 => ramp(x, width),
+        1 => ramp(y, height),
+        _ => 128,
+    }
+}
+
+/// A frame with the luma increasing from left to right and the blue
+/// difference from top to bottom
+pub fn gradient(width: usize, height: usize, format: &Arc<Formaton>) -> Frame {
+    pattern(width, height, format, |i, x, y| {
+        gradient_sample(i, x, y, width, height)
+    })
+}
+
+/// Uniformly distributed bytes, the same for the same `seed`
+pub fn random_bytes(seed: u64) -> impl Iterator<Item = u8> {
+    // xorshift64*, the state must not be zero
+    let mut state = seed ^ 0x9e37_79b9_7f4a_7c15;
+
+    std::iter::repeat_with(move || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+
+        (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    })
+}
+
+/// A frame of uniform noise, the same for the same `seed`
+pub fn noise(width: usize, height: usize, format: &Arc<Formaton>, seed: u64) -> Frame {
+    let mut bytes = random_bytes(seed);
+
+    pattern(width, height, format, |_, _, _| bytes.next().unwrap())
+}
+
+/// A frame of luma noise spanning `amplitude` levels around mid-gray, over
+/// a flat chroma, the same for the same `seed`
+pub fn luma_noise(
+    width: usize,
+    height: usize,
+    format: &Arc<Formaton>,
+    seed: u64,
+    amplitude: u8,
+) -> Frame {
+    let mut bytes = random_bytes(seed);
+    let low = 128 - amplitude / 2;
+
+    pattern(width, height, format, |i, _, _| match i {
+        0 => low + ((bytes.next().unwrap() as u16 * amplitude as u16) >> 8) as u8,
+        _ => 128,
+    })
+}
+
+/// The frame `index` of a sequence where a red box moves over a
+/// [`gradient`]
+///
+/// The box is a quarter of the frame size, it moves 2 samples right and 1
+/// down every frame and wraps around the edges.
+pub fn moving_box(width: usize, height: usize, format: &Arc<Formaton>, index: usize) -> Frame {
+    let size = (width.min(height) / 4).max(1);
+    let (bx, by) = ((index * 2) % width, index % height);
+
+    pattern(width, height, format, |i, x, y| {
+        let inside = (x + width - bx) % width < size && (y + height - by) % height < size;
+
+        match (i, inside) {
+            (0, true) => 81,
+            (1, true) => 90,
+            (_, true) => 240,
+            _ => gradient_sample(i, x, y, width, height),
+        }
+    })
+}
+
+/// Iterator over the frames of [`moving_box`]
+///
+/// The frames are timestamped with their index and the timebase of `t`.
+pub struct MovingBox {
+    width: usize,
+    height: usize,
+    format: Arc<Formaton>,
+    t: TimeInfo,
+    index: usize,
+}
+
+impl MovingBox {
+    /// Start the sequence
+    pub fn new(width: usize, height: usize, format: &Arc<Formaton>, t: &TimeInfo) -> Self {
+        MovingBox {
+            width,
+            height,
+            format: Arc::clone(format),
+            t: t.clone(),
+            index: 0,
+        }
+    }
+}
+
+impl Iterator for MovingBox {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        let mut f = moving_box(self.width, self.height, &self.format, self.index);
+
+        f.t = TimeInfo {
+            pts: Some(self.index as i64),
+            ..self.t.clone()
+        };
+        self.index += 1;
+
+        Some(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use av_data::pixel::formats::{YUV420, YUV420_10};
+
+    fn sample(f: &Frame, plane: usize, x: usize, y: usize) -> u8 {
+        let stride = f.buf.linesize(plane).unwrap();
+        let data: &[u8] = f.buf.as_slice(plane).unwrap();
+
+        data[y * stride + x]
+    }
+
+    fn luma(f: &Frame) -> &[u8] {
+        f.buf.as_slice(0).unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        let format = Arc::new(*YUV420);
+
+        let f = solid(64, 48, &format, [16, 128, 240]);
+        assert_eq!(sample(&f, 0, 63, 47), 16);
+        assert_eq!(sample(&f, 2, 31, 23), 240);
+
+        let f = gradient(64, 48, &format);
+        assert_eq!((sample(&f, 0, 0, 0), sample(&f, 0, 63, 0)), (0, 255));
+
+        let a = noise(64, 48, &format, 1);
+        assert_eq!(luma(&a), luma(&noise(64, 48, &format, 1)));
+        assert_ne!(luma(&a), luma(&noise(64, 48, &format, 2)));
+
+        let f = luma_noise(64, 48, &format, 1, 16);
+        assert!(luma(&f)[..64].iter().all(|v| (120..136).contains(v)));
+        assert_ne!(
+            luma(&f)[..64],
+            luma(&luma_noise(64, 48, &format, 2, 16))[..64]
+        );
+        assert_eq!(sample(&f, 1, 31, 23), 128);
+
+        // The box is 12x12, at 0,0 then at 2,1
+        let t = TimeInfo::default();
+        let frames: Vec<_> = MovingBox::new(64, 48, &format, &t).take(2).collect();
+        assert_eq!(frames[1].t.pts, Some(1));
+        assert_eq!(sample(&frames[0], 0, 0, 0), 81);
+        assert_eq!(
+            sample(&frames[0], 0, 12, 0),
+            gradient_sample(0, 12, 0, 64, 48)
+        );
+        assert_ne!(sample(&frames[1], 0, 0, 0), 81);
+        assert_eq!(sample(&frames[1], 0, 13, 12), 81);
+        assert_eq!(sample(&frames[1], 2, 1, 1), 240);
+
+        // 2 bytes per sample, scaled up to 10 bits
+        let f = solid(64, 48, &Arc::new(*YUV420_10), [16, 128, 240]);
+        let data: &[u8] = f.buf.as_slice(2).unwrap();
+        let stride = f.buf.linesize(2).unwrap();
+        let last = 23 * stride + 31 * 2;
+        assert_eq!(u16::from_ne_bytes([data[last], data[last + 1]]), 960);
+    }
+}
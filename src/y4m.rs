@@ -0,0 +1,292 @@
+//! Minimal YUV4MPEG2 reader
+//!
+//! It produces frames ready to be fed to the encoder out of the `.y4m`
+//! clips `aomenc` accepts.
+
+#![deny(missing_docs)]
+
+use std::io::{self, BufRead};
+use std::sync::Arc;
+
+use av_data::frame::{Frame, FrameBufferConv, FrameType, VideoInfo};
+use av_data::pixel::formats::{YUV420, YUV422, YUV444};
+use av_data::pixel::Formaton;
+use av_data::rational::Rational64;
+use av_data::timeinfo::TimeInfo;
+
+use crate::common::{new_frame, with_depth};
+
+const SIGNATURE: &str = "YUV4MPEG2 ";
+// The AV1 frame size fields take 16 bits
+const MAX_SIZE: usize = 1 << 16;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// YUV4MPEG2 reader
+///
+/// The 8 and 10-bit 4:2:0, 4:2:2 and 4:4:4 clips are supported, the
+/// 10-bit samples take 2 bytes in the native byte order, as in the frames
+/// of the decoder.
+///
+/// The frames are timestamped with their index, in a timebase matching the
+/// frame rate of the clip, and yielded in order.
+pub struct Y4mReader<R: BufRead> {
+    inner: R,
+    width: usize,
+    height: usize,
+    timebase: Rational64,
+    format: Arc<Formaton>,
+    frames: i64,
+}
+
+fn read_line<R: BufRead>(r: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+
+    r.read_until(b'\n', &mut line)?;
+    if line.pop() != Some(b'\n') {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    String::from_utf8(line).map_err(|_| invalid("invalid YUV4MPEG2 header"))
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    /// Read the stream header
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let line = read_line(&mut inner)?;
+        let params = line
+            .strip_prefix(SIGNATURE)
+            .ok_or_else(|| invalid("not a YUV4MPEG2 file"))?;
+
+        let (mut width, mut height) = (0, 0);
+        let mut rate = (25, 1);
+        let mut format = *YUV420;
+
+        for param in params.split(' ').filter(|p| !p.is_empty()) {
+            let mut chars = param.chars();
+            let tag = chars.next().unwrap();
+            let val = chars.as_str();
+
+            match tag {
+                'W' => width = val.parse().map_err(|_| invalid("invalid width"))?,
+                'H' => height = val.parse().map_err(|_| invalid("invalid height"))?,
+                'F' => {
+                    rate = val
+                        .split_once(':')
+                        .and_then(|(num, den)| Some((num.parse().ok()?, den.parse().ok()?)))
+                        .filter(|&(num, den): &(i64, i64)| num > 0 && den > 0)
+                        .ok_or_else(|| invalid("invalid frame rate"))?
+                }
+                'C' => {
+                    format = match val {
+                        "420" | "420jpeg" | "420paldv" | "420mpeg2" => *YUV420,
+                        "422" => *YUV422,
+                        "444" => *YUV444,
+                        "420p10" => with_depth(YUV420, 10),
+                        "422p10" => with_depth(YUV422, 10),
+                        "444p10" => with_depth(YUV444, 10),
+                        _ => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::Unsupported,
+                                format!("unsupported colorspace {}", val),
+                            ))
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if width == 0 || height == 0 {
+            return Err(invalid("missing frame size"));
+        }
+        if width > MAX_SIZE || height > MAX_SIZE {
+            return Err(invalid("frame size too large"));
+        }
+
+        Ok(Y4mReader {
+            inner,
+            width,
+            height,
+            timebase: Rational64::new(rate.1, rate.0),
+            format: Arc::new(format),
+            frames: 0,
+        })
+    }
+
+    /// Frame width
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Frame height
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Timebase of the frames, the inverse of the frame rate
+    pub fn timebase(&self) -> Rational64 {
+        self.timebase
+    }
+
+    /// Pixel format of the frames
+    pub fn format(&self) -> Arc<Formaton> {
+        self.format.clone()
+    }
+
+    /// Read the next frame, `None` at the end of the stream
+    pub fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        if self.inner.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        if !read_line(&mut self.inner)?.starts_with("FRAME") {
+            return Err(invalid("missing FRAME marker"));
+        }
+
+        let info = VideoInfo::new(
+            self.width,
+            self.height,
+            false,
+            FrameType::OTHER,
+            self.format.clone(),
+        );
+        let t = TimeInfo {
+            pts: Some(self.frames),
+            duration: Some(1),
+            timebase: Some(self.timebase),
+            ..Default::default()
+        };
+        let mut f = new_frame(info, Some(t));
+
+        for (i, c) in self.format.iter().flatten().enumerate() {
+            let (w, h) = c.get_subsampling();
+            let w = (self.width + (1 << w) - 1) >> w;
+            let h = (self.height + (1 << h) - 1) >> h;
+            let bytes = if c.get_depth() > 8 { 2 } else { 1 };
+            let stride = f.buf.linesize(i).unwrap();
+            let plane: &mut [u8] = f.buf.as_mut_slice(i).unwrap();
+
+            for row in plane.chunks_mut(stride).take(h) {
+                let row = &mut row[..w * bytes];
+                self.inner.read_exact(row)?;
+
+                // The clips store the 16-bit samples in little endian
+                if bytes == 2 {
+                    for s in row.chunks_exact_mut(2) {
+                        let v = u16::from_le_bytes([s[0], s[1]]);
+                        s.copy_from_slice(&v.to_ne_bytes());
+                    }
+                }
+            }
+        }
+
+        self.frames += 1;
+
+        Ok(Some(f))
+    }
+}
+
+impl<R: BufRead> Iterator for Y4mReader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+#[cfg(all(test, feature = "encoder"))]
+mod tests {
+    use super::*;
+    use crate::encoder::{AOMPacket, AV1EncoderConfig};
+
+    const TINY: &[u8] = include_bytes!("../examples/tiny.y4m");
+
+    #[test]
+    fn y4m_read() {
+        let r = Y4mReader::new(TINY).unwrap();
+
+        assert_eq!((r.width(), r.height()), (32, 32));
+        assert_eq!(r.timebase(), Rational64::new(1, 30));
+        assert_eq!(*r.format(), *YUV420);
+
+        let frames: Vec<_> = r.map(|f| f.unwrap()).collect();
+        assert_eq!(frames.len(), 10);
+        assert_eq!(frames[9].t.pts, Some(9));
+
+        // First row of the third frame
+        let header = TINY.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let offset = header + 2 * (6 + 32 * 32 * 3 / 2) + 6;
+        let plane: &[u8] = frames[2].buf.as_slice(0).unwrap();
+        assert_eq!(plane[..32], TINY[offset..offset + 32]);
+
+        let mut cfg = AV1EncoderConfig::new()
+            .unwrap()
+            .width(32)
+            .height(32)
+            .timebase(Rational64::new(1, 30))
+            .lag_in_frames(0);
+        let mut e = cfg.get_encoder().unwrap();
+        let mut packets = 0;
+        for f in &frames {
+            e.encode(f).unwrap();
+            while let Some(p) = e.get_packet() {
+                packets += matches!(p, AOMPacket::Packet(_)) as usize;
+            }
+        }
+        assert!(packets > 0);
+    }
+
+    #[test]
+    fn y4m_read_10bit() {
+        let mut clip = b"YUV4MPEG2 W4 H2 F30:1 C420p10\nFRAME\n".to_vec();
+        let samples: Vec<u16> = (0..8)
+            .map(|i| i * 100 + 3)
+            .chain([512, 600, 1000, 1023])
+            .collect();
+        for s in &samples {
+            clip.extend_from_slice(&s.to_le_bytes());
+        }
+
+        let mut r = Y4mReader::new(&clip[..]).unwrap();
+        assert_eq!(r.format().get_chromaton(0).unwrap().get_depth(), 10);
+
+        let f = r.read_frame().unwrap().unwrap();
+        let read = |i: usize, w: usize, h: usize| -> Vec<u16> {
+            let stride = f.buf.linesize(i).unwrap();
+            let plane: &[u8] = f.buf.as_slice(i).unwrap();
+            plane
+                .chunks(stride)
+                .take(h)
+                .flat_map(|row| row[..w * 2].chunks_exact(2))
+                .map(|s| u16::from_ne_bytes([s[0], s[1]]))
+                .collect()
+        };
+        assert_eq!(read(0, 4, 2), samples[..8]);
+        assert_eq!(read(1, 2, 1), [512, 600]);
+        assert_eq!(read(2, 2, 1), [1000, 1023]);
+        assert!(r.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn y4m_malformed() {
+        assert!(Y4mReader::new(&b"RIFF\n"[..]).is_err());
+        assert!(Y4mReader::new(&b"YUV4MPEG2 W32\n"[..]).is_err());
+        assert!(Y4mReader::new("YUV4MPEG2 W32 H32 \u{e9}t\u{e9}\n".as_bytes()).is_ok());
+        assert!(Y4mReader::new(&b"YUV4MPEG2 W32 H100000\n"[..]).is_err());
+        assert!(Y4mReader::new(&b"YUV4MPEG2 W99999999999 H32\n"[..]).is_err());
+        assert_eq!(
+            Y4mReader::new(&b"YUV4MPEG2 W32 H32 C420p12\n"[..])
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+
+        let header = TINY.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let mut r = Y4mReader::new(&TINY[..header + 100]).unwrap();
+        assert!(r.next().unwrap().is_err());
+    }
+}